@@ -0,0 +1,120 @@
+// Benchmarks of evaluation, run by `cargo bench`. Phrases are evaluated in
+// sessions with some global bindings, as environments of closures and local
+// bindings are made on top of the global ones, and by each backend. Sessions
+// making many top-level definitions are measured as well.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rcaml::{Backend, Session};
+
+const BACKENDS: [(&str, Backend); 2] = [("ast", Backend::Ast), ("bytecode", Backend::Bytecode)];
+
+// Session evaluating by `backend`, with `globals` values bound, and the
+// definitions given.
+fn session(backend: Backend, globals: usize, defs: &[&str]) -> Session {
+    let mut session = Session::new();
+    session.set_backend(backend);
+    for i in 0..globals {
+        session
+            .eval_phrase(&format!("let g{} = [{}; {}]", i, i, i + 1))
+            .unwrap();
+    }
+    for def in defs {
+        session.eval_phrase(def).unwrap();
+    }
+    session
+}
+
+// let x0 = 0 in let x1 = x0 + 1 in ... in x<depth-1>
+fn nested_lets(depth: usize) -> String {
+    let mut phrase = "let x0 = 0 in ".to_string();
+    for i in 1..depth {
+        phrase += &format!("let x{} = x{} + 1 in ", i, i - 1);
+    }
+    phrase + &format!("x{}", depth - 1)
+}
+
+fn bench_nested_lets(c: &mut Criterion) {
+    let mut group = c.benchmark_group("nested_lets");
+    for (name, backend) in BACKENDS {
+        for globals in [0, 1000] {
+            let mut session = session(backend, globals, &[]);
+            let phrase = nested_lets(200);
+            group.bench_with_input(BenchmarkId::new(name, globals), &phrase, |b, phrase| {
+                b.iter(|| session.eval_phrase(phrase).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_tail_calls(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tail_calls");
+    let def = "let rec loop n acc = if n = 0 then acc else loop (n - 1) (acc + n)";
+    for (name, backend) in BACKENDS {
+        for globals in [0, 1000] {
+            let mut session = session(backend, globals, &[def]);
+            group.bench_function(BenchmarkId::new(name, globals), |b| {
+                b.iter(|| session.eval_phrase("loop 10_000 0").unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_closures(c: &mut Criterion) {
+    let mut group = c.benchmark_group("closures");
+    let defs = [
+        "let twice f x = f (f x)",
+        "let compose f g x = f (g x)",
+        "let inc x = x + 1",
+    ];
+    let phrase = "let f = twice (compose inc inc) in twice (twice (twice (twice f))) 0";
+    for (name, backend) in BACKENDS {
+        for globals in [0, 1000] {
+            let mut session = session(backend, globals, &defs);
+            group.bench_function(BenchmarkId::new(name, globals), |b| {
+                b.iter(|| session.eval_phrase(phrase).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+// let d0 = 0, let d1 x = x + d0, let d2 = 2, ... each a phrase of its own,
+// so that functions keep the globals they were made with
+fn definitions(n: usize) -> Vec<String> {
+    (0..n)
+        .map(|i| match i % 2 {
+            0 => format!("let d{} = {}", i, i),
+            _ => format!("let d{} x = x + d{}", i, i - 1),
+        })
+        .collect()
+}
+
+fn bench_definitions(c: &mut Criterion) {
+    let mut group = c.benchmark_group("definitions");
+    group.sample_size(10);
+    for (name, backend) in BACKENDS {
+        for n in [1000, 4000] {
+            let phrases = definitions(n);
+            group.bench_with_input(BenchmarkId::new(name, n), &phrases, |b, phrases| {
+                b.iter(|| {
+                    let mut session = session(backend, 0, &[]);
+                    for phrase in phrases {
+                        session.eval_phrase(phrase).unwrap();
+                    }
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_nested_lets,
+    bench_tail_calls,
+    bench_closures,
+    bench_definitions
+);
+criterion_main!(benches);
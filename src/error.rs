@@ -0,0 +1,859 @@
+use crate::eval::{escape, LineIndex, Span};
+use std::fmt;
+
+/// errors raised while reading and evaluating inputs
+#[derive(Debug, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    /// failed to tokenize
+    Lex(LexError),
+    /// failed to parse, with all errors in source order if any
+    Parse(Vec<ParseError>),
+    /// ill-typed expression
+    Type(TypeError),
+    /// uncaught runtime exception
+    Exception(Exception),
+    /// unknown or misused toplevel directive
+    Directive(DirectiveError),
+    /// evaluation exceeded the steps allowed
+    OutOfFuel(u64),
+    /// failure of rcaml itself, e.g. I/O errors
+    Internal(String),
+    /// error in a file run by #use
+    Used(Box<UsedError>),
+}
+
+/// error located in a phrase of a file run by `#use`, rather than in the
+/// input which used it
+#[derive(Debug, PartialEq)]
+pub struct UsedError {
+    pub path: String,
+    /// phrase of the file
+    pub input: String,
+    /// line of the file which the phrase begins on
+    pub line: usize,
+    pub error: Error,
+}
+
+/// error tokenizing an input
+#[derive(Debug, PartialEq)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq)]
+#[non_exhaustive]
+pub enum LexErrorKind {
+    /// character which begins no token
+    IllegalCharacter(char),
+    /// integer literal not fitting in int
+    IntegerOutOfRange(String),
+    /// string literal lacking the closing quote
+    UnterminatedString,
+    /// comment lacking the closing "*)"
+    UnterminatedComment,
+    /// unknown escape sequence in a string literal
+    IllegalEscape(String),
+}
+
+/// error parsing an input
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq)]
+#[non_exhaustive]
+pub enum ParseErrorKind {
+    /// `expected` is required in place of the token `found`, or in place of
+    /// the end of input if `found` is None.
+    Expected {
+        expected: String,
+        found: Option<String>,
+    },
+    /// ";;" is required at the end of an input
+    MissingTerminator,
+}
+
+/// error typing an input
+#[derive(Debug, PartialEq)]
+pub struct TypeError {
+    pub kind: TypeErrorKind,
+    /// expression ill-typed, or none for a type given by an embedder
+    pub span: Option<Span>,
+}
+
+#[derive(Debug, PartialEq)]
+#[non_exhaustive]
+pub enum TypeErrorKind {
+    /// undefined name
+    UnboundValue(String),
+    /// undefined type name
+    UnboundTypeConstructor(String),
+    /// type variable not a parameter of a definition
+    UnboundTypeVariable(String),
+    /// undefined variant constructor
+    UnboundConstructor(String),
+    /// undefined record field
+    UnboundField(String),
+    /// type of a non-function value being applied
+    NotAFunction(String),
+    /// type abbreviation expanded to itself
+    CyclicAbbreviation(String),
+    /// primitive neither built in nor registered
+    UnboundPrimitive(String),
+    /// non-function type given to a primitive
+    ExternalNotFunction(String),
+    /// fields lacking in a record expression
+    MissingFields(Vec<String>),
+    /// `let rec` binding a value other than a function
+    IllegalLetRec,
+    /// `field` of the record type `types.0` is used with fields of type
+    /// `types.1`; boxed to keep errors small
+    MixedFields {
+        field: String,
+        types: Box<(String, String)>,
+    },
+    /// primitive `name` of type `types.0` is declared with type `types.1`,
+    /// which is not an instance of it
+    PrimitiveType {
+        name: String,
+        types: Box<(String, String)>,
+    },
+    /// constructor `name` taking `expected` arguments is applied to `actual`
+    /// ones
+    ConstructorArity {
+        name: String,
+        expected: usize,
+        actual: usize,
+    },
+    /// type constructor `name` taking `expected` arguments is applied to
+    /// `actual` ones
+    TypeArity {
+        name: String,
+        expected: usize,
+        actual: usize,
+    },
+    /// The expression has type `types.0` where type `types.1` is required;
+    /// boxed likewise. `trace` explains why they are incompatible, from the
+    /// outermost types.
+    Mismatch {
+        types: Box<(String, String)>,
+        trace: Vec<TypeClash>,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+#[non_exhaustive]
+pub enum TypeClash {
+    /// nested types which differ
+    Incompatible(String, String),
+    /// type variable occurring inside a type
+    Occurs(String, String),
+}
+
+/// exception raised by evaluation
+#[derive(Debug, PartialEq)]
+pub struct Exception {
+    pub kind: ExceptionKind,
+    /// expression which raised the exception, or none until an exception
+    /// raised by a primitive is located at its application
+    pub span: Option<Span>,
+}
+
+/// error running a toplevel directive
+#[derive(Debug, PartialEq)]
+pub struct DirectiveError {
+    pub kind: DirectiveErrorKind,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq)]
+#[non_exhaustive]
+pub enum DirectiveErrorKind {
+    /// no directive of the name
+    Unknown(String),
+    /// argument missing or of a wrong kind for the directive
+    WrongArgument(String),
+    /// file given to #use which cannot be read
+    CannotFindFile(String),
+    /// name given to #show which is not bound
+    UnknownElement(String),
+}
+
+#[derive(Debug, PartialEq)]
+#[non_exhaustive]
+pub enum ExceptionKind {
+    /// Division_by_zero
+    DivisionByZero,
+    /// Failure raised by `failwith` with its message
+    Failure(String),
+    /// Invalid_argument with its message, e.g. on comparing functions
+    InvalidArgument(String),
+    /// Stack_overflow, raised beyond the depth limit
+    StackOverflow,
+    /// Out_of_memory, raised beyond the allocation limit
+    OutOfMemory,
+    /// Sys.Break, raised on Ctrl-C or by `Session::set_interrupt`, and
+    /// rendered as `Interrupted.`
+    Interrupted,
+}
+
+impl Error {
+    /// Machine-readable code identifying the kind of the error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Lex(err) => match err.kind {
+                LexErrorKind::IllegalCharacter(_) => "lex/illegal-character",
+                LexErrorKind::IntegerOutOfRange(_) => "lex/integer-out-of-range",
+                LexErrorKind::UnterminatedString => "lex/unterminated-string",
+                LexErrorKind::UnterminatedComment => "lex/unterminated-comment",
+                LexErrorKind::IllegalEscape(_) => "lex/illegal-escape",
+            },
+            Error::Parse(errs) => match errs.first().map(|err| &err.kind) {
+                Some(ParseErrorKind::Expected { .. }) => "parse/expected",
+                Some(ParseErrorKind::MissingTerminator) => "parse/missing-terminator",
+                None => "parse",
+            },
+            Error::Type(err) => match err.kind {
+                TypeErrorKind::UnboundValue(_) => "type/unbound-value",
+                TypeErrorKind::UnboundTypeConstructor(_) => "type/unbound-type-constructor",
+                TypeErrorKind::TypeArity { .. } => "type/arity",
+                TypeErrorKind::UnboundTypeVariable(_) => "type/unbound-type-variable",
+                TypeErrorKind::UnboundConstructor(_) => "type/unbound-constructor",
+                TypeErrorKind::UnboundField(_) => "type/unbound-field",
+                TypeErrorKind::NotAFunction(_) => "type/not-a-function",
+                TypeErrorKind::CyclicAbbreviation(_) => "type/cyclic-abbreviation",
+                TypeErrorKind::UnboundPrimitive(_) => "type/unbound-primitive",
+                TypeErrorKind::ExternalNotFunction(_) => "type/external-not-function",
+                TypeErrorKind::PrimitiveType { .. } => "type/primitive-type",
+                TypeErrorKind::MissingFields(_) => "type/missing-fields",
+                TypeErrorKind::IllegalLetRec => "type/illegal-let-rec",
+                TypeErrorKind::MixedFields { .. } => "type/mixed-fields",
+                TypeErrorKind::ConstructorArity { .. } => "type/constructor-arity",
+                TypeErrorKind::Mismatch { .. } => "type/mismatch",
+            },
+            Error::Exception(exn) => match exn.kind {
+                ExceptionKind::DivisionByZero => "exception/division-by-zero",
+                ExceptionKind::Failure(_) => "exception/failure",
+                ExceptionKind::InvalidArgument(_) => "exception/invalid-argument",
+                ExceptionKind::StackOverflow => "exception/stack-overflow",
+                ExceptionKind::OutOfMemory => "exception/out-of-memory",
+                ExceptionKind::Interrupted => "exception/interrupted",
+            },
+            Error::Directive(err) => match err.kind {
+                DirectiveErrorKind::Unknown(_) => "directive/unknown",
+                DirectiveErrorKind::WrongArgument(_) => "directive/wrong-argument",
+                DirectiveErrorKind::CannotFindFile(_) => "directive/cannot-find-file",
+                DirectiveErrorKind::UnknownElement(_) => "directive/unknown-element",
+            },
+            Error::OutOfFuel(_) => "out-of-fuel",
+            Error::Internal(_) => "internal",
+            Error::Used(err) => err.error.code(),
+        }
+    }
+
+    /// Location in the input where the error occurred.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Error::Lex(err) => Some(err.span),
+            Error::Parse(errs) => errs.first().map(|err| err.span),
+            Error::Type(err) => err.span,
+            Error::Exception(exn) => exn.span,
+            Error::Directive(err) => Some(err.span),
+            // located in another file
+            Error::OutOfFuel(_) | Error::Internal(_) | Error::Used(_) => None,
+        }
+    }
+
+    /// Formats the error as the OCaml toplevel does, e.g.
+    ///
+    /// ```text
+    /// Line 1, characters 4-8:
+    /// 1 | 1 + true;;
+    ///         ^^^^
+    /// Error: This expression has type bool but an expression was expected of type int
+    /// ```
+    ///
+    /// Uncaught exceptions are reported without locations.
+    pub fn render(&self, source: &str) -> String {
+        self.render_with(source, None, 1)
+    }
+
+    /// Formats the error in a source file as `ocaml file.ml` does, locating
+    /// it like `File "file.ml", line 1, characters 4-8:`.
+    pub fn render_in_file(&self, source: &str, path: &str) -> String {
+        self.render_with(source, Some(path), 1)
+    }
+
+    // Formats the error in a phrase of a source which begins on `line`,
+    // numbering lines as in the source.
+    pub(crate) fn render_phrase(&self, phrase: &str, line: usize, file: Option<&str>) -> String {
+        self.render_with(phrase, file, line)
+    }
+
+    fn render_with(&self, source: &str, file: Option<&str>, first: usize) -> String {
+        match (self, self.span()) {
+            (Error::Parse(errs), _) if !errs.is_empty() => errs
+                .iter()
+                .map(|err| {
+                    let location = render_location(err.span, source, file, first);
+                    format!("{}Error: {}", location, err)
+                })
+                .collect::<Vec<String>>()
+                .join("\n"),
+            // reported like Ctrl-C at the prompt
+            (Error::Exception(exn), _) if exn.kind == ExceptionKind::Interrupted => {
+                "Interrupted.".to_string()
+            }
+            (Error::Exception(exn), _) => format!("Exception: {}.", exn),
+            (Error::Used(err), _) => err
+                .error
+                .render_phrase(&err.input, err.line, Some(&err.path)),
+            (_, Some(span)) => {
+                let location = render_location(span, source, file, first);
+                format!("{}Error: {}", location, self)
+            }
+            (_, None) => format!("Error: {}", self),
+        }
+    }
+}
+
+// Formats a span with the source lines it covers, underlining it if it is
+// within a line. The location is prefixed by the path of the file if any,
+// and lines are numbered from `first`. Lines and columns are looked up from
+// the offsets, those beyond the source, as of the end of input, being its
+// end.
+fn render_location(span: Span, source: &str, file: Option<&str>, first: usize) -> String {
+    let index = LineIndex::new(source);
+    let start = index.pos(span.start.offset.min(source.len()));
+    let end = index.pos(span.end.offset.clamp(start.offset, source.len()));
+    let lines: Vec<&str> = source.split('\n').collect();
+
+    let (mut out, line) = match file {
+        Some(path) => (format!("File \"{}\", ", path), "line"),
+        None => (String::new(), "Line"),
+    };
+    let number = |line: usize| line + first - 1;
+    if start.line == end.line {
+        out += &format!(
+            "{} {}, characters {}-{}:\n",
+            line,
+            number(start.line),
+            start.column,
+            end.column
+        );
+        let text = lines[start.line - 1];
+        let prefix = format!("{} | ", number(start.line));
+        let pad = prefix.len() + char_count(text, start.column);
+        let carets = (char_count(text, end.column) - char_count(text, start.column)).max(1);
+        out += &format!("{}{}\n", prefix, text);
+        out += &format!("{}{}\n", " ".repeat(pad), "^".repeat(carets));
+    } else {
+        out += &format!(
+            "{}s {}-{}, characters {}-{}:\n",
+            line,
+            number(start.line),
+            number(end.line),
+            start.column,
+            end.column
+        );
+        for line in start.line..=end.line {
+            out += &format!("{} | {}\n", number(line), lines[line - 1]);
+        }
+    }
+    out
+}
+
+// Counts characters within the first `bytes` bytes of a line.
+fn char_count(text: &str, bytes: usize) -> usize {
+    text.char_indices()
+        .take_while(|(idx, _)| *idx < bytes)
+        .count()
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Lex(err) => err.fmt(f),
+            Error::Parse(errs) if errs.is_empty() => write!(f, "Syntax error"),
+            Error::Parse(errs) => write!(
+                f,
+                "{}",
+                errs.iter()
+                    .map(|err| err.to_string())
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            ),
+            Error::Type(err) => err.fmt(f),
+            Error::Exception(exn) => write!(f, "Exception: {}", exn),
+            Error::Directive(err) => err.fmt(f),
+            Error::OutOfFuel(fuel) => write!(f, "Evaluation ran out of fuel after {} steps", fuel),
+            Error::Internal(msg) => write!(f, "{}", msg),
+            Error::Used(err) => err.error.fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            LexErrorKind::IllegalCharacter(c) => write!(f, "Illegal character ({})", c),
+            LexErrorKind::IntegerOutOfRange(_) => write!(
+                f,
+                "Integer literal exceeds the range of representable integers of type int"
+            ),
+            LexErrorKind::UnterminatedString => write!(f, "String literal not terminated"),
+            LexErrorKind::UnterminatedComment => write!(f, "Comment not terminated"),
+            LexErrorKind::IllegalEscape(seq) => write!(
+                f,
+                "Illegal backslash escape in string or character ({})",
+                seq
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ParseErrorKind::Expected { expected, found } => {
+                write!(f, "Syntax error: expected {}, ", expected)?;
+                match found {
+                    Some(found) => write!(f, r#"found "{}""#, found),
+                    None => write!(f, "found end of input"),
+                }
+            }
+            ParseErrorKind::MissingTerminator => {
+                write!(f, r#"";;" is required at the end of a expression"#)
+            }
+        }
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            TypeErrorKind::UnboundValue(name) => write!(f, "Unbound value {}", name),
+            TypeErrorKind::UnboundTypeConstructor(name) => {
+                write!(f, "Unbound type constructor {}", name)
+            }
+            TypeErrorKind::UnboundTypeVariable(name) => write!(
+                f,
+                "The type variable '{} is unbound in this type declaration.",
+                name
+            ),
+            TypeErrorKind::UnboundConstructor(name) => write!(f, "Unbound constructor {}", name),
+            TypeErrorKind::UnboundField(name) => write!(f, "Unbound record field {}", name),
+            TypeErrorKind::NotAFunction(ty) => write!(
+                f,
+                "This expression has type {}\n       This is not a function; it cannot be applied.",
+                ty
+            ),
+            TypeErrorKind::CyclicAbbreviation(name) => {
+                write!(f, "The type abbreviation {} is cyclic", name)
+            }
+            TypeErrorKind::UnboundPrimitive(name) => {
+                write!(f, "Unbound primitive \"{}\"", name)
+            }
+            TypeErrorKind::ExternalNotFunction(name) => write!(
+                f,
+                "The primitive \"{}\" must be a function,\n       since external identifiers must be functions",
+                name
+            ),
+            TypeErrorKind::PrimitiveType { name, types } => write!(
+                f,
+                "The primitive \"{}\" has type {}\n       but is declared here with type {}",
+                name, types.0, types.1
+            ),
+            TypeErrorKind::IllegalLetRec => write!(
+                f,
+                "This kind of expression is not allowed as right-hand side of `let rec'"
+            ),
+            TypeErrorKind::MissingFields(fields) => {
+                write!(f, "Some record fields are undefined: {}", fields.join(" "))
+            }
+            TypeErrorKind::MixedFields { field, types } => write!(
+                f,
+                "The record field {} belongs to the type {}\n       but is mixed here with fields of type {}",
+                field, types.0, types.1
+            ),
+            TypeErrorKind::ConstructorArity {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "The constructor {} expects {} argument(s),\n       but is applied here to {} argument(s)",
+                name, expected, actual
+            ),
+            TypeErrorKind::TypeArity {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "The type constructor {} expects {} argument(s),\n       but is here applied to {} argument(s)",
+                name, expected, actual
+            ),
+            TypeErrorKind::Mismatch { types, trace } => {
+                write!(
+                    f,
+                    "This expression has type {} but an expression was expected of type {}",
+                    types.0, types.1
+                )?;
+                for clash in trace {
+                    match clash {
+                        TypeClash::Incompatible(actual, expected) => write!(
+                            f,
+                            "\n       Type {} is not compatible with type {}",
+                            actual, expected
+                        )?,
+                        TypeClash::Occurs(var, ty) => {
+                            write!(f, "\n       The type variable {} occurs inside {}", var, ty)?
+                        }
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for DirectiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            DirectiveErrorKind::Unknown(name) => write!(f, "Unknown directive `{}'.", name),
+            DirectiveErrorKind::WrongArgument(name) => {
+                write!(f, "Wrong type of argument for directive `{}'.", name)
+            }
+            DirectiveErrorKind::CannotFindFile(path) => write!(f, "Cannot find file {}.", path),
+            DirectiveErrorKind::UnknownElement(_) => write!(f, "Unknown element."),
+        }
+    }
+}
+
+impl Exception {
+    /// `Failure msg` raised by a primitive, which is located at its
+    /// application once raised.
+    pub fn failure(msg: impl Into<String>) -> Self {
+        Self {
+            kind: ExceptionKind::Failure(msg.into()),
+            span: None,
+        }
+    }
+
+    /// `Invalid_argument msg` raised by a primitive, located likewise.
+    pub fn invalid_argument(msg: impl Into<String>) -> Self {
+        Self {
+            kind: ExceptionKind::InvalidArgument(msg.into()),
+            span: None,
+        }
+    }
+
+    /// Formats the exception as an uncaught one is reported when running a
+    /// file, e.g. `Failure("boom")` rather than `Failure "boom"`.
+    pub fn fatal(&self) -> String {
+        match &self.kind {
+            ExceptionKind::Failure(msg) => format!("Failure(\"{}\")", escape(msg.as_bytes())),
+            ExceptionKind::InvalidArgument(msg) => {
+                format!("Invalid_argument(\"{}\")", escape(msg.as_bytes()))
+            }
+            ExceptionKind::Interrupted => "Stdlib.Sys.Break".to_string(),
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ExceptionKind::DivisionByZero => write!(f, "Division_by_zero"),
+            ExceptionKind::Failure(msg) => write!(f, "Failure \"{}\"", escape(msg.as_bytes())),
+            ExceptionKind::InvalidArgument(msg) => {
+                write!(f, "Invalid_argument \"{}\"", escape(msg.as_bytes()))
+            }
+            ExceptionKind::StackOverflow => write!(f, "Stack_overflow"),
+            ExceptionKind::OutOfMemory => write!(f, "Out_of_memory"),
+            ExceptionKind::Interrupted => write!(f, "Sys.Break"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Lex(err) => Some(err),
+            Error::Parse(errs) => errs.first().map(|err| err as _),
+            Error::Type(err) => Some(err),
+            Error::Exception(exn) => Some(exn),
+            Error::Directive(err) => Some(err),
+            Error::Used(err) => Some(&err.error),
+            Error::OutOfFuel(_) | Error::Internal(_) => None,
+        }
+    }
+}
+
+impl std::error::Error for LexError {}
+impl std::error::Error for ParseError {}
+impl std::error::Error for TypeError {}
+impl std::error::Error for Exception {}
+impl std::error::Error for DirectiveError {}
+
+impl From<LexError> for Error {
+    fn from(err: LexError) -> Self {
+        Error::Lex(err)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Self {
+        Error::Parse(vec![err])
+    }
+}
+
+impl From<Vec<ParseError>> for Error {
+    fn from(errs: Vec<ParseError>) -> Self {
+        Error::Parse(errs)
+    }
+}
+
+impl From<TypeError> for Error {
+    fn from(err: TypeError) -> Self {
+        Error::Type(err)
+    }
+}
+
+impl From<DirectiveError> for Error {
+    fn from(err: DirectiveError) -> Self {
+        Error::Directive(err)
+    }
+}
+
+impl From<Exception> for Error {
+    fn from(exn: Exception) -> Self {
+        Error::Exception(exn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span_of(source: &str, start: usize, end: usize) -> Span {
+        let index = LineIndex::new(source);
+        Span::new(index.pos(start), index.pos(end))
+    }
+
+    fn type_error(kind: TypeErrorKind, span: Span) -> Error {
+        Error::Type(TypeError {
+            kind,
+            span: Some(span),
+        })
+    }
+
+    fn mismatch(actual: &str, expected: &str, trace: &[(&str, &str)]) -> TypeErrorKind {
+        TypeErrorKind::Mismatch {
+            types: Box::new((actual.to_string(), expected.to_string())),
+            trace: trace
+                .iter()
+                .map(|(a, e)| TypeClash::Incompatible(a.to_string(), e.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn render_single_line_span() {
+        let source = "1 + true;;";
+        let err = type_error(mismatch("bool", "int", &[]), span_of(source, 4, 8));
+        assert_eq!(
+            err.render(source),
+            "Line 1, characters 4-8:\n1 | 1 + true;;\n        ^^^^\nError: This expression has type bool but an expression was expected of type int"
+        );
+    }
+
+    #[test]
+    fn render_span_on_later_line() {
+        let source = "let x = 1 in\nx + foo;;";
+        let err = type_error(
+            TypeErrorKind::UnboundValue("foo".to_string()),
+            span_of(source, 17, 20),
+        );
+        assert_eq!(
+            err.render(source),
+            "Line 2, characters 4-7:\n2 | x + foo;;\n        ^^^\nError: Unbound value foo"
+        );
+    }
+
+    #[test]
+    fn render_multi_line_span() {
+        let source = "let x = 1 in\nx + 1;;";
+        let err = type_error(mismatch("int", "bool", &[]), span_of(source, 0, 18));
+        assert_eq!(
+            err.render(source),
+            "Lines 1-2, characters 0-5:\n1 | let x = 1 in\n2 | x + 1;;\nError: This expression has type int but an expression was expected of type bool"
+        );
+    }
+
+    #[test]
+    fn render_unification_trace() {
+        let source = "[1] == [true];;";
+        let err = type_error(
+            mismatch("bool list", "int list", &[("bool", "int")]),
+            span_of(source, 7, 13),
+        );
+        assert_eq!(
+            err.render(source),
+            concat!(
+                "Line 1, characters 7-13:\n1 | [1] == [true];;\n           ^^^^^^\n",
+                "Error: This expression has type bool list but an expression was expected of type int list\n",
+                "       Type bool is not compatible with type int",
+            )
+        );
+    }
+
+    #[test]
+    fn render_end_of_input() {
+        let source = "1 +";
+        let err = Error::from(ParseError {
+            kind: ParseErrorKind::Expected {
+                expected: "an expression".to_string(),
+                found: None,
+            },
+            span: Span::EOF,
+        });
+        assert_eq!(
+            err.render(source),
+            "Line 1, characters 3-3:\n1 | 1 +\n       ^\nError: Syntax error: expected an expression, found end of input"
+        );
+    }
+
+    #[test]
+    fn render_span_beyond_source() {
+        // e.g. an error in a phrase rendered with a shorter source
+        let err = type_error(mismatch("bool", "int", &[]), span_of("x + true;;", 4, 8));
+        assert_eq!(
+            err.render("x"),
+            "Line 1, characters 1-1:\n1 | x\n     ^\nError: This expression has type bool but an expression was expected of type int"
+        );
+    }
+
+    #[test]
+    fn render_multiple_parse_errors() {
+        let source = "[1; x; 3; y];;";
+        let expected_int = |found: &str, start, end| ParseError {
+            kind: ParseErrorKind::Expected {
+                expected: "an integer".to_string(),
+                found: Some(found.to_string()),
+            },
+            span: span_of(source, start, end),
+        };
+        let err = Error::Parse(vec![expected_int("x", 4, 5), expected_int("y", 10, 11)]);
+        assert_eq!(
+            err.render(source),
+            concat!(
+                "Line 1, characters 4-5:\n1 | [1; x; 3; y];;\n        ^\n",
+                "Error: Syntax error: expected an integer, found \"x\"\n",
+                "Line 1, characters 10-11:\n1 | [1; x; 3; y];;\n              ^\n",
+                "Error: Syntax error: expected an integer, found \"y\"",
+            )
+        );
+    }
+
+    #[test]
+    fn render_parse_errors_of_none() {
+        let err = Error::Parse(vec![]);
+        assert_eq!(err.code(), "parse");
+        assert_eq!(err.span(), None);
+        assert!(std::error::Error::source(&err).is_none());
+        assert_eq!(err.render("x;;"), "Error: Syntax error");
+    }
+
+    #[test]
+    fn render_exception_without_location() {
+        let source = "1 / 0;;";
+        let err = Error::Exception(Exception {
+            kind: ExceptionKind::DivisionByZero,
+            span: Some(span_of(source, 0, 5)),
+        });
+        assert_eq!(err.render(source), "Exception: Division_by_zero.");
+    }
+
+    #[test]
+    fn render_error_in_file() {
+        let source = "let x = 1\nlet y = x +\n  true;;";
+        let err = type_error(mismatch("bool", "int", &[]), span_of(source, 8, 28));
+        assert_eq!(
+            err.render_in_file(source, "a.ml"),
+            concat!(
+                "File \"a.ml\", lines 1-3, characters 8-6:\n",
+                "1 | let x = 1\n2 | let y = x +\n3 |   true;;\n",
+                "Error: This expression has type bool but an expression was expected of type int",
+            )
+        );
+        let err = type_error(mismatch("bool", "int", &[]), span_of(source, 24, 28));
+        assert!(err
+            .render_in_file(source, "a.ml")
+            .starts_with("File \"a.ml\", line 3, characters 2-6:\n3 |   true;;\n"));
+    }
+
+    #[test]
+    fn render_error_in_used_file() {
+        // phrase on the second line of the file
+        let source = "let y = x + true;;";
+        let error = type_error(mismatch("bool", "int", &[]), span_of(source, 12, 16));
+        let err = Error::Used(Box::new(UsedError {
+            path: "u.ml".to_string(),
+            input: source.to_string(),
+            line: 2,
+            error,
+        }));
+        assert_eq!(err.code(), "type/mismatch");
+        assert_eq!(err.span(), None);
+        // The caller's input and file are not those of the error.
+        assert!(err
+            .render_in_file("#use \"u.ml\";;", "a.ml")
+            .starts_with("File \"u.ml\", line 2, characters 12-16:\n2 | let y = x + true;;\n"));
+    }
+
+    #[test]
+    fn format_failures() {
+        let exn = Exception {
+            kind: ExceptionKind::Failure("a\"b\n".to_string()),
+            span: None,
+        };
+        assert_eq!(exn.to_string(), r#"Failure "a\"b\n""#);
+        assert_eq!(exn.fatal(), r#"Failure("a\"b\n")"#);
+    }
+
+    #[test]
+    fn format_interruptions() {
+        let exn = Exception {
+            kind: ExceptionKind::Interrupted,
+            span: None,
+        };
+        assert_eq!(exn.fatal(), "Stdlib.Sys.Break");
+        assert_eq!(Error::Exception(exn).render("f ();;"), "Interrupted.");
+    }
+
+    #[test]
+    fn distinguish_errors_by_codes() {
+        let span = Span::default();
+        assert_eq!(
+            type_error(TypeErrorKind::UnboundValue("x".to_string()), span).code(),
+            "type/unbound-value"
+        );
+        assert_eq!(
+            Error::Lex(LexError {
+                kind: LexErrorKind::IllegalCharacter('@'),
+                span
+            })
+            .code(),
+            "lex/illegal-character"
+        );
+        assert_eq!(Error::OutOfFuel(100).code(), "out-of-fuel");
+        assert_eq!(Error::Internal("oops".to_string()).code(), "internal");
+    }
+}
@@ -1,36 +1,535 @@
+mod builtins;
+mod bytecode;
+mod c;
+mod emit;
+mod env;
 mod eval_ast;
+mod format;
+mod lambda;
 mod lexer;
+mod meter;
 mod parser;
+mod printer;
+mod span;
+mod types;
+mod typing;
 mod value;
+mod vm;
+mod wasm;
 
+use crate::error::{
+    DirectiveError, DirectiveErrorKind, Error, LexError, LexErrorKind, ParseError, ParseErrorKind,
+    TypeError, TypeErrorKind,
+};
 use crate::Bounds;
+use crate::EvalOutput;
+pub(crate) use builtins::{Native, NativeFn};
+pub(crate) use env::Globals;
 use eval_ast::eval_ast;
-use lexer::tokenize;
-use parser::parse;
-pub(crate) use value::Value;
+use format::Doc;
+pub(crate) use lexer::escape;
+use lexer::{tokenize, TokenKind};
+pub use meter::EvalLimits;
+pub(crate) use meter::Meter;
+use parser::{definition_starts, parse, parse_type_expr, Node, NodeKind};
+use printer::show_value;
+pub(crate) use printer::Limits;
+pub(crate) use span::LineIndex;
+pub use span::{Pos, Span};
+use std::ops::Range;
+use std::rc::Rc;
+pub(crate) use types::{Type, TypeDecls};
+use typing::{declare_types, external_type, infer, type_scheme};
+pub(crate) use value::{Func, List, Value};
+
+pub(crate) fn keywords() -> impl Iterator<Item = &'static str> {
+    lexer::KEYWORDS.iter().map(|(kw, _)| *kw)
+}
+
+// Names of the primitives built in with their types, e.g. `print_int`.
+pub(crate) fn prims() -> impl Iterator<Item = (&'static str, Type)> {
+    builtins::prims().map(|prim| (prim.name, (prim.ty)()))
+}
+
+/// how phrases are evaluated, chosen by `--backend`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Backend {
+    /// walking the syntax tree
+    #[default]
+    Ast,
+    /// compiling to bytecode run by a stack machine
+    Bytecode,
+}
+
+// what `rcaml compile` compiles to, chosen by the extension of the output
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Target {
+    // a C file, e.g. `out.c`
+    C,
+    // a WebAssembly module in the text format, e.g. `out.wat`
+    Wasm,
+}
+
+// program compiled phrase by phrase to a target
+pub(crate) enum Compiled {
+    C(c::CProgram),
+    Wasm(wasm::WasmProgram),
+}
+
+impl Compiled {
+    pub fn new(target: Target) -> Self {
+        match target {
+            Target::C => Compiled::C(c::CProgram::new()),
+            Target::Wasm => Compiled::Wasm(wasm::WasmProgram::new()),
+        }
+    }
+
+    fn phrase(&mut self, program: &lambda::Program, decls: &TypeDecls) -> Result<(), Error> {
+        match self {
+            Compiled::C(c) => c.phrase(program, decls),
+            Compiled::Wasm(wasm) => wasm.phrase(program, decls),
+        }
+    }
+
+    fn external(&mut self, name: &str, prim: &str) -> Result<(), Error> {
+        match self {
+            Compiled::C(c) => c.external(name, prim),
+            Compiled::Wasm(wasm) => wasm.external(name, prim),
+        }
+    }
+
+    // Source of the whole program with its runtime.
+    pub fn finish(&self) -> String {
+        match self {
+            Compiled::C(c) => c.finish(),
+            Compiled::Wasm(wasm) => wasm.finish(),
+        }
+    }
+}
+
+// toplevel directive, e.g. `#use "file.ml"`
+#[derive(Debug, PartialEq)]
+pub(crate) struct Directive {
+    pub name: String,
+    pub arg: Option<DirectiveArg>,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum DirectiveArg {
+    Int(i64),
+    Str(String),
+    Ident(String),
+    Bool(bool),
+}
+
+// Parses an input if it is a directive.
+pub(crate) fn parse_directive(input: &str) -> Result<Option<Directive>, Error> {
+    if !input.trim_start().starts_with('#') {
+        return Ok(None);
+    }
+    let ast = parse_phrase(input)?;
+    let (name, arg) = match ast.kind {
+        NodeKind::Directive(name, arg) => (name, arg),
+        _ => return Ok(None),
+    };
+    let arg = arg.map(|arg| match arg.kind {
+        NodeKind::Int(i) => DirectiveArg::Int(i),
+        NodeKind::Str(s) => DirectiveArg::Str(String::from_utf8_lossy(&s).into_owned()),
+        NodeKind::Bool(b) => DirectiveArg::Bool(b),
+        NodeKind::Ident(name) => DirectiveArg::Ident(name),
+        kind => unreachable!("Unexpected directive argument: {:?}", kind),
+    });
+    Ok(Some(Directive {
+        name,
+        arg,
+        span: ast.span,
+    }))
+}
+
+// Type of a bound value, or of a primitive.
+pub(crate) fn type_of(name: &str, bounds: &Bounds) -> Option<Type> {
+    match bounds.get_type(name) {
+        Some(ty) => Some(ty.clone()),
+        None => builtins::prim(name).map(|prim| (prim.ty)()),
+    }
+}
+
+// Byte offset of the first ";;" outside string literals and comments.
+pub(crate) fn terminator(input: &str) -> Option<usize> {
+    let bytes = input.as_bytes();
+    let (mut in_str, mut comments) = (false, 0);
+    let mut idx = 0;
+    while idx < bytes.len() {
+        match (in_str, bytes[idx], bytes.get(idx + 1)) {
+            (true, b'\\', _) => idx += 1,
+            (_, b'"', _) => in_str = !in_str,
+            (false, b'(', Some(b'*')) => {
+                comments += 1;
+                idx += 1;
+            }
+            (false, b'*', Some(b')')) if comments > 0 => {
+                comments -= 1;
+                idx += 1;
+            }
+            (false, b';', Some(b';')) if comments == 0 => return Some(idx),
+            _ => (),
+        }
+        idx += 1;
+    }
+    None
+}
+
+// Splits a source into phrases, returning the byte range of each. Phrases
+// are terminated by ";;", or begin with `let` or `type` following a
+// definition. Text after the last phrase is ignored if it is blank or
+// comments.
+pub(crate) fn phrases(source: &str) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    while start < source.len() {
+        let end = match terminator(&source[start..]) {
+            Some(idx) => start + idx + 2,
+            None if tokenize(&source[start..]).is_ok_and(|tokens| tokens.is_empty()) => break,
+            None => source.len(),
+        };
+        // Phrases failing to tokenize are left to report the error.
+        let text = &source[start..end];
+        if let Ok(tokens) = tokenize(text.strip_suffix(";;").unwrap_or(text)) {
+            let base = start;
+            for idx in definition_starts(&tokens) {
+                let offset = base + tokens[idx].span.start.offset;
+                ranges.push(start..offset);
+                start = offset;
+            }
+        }
+        ranges.push(start..end);
+        start = end;
+    }
+    ranges
+}
+
+// Parses an input up to ";;".
+fn parse_phrase(input: &str) -> Result<Node, Error> {
+    let index = LineIndex::new(input);
 
-pub(crate) fn eval(input: &str, bounds: &mut Bounds) -> Result<String, String> {
     // Extract an expression that precedes ";;".
-    let expr = match input.find(";;") {
-        Some(idx) => (&input[0..idx]).trim(),
-        None => return Err(r#"";;" is required at the end of a expression"#.to_string()),
+    let idx = match terminator(input) {
+        Some(idx) => idx,
+        None => {
+            let end = index.pos(input.len());
+            return Err(Error::from(ParseError {
+                kind: ParseErrorKind::MissingTerminator,
+                span: Span::new(end, end),
+            }));
+        }
     };
+    let expr = &input[0..idx];
 
     let tokens = tokenize(expr)?;
-    let ast = parse(&tokens)?;
-    let output = eval_ast(&ast, bounds)?;
-    Ok(output.to_string())
+    let ast = parse(&tokens).map_err(|mut errs| {
+        // Errors at the end of input are reported at ";;".
+        for err in errs.iter_mut().filter(|err| err.span == Span::EOF) {
+            err.span = Span::new(index.pos(idx), index.pos(idx + 2));
+            if let ParseErrorKind::Expected { found, .. } = &mut err.kind {
+                *found = Some(";;".to_string());
+            }
+        }
+        errs
+    })?;
+    Ok(ast)
+}
+
+// Evaluates an input, returning the toplevel output. Directives are run by
+// the toplevel beforehand.
+pub(crate) fn eval(input: &str, bounds: &mut Bounds) -> Result<String, Error> {
+    eval_phrase(input, bounds).map(|output| output.to_string())
+}
+
+// IR of an input, as dumped by `-dlambda`, if it is an expression or a
+// binding.
+pub(crate) fn lambda(input: &str, bounds: &Bounds) -> Result<Option<String>, Error> {
+    let ast = parse_phrase(input)?;
+    match &ast.kind {
+        NodeKind::Directive(..) | NodeKind::TypeDef(_) | NodeKind::External(..) => Ok(None),
+        _ => {
+            infer(&ast, bounds)?;
+            let program = lambda::lower(&ast, bounds)?;
+            Ok(Some(program.to_string()))
+        }
+    }
+}
+
+// Evaluates an input, returning the value with its type.
+pub(crate) fn eval_phrase(input: &str, bounds: &mut Bounds) -> Result<EvalOutput, Error> {
+    let ast = parse_phrase(input)?;
+    if let NodeKind::Directive(name, _) = &ast.kind {
+        return Err(Error::from(DirectiveError {
+            kind: DirectiveErrorKind::Unknown(name.clone()),
+            span: ast.span,
+        }));
+    }
+    if let NodeKind::TypeDef(defs) = &ast.kind {
+        return Ok(EvalOutput::Types {
+            text: define_types(defs, bounds)?,
+        });
+    }
+    if let NodeKind::External(name, texpr, prim) = &ast.kind {
+        let ty = define_external(&ast, name, texpr, prim, bounds)?;
+        let text = format!(
+            "external {} : {} = \"{}\"",
+            name,
+            ty,
+            escape(prim.as_bytes())
+        );
+        return Ok(EvalOutput::External {
+            name: name.clone(),
+            ty: ty.to_string(),
+            text,
+        });
+    }
+    let ty = infer(&ast, bounds)?;
+    // Weak type variables are named '_weak1, '_weak2, ... throughout the
+    // session once printed.
+    for var in ty.vars() {
+        if var.weak().is_none() {
+            bounds.weak_vars += 1;
+            var.set_weak(bounds.weak_vars);
+        }
+    }
+    let output = stacker::grow(meter::STACK_SIZE, || {
+        bounds.meter.reset();
+        match bounds.backend {
+            Backend::Ast => eval_ast(&ast, bounds),
+            Backend::Bytecode => vm::eval(&ast, bounds),
+        }
+    })?;
+    let shown = show_value(&output.value, &ty, &bounds.decls, &bounds.limits);
+    // Laid out like `@[<2>@[<2>val x :@ ty@] =@ value@]` or
+    // `@[- : ty@ =@ value@]` by the OCaml toplevel.
+    let doc = match &output.name {
+        Some(name) => {
+            bounds.bind_type(name.clone(), ty.clone());
+            let header = vec![
+                Doc::text(format!("val {} :", name)),
+                Doc::space(),
+                Doc::text(ty.to_string()),
+            ];
+            Doc::Box(
+                2,
+                vec![Doc::Box(2, header), Doc::text(" ="), Doc::space(), shown],
+            )
+        }
+        None => Doc::Box(
+            0,
+            vec![
+                Doc::text(format!("- : {}", ty)),
+                Doc::space(),
+                Doc::text("="),
+                Doc::space(),
+                shown,
+            ],
+        ),
+    };
+    Ok(EvalOutput::Value {
+        name: output.name,
+        ty: ty.to_string(),
+        value: crate::Value(output.value),
+        text: doc.render(),
+    })
+}
+
+// Declares the types of a definition, returning them as printed.
+fn define_types(defs: &[parser::TypeDefStruct], bounds: &mut Bounds) -> Result<String, Error> {
+    let decls = declare_types(defs, &bounds.decls)?;
+    let mut lines = Vec::new();
+    for (i, decl) in decls.into_iter().enumerate() {
+        lines.push(decl.show(if i == 0 { "type" } else { "and" }));
+        Rc::make_mut(&mut bounds.decls).declare(decl);
+    }
+    Ok(lines.join("\n"))
+}
+
+// Binds the name of an external declaration to its primitive, returning its
+// type.
+fn define_external(
+    ast: &Node,
+    name: &str,
+    texpr: &parser::TypeExpr,
+    prim: &str,
+    bounds: &mut Bounds,
+) -> Result<Type, Error> {
+    let (value, prim_ty) = builtins::primitive(prim, bounds).ok_or_else(|| {
+        Error::from(TypeError {
+            kind: TypeErrorKind::UnboundPrimitive(prim.to_string()),
+            span: Some(ast.span),
+        })
+    })?;
+    let ty = external_type(texpr, prim, &prim_ty, &bounds.decls)?;
+    bounds.bind(name.to_string(), value);
+    bounds.bind_type(name.to_string(), ty.clone());
+    Ok(ty)
+}
+
+// Compiles an input as part of a program, binding the types of the names it
+// binds. Directives cannot be compiled.
+pub(crate) fn compile_phrase(
+    input: &str,
+    bounds: &mut Bounds,
+    program: &mut Compiled,
+) -> Result<(), Error> {
+    let ast = parse_phrase(input)?;
+    check_int_range(input)?;
+    match &ast.kind {
+        NodeKind::Directive(name, _) => Err(Error::from(DirectiveError {
+            kind: DirectiveErrorKind::Unknown(name.clone()),
+            span: ast.span,
+        })),
+        NodeKind::TypeDef(defs) => define_types(defs, bounds).map(|_| ()),
+        NodeKind::External(name, texpr, prim) => {
+            define_external(&ast, name, texpr, prim, bounds)?;
+            program.external(name, prim)
+        }
+        _ => {
+            let ty = infer(&ast, bounds)?;
+            let lowered = lambda::lower(&ast, bounds)?;
+            program.phrase(&lowered, &bounds.decls)?;
+            if let Some(name) = lowered.name {
+                bounds.bind_type(name, ty);
+            }
+            Ok(())
+        }
+    }
+}
+
+// Rejects int literals beyond `max_int` of compiled programs, whose ints
+// are tagged in 63 bits as OCaml's are, rather than compiling them changed.
+fn check_int_range(input: &str) -> Result<(), Error> {
+    const MAX_INT: i64 = (1 << 62) - 1;
+    let expr = &input[..terminator(input).unwrap_or(input.len())];
+    for token in tokenize(expr)? {
+        match token.kind {
+            TokenKind::Int(int) if int > MAX_INT => {
+                return Err(Error::from(LexError {
+                    kind: LexErrorKind::IntegerOutOfRange(int.to_string()),
+                    span: token.span,
+                }))
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+// Makes a native function of a type written in OCaml, e.g.
+// `string -> string`, applied once given all its arguments.
+pub(crate) fn native(
+    name: &str,
+    ty: &str,
+    decls: &TypeDecls,
+    apply: NativeFn,
+) -> Result<Native, Error> {
+    let ty = parse_type_scheme(ty, decls)?;
+    let arity = builtins::arity(&ty);
+    if arity == 0 {
+        return Err(Error::from(TypeError {
+            kind: TypeErrorKind::ExternalNotFunction(name.to_string()),
+            span: None,
+        }));
+    }
+    Ok(Native {
+        name: name.to_string(),
+        arity,
+        ty,
+        apply,
+    })
+}
+
+// Parses a type written in OCaml, e.g. `'a list -> int`, quantifying its
+// type variables.
+pub(crate) fn parse_type_scheme(input: &str, decls: &TypeDecls) -> Result<Type, Error> {
+    let tokens = tokenize(input)?;
+    let texpr = parse_type_expr(&tokens)?;
+    type_scheme(&texpr, decls)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::collections::HashMap;
+    use std::ops::{Deref, DerefMut};
+
+    // Bounds of phrases evaluated by each backend, so that the tests here
+    // check that the bytecode machine agrees with the tree-walking
+    // evaluator. Bounds are otherwise those of the latter.
+    #[derive(Debug)]
+    struct Backends {
+        ast: Bounds,
+        bytecode: Bounds,
+    }
+
+    impl Backends {
+        fn new() -> Self {
+            Self {
+                ast: Bounds::new(),
+                bytecode: Bounds {
+                    backend: Backend::Bytecode,
+                    ..Bounds::new()
+                },
+            }
+        }
+
+        fn bind(&mut self, name: String, value: Value) {
+            self.ast.bind(name.clone(), value.clone());
+            self.bytecode.bind(name, value);
+        }
+
+        fn bind_type(&mut self, name: String, ty: Type) {
+            self.ast.bind_type(name.clone(), ty.clone());
+            self.bytecode.bind_type(name, ty);
+        }
+    }
+
+    impl Deref for Backends {
+        type Target = Bounds;
+
+        fn deref(&self) -> &Bounds {
+            &self.ast
+        }
+    }
+
+    impl DerefMut for Backends {
+        fn deref_mut(&mut self) -> &mut Bounds {
+            &mut self.ast
+        }
+    }
+
+    impl PartialEq<Bounds> for Backends {
+        fn eq(&self, other: &Bounds) -> bool {
+            self.ast == *other && self.bytecode.values == other.values
+        }
+    }
+
+    // Evaluates an input by both backends, which must agree.
+    fn eval(input: &str, bounds: &mut Backends) -> Result<String, Error> {
+        let render = |result: &Result<String, Error>| match result {
+            Ok(output) => output.clone(),
+            Err(err) => err.render(input),
+        };
+        let expected = super::eval(input, &mut bounds.ast);
+        let actual = super::eval(input, &mut bounds.bytecode);
+        assert_eq!(
+            render(&expected),
+            render(&actual),
+            "backends disagree on {:?}",
+            input
+        );
+        expected
+    }
 
     #[test]
     fn eval_integer_input() {
         let input = "123;;";
-        let mut bounds = Bounds::new();
+        let mut bounds = Backends::new();
         let expected = "- : int = 123";
         let actual = eval(input, &mut bounds).unwrap();
         assert_eq!(expected, actual);
@@ -39,7 +538,7 @@ mod tests {
     #[test]
     fn eval_input_with_spaces() {
         let input = " 123  ;;";
-        let mut bounds = Bounds::new();
+        let mut bounds = Backends::new();
         let expected = "- : int = 123";
         let actual = eval(input, &mut bounds).unwrap();
         assert_eq!(expected, actual);
@@ -48,7 +547,7 @@ mod tests {
     #[test]
     fn eval_arithmetic_expr() {
         let input = "2+3*4+5-6/2+(3-1)*2;;";
-        let mut bounds = Bounds::new();
+        let mut bounds = Backends::new();
         let expected = "- : int = 20";
         let actual = eval(input, &mut bounds).unwrap();
         assert_eq!(expected, actual);
@@ -57,46 +556,50 @@ mod tests {
     #[test]
     fn eval_global_binding() {
         let input = "let foo = 42;;";
-        let mut bounds = Bounds::new();
+        let mut bounds = Backends::new();
         let expected = "val foo : int = 42";
         let actual = eval(input, &mut bounds).unwrap();
         assert_eq!(expected, actual);
         assert_eq!(
-            bounds,
-            Bounds(HashMap::from([("foo".to_string(), Value::Int(42))]))
+            bounds.values,
+            Globals::from([("foo".to_string(), Value::Int(42))])
         );
     }
 
     #[test]
     fn overwrites_existing_global_binding() {
         let input = "let foo = 123;;";
-        let mut bounds = Bounds(HashMap::from([("foo".to_string(), Value::Int(42))]));
+        let mut bounds = Backends::new();
+        bounds.bind("foo".to_string(), Value::Int(42));
+        bounds.bind_type("foo".to_string(), Type::Int);
         let expected = "val foo : int = 123";
         let actual = eval(input, &mut bounds).unwrap();
         assert_eq!(expected, actual);
         assert_eq!(
-            bounds,
-            Bounds(HashMap::from([("foo".to_string(), Value::Int(123))]))
+            bounds.values,
+            Globals::from([("foo".to_string(), Value::Int(123))])
         );
     }
 
     #[test]
     fn eval_existing_global_binding() {
         let input = "foo;;";
-        let mut bounds = Bounds(HashMap::from([("foo".to_string(), Value::Int(456))]));
+        let mut bounds = Backends::new();
+        bounds.bind("foo".to_string(), Value::Int(456));
+        bounds.bind_type("foo".to_string(), Type::Int);
         let expected = "- : int = 456";
         let actual = eval(input, &mut bounds).unwrap();
         assert_eq!(expected, actual);
         assert_eq!(
-            bounds,
-            Bounds(HashMap::from([("foo".to_string(), Value::Int(456))]))
+            bounds.values,
+            Globals::from([("foo".to_string(), Value::Int(456))])
         );
     }
 
     #[test]
     fn eval_local_binding() {
         let input = "let lv = 345 in lv + 2;;";
-        let mut bounds = Bounds::new();
+        let mut bounds = Backends::new();
         let expected = "- : int = 347";
         let actual = eval(input, &mut bounds).unwrap();
         assert_eq!(expected, actual);
@@ -110,7 +613,7 @@ mod tests {
             let b = 2 in
             a;;
         "#;
-        let mut bounds = Bounds::new();
+        let mut bounds = Backends::new();
         let expected = "- : int = 1";
         let actual = eval(input, &mut bounds).unwrap();
         assert_eq!(expected, actual);
@@ -124,7 +627,7 @@ mod tests {
             let b = 2 in
             b;;
         "#;
-        let mut bounds = Bounds::new();
+        let mut bounds = Backends::new();
         let expected = "- : int = 2";
         let actual = eval(input, &mut bounds).unwrap();
         assert_eq!(expected, actual);
@@ -134,8 +637,8 @@ mod tests {
     #[test]
     fn eval_empty_list() {
         let input = "[];;";
-        let mut bounds = Bounds::new();
-        let expected = "- : int list = []";
+        let mut bounds = Backends::new();
+        let expected = "- : 'a list = []";
         let actual = eval(input, &mut bounds).unwrap();
         assert_eq!(expected, actual);
         assert_eq!(bounds, Bounds::new());
@@ -144,7 +647,7 @@ mod tests {
     #[test]
     fn eval_list() {
         let input = "[1; 2; 3];;";
-        let mut bounds = Bounds::new();
+        let mut bounds = Backends::new();
         let expected = "- : int list = [1; 2; 3]";
         let actual = eval(input, &mut bounds).unwrap();
         assert_eq!(expected, actual);
@@ -154,7 +657,7 @@ mod tests {
     #[test]
     fn eval_true() {
         let input = "true;;";
-        let mut bounds = Bounds::new();
+        let mut bounds = Backends::new();
         let expected = "- : bool = true";
         let actual = eval(input, &mut bounds).unwrap();
         assert_eq!(expected, actual);
@@ -164,7 +667,7 @@ mod tests {
     #[test]
     fn eval_false() {
         let input = "false;;";
-        let mut bounds = Bounds::new();
+        let mut bounds = Backends::new();
         let expected = "- : bool = false";
         let actual = eval(input, &mut bounds).unwrap();
         assert_eq!(expected, actual);
@@ -174,7 +677,7 @@ mod tests {
     #[test]
     fn eval_equal_between_int_1() {
         let input = "2 == 3;;";
-        let mut bounds = Bounds::new();
+        let mut bounds = Backends::new();
         let expected = "- : bool = false";
         let actual = eval(input, &mut bounds).unwrap();
         assert_eq!(expected, actual);
@@ -184,7 +687,7 @@ mod tests {
     #[test]
     fn eval_equal_between_int_2() {
         let input = "3 == 3;;";
-        let mut bounds = Bounds::new();
+        let mut bounds = Backends::new();
         let expected = "- : bool = true";
         let actual = eval(input, &mut bounds).unwrap();
         assert_eq!(expected, actual);
@@ -194,7 +697,7 @@ mod tests {
     #[test]
     fn eval_not_equal_between_int_1() {
         let input = "2 != 3;;";
-        let mut bounds = Bounds::new();
+        let mut bounds = Backends::new();
         let expected = "- : bool = true";
         let actual = eval(input, &mut bounds).unwrap();
         assert_eq!(expected, actual);
@@ -204,7 +707,7 @@ mod tests {
     #[test]
     fn eval_not_equal_between_int_2() {
         let input = "3 != 3;;";
-        let mut bounds = Bounds::new();
+        let mut bounds = Backends::new();
         let expected = "- : bool = false";
         let actual = eval(input, &mut bounds).unwrap();
         assert_eq!(expected, actual);
@@ -214,24 +717,370 @@ mod tests {
     #[test]
     fn eval_func_definition() {
         let input = "let square x = x * x;;";
-        let mut bounds = Bounds::new();
-        // TODO
-        let expected = "val square : ... = <fun>";
+        let mut bounds = Backends::new();
+        let expected = "val square : int -> int = <fun>";
         let actual = eval(input, &mut bounds).unwrap();
         assert_eq!(expected, actual);
-        assert_eq!(
-            bounds,
-            Bounds(HashMap::from([("square".to_string(), Value::Fn)])),
-        );
+        assert_eq!(eval("square 7;;", &mut bounds).unwrap(), "- : int = 49");
     }
 
     #[test]
     fn eval_local_func_definition() {
         let input = "let add a b = a + b in 42;;";
-        let mut bounds = Bounds::new();
+        let mut bounds = Backends::new();
         let expected = "- : int = 42";
         let actual = eval(input, &mut bounds).unwrap();
         assert_eq!(expected, actual);
         assert_eq!(bounds, Bounds::new());
     }
+
+    #[test]
+    fn eval_tuple() {
+        let input = "let t = 1 + 2, [true], (3, 4);;";
+        let mut bounds = Backends::new();
+        let expected = "val t : int * bool list * (int * int) = (3, [true], (3, 4))";
+        let actual = eval(input, &mut bounds).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn eval_annotated_func_definition() {
+        let input = "let pair (x : int) y : 'a list = [x; y];;";
+        let mut bounds = Backends::new();
+        let expected = "val pair : int -> int -> int list = <fun>";
+        let actual = eval(input, &mut bounds).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn eval_type_definitions() {
+        let mut bounds = Backends::new();
+        assert_eq!(
+            eval(
+                "type 'a tree = Leaf | Node of 'a tree * 'a * 'a tree and t;;",
+                &mut bounds
+            )
+            .unwrap(),
+            "type 'a tree = Leaf | Node of 'a tree * 'a * 'a tree\nand t"
+        );
+        assert_eq!(
+            eval("type ('k, 'v) assoc = ('k * 'v) list;;", &mut bounds).unwrap(),
+            "type ('k, 'v) assoc = ('k * 'v) list"
+        );
+        assert_eq!(
+            eval("let t = Node (Leaf, 1, Leaf);;", &mut bounds).unwrap(),
+            "val t : int tree = Node (Leaf, 1, Leaf)"
+        );
+        assert_eq!(
+            eval("type point = { x : int; y : bool };;", &mut bounds).unwrap(),
+            "type point = { x : int; y : bool; }"
+        );
+        assert_eq!(
+            eval("let p = { y = true; x = 1 };;", &mut bounds).unwrap(),
+            "val p : point = {x = 1; y = true}"
+        );
+        assert_eq!(eval("p.y;;", &mut bounds).unwrap(), "- : bool = true");
+    }
+
+    #[test]
+    fn eval_values_directed_by_types() {
+        let mut bounds = Backends::new();
+        assert_eq!(
+            eval(r#"("a\tb", [|[1]; []|]);;"#, &mut bounds).unwrap(),
+            r#"- : string * int list array = ("a\tb", [|[1]; []|])"#
+        );
+        eval("type 'a option = None | Some of 'a;;", &mut bounds).unwrap();
+        assert_eq!(
+            eval("Some (Some [||]);;", &mut bounds).unwrap(),
+            "- : 'a array option option = Some (Some [||])"
+        );
+        assert_eq!(
+            eval("let f x = Some x;;", &mut bounds).unwrap(),
+            "val f : 'a -> 'a option = <fun>"
+        );
+        assert_eq!(
+            eval("let l = [0; 1; 2; 3; 4; 5; 6; 7; 8; 9; 10; 11; 12; 13; 14; 15; 16; 17; 18; 19; 20; 21; 22; 23; 24; 25];;", &mut bounds).unwrap(),
+            "val l : int list =\n  \
+             [0; 1; 2; 3; 4; 5; 6; 7; 8; 9; 10; 11; 12; 13; 14; 15; 16; 17; 18; 19; 20;\n   \
+             21; 22; 23; 24; 25]"
+        );
+    }
+
+    #[test]
+    fn split_phrases() {
+        let source = "let s = \"a;;\\\";;\";;\n1;;\n2\n";
+        let split: Vec<&str> = phrases(source).into_iter().map(|r| &source[r]).collect();
+        assert_eq!(split, vec!["let s = \"a;;\\\";;\";;", "\n1;;", "\n2\n"]);
+        // Trailing whitespace is no phrase.
+        assert_eq!(phrases("1;;\n ").len(), 1);
+        // nor are comments, which may contain ";;"
+        let source = "let x = 1 (* a;; (* b;; *) \"*);;\" *)\nlet y = 2;; (* c;; *)\n";
+        let split: Vec<&str> = phrases(source).into_iter().map(|r| &source[r]).collect();
+        assert_eq!(
+            split,
+            vec!["let x = 1 (* a;; (* b;; *) \"*);;\" *)\n", "let y = 2;;"]
+        );
+
+        let source = "let x = 1\ntype t = A let f x = x; 2\nlet y = let z = 1 in z;;";
+        let split: Vec<&str> = phrases(source).into_iter().map(|r| &source[r]).collect();
+        assert_eq!(
+            split,
+            vec![
+                "let x = 1\n",
+                "type t = A ",
+                "let f x = x; 2\n",
+                "let y = let z = 1 in z;;"
+            ]
+        );
+    }
+
+    #[test]
+    fn keep_declarations_on_type_error() {
+        let mut bounds = Backends::new();
+        eval("type t = A;;", &mut bounds).unwrap();
+        assert!(eval("type u = B of u and v = v list;;", &mut bounds).is_err());
+        assert!(eval("B A;;", &mut bounds).is_err());
+        assert_eq!(eval("A;;", &mut bounds).unwrap(), "- : t = A");
+    }
+
+    #[test]
+    fn eval_weak_type_variables() {
+        let mut bounds = Backends::new();
+        assert_eq!(
+            eval("let r = ref [];;", &mut bounds).unwrap(),
+            "val r : '_weak1 list ref = {contents = []}"
+        );
+        assert_eq!(
+            eval("let id x = x;;", &mut bounds).unwrap(),
+            "val id : 'a -> 'a = <fun>"
+        );
+        assert_eq!(
+            eval("let f = id id;;", &mut bounds).unwrap(),
+            "val f : '_weak2 -> '_weak2 = <fun>"
+        );
+        assert_eq!(eval("!r;;", &mut bounds).unwrap(), "- : '_weak1 list = []");
+
+        // Weak type variables are resolved on later use, unless the input is
+        // ill-typed.
+        assert!(eval("r := [true; 1];;", &mut bounds).is_err());
+        assert_eq!(
+            eval("r;;", &mut bounds).unwrap(),
+            "- : '_weak1 list ref = {contents = []}"
+        );
+        assert_eq!(eval("r := [1];;", &mut bounds).unwrap(), "- : unit = ()");
+        assert_eq!(
+            eval("r;;", &mut bounds).unwrap(),
+            "- : int list ref = {contents = [1]}"
+        );
+        assert_eq!(
+            eval("ref (f 1);;", &mut bounds).unwrap(),
+            "- : int ref = {contents = 1}"
+        );
+        assert_eq!(
+            eval("ref [];;", &mut bounds).unwrap(),
+            "- : '_weak3 list ref = {contents = []}"
+        );
+    }
+
+    #[test]
+    fn error_with_location() {
+        let input = "1 + foo;;";
+        let mut bounds = Backends::new();
+        let expected =
+            "Line 1, characters 4-7:\n1 | 1 + foo;;\n        ^^^\nError: Unbound value foo";
+        let actual = eval(input, &mut bounds).unwrap_err().render(input);
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn error_on_comparison_between_different_types() {
+        let input = "true == 1;;";
+        let mut bounds = Backends::new();
+        let expected = "Line 1, characters 8-9:\n1 | true == 1;;\n            ^\nError: This expression has type int but an expression was expected of type bool";
+        let actual = eval(input, &mut bounds).unwrap_err().render(input);
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn type_with_global_bindings() {
+        let mut bounds = Backends::new();
+        assert_eq!(
+            eval("let xs = [[1]];;", &mut bounds).unwrap(),
+            "val xs : int list list = [[1]]"
+        );
+        assert_eq!(
+            eval("xs == [[2]; []];;", &mut bounds).unwrap(),
+            "- : bool = false"
+        );
+        assert_eq!(
+            eval("xs == [true];;", &mut bounds).unwrap_err().code(),
+            "type/mismatch"
+        );
+    }
+
+    #[test]
+    fn keep_bindings_on_type_error() {
+        let mut bounds = Backends::new();
+        eval("let x = 1;;", &mut bounds).unwrap();
+        assert!(eval("let x = x + true;;", &mut bounds).is_err());
+        assert_eq!(eval("x;;", &mut bounds).unwrap(), "- : int = 1");
+    }
+
+    #[test]
+    fn error_at_end_of_input() {
+        let input = "1 +;;";
+        let mut bounds = Backends::new();
+        let expected =
+            "Line 1, characters 3-5:\n1 | 1 +;;\n       ^^\nError: Syntax error: expected an expression, found \";;\"";
+        let actual = eval(input, &mut bounds).unwrap_err().render(input);
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn eval_externals() {
+        let mut bounds = Backends::new();
+        assert_eq!(
+            eval(r#"external mk : 'a -> 'a ref = "ref";;"#, &mut bounds).unwrap(),
+            r#"external mk : 'a -> 'a ref = "ref""#
+        );
+        assert_eq!(
+            eval("mk 1, mk true;;", &mut bounds).unwrap(),
+            "- : int ref * bool ref = ({contents = 1}, {contents = true})"
+        );
+        assert_eq!(
+            eval(
+                r#"external fail : string -> int = "failwith";;"#,
+                &mut bounds
+            )
+            .unwrap(),
+            r#"external fail : string -> int = "failwith""#
+        );
+        assert_eq!(
+            eval("fail;;", &mut bounds).unwrap(),
+            "- : string -> int = <fun>"
+        );
+
+        let input = r#"external mk : 'a -> 'b ref = "ref";;"#;
+        let expected = "Line 1, characters 14-26:\n1 | external mk : 'a -> 'b ref = \"ref\";;\n                  ^^^^^^^^^^^^\nError: The primitive \"ref\" has type 'a -> 'a ref\n       but is declared here with type 'a -> 'b ref";
+        let actual = eval(input, &mut bounds).unwrap_err().render(input);
+        assert_eq!(expected, actual);
+        assert_eq!(
+            eval(r#"external f : int -> int = "nothing";;"#, &mut bounds)
+                .unwrap_err()
+                .code(),
+            "type/unbound-primitive"
+        );
+        assert_eq!(
+            eval(r#"external f : int = "ref";;"#, &mut bounds)
+                .unwrap_err()
+                .code(),
+            "type/primitive-type"
+        );
+    }
+
+    #[test]
+    fn eval_recursive_functions() {
+        let mut bounds = Backends::new();
+        assert_eq!(
+            eval(
+                "let rec fact n = if n <= 1 then 1 else n * fact (n - 1);;",
+                &mut bounds
+            )
+            .unwrap(),
+            "val fact : int -> int = <fun>"
+        );
+        assert_eq!(eval("fact 10;;", &mut bounds).unwrap(), "- : int = 3628800");
+        // The name is bound to the function only in its body.
+        assert_eq!(
+            eval(
+                "let fact n = if n = 0 then 0 else fact n in fact 5;;",
+                &mut bounds
+            )
+            .unwrap(),
+            "- : int = 120"
+        );
+    }
+
+    #[test]
+    fn eval_tail_calls_in_constant_stack() {
+        let mut bounds = Backends::new();
+        let input = "let rec loop n = if n = 0 then 0 else loop (n - 1) in loop 100_000;;";
+        assert_eq!(eval(input, &mut bounds).unwrap(), "- : int = 0");
+        // through partial applications, sequences and local bindings
+        let input =
+            "let rec sum acc n = if n = 0 then acc else ((); let m = n - 1 in sum (acc + n) m);;";
+        eval(input, &mut bounds).unwrap();
+        assert_eq!(
+            eval("sum 0 100_000;;", &mut bounds).unwrap(),
+            "- : int = 5000050000"
+        );
+        // Calls not in tail position overflow the stack.
+        let input = "let rec deep n = if n = 0 then 0 else 1 + deep (n - 1) in deep 100_000;;";
+        assert_eq!(
+            eval(input, &mut bounds).unwrap_err().code(),
+            "exception/stack-overflow"
+        );
+    }
+
+    #[test]
+    fn eval_deep_calls_not_in_tail_position() {
+        let mut bounds = Backends::new();
+        let input = "let rec range a b = if a > b then [] else a :: range (a + 1) b;;";
+        eval(input, &mut bounds).unwrap();
+        let input = "let rec build n acc = if n = 0 then acc else build (n - 1) (n :: acc);;";
+        eval(input, &mut bounds).unwrap();
+        assert_eq!(
+            eval("range 1 10_000 = build 10_000 [];;", &mut bounds).unwrap(),
+            "- : bool = true"
+        );
+        let input = "let rec sum n = if n = 0 then 0 else n + sum (n - 1) in sum 10_000;;";
+        assert_eq!(eval(input, &mut bounds).unwrap(), "- : int = 50005000");
+    }
+
+    #[test]
+    fn eval_cons() {
+        let mut bounds = Backends::new();
+        eval("let xs = [2; 3];;", &mut bounds).unwrap();
+        assert_eq!(
+            eval("let ys = 0 :: 1 :: xs;;", &mut bounds).unwrap(),
+            "val ys : int list = [0; 1; 2; 3]"
+        );
+        assert_eq!(eval("xs;;", &mut bounds).unwrap(), "- : int list = [2; 3]");
+        // Long lists are built in linear time, and compared and dropped
+        // without overflowing the stack.
+        let input = "let rec build n acc = if n = 0 then acc else build (n - 1) (n :: acc);;";
+        eval(input, &mut bounds).unwrap();
+        assert_eq!(
+            eval("build 200_000 [] = build 200_000 [];;", &mut bounds).unwrap(),
+            "- : bool = true"
+        );
+    }
+
+    #[test]
+    fn eval_comparisons() {
+        let mut bounds = Backends::new();
+        eval("type t = A | B of int | C | D of bool;;", &mut bounds).unwrap();
+        assert_eq!(
+            eval(
+                "1 < 2, \"b\" <= \"a\", [1; 2] < [1; 2; 0], (1, true) > (1, false);;",
+                &mut bounds
+            )
+            .unwrap(),
+            "- : bool * bool * bool * bool = (true, false, true, true)"
+        );
+        // Constant constructors come first, each in the declared order.
+        assert_eq!(
+            eval(
+                "C < B 0, B 5 < D false, A < C, B 1 = B 1, [|9|] < [|0; 0|];;",
+                &mut bounds
+            )
+            .unwrap(),
+            "- : bool * bool * bool * bool * bool = (true, true, true, true, true)"
+        );
+        let input = "let f x = x in f = f;;";
+        assert_eq!(
+            eval(input, &mut bounds).unwrap_err().render(input),
+            "Exception: Invalid_argument \"equal: functional value\"."
+        );
+    }
 }
@@ -0,0 +1,196 @@
+use super::types::Type;
+use super::value::{Func, Value};
+use crate::error::{Error, Exception};
+use crate::Bounds;
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+// function implemented natively, applied once given all its arguments
+#[derive(Debug)]
+pub(crate) struct Prim {
+    pub name: &'static str,
+    pub arity: usize,
+    // type whose type variables are quantified
+    pub ty: fn() -> Type,
+    pub apply: fn(&[Value]) -> Result<Value, Error>,
+    // whether the result is a value newly allocated
+    pub alloc: bool,
+}
+
+// function implemented in Rust by an embedder, registered to a session
+pub(crate) struct Native {
+    pub name: String,
+    pub arity: usize,
+    // type whose type variables are quantified
+    pub ty: Type,
+    pub apply: NativeFn,
+}
+
+pub(crate) type NativeFn = Box<dyn Fn(&[Value]) -> Result<Value, Error>>;
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Native({}, {})", self.name, self.ty)
+    }
+}
+
+// Natives are equal only if they are the same one.
+impl PartialEq for Native {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+static PRIMS: [Prim; 8] = [
+    // 'a -> 'a ref
+    Prim {
+        name: "ref",
+        arity: 1,
+        ty: || Type::Fn(Box::new(Type::Gen(0)), Box::new(reference(Type::Gen(0)))),
+        apply: |args| Ok(Value::Ref(Rc::new(RefCell::new(args[0].clone())))),
+        alloc: true,
+    },
+    // 'a ref -> 'a
+    Prim {
+        name: "!",
+        arity: 1,
+        ty: || Type::Fn(Box::new(reference(Type::Gen(0))), Box::new(Type::Gen(0))),
+        apply: |args| match &args[0] {
+            Value::Ref(cell) => Ok(cell.borrow().clone()),
+            _ => Err(Error::Internal("Expected a reference".to_string())),
+        },
+        alloc: false,
+    },
+    // 'a ref -> 'a -> unit
+    Prim {
+        name: ":=",
+        arity: 2,
+        ty: || {
+            Type::Fn(
+                Box::new(reference(Type::Gen(0))),
+                Box::new(Type::Fn(Box::new(Type::Gen(0)), Box::new(unit()))),
+            )
+        },
+        apply: |args| match &args[0] {
+            Value::Ref(cell) => {
+                *cell.borrow_mut() = args[1].clone();
+                Ok(Value::Unit)
+            }
+            _ => Err(Error::Internal("Expected a reference".to_string())),
+        },
+        alloc: false,
+    },
+    // string -> unit
+    Prim {
+        name: "print_string",
+        arity: 1,
+        ty: || Type::Fn(Box::new(string()), Box::new(unit())),
+        apply: |args| print(str_of(&args[0])?),
+        alloc: false,
+    },
+    // string -> unit
+    Prim {
+        name: "print_endline",
+        arity: 1,
+        ty: || Type::Fn(Box::new(string()), Box::new(unit())),
+        apply: |args| print(&[str_of(&args[0])?, b"\n"].concat()),
+        alloc: false,
+    },
+    // int -> unit
+    Prim {
+        name: "print_int",
+        arity: 1,
+        ty: || Type::Fn(Box::new(Type::Int), Box::new(unit())),
+        apply: |args| match &args[0] {
+            Value::Int(i) => print(i.to_string().as_bytes()),
+            _ => Err(Error::Internal("Expected an integer".to_string())),
+        },
+        alloc: false,
+    },
+    // unit -> unit
+    Prim {
+        name: "print_newline",
+        arity: 1,
+        ty: || Type::Fn(Box::new(unit()), Box::new(unit())),
+        apply: |_| print(b"\n"),
+        alloc: false,
+    },
+    // string -> 'a
+    Prim {
+        name: "failwith",
+        arity: 1,
+        ty: || Type::Fn(Box::new(string()), Box::new(Type::Gen(0))),
+        apply: |args| {
+            let msg = String::from_utf8_lossy(str_of(&args[0])?).into_owned();
+            // located at the application by eval_ast::apply()
+            Err(Error::Exception(Exception::failure(msg)))
+        },
+        alloc: false,
+    },
+];
+
+fn str_of(value: &Value) -> Result<&[u8], Error> {
+    match value {
+        Value::Str(s) => Ok(s),
+        _ => Err(Error::Internal("Expected a string".to_string())),
+    }
+}
+
+// Writes to stdout right away, so that outputs are ordered with the
+// toplevel's.
+fn print(bytes: &[u8]) -> Result<Value, Error> {
+    let mut stdout = io::stdout();
+    stdout
+        .write_all(bytes)
+        .and_then(|_| stdout.flush())
+        .map_err(|err| Error::Internal(err.to_string()))?;
+    Ok(Value::Unit)
+}
+
+// Primitives built in, each bound to its name unless shadowed.
+pub(super) fn prims() -> impl Iterator<Item = &'static Prim> {
+    PRIMS.iter()
+}
+
+// Primitive bound to `name` unless shadowed by user bindings.
+pub(super) fn prim(name: &str) -> Option<&'static Prim> {
+    PRIMS.iter().find(|prim| prim.name == name)
+}
+
+// Function value of a primitive named in an external declaration, which is
+// registered to the session or built in, with its type.
+pub(super) fn primitive(name: &str, bounds: &Bounds) -> Option<(Value, Type)> {
+    match bounds.natives.get(name) {
+        Some(native) => Some((
+            Value::Fn(Func::Native(native.clone(), Rc::from([]))),
+            native.ty.clone(),
+        )),
+        None => prim(name).map(|prim| (Value::Fn(Func::Prim(prim, Rc::from([]))), (prim.ty)())),
+    }
+}
+
+// Number of arguments a function of type `ty` takes.
+pub(super) fn arity(ty: &Type) -> usize {
+    match ty {
+        Type::Fn(_, ret) => 1 + arity(ret),
+        _ => 0,
+    }
+}
+
+pub(super) fn unit() -> Type {
+    Type::Con("unit".to_string(), Vec::new())
+}
+
+pub(super) fn reference(ty: Type) -> Type {
+    Type::Con("ref".to_string(), vec![ty])
+}
+
+pub(super) fn string() -> Type {
+    Type::Con("string".to_string(), Vec::new())
+}
+
+pub(super) fn array(ty: Type) -> Type {
+    Type::Con("array".to_string(), vec![ty])
+}
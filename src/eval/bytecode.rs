@@ -0,0 +1,481 @@
+use super::{
+    env::Globals,
+    lambda::{Atom, Ident, Prim, Program, Term},
+    parser::CompareOp,
+    span::Span,
+    types::TypeDecls,
+    value::Func,
+    Value,
+};
+use crate::error::Error;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+// Instruction of the bytecode machine, after OCaml's ZINC machine. The value
+// computed last is kept in an accumulator, and operands waiting for the
+// others, arguments and local values on a stack.
+#[derive(Clone, Debug)]
+pub(super) enum Instr {
+    Const(Value),            // literal, global value or primitive
+    Load(Var),               // local or captured value
+    Push,                    // pushes the accumulator
+    Pop(usize),              // drops values from the stack
+    Closure(Rc<Lambda>),     // closure capturing values of the frame
+    Apply,                   // applies the function popped to the accumulator
+    TailApply,               // applies in place of the frame, then returns
+    Return,                  // returns the accumulator to the caller
+    Branch(usize),           // jumps to an instruction
+    BranchIfNot(usize),      // jumps if the accumulator is false
+    Arith(ArithOp),          // operator on the int popped and the accumulator
+    Eq,                      // ==
+    Ne,                      // !=
+    Compare(CompareOp),      // =, <>, <, >, <= or >=
+    MakeBlock(Block, usize), // block of values popped and the accumulator
+    Cons,                    // conses the value popped onto the accumulator
+    Field(usize),            // field of the record in the accumulator
+}
+
+// where a variable is found when running a function
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) enum Var {
+    Local(usize),    // slot of the frame, from its arguments
+    Captured(usize), // value captured by the closure
+    Itself,          // the closure itself, for `let rec`
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+// kind of a block made by `MakeBlock`
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum Block {
+    Tuple,
+    List,
+    Array,
+    Constr(Rc<str>),
+    // record with the names of its fields in the declared order
+    Record(Rc<[String]>),
+}
+
+// function compiled to bytecode, or a phrase, which takes no arguments
+#[derive(Debug)]
+pub(crate) struct Lambda {
+    pub(super) arity: usize,
+    // where the values captured are found when the closure is made
+    pub(super) captures: Vec<Var>,
+    pub(super) code: Vec<Instr>,
+    // span of the term each instruction is compiled from
+    pub(super) spans: Vec<Span>,
+    // types declared where it was defined, by which values are compared
+    pub(super) decls: Rc<TypeDecls>,
+}
+
+// Compiles the IR of a phrase at `span` to a lambda computing its value.
+// Global values are compiled as constants, as they are bound when the phrase
+// is.
+pub(super) fn compile(
+    program: &Program,
+    span: Span,
+    globals: &Globals,
+    decls: &Rc<TypeDecls>,
+) -> Result<Rc<Lambda>, Error> {
+    let mut compiler = Compiler {
+        program,
+        globals,
+        decls,
+        scopes: vec![Scope::default()],
+        inlined: HashMap::new(),
+    };
+    compiler.term(&program.body, span, true)?;
+    compiler.emit(Instr::Return, span);
+    Ok(compiler.finish())
+}
+
+struct Compiler<'a> {
+    program: &'a Program,
+    globals: &'a Globals,
+    decls: &'a Rc<TypeDecls>,
+    // functions being compiled, the innermost last
+    scopes: Vec<Scope>,
+    // terms bound to variables used once, compiled where they are loaded
+    inlined: HashMap<Ident, &'a Term>,
+}
+
+// function being compiled
+#[derive(Default)]
+struct Scope {
+    arity: usize,
+    // where the variables in scope are found, which are unique
+    vars: HashMap<Ident, Var>,
+    // where the values captured are found in the enclosing function
+    captures: Vec<Var>,
+    // values on the stack of the frame at this point of the code
+    depth: usize,
+    code: Vec<Instr>,
+    spans: Vec<Span>,
+}
+
+impl<'a> Compiler<'a> {
+    fn scope(&mut self) -> &mut Scope {
+        self.scopes.last_mut().unwrap()
+    }
+
+    // Emits an instruction, returning its index.
+    fn emit(&mut self, instr: Instr, span: Span) -> usize {
+        let scope = self.scope();
+        scope.depth = match instr {
+            Instr::Push => scope.depth + 1,
+            Instr::Pop(n) => scope.depth - n,
+            Instr::MakeBlock(_, len) => scope.depth + 1 - len.max(1),
+            Instr::Apply
+            | Instr::TailApply
+            | Instr::Arith(_)
+            | Instr::Eq
+            | Instr::Ne
+            | Instr::Compare(_)
+            | Instr::Cons => scope.depth - 1,
+            _ => scope.depth,
+        };
+        scope.code.push(instr);
+        scope.spans.push(span);
+        scope.code.len() - 1
+    }
+
+    // Makes a branch emitted at `at` jump to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let scope = self.scope();
+        let target = scope.code.len();
+        match &mut scope.code[at] {
+            Instr::Branch(to) | Instr::BranchIfNot(to) => *to = target,
+            instr => unreachable!("Expected a branch: {:?}", instr),
+        }
+    }
+
+    fn finish(&mut self) -> Rc<Lambda> {
+        let scope = self.scopes.pop().unwrap();
+        Rc::new(Lambda {
+            arity: scope.arity,
+            captures: scope.captures,
+            code: scope.code,
+            spans: scope.spans,
+            decls: self.decls.clone(),
+        })
+    }
+
+    // Compiles a term leaving its value in the accumulator. Instructions of
+    // terms without a span of their own are located at `span` of the one
+    // enclosing them. In tail position, applications are made in place of
+    // the frame.
+    fn term(&mut self, term: &'a Term, span: Span, tail: bool) -> Result<(), Error> {
+        match term {
+            Term::Atom(atom) => self.atom(atom, span)?,
+            // A value used once, by the first operand loaded, is computed
+            // there rather than kept on the stack, so that frames take no
+            // more of it than the operands waiting for the others.
+            Term::Let(ident, bound, body)
+                if loaded_first(body, ident) && uses(body, ident) == 1 =>
+            {
+                self.inlined.insert(ident.clone(), bound);
+                self.term(body, span, tail)?;
+            }
+            Term::Let(ident, bound, body) => {
+                self.term(bound, span, false)?;
+                self.emit(Instr::Push, span);
+                let slot = self.scope().depth - 1;
+                self.scope().vars.insert(ident.clone(), Var::Local(slot));
+                self.term(body, span, tail)?;
+                self.emit(Instr::Pop(1), span);
+            }
+            Term::Prim(prim, args, span) => {
+                let instr = match prim {
+                    Prim::Arith(op) => Instr::Arith(*op),
+                    Prim::Eq => Instr::Eq,
+                    Prim::Ne => Instr::Ne,
+                    Prim::Compare(op) => Instr::Compare(*op),
+                    Prim::MakeBlock(block) => Instr::MakeBlock(block.clone(), args.len()),
+                    Prim::Cons => Instr::Cons,
+                    Prim::Field(index) => Instr::Field(*index),
+                };
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        self.emit(Instr::Push, *span);
+                    }
+                    self.atom(arg, *span)?;
+                }
+                self.emit(instr, *span);
+            }
+            Term::Apply(func, args) => {
+                self.atom(func, span)?;
+                for (i, (arg, span)) in args.iter().enumerate() {
+                    self.emit(Instr::Push, *span);
+                    self.atom(arg, *span)?;
+                    let instr = match tail && i + 1 == args.len() {
+                        true => Instr::TailApply,
+                        false => Instr::Apply,
+                    };
+                    self.emit(instr, *span);
+                }
+            }
+            Term::Closure(index, captured) => {
+                let program = self.program;
+                let function = &program.functions[*index];
+                let mut captures = Vec::new();
+                for atom in captured {
+                    match atom {
+                        Atom::Var(ident) => captures.push(self.var(ident)?),
+                        _ => return Err(Error::Internal("Expected a variable".to_string())),
+                    }
+                }
+                let mut vars: HashMap<Ident, Var> = function
+                    .params
+                    .iter()
+                    .cloned()
+                    .zip((0..).map(Var::Local))
+                    .chain(
+                        function
+                            .captured
+                            .iter()
+                            .cloned()
+                            .zip((0..).map(Var::Captured)),
+                    )
+                    .collect();
+                if function.recursive {
+                    vars.insert(function.name.clone(), Var::Itself);
+                }
+                self.scopes.push(Scope {
+                    arity: function.params.len(),
+                    vars,
+                    captures,
+                    depth: function.params.len(),
+                    ..Scope::default()
+                });
+                self.term(&function.body, function.span, true)?;
+                self.emit(Instr::Return, function.span);
+                let lambda = self.finish();
+                self.emit(Instr::Closure(lambda), function.span);
+            }
+            Term::If(cond, then, other) => {
+                self.atom(cond, span)?;
+                let to_else = self.emit(Instr::BranchIfNot(0), span);
+                self.term(then, span, tail)?;
+                let to_end = self.emit(Instr::Branch(0), span);
+                self.patch(to_else);
+                self.term(other, span, tail)?;
+                self.patch(to_end);
+            }
+            Term::Seq(lhs, rhs) => {
+                self.term(lhs, span, false)?;
+                self.term(rhs, span, tail)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Loads an operand, or computes it if inlined.
+    fn atom(&mut self, atom: &Atom, span: Span) -> Result<(), Error> {
+        let instr = match atom {
+            Atom::Var(ident) => match self.inlined.remove(ident) {
+                Some(bound) => return self.term(bound, span, false),
+                None => Instr::Load(self.var(ident)?),
+            },
+            Atom::Const(value) => Instr::Const(value.clone()),
+            Atom::Global(name) => match self.globals.get(name) {
+                Some(value) => Instr::Const(value.clone()),
+                None => return Err(Error::Internal(format!("Unbound global {}", name))),
+            },
+        };
+        self.emit(instr, span);
+        Ok(())
+    }
+
+    // Where a variable is found in the function being compiled.
+    fn var(&mut self, ident: &Ident) -> Result<Var, Error> {
+        match self.scope().vars.get(ident) {
+            Some(var) => Ok(*var),
+            None => Err(Error::Internal(format!("Unbound variable {}", ident))),
+        }
+    }
+}
+
+// Whether a variable is the first operand a term loads, after atoms only,
+// so that nothing is run between binding and loading it.
+fn loaded_first(term: &Term, ident: &Ident) -> bool {
+    let is = |atom: &Atom| matches!(atom, Atom::Var(var) if var == ident);
+    match term {
+        Term::Atom(atom) | Term::If(atom, ..) => is(atom),
+        Term::Let(_, bound, _) => loaded_first(bound, ident),
+        Term::Seq(lhs, _) => loaded_first(lhs, ident),
+        Term::Prim(_, args, _) => args.iter().any(is),
+        Term::Apply(func, args) => is(func) || args.first().is_some_and(|(arg, _)| is(arg)),
+        Term::Closure(..) => false,
+    }
+}
+
+// Number of times a term uses a variable, other than in the functions lifted.
+fn uses(term: &Term, ident: &Ident) -> usize {
+    let count = |atoms: &mut dyn Iterator<Item = &Atom>| {
+        atoms
+            .filter(|atom| matches!(atom, Atom::Var(var) if var == ident))
+            .count()
+    };
+    match term {
+        Term::Atom(atom) => count(&mut [atom].into_iter()),
+        Term::Let(_, bound, body) | Term::Seq(bound, body) => {
+            uses(bound, ident) + uses(body, ident)
+        }
+        Term::Prim(_, args, _) | Term::Closure(_, args) => count(&mut args.iter()),
+        Term::Apply(func, args) => {
+            count(&mut [func].into_iter().chain(args.iter().map(|(arg, _)| arg)))
+        }
+        Term::If(cond, then, other) => {
+            count(&mut [cond].into_iter()) + uses(then, ident) + uses(other, ident)
+        }
+    }
+}
+
+// Lists instructions one per line, with those of closures indented after
+// them, e.g. for tests.
+impl fmt::Display for Lambda {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn write(lambda: &Lambda, indent: usize, f: &mut fmt::Formatter) -> fmt::Result {
+            for (i, instr) in lambda.code.iter().enumerate() {
+                write!(f, "{:indent$}{}: ", "", i, indent = indent)?;
+                match instr {
+                    Instr::Const(value) => writeln!(f, "const {}", show(value))?,
+                    Instr::Load(Var::Local(slot)) => writeln!(f, "local {}", slot)?,
+                    Instr::Load(Var::Captured(index)) => writeln!(f, "captured {}", index)?,
+                    Instr::Load(Var::Itself) => writeln!(f, "itself")?,
+                    Instr::Closure(lambda) => {
+                        writeln!(f, "closure {} {:?}", lambda.arity, lambda.captures)?;
+                        write(lambda, indent + 2, f)?;
+                    }
+                    Instr::MakeBlock(block, len) => writeln!(f, "makeblock {:?} {}", block, len)?,
+                    instr => writeln!(f, "{}", format!("{:?}", instr).to_lowercase())?,
+                }
+            }
+            Ok(())
+        }
+        write(self, 0, f)
+    }
+}
+
+// Constants are shown briefly, as values are printed by their types.
+pub(super) fn show(value: &Value) -> String {
+    match value {
+        Value::Int(i) => i.to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Unit => "()".to_string(),
+        Value::Str(s) => format!("\"{}\"", super::escape(s)),
+        Value::List(list) if list.iter().next().is_none() => "[]".to_string(),
+        Value::Constr(name, None) => name.to_string(),
+        Value::Fn(Func::Prim(prim, _)) => format!("<prim {}>", prim.name),
+        _ => "<value>".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::lambda::lower;
+    use crate::eval::lexer::tokenize;
+    use crate::eval::parser::parse;
+    use crate::eval::types::Type;
+    use crate::Bounds;
+
+    fn compiled(input: &str) -> String {
+        let ast = parse(&tokenize(input).unwrap()).unwrap();
+        let mut bounds = Bounds::new();
+        bounds.bind("g".to_string(), Value::Int(7));
+        bounds.bind_type("g".to_string(), Type::Int);
+        let program = lower(&ast, &bounds).unwrap();
+        let lambda = compile(&program, ast.span, &bounds.values, &Rc::default()).unwrap();
+        lambda.to_string()
+    }
+
+    #[test]
+    fn compile_operators() {
+        assert_eq!(
+            compiled("1 + g * 2 :: []"),
+            "0: const 1\n\
+             1: push\n\
+             2: const 7\n\
+             3: push\n\
+             4: const 2\n\
+             5: arith(mul)\n\
+             6: arith(add)\n\
+             7: push\n\
+             8: const []\n\
+             9: cons\n\
+             10: return\n"
+        );
+        assert_eq!(
+            compiled("if true then (1, 2) else (3, 4)"),
+            "0: const true\n\
+             1: branchifnot(7)\n\
+             2: const 1\n\
+             3: push\n\
+             4: const 2\n\
+             5: makeblock Tuple 2\n\
+             6: branch(11)\n\
+             7: const 3\n\
+             8: push\n\
+             9: const 4\n\
+             10: makeblock Tuple 2\n\
+             11: return\n"
+        );
+    }
+
+    #[test]
+    fn compile_values_used_once_in_place() {
+        // `x` is used twice, so it is kept on the stack, while the sum is
+        // computed where it is loaded.
+        assert_eq!(
+            compiled("let x = g * 2 in (x, x + 1)"),
+            "0: const 7\n\
+             1: push\n\
+             2: const 2\n\
+             3: arith(mul)\n\
+             4: push\n\
+             5: local 0\n\
+             6: push\n\
+             7: local 0\n\
+             8: push\n\
+             9: const 1\n\
+             10: arith(add)\n\
+             11: makeblock Tuple 2\n\
+             12: pop(1)\n\
+             13: return\n"
+        );
+    }
+
+    #[test]
+    fn compile_closures_capturing_values() {
+        assert_eq!(
+            compiled("let rec f x = let y = x in let g z = f (y + z) in g in f 1"),
+            "0: closure 1 []\n\
+             \x20 0: local 0\n\
+             \x20 1: push\n\
+             \x20 2: closure 1 [Itself, Local(1)]\n\
+             \x20   0: captured 0\n\
+             \x20   1: push\n\
+             \x20   2: captured 1\n\
+             \x20   3: push\n\
+             \x20   4: local 0\n\
+             \x20   5: arith(add)\n\
+             \x20   6: tailapply\n\
+             \x20   7: return\n\
+             \x20 3: pop(1)\n\
+             \x20 4: return\n\
+             1: push\n\
+             2: const 1\n\
+             3: tailapply\n\
+             4: return\n"
+        );
+    }
+}
@@ -0,0 +1,388 @@
+use super::{
+    bytecode::ArithOp,
+    emit::{bound_function, function_names, indented, prim_name, Emitter, Global, Language},
+    lambda::{Prim, Program},
+    parser::CompareOp,
+    types::TypeDecls,
+};
+use crate::error::Error;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+// runtime which the compiled code follows
+const RUNTIME: &str = include_str!("runtime.c");
+
+// C program compiled from phrases in turn, e.g. those of a file compiled by
+// `rcaml compile`. Global values and string constants are held by an array
+// `G`, and each phrase is run by a function called by `main()` in turn.
+#[derive(Default)]
+pub(crate) struct CProgram {
+    // what each slot of `G` holds, for comments
+    slots: Vec<String>,
+    // global values in scope by their names
+    globals: HashMap<String, Global>,
+    // slots of the string constants
+    strings: HashMap<Vec<u8>, usize>,
+    // C functions, each with its prototype
+    prototypes: Vec<String>,
+    functions: Vec<String>,
+    // statements of `main()` allocating the constants
+    constants: Vec<String>,
+    // names of the functions of the phrases
+    phrases: Vec<String>,
+}
+
+impl CProgram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Compiles a phrase lowered to the IR, binding the name it binds.
+    pub(super) fn phrase(&mut self, program: &Program, decls: &TypeDecls) -> Result<(), Error> {
+        let base = self.functions.len();
+        let names = function_names(base, program);
+        for (index, function) in program.functions.iter().enumerate() {
+            let mut emitter = Emitter::for_function(self, program, &names, base, decls, index);
+            emitter.indent = 1;
+            emitter.term(&function.body, None)?;
+            let mut code = format!(
+                "static value {}(value self, value *args) {{\n  RC_ENTER({});\n  v[0] = self;\n",
+                names[index], emitter.slots
+            );
+            for i in 0..function.params.len() {
+                writeln!(code, "  v[{}] = args[{}];", i + 1, i).unwrap();
+            }
+            if emitter.tail_calls {
+                code += "top:\n";
+            }
+            code += &emitter.code;
+            code += "}\n";
+            self.prototypes.push(format!(
+                "static value {}(value self, value *args);",
+                names[index]
+            ));
+            self.functions.push(code);
+        }
+
+        let name = format!("phrase{}", self.phrases.len());
+        let mut emitter = Emitter::new(self, program, &names, base, decls);
+        emitter.indent = 1;
+        let result = emitter.slot();
+        emitter.term(&program.body, Some(result))?;
+        let (slots, body) = (emitter.slots, emitter.code);
+        let mut code = format!("static void {}(void) {{\n  RC_ENTER({});\n", name, slots);
+        code += &body;
+        if let Some(bound) = &program.name {
+            let slot = self.slot(bound);
+            writeln!(code, "  G[{}] = v[{}];", slot, result).unwrap();
+            let function = bound_function(program, &names);
+            self.globals
+                .insert(bound.clone(), Global { slot, function });
+        }
+        code += "  rc_frames = rc_frame.prev;\n}\n";
+        self.prototypes.push(format!("static void {}(void);", name));
+        self.functions.push(code);
+        self.phrases.push(name);
+        Ok(())
+    }
+
+    // Binds a name to a primitive, by `external`.
+    pub(super) fn external(&mut self, name: &str, prim: &str) -> Result<(), Error> {
+        let value = prim_value(prim)?;
+        let slot = self.slot(name);
+        self.constants.push(format!("G[{}] = {};", slot, value));
+        self.globals.insert(
+            name.to_string(),
+            Global {
+                slot,
+                function: None,
+            },
+        );
+        Ok(())
+    }
+
+    // Source of the whole program with the runtime.
+    pub fn finish(&self) -> String {
+        let mut out = RUNTIME.to_string();
+        out += "\n/* Program */\n\n";
+        let len = self.slots.len().max(1);
+        for (slot, what) in self.slots.iter().enumerate() {
+            writeln!(out, "/* G[{}]: {} */", slot, what).unwrap();
+        }
+        writeln!(out, "static value G[{}];", len).unwrap();
+        writeln!(
+            out,
+            "static struct frame rc_globals = {{NULL, {}, G}};\n",
+            len
+        )
+        .unwrap();
+        for prototype in &self.prototypes {
+            writeln!(out, "{}", prototype).unwrap();
+        }
+        for function in &self.functions {
+            out += "\n";
+            out += function;
+        }
+        out += "\nint main(void) {\n  rc_init(&rc_globals);\n";
+        for constant in &self.constants {
+            writeln!(out, "  {}", constant).unwrap();
+        }
+        for phrase in &self.phrases {
+            writeln!(out, "  {}();", phrase).unwrap();
+        }
+        out += "  fflush(stdout);\n  return 0;\n}\n";
+        out
+    }
+
+    fn slot(&mut self, what: &str) -> usize {
+        self.slots.push(what.to_string());
+        self.slots.len() - 1
+    }
+
+    // Slot of a string constant, allocated by `main()` once.
+    fn string_slot(&mut self, bytes: &[u8]) -> usize {
+        if let Some(slot) = self.strings.get(bytes) {
+            return *slot;
+        }
+        let literal = c_string(bytes);
+        let slot = self.slot(&format!("string {}", literal.replace("*/", "*\\/")));
+        self.constants.push(format!(
+            "G[{}] = rc_string({}, {});",
+            slot,
+            literal,
+            bytes.len()
+        ));
+        self.strings.insert(bytes.to_vec(), slot);
+        slot
+    }
+}
+
+impl Language for CProgram {
+    fn globals(&self) -> &HashMap<String, Global> {
+        &self.globals
+    }
+
+    // Values are held by the slots of the array `v`, a frame of the
+    // collector.
+    fn local(slot: usize) -> String {
+        format!("v[{}]", slot)
+    }
+
+    fn global(slot: usize) -> String {
+        format!("G[{}]", slot)
+    }
+
+    fn set(slot: usize, expr: &str) -> String {
+        format!("v[{}] = {};", slot, expr)
+    }
+
+    fn ret(expr: &str) -> String {
+        format!("RC_RETURN({});", expr)
+    }
+
+    fn int(i: i64) -> String {
+        match i32::try_from(i) {
+            Ok(_) => format!("Val_int({})", i),
+            Err(_) => format!("Val_int({}LL)", i),
+        }
+    }
+
+    fn bool(b: bool) -> String {
+        match b {
+            true => "Val_true".to_string(),
+            false => "Val_false".to_string(),
+        }
+    }
+
+    fn unit() -> String {
+        "Val_unit".to_string()
+    }
+
+    fn string(&mut self, bytes: &[u8]) -> String {
+        format!("G[{}]", self.string_slot(bytes))
+    }
+
+    fn prim_closure(&mut self, prim: &str) -> Result<String, Error> {
+        prim_value(prim)
+    }
+
+    fn alloc(size: usize, tag: usize) -> String {
+        format!("rc_alloc({}, {})", size, tag)
+    }
+
+    fn closure(_: usize, name: &str, arity: usize, captured: usize) -> String {
+        format!("rc_closure({}, {}, {})", name, arity, captured)
+    }
+
+    fn field(block: &str, index: usize) -> String {
+        format!("Field({}, {})", block, index)
+    }
+
+    fn set_field(block: &str, index: usize, value: &str) -> String {
+        format!("Field({}, {}) = {};", block, index, value)
+    }
+
+    fn prim(prim: &Prim, args: &[String]) -> String {
+        prim_expr(prim, args)
+    }
+
+    fn call_prim(name: &str, args: &[String]) -> String {
+        format!("rc_{}({})", name, args.join(", "))
+    }
+
+    fn call(name: &str, closure: &str, args: &[String]) -> String {
+        format!("{}({}, (value[]){{{}}})", name, closure, args.join(", "))
+    }
+
+    fn apply(closure: &str, args: &[String]) -> (Vec<String>, String, Vec<String>) {
+        let expr = format!(
+            "rc_apply({}, {}, (value[]){{{}}})",
+            closure,
+            args.len(),
+            args.join(", ")
+        );
+        (vec![], expr, vec![])
+    }
+
+    fn branch(cond: &str, then: &str, other: &str) -> String {
+        format!(
+            "if ({} != Val_false) {{\n{}}} else {{\n{}}}\n",
+            cond,
+            indented(then, 1),
+            indented(other, 1)
+        )
+    }
+
+    const JUMP: &'static str = "goto top;";
+}
+
+fn prim_expr(prim: &Prim, args: &[String]) -> String {
+    let compare = |prim: &str, test: &str| {
+        format!(
+            "Val_bool(rc_compare({}, {}, \"{}\") {})",
+            args[0], args[1], prim, test
+        )
+    };
+    match prim {
+        Prim::Arith(op) => {
+            let name = match op {
+                ArithOp::Add => "add",
+                ArithOp::Sub => "sub",
+                ArithOp::Mul => "mul",
+                ArithOp::Div => "div",
+            };
+            format!("rc_{}({}, {})", name, args[0], args[1])
+        }
+        Prim::Eq => format!("Val_bool(rc_equal({}, {}))", args[0], args[1]),
+        Prim::Ne => format!("Val_bool(!rc_equal({}, {}))", args[0], args[1]),
+        Prim::Compare(op) => match op {
+            CompareOp::Eq => compare("equal", "== 0"),
+            CompareOp::Ne => compare("equal", "!= 0"),
+            CompareOp::Lt => compare("compare", "< 0"),
+            CompareOp::Gt => compare("compare", "> 0"),
+            CompareOp::Le => compare("compare", "<= 0"),
+            CompareOp::Ge => compare("compare", ">= 0"),
+        },
+        Prim::Cons => format!("rc_cons({}, {})", args[0], args[1]),
+        Prim::Field(index) => format!("Field({}, {})", args[0], index),
+        // Blocks are made by statements filling their fields.
+        Prim::MakeBlock(_) => unreachable!("Blocks are made by Emitter::term()"),
+    }
+}
+
+// Closure of a primitive held by the runtime.
+fn prim_value(prim: &str) -> Result<String, Error> {
+    Ok(format!(
+        "rc_prims[RC_PRIM_{}]",
+        prim_name(prim)?.to_uppercase()
+    ))
+}
+
+// C literal of a string, escaping what is not printable.
+fn c_string(bytes: &[u8]) -> String {
+    let mut literal = "\"".to_string();
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' | b'?' => {
+                literal.push('\\');
+                literal.push(byte as char);
+            }
+            b' '..=b'~' => literal.push(byte as char),
+            _ => write!(literal, "\\{:03o}", byte).unwrap(),
+        }
+    }
+    literal + "\""
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::{compile_phrase, Compiled, Target};
+    use crate::Bounds;
+
+    fn compiled(source: &str) -> String {
+        let mut bounds = Bounds::new();
+        let mut program = Compiled::new(Target::C);
+        for input in source.split_inclusive(";;") {
+            compile_phrase(input, &mut bounds, &mut program).unwrap();
+        }
+        program.finish()
+    }
+
+    // Body of a C function, e.g. `static value f0_fact(...)`.
+    fn function<'a>(c: &'a str, name: &str) -> &'a str {
+        let start = c
+            .find(&format!(
+                "static value {}(value self, value *args) {{",
+                name
+            ))
+            .unwrap();
+        let len = c[start..].find("\n}\n").unwrap() + 2;
+        &c[start..start + len]
+    }
+
+    #[test]
+    fn compile_self_tail_calls_to_jumps() {
+        let c = compiled("let rec loop i acc = if i = 0 then acc else loop (i - 1) (acc + i);;");
+        let body = function(&c, "f0_loop");
+        assert!(body.contains("top:\n"));
+        assert!(body.contains("goto top;"));
+        assert!(!body.contains("rc_apply"));
+
+        // Spines split to keep the order of evaluation are joined again.
+        let c = compiled("let rec loop i acc = if i = 0 then acc else loop (i - 1) (acc / i);;");
+        assert!(function(&c, "f0_loop").contains("goto top;"));
+
+        let c = compiled("let rec fact n = if n = 0 then 1 else n * fact (n - 1);;");
+        let body = function(&c, "f0_fact");
+        assert!(!body.contains("top:"));
+        assert!(body.contains("v[4] = f0_fact(v[0], (value[]){v[3]});"));
+    }
+
+    #[test]
+    fn compile_known_calls_directly() {
+        let c = compiled(concat!(
+            "let add x y = x + y;;",
+            "let () = print_int (add 1 2);;",
+            "let inc = add 1;;",
+            "let f x = let g y = x + y in g 1;;",
+        ));
+        assert!(c.contains("f0_add(G[0], (value[]){Val_int(1), Val_int(2)})"));
+        assert!(c.contains("rc_print_int(v[1])"));
+        assert!(c.contains("rc_apply(G[0], 1, (value[]){Val_int(1)})"));
+        assert!(function(&c, "f5_f").contains("RC_RETURN(f4_g(v[2], (value[]){Val_int(1)}));"));
+    }
+
+    #[test]
+    fn compile_constants() {
+        let c = compiled(concat!(
+            "type t = A | B of int | C | D of bool;;",
+            "let x = (\"a\\\"b\\n\", \"a\\\"b\\n\", C, D true, 4611686018427387903, ref);;",
+        ));
+        assert_eq!(c.matches("= rc_string(").count(), 1);
+        assert!(c.contains("G[0] = rc_string(\"a\\\"b\\012\", 4);"));
+        assert!(c.contains("rc_alloc(1, 1);"));
+        assert!(c.contains("Field(v[0], 2) = Val_int(1);"));
+        assert!(c.contains("Val_int(4611686018427387903LL)"));
+        assert!(c.contains("rc_prims[RC_PRIM_REF]"));
+    }
+}
@@ -0,0 +1,485 @@
+use super::{
+    bytecode::Block,
+    eval_ast::tag,
+    lambda::{Atom, Ident, Prim, Program, Term},
+    span::Span,
+    types::TypeDecls,
+    value::Func,
+    Value,
+};
+use crate::error::Error;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+// Language compiled to from the IR, e.g. C, giving the code of each kind of
+// statement and expression the emitter needs. Values are held by the slots
+// of a frame, numbered from 0, and global values by slots of their own.
+pub(super) trait Language {
+    // global values in scope by their names
+    fn globals(&self) -> &HashMap<String, Global>;
+
+    // value held by a slot of the frame, and one by a global slot
+    fn local(slot: usize) -> String;
+    fn global(slot: usize) -> String;
+
+    // statement storing a value to a slot of the frame
+    fn set(slot: usize, expr: &str) -> String;
+
+    // statement returning a value from the function or phrase
+    fn ret(expr: &str) -> String;
+
+    // constants which are not blocks
+    fn int(i: i64) -> String;
+    fn bool(b: bool) -> String {
+        Self::int(b as i64)
+    }
+    fn unit() -> String {
+        Self::int(0)
+    }
+
+    // constants allocated once by the program
+    fn string(&mut self, bytes: &[u8]) -> String;
+    fn prim_closure(&mut self, prim: &str) -> Result<String, Error>;
+
+    // block allocated with `size` fields, which are filled by statements
+    fn alloc(size: usize, tag: usize) -> String;
+    fn closure(function: usize, name: &str, arity: usize, captured: usize) -> String;
+    fn field(block: &str, index: usize) -> String;
+    fn set_field(block: &str, index: usize, value: &str) -> String;
+
+    // operation of the runtime, other than making a block
+    fn prim(prim: &Prim, args: &[String]) -> String;
+
+    // calls of a primitive of the runtime by its name, and of a function by
+    // its name given its closure, both with as many arguments as they take
+    fn call_prim(name: &str, args: &[String]) -> String;
+    fn call(name: &str, closure: &str, args: &[String]) -> String;
+
+    // Application of a closure to any number of arguments: the statements
+    // before the expression of its result, the expression, and those after.
+    fn apply(closure: &str, args: &[String]) -> (Vec<String>, String, Vec<String>);
+
+    // conditional statement running either branch, indented from it
+    fn branch(cond: &str, then: &str, other: &str) -> String;
+
+    // statement jumping back to the beginning of the function
+    const JUMP: &'static str;
+}
+
+// global value with its slot, and the function of its closure if known, by
+// its name and arity
+#[derive(Clone)]
+pub(super) struct Global {
+    pub slot: usize,
+    pub function: Option<(String, usize)>,
+}
+
+// Emits the statements of a function or phrase, whose values are held by
+// the slots of its frame.
+pub(super) struct Emitter<'a, L> {
+    out: &'a mut L,
+    program: &'a Program,
+    // names of the functions of the phrase, and the number of the first
+    // among all the functions of the program
+    names: &'a [String],
+    base: usize,
+    decls: &'a TypeDecls,
+    // expressions of the variables in scope
+    vars: HashMap<Ident, String>,
+    // functions of the phrase whose closures variables are bound to
+    known: HashMap<Ident, usize>,
+    // partial applications of known functions variables are bound to
+    partial: HashMap<Ident, (Atom, Vec<String>)>,
+    pub slots: usize,
+    // function emitted, whose calls to itself in tail position jump back
+    this: Option<usize>,
+    pub tail_calls: bool,
+    pub code: String,
+    pub indent: usize,
+}
+
+impl<'a, L: Language> Emitter<'a, L> {
+    pub fn new(
+        out: &'a mut L,
+        program: &'a Program,
+        names: &'a [String],
+        base: usize,
+        decls: &'a TypeDecls,
+    ) -> Self {
+        Self {
+            out,
+            program,
+            names,
+            base,
+            decls,
+            vars: HashMap::new(),
+            known: HashMap::new(),
+            partial: HashMap::new(),
+            slots: 0,
+            this: None,
+            tail_calls: false,
+            code: String::new(),
+            indent: 0,
+        }
+    }
+
+    // Emitter of function `index` of the phrase, whose frame holds its
+    // closure followed by its parameters.
+    pub fn for_function(
+        out: &'a mut L,
+        program: &'a Program,
+        names: &'a [String],
+        base: usize,
+        decls: &'a TypeDecls,
+        index: usize,
+    ) -> Self {
+        let mut emitter = Self::new(out, program, names, base, decls);
+        let function = &program.functions[index];
+        emitter.vars.insert(function.name.clone(), L::local(0));
+        if function.recursive {
+            emitter.known.insert(function.name.clone(), index);
+        }
+        for (i, param) in function.params.iter().enumerate() {
+            emitter.vars.insert(param.clone(), L::local(i + 1));
+        }
+        for (i, ident) in function.captured.iter().enumerate() {
+            emitter
+                .vars
+                .insert(ident.clone(), L::field(&L::local(0), i + 2));
+        }
+        emitter.slots = function.params.len() + 1;
+        emitter.this = Some(index);
+        emitter
+    }
+
+    fn line(&mut self, line: &str) {
+        writeln!(
+            self.code,
+            "{:indent$}{}",
+            "",
+            line,
+            indent = self.indent * 2
+        )
+        .unwrap();
+    }
+
+    pub fn slot(&mut self) -> usize {
+        self.slots += 1;
+        self.slots - 1
+    }
+
+    // Stores the value of an expression to `dest`, or returns it if none.
+    fn assign(&mut self, dest: Option<usize>, expr: &str) {
+        match dest {
+            Some(dest) => self.line(&L::set(dest, expr)),
+            None => self.line(&L::ret(expr)),
+        }
+    }
+
+    // Emits a term storing its value to `dest`, or returning it if none,
+    // i.e. in tail position.
+    pub fn term(&mut self, term: &Term, dest: Option<usize>) -> Result<(), Error> {
+        match term {
+            Term::Atom(atom) => {
+                let expr = self.atom(atom)?;
+                self.assign(dest, &expr);
+            }
+            Term::Let(ident, bound, body) => {
+                let slot = self.slot();
+                match &**bound {
+                    Term::Closure(index, _) => {
+                        self.known.insert(ident.clone(), *index);
+                    }
+                    // Applications split to keep the order of evaluation
+                    // are joined again, so that calls in tail position are
+                    // direct.
+                    Term::Apply(func, args) => {
+                        let args = self.atoms(args.iter().map(|(arg, _)| arg))?;
+                        let (func, args) = self.saturate(func, args);
+                        if matches!(self.function(&func), Some((.., arity)) if args.len() < arity) {
+                            self.partial.insert(ident.clone(), (func, args));
+                        }
+                    }
+                    _ => (),
+                }
+                self.term(bound, Some(slot))?;
+                self.vars.insert(ident.clone(), L::local(slot));
+                self.term(body, dest)?;
+            }
+            Term::Prim(Prim::MakeBlock(block), args, _) => {
+                let block_dest = match dest {
+                    Some(dest) => dest,
+                    None => self.slot(),
+                };
+                let args = self.atoms(args)?;
+                let block_local = L::local(block_dest);
+                match block {
+                    Block::List => {
+                        self.assign(Some(block_dest), &L::int(0));
+                        for arg in args.iter().rev() {
+                            let cons = L::prim(&Prim::Cons, &[arg.clone(), block_local.clone()]);
+                            self.assign(Some(block_dest), &cons);
+                        }
+                    }
+                    _ => {
+                        let tag = match block {
+                            Block::Constr(name) => tag(name, true, self.decls).1,
+                            _ => 0,
+                        };
+                        self.assign(Some(block_dest), &L::alloc(args.len(), tag));
+                        for (i, arg) in args.iter().enumerate() {
+                            self.line(&L::set_field(&block_local, i, arg));
+                        }
+                    }
+                }
+                if dest.is_none() {
+                    self.assign(None, &block_local);
+                }
+            }
+            Term::Prim(prim, args, _) => {
+                let args = self.atoms(args)?;
+                self.assign(dest, &L::prim(prim, &args));
+            }
+            Term::Apply(func, args) => self.apply(func, args, dest)?,
+            Term::Closure(index, captured) => {
+                let closure = match dest {
+                    Some(dest) => dest,
+                    None => self.slot(),
+                };
+                let captured = self.atoms(captured)?;
+                let expr = L::closure(
+                    self.base + index,
+                    &self.names[*index],
+                    self.program.functions[*index].params.len(),
+                    captured.len(),
+                );
+                self.assign(Some(closure), &expr);
+                for (i, value) in captured.iter().enumerate() {
+                    self.line(&L::set_field(&L::local(closure), i + 2, value));
+                }
+                if dest.is_none() {
+                    self.assign(None, &L::local(closure));
+                }
+            }
+            Term::If(cond, then, other) => {
+                let cond = self.atom(cond)?;
+                let then = self.branch(then, dest)?;
+                let other = self.branch(other, dest)?;
+                for line in L::branch(&cond, &then, &other).lines() {
+                    self.line(line);
+                }
+            }
+            Term::Seq(lhs, rhs) => {
+                let ignored = self.slot();
+                self.term(lhs, Some(ignored))?;
+                self.term(rhs, dest)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Code of a branch of a conditional, unindented.
+    fn branch(&mut self, term: &Term, dest: Option<usize>) -> Result<String, Error> {
+        let (code, indent) = (std::mem::take(&mut self.code), self.indent);
+        self.indent = 0;
+        let result = self.term(term, dest);
+        self.indent = indent;
+        let branch = std::mem::replace(&mut self.code, code);
+        result.map(|_| branch)
+    }
+
+    // Applies a function, calling primitives and functions known to take as
+    // many arguments directly. Calls to the function itself in tail position
+    // jump back to its beginning.
+    fn apply(
+        &mut self,
+        func: &Atom,
+        args: &[(Atom, Span)],
+        dest: Option<usize>,
+    ) -> Result<(), Error> {
+        let args = self.atoms(args.iter().map(|(arg, _)| arg))?;
+        let (func, args) = self.saturate(func, args);
+        if let Atom::Const(Value::Fn(Func::Prim(prim, applied))) = &func {
+            if applied.is_empty() && prim.arity == args.len() {
+                let expr = L::call_prim(prim_name(prim.name)?, &args);
+                self.assign(dest, &expr);
+                return Ok(());
+            }
+        }
+        let closure = self.atom(&func)?;
+        match self.function(&func) {
+            Some((index, _, arity))
+                if dest.is_none()
+                    && index.is_some()
+                    && index == self.this
+                    && arity == args.len() =>
+            {
+                // Arguments are evaluated before any parameter is set.
+                let temps: Vec<usize> = args.iter().map(|_| self.slot()).collect();
+                for (temp, arg) in temps.iter().zip(&args) {
+                    self.assign(Some(*temp), arg);
+                }
+                for (i, temp) in temps.iter().enumerate() {
+                    self.assign(Some(i + 1), &L::local(*temp));
+                }
+                self.line(L::JUMP);
+                self.tail_calls = true;
+            }
+            Some((_, name, arity)) if arity == args.len() => {
+                self.assign(dest, &L::call(&name, &closure, &args));
+            }
+            _ => {
+                let (before, expr, after) = L::apply(&closure, &args);
+                for line in &before {
+                    self.line(line);
+                }
+                if after.is_empty() {
+                    self.assign(dest, &expr);
+                    return Ok(());
+                }
+                let result = match dest {
+                    Some(dest) => dest,
+                    None => self.slot(),
+                };
+                self.assign(Some(result), &expr);
+                for line in &after {
+                    self.line(line);
+                }
+                if dest.is_none() {
+                    self.assign(None, &L::local(result));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Function known to be applied, by its index if lifted from the phrase,
+    // with its name and arity.
+    fn function(&self, func: &Atom) -> Option<(Option<usize>, String, usize)> {
+        match func {
+            Atom::Var(ident) => self.known.get(ident).map(|index| {
+                let function = &self.program.functions[*index];
+                (
+                    Some(*index),
+                    self.names[*index].clone(),
+                    function.params.len(),
+                )
+            }),
+            Atom::Global(name) => self
+                .out
+                .globals()
+                .get(name)
+                .and_then(|global| global.function.clone())
+                .map(|(name, arity)| (None, name, arity)),
+            Atom::Const(_) => None,
+        }
+    }
+
+    // Function applied with all the arguments, those of a partial
+    // application it is bound to first.
+    fn saturate(&self, func: &Atom, args: Vec<String>) -> (Atom, Vec<String>) {
+        match func {
+            Atom::Var(ident) => match self.partial.get(ident) {
+                Some((func, applied)) => {
+                    (func.clone(), applied.iter().cloned().chain(args).collect())
+                }
+                None => (func.clone(), args),
+            },
+            _ => (func.clone(), args),
+        }
+    }
+
+    fn atoms<'t>(
+        &mut self,
+        atoms: impl IntoIterator<Item = &'t Atom>,
+    ) -> Result<Vec<String>, Error> {
+        atoms.into_iter().map(|atom| self.atom(atom)).collect()
+    }
+
+    fn atom(&mut self, atom: &Atom) -> Result<String, Error> {
+        match atom {
+            Atom::Var(ident) => match self.vars.get(ident) {
+                Some(var) => Ok(var.clone()),
+                None => Err(Error::Internal(format!("Unbound variable {}", ident))),
+            },
+            Atom::Global(name) => match self.out.globals().get(name) {
+                Some(global) => Ok(L::global(global.slot)),
+                None => Err(Error::Internal(format!("Unbound global {}", name))),
+            },
+            Atom::Const(value) => Ok(match value {
+                Value::Int(i) => L::int(*i),
+                Value::Bool(b) => L::bool(*b),
+                Value::Unit => L::unit(),
+                Value::Str(s) => self.out.string(s),
+                Value::List(list) if list.iter().next().is_none() => L::int(0),
+                Value::Constr(name, None) => L::int(tag(name, false, self.decls).1 as i64),
+                Value::Fn(Func::Prim(prim, args)) if args.is_empty() => {
+                    self.out.prim_closure(prim.name)?
+                }
+                value => {
+                    return Err(Error::Internal(format!(
+                        "Cannot compile the value {:?}",
+                        value
+                    )))
+                }
+            }),
+        }
+    }
+}
+
+// Names of the functions lifted from a phrase, unique by their numbers from
+// `base`, e.g. `f3_fact`.
+pub(super) fn function_names(base: usize, program: &Program) -> Vec<String> {
+    program
+        .functions
+        .iter()
+        .enumerate()
+        .map(|(index, function)| {
+            let name: String = function
+                .name
+                .name
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            format!("f{}_{}", base + index, name)
+        })
+        .collect()
+}
+
+// Function of the closure a phrase binds, by its name and arity, if known.
+pub(super) fn bound_function(program: &Program, names: &[String]) -> Option<(String, usize)> {
+    match &program.body {
+        Term::Closure(index, _) => Some((
+            names[*index].clone(),
+            program.functions[*index].params.len(),
+        )),
+        _ => None,
+    }
+}
+
+// Name of a primitive in the runtimes, e.g. `print_int`.
+pub(super) fn prim_name(prim: &str) -> Result<&'static str, Error> {
+    Ok(match prim {
+        "ref" => "ref",
+        "!" => "deref",
+        ":=" => "assign",
+        "print_string" => "print_string",
+        "print_endline" => "print_endline",
+        "print_int" => "print_int",
+        "print_newline" => "print_newline",
+        "failwith" => "failwith",
+        _ => {
+            return Err(Error::Internal(format!(
+                "Cannot compile the primitive {}",
+                prim
+            )))
+        }
+    })
+}
+
+// Code indented by `depth` levels.
+pub(super) fn indented(code: &str, depth: usize) -> String {
+    code.lines()
+        .map(|line| format!("{:indent$}{}\n", "", line, indent = depth * 2))
+        .collect()
+}
@@ -0,0 +1,308 @@
+use super::types::TypeDecls;
+use super::value::{Func, Value};
+use std::cmp::Ordering;
+use std::fmt;
+use std::rc::Rc;
+
+// Environment an expression is evaluated in: values bound locally, the
+// innermost first, over the global values and types as they were when it was
+// made. Environments are persistent; binding a value makes a new one sharing
+// the rest, so that closures capture environments without copying them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Env {
+    locals: Option<Rc<Scope>>,
+    globals: Globals,
+    decls: Rc<TypeDecls>,
+}
+
+// a local binding, linked to the enclosing ones
+#[derive(Debug, PartialEq)]
+struct Scope {
+    name: String,
+    value: Value,
+    outer: Option<Rc<Scope>>,
+}
+
+impl Env {
+    pub fn new(globals: Globals, decls: Rc<TypeDecls>) -> Self {
+        Self {
+            locals: None,
+            globals,
+            decls,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        let mut scope = self.locals.as_deref();
+        while let Some(Scope {
+            name: n,
+            value,
+            outer,
+        }) = scope
+        {
+            if n == name {
+                return Some(value);
+            }
+            scope = outer.as_deref();
+        }
+        self.globals.get(name)
+    }
+
+    // Makes an environment with `name` bound to `value` in addition.
+    pub fn bind(&self, name: String, value: Value) -> Self {
+        Self {
+            locals: Some(Rc::new(Scope {
+                name,
+                value,
+                outer: self.locals.clone(),
+            })),
+            globals: self.globals.clone(),
+            decls: self.decls.clone(),
+        }
+    }
+
+    pub fn decls(&self) -> &TypeDecls {
+        &self.decls
+    }
+}
+
+// Scopes, and closures bound in them, are dropped one by one rather than
+// recursively, since applications can chain them deeper than the stack.
+impl Drop for Env {
+    fn drop(&mut self) {
+        let mut scopes: Vec<Rc<Scope>> = self.locals.take().into_iter().collect();
+        while let Some(scope) = scopes.pop() {
+            let Ok(mut scope) = Rc::try_unwrap(scope) else {
+                continue;
+            };
+            scopes.extend(scope.outer.take());
+            if let Value::Fn(Func::Closure(closure)) = &mut scope.value {
+                if let Some(closure) = Rc::get_mut(closure) {
+                    scopes.extend(closure.env.locals.take());
+                }
+            }
+        }
+    }
+}
+
+// Global values by their names, in a balanced tree. The map is persistent:
+// binding a value makes a new tree sharing all but the path to it with the
+// old one, so that environments keep the globals they were made with, and
+// each top-level binding takes logarithmic time however many there are.
+#[derive(Clone, Default)]
+pub(crate) struct Globals {
+    root: Tree,
+    len: usize,
+}
+
+type Tree = Option<Rc<Node>>;
+
+struct Node {
+    name: String,
+    value: Value,
+    height: u8,
+    left: Tree,
+    right: Tree,
+}
+
+impl Globals {
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        let mut tree = &self.root;
+        while let Some(node) = tree {
+            tree = match name.cmp(&node.name) {
+                Ordering::Less => &node.left,
+                Ordering::Greater => &node.right,
+                Ordering::Equal => return Some(&node.value),
+            };
+        }
+        None
+    }
+
+    // Binds `name` to `value`, replacing the value it is bound to if any.
+    pub fn insert(&mut self, name: String, value: Value) {
+        let (root, added) = insert(&self.root, name, value);
+        self.root = Some(root);
+        self.len += added as usize;
+    }
+
+    // Bindings in the order of their names.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Value)> {
+        let mut stack = Vec::new();
+        push_left(&mut stack, &self.root);
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            push_left(&mut stack, &node.right);
+            Some((&node.name, &node.value))
+        })
+    }
+}
+
+fn push_left<'a>(stack: &mut Vec<&'a Node>, mut tree: &'a Tree) {
+    while let Some(node) = tree {
+        stack.push(node);
+        tree = &node.left;
+    }
+}
+
+fn height(tree: &Tree) -> u8 {
+    tree.as_ref().map_or(0, |node| node.height)
+}
+
+fn node(name: String, value: Value, left: Tree, right: Tree) -> Rc<Node> {
+    let height = height(&left).max(height(&right)) + 1;
+    Rc::new(Node {
+        name,
+        value,
+        height,
+        left,
+        right,
+    })
+}
+
+// Makes a node of subtrees whose heights differ by at most 2, rotating them
+// so that they differ by at most 1, as OCaml's Map does.
+fn balance(name: String, value: Value, left: Tree, right: Tree) -> Rc<Node> {
+    let (hl, hr) = (height(&left), height(&right));
+    if hl > hr + 1 {
+        let l = left.as_deref().unwrap();
+        if height(&l.left) >= height(&l.right) {
+            let right = node(name, value, l.right.clone(), right);
+            node(l.name.clone(), l.value.clone(), l.left.clone(), Some(right))
+        } else {
+            let lr = l.right.as_deref().unwrap();
+            let left = node(
+                l.name.clone(),
+                l.value.clone(),
+                l.left.clone(),
+                lr.left.clone(),
+            );
+            let right = node(name, value, lr.right.clone(), right);
+            node(lr.name.clone(), lr.value.clone(), Some(left), Some(right))
+        }
+    } else if hr > hl + 1 {
+        let r = right.as_deref().unwrap();
+        if height(&r.right) >= height(&r.left) {
+            let left = node(name, value, left, r.left.clone());
+            node(r.name.clone(), r.value.clone(), Some(left), r.right.clone())
+        } else {
+            let rl = r.left.as_deref().unwrap();
+            let left = node(name, value, left, rl.left.clone());
+            let right = node(
+                r.name.clone(),
+                r.value.clone(),
+                rl.right.clone(),
+                r.right.clone(),
+            );
+            node(rl.name.clone(), rl.value.clone(), Some(left), Some(right))
+        }
+    } else {
+        node(name, value, left, right)
+    }
+}
+
+// Tree with `name` bound to `value`, copying the path to it, and whether the
+// name was not bound before.
+fn insert(tree: &Tree, name: String, value: Value) -> (Rc<Node>, bool) {
+    let Some(n) = tree else {
+        return (node(name, value, None, None), true);
+    };
+    match name.cmp(&n.name) {
+        Ordering::Less => {
+            let (left, added) = insert(&n.left, name, value);
+            let tree = balance(n.name.clone(), n.value.clone(), Some(left), n.right.clone());
+            (tree, added)
+        }
+        Ordering::Greater => {
+            let (right, added) = insert(&n.right, name, value);
+            let tree = balance(n.name.clone(), n.value.clone(), n.left.clone(), Some(right));
+            (tree, added)
+        }
+        Ordering::Equal => (node(name, value, n.left.clone(), n.right.clone()), false),
+    }
+}
+
+// Trees, and the globals of closures bound in them, are dropped node by node
+// rather than recursively, since each closure keeps the globals it was made
+// with, which can chain them deeper than the stack.
+impl Drop for Globals {
+    fn drop(&mut self) {
+        let mut nodes: Vec<Rc<Node>> = self.root.take().into_iter().collect();
+        while let Some(node) = nodes.pop() {
+            let Ok(mut node) = Rc::try_unwrap(node) else {
+                continue;
+            };
+            nodes.extend(node.left.take());
+            nodes.extend(node.right.take());
+            if let Value::Fn(Func::Closure(closure)) = &mut node.value {
+                if let Some(closure) = Rc::get_mut(closure) {
+                    nodes.extend(closure.env.globals.root.take());
+                }
+            }
+        }
+    }
+}
+
+impl<const N: usize> From<[(String, Value); N]> for Globals {
+    fn from(bindings: [(String, Value); N]) -> Self {
+        let mut globals = Globals::default();
+        for (name, value) in bindings {
+            globals.insert(name, value);
+        }
+        globals
+    }
+}
+
+impl PartialEq for Globals {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl fmt::Debug for Globals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bind_values_persistently() {
+        let globals = Globals::from([("x".to_string(), Value::Int(1))]);
+        let env = Env::new(globals, Rc::default());
+        let inner = env.bind("y".to_string(), Value::Int(2));
+        let shadowed = inner.bind("x".to_string(), Value::Int(3));
+        assert_eq!(env.get("x"), Some(&Value::Int(1)));
+        assert_eq!(env.get("y"), None);
+        assert_eq!(inner.get("x"), Some(&Value::Int(1)));
+        assert_eq!(inner.get("y"), Some(&Value::Int(2)));
+        assert_eq!(shadowed.get("x"), Some(&Value::Int(3)));
+        assert_eq!(shadowed.get("y"), Some(&Value::Int(2)));
+    }
+
+    #[test]
+    fn bind_globals_persistently() {
+        let mut globals = Globals::default();
+        let mut snapshots = Vec::new();
+        for i in 0..1000 {
+            globals.insert(format!("g{}", i), Value::Int(i));
+            snapshots.push(globals.clone());
+        }
+        globals.insert("g7".to_string(), Value::Int(-7));
+        assert_eq!(globals.len, 1000);
+        assert_eq!(globals.get("g7"), Some(&Value::Int(-7)));
+        assert_eq!(globals.get("g999"), Some(&Value::Int(999)));
+        assert_eq!(globals.get("g1000"), None);
+        // Earlier maps are left as they were.
+        assert_eq!(snapshots[7].get("g7"), Some(&Value::Int(7)));
+        assert_eq!(snapshots[7].get("g8"), None);
+        assert_eq!(snapshots[500].len, 501);
+        let names: Vec<&String> = snapshots[999].iter().map(|(name, _)| name).collect();
+        assert!(names.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(names.len(), 1000);
+        // The tree stays balanced.
+        assert!(height(&globals.root) <= 15);
+    }
+}
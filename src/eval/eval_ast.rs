@@ -1,245 +1,564 @@
 use super::{
-    parser::{BindStruct, LocalBindStruct, Node},
-    Value,
+    builtins::prim,
+    env::Env,
+    meter::Meter,
+    parser::{BindStruct, CompareOp, LocalBindStruct, Node, NodeKind},
+    span::Span,
+    types::{TypeDeclKind, TypeDecls},
+    value::{applied, Closure, Func, List},
+    vm, Value,
 };
+use crate::error::{Error, Exception, ExceptionKind, TypeError, TypeErrorKind};
 use crate::Bounds;
-use std::fmt;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::rc::Rc;
 
 #[derive(Debug, PartialEq)]
 pub(super) struct Output {
     // Representing bound names only for binding expressions.
-    name: Option<String>,
-    value: Value,
+    pub name: Option<String>,
+    pub value: Value,
 }
 
-impl fmt::Display for Output {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
-        let Output { name, value } = self;
-        match name {
-            Some(name) => write!(f, "val {} : ", name)?,
-            None => write!(f, "- : ")?,
-        }
-        match value {
-            Value::Int(int) => write!(f, "int = {}", int),
-            Value::Bool(b) => write!(f, "bool = {}", if *b { "true" } else { "false" }),
-            Value::List(list) => {
-                write!(f, "int list = ")?;
-                list.fmt(f)
-            }
-            Value::Fn => write!(f, "... = <fun>"),
-        }
-    }
+// value of an expression in tail position, of which the application is left
+// to the caller, so that tail calls are made in constant stack space
+enum Tail {
+    Value(Value),
+    // function, its argument and the application
+    Call(Value, Value, Span),
 }
 
-pub(super) fn eval_ast(ast: &Node, bounds: &mut Bounds) -> Result<Output, String> {
-    match ast {
-        Node::Int(i) => Ok(Output {
-            name: None,
-            value: Value::Int(*i),
-        }),
-        Node::Bool(b) => Ok(Output {
+// Evaluates a well-typed AST; type errors are found by typing::infer()
+// beforehand. Steps and depth of evaluation are counted by the meter.
+pub(super) fn eval_ast(ast: &Node, bounds: &mut Bounds) -> Result<Output, Error> {
+    let env = Env::new(bounds.values.clone(), bounds.decls.clone());
+    let meter = &bounds.meter;
+    match &ast.kind {
+        NodeKind::Bind(bind) => {
+            meter.enter(ast.span)?;
+            let bound = eval_bound(bind, &env, meter);
+            meter.leave();
+            let (name, value) = bound?;
+            drop(env);
+            bounds.bind(name.clone(), value.clone());
+            Ok(Output {
+                name: Some(name),
+                value,
+            })
+        }
+        _ => Ok(Output {
             name: None,
-            value: Value::Bool(*b),
+            value: eval_expr(ast, &env, meter)?,
         }),
-        Node::List(list) => Ok(Output {
-            name: None,
-            value: Value::List(list.clone()),
-        }),
-        Node::Add(lhs, rhs) => match (eval_ast(lhs, bounds)?, eval_ast(rhs, bounds)?) {
-            (
-                Output {
-                    name: None,
-                    value: Value::Int(l),
-                },
-                Output {
-                    name: None,
-                    value: Value::Int(r),
-                },
-            ) => Ok(Output {
-                name: None,
-                value: Value::Int(l + r),
-            }),
-            _ => Err("This expression has a type other than int".to_string()),
+    }
+}
+
+// Evaluates an expression in `env`.
+fn eval_expr(ast: &Node, env: &Env, meter: &Meter) -> Result<Value, Error> {
+    meter.enter(ast.span)?;
+    let value = eval_node(ast, env, meter);
+    meter.leave();
+    value
+}
+
+// Nodes evaluated other than by a few steps are evaluated by functions of
+// their own, which are not inlined, so that the frame of this function,
+// which recursion goes through, takes little stack.
+fn eval_node(ast: &Node, env: &Env, meter: &Meter) -> Result<Value, Error> {
+    match &ast.kind {
+        NodeKind::Int(i) => Ok(Value::Int(*i)),
+        NodeKind::Bool(b) => Ok(Value::Bool(*b)),
+        NodeKind::Unit => Ok(Value::Unit),
+        NodeKind::Str(s) => Ok(Value::Str(Rc::from(s.as_slice()))),
+        NodeKind::Array(_)
+        | NodeKind::List(_)
+        | NodeKind::Tuple(_)
+        | NodeKind::Record(_)
+        | NodeKind::Constr(..) => eval_block(ast, env, meter),
+        NodeKind::Cons(head, tail) => eval_cons(head, tail, env, meter, ast.span),
+        NodeKind::Add(..) | NodeKind::Sub(..) | NodeKind::Mul(..) | NodeKind::Div(..) => {
+            eval_arith(ast, env, meter).map(Value::Int)
+        }
+        NodeKind::Eql(..) | NodeKind::Neql(..) | NodeKind::Compare(..) => {
+            eval_compare(ast, env, meter)
+        }
+        NodeKind::Ident(name) => match env.get(name) {
+            Some(value) => Ok(value.clone()),
+            None => eval_prim(ast, name),
         },
-        Node::Sub(lhs, rhs) => match (eval_ast(lhs, bounds)?, eval_ast(rhs, bounds)?) {
-            (
-                Output {
-                    name: None,
-                    value: Value::Int(l),
-                },
-                Output {
-                    name: None,
-                    value: Value::Int(r),
-                },
-            ) => Ok(Output {
-                name: None,
-                value: Value::Int(l - r),
-            }),
-            _ => Err("This expression has a type other than int".to_string()),
+        // Expressions which may end with applications in tail position.
+        NodeKind::LocalBind(_) | NodeKind::App(..) | NodeKind::Seq(..) | NodeKind::If(..) => {
+            match eval_tail_node(ast, env, meter)? {
+                Tail::Value(value) => Ok(value),
+                Tail::Call(func, arg, span) => apply(func, arg, span, meter),
+            }
+        }
+        NodeKind::Constraint(expr, _) => eval_expr(expr, env, meter),
+        NodeKind::Field(expr, field) => match &eval_expr(expr, env, meter)? {
+            Value::Record(values) => match values.iter().find(|(name, _)| name == field) {
+                Some((_, value)) => Ok(value.clone()),
+                None => Err(internal("Expected a record field")),
+            },
+            _ => Err(internal("Expected a record")),
         },
-        Node::Mul(lhs, rhs) => match (eval_ast(lhs, bounds)?, eval_ast(rhs, bounds)?) {
-            (
-                Output {
-                    name: None,
-                    value: Value::Int(l),
-                },
-                Output {
-                    name: None,
-                    value: Value::Int(r),
-                },
-            ) => Ok(Output {
-                name: None,
-                value: Value::Int(l * r),
-            }),
-            _ => Err("This expression has a type other than int".to_string()),
+        // Global bindings are evaluated by eval_ast().
+        NodeKind::Bind(_) => Err(internal("Expected an expression")),
+        // Type definitions are handled by eval().
+        NodeKind::TypeDef(_) => Err(internal("Found a type definition in an expression")),
+        NodeKind::Directive(..) => Err(internal("Found a directive in an expression")),
+        NodeKind::External(..) => Err(internal("Found an external declaration in an expression")),
+        // parse() never returns nodes containing errors.
+        NodeKind::Error => Err(internal("Found a node failed to parse")),
+    }
+}
+
+// Evaluates an array, list, tuple, record or constructor.
+#[inline(never)]
+fn eval_block(ast: &Node, env: &Env, meter: &Meter) -> Result<Value, Error> {
+    let eval_all = |elems: &[Node]| -> Result<Vec<Value>, Error> {
+        elems
+            .iter()
+            .map(|elem| eval_expr(elem, env, meter))
+            .collect()
+    };
+    let value = match &ast.kind {
+        NodeKind::Array(elems) => Value::Array(Rc::new(RefCell::new(eval_all(elems)?))),
+        NodeKind::List(elems) => Value::List(List::from(eval_all(elems)?)),
+        NodeKind::Tuple(elems) => Value::Tuple(eval_all(elems)?.into()),
+        NodeKind::Record(fields) => {
+            // Fields are stored in the declared order.
+            let names = match env.decls().record_of(&fields[0].0).map(|decl| &decl.kind) {
+                Some(TypeDeclKind::Record(decl_fields)) => decl_fields
+                    .iter()
+                    .map(|(name, _)| name.clone())
+                    .collect::<Vec<_>>(),
+                _ => return Err(internal("Expected a record type")),
+            };
+            let mut values = Vec::new();
+            for name in names {
+                let expr = match fields.iter().find(|(field, _)| *field == name) {
+                    Some((_, expr)) => expr,
+                    None => return Err(internal("Expected a record field")),
+                };
+                values.push((name, eval_expr(expr, env, meter)?));
+            }
+            Value::Record(values.into())
+        }
+        NodeKind::Constr(name, arg) => {
+            let arg = match arg {
+                Some(arg) => Some(Rc::new(eval_expr(arg, env, meter)?)),
+                None => None,
+            };
+            Value::Constr(Rc::from(name.as_str()), arg)
+        }
+        _ => return Err(internal("Expected a block")),
+    };
+    meter.alloc(&value, ast.span)?;
+    Ok(value)
+}
+
+// Evaluates `head :: tail`, of which the tail is shared rather than copied.
+#[inline(never)]
+fn eval_cons(
+    head: &Node,
+    tail: &Node,
+    env: &Env,
+    meter: &Meter,
+    span: Span,
+) -> Result<Value, Error> {
+    let head = eval_expr(head, env, meter)?;
+    match eval_expr(tail, env, meter)? {
+        Value::List(ref list) => {
+            meter.alloc_words(3, span)?;
+            Ok(Value::List(list.cons(head)))
+        }
+        _ => Err(internal("Expected a list")),
+    }
+}
+
+// Evaluates an arithmetic operation.
+#[inline(never)]
+fn eval_arith(ast: &Node, env: &Env, meter: &Meter) -> Result<i64, Error> {
+    let (lhs, rhs) = match &ast.kind {
+        NodeKind::Add(lhs, rhs)
+        | NodeKind::Sub(lhs, rhs)
+        | NodeKind::Mul(lhs, rhs)
+        | NodeKind::Div(lhs, rhs) => (eval_int(lhs, env, meter)?, eval_int(rhs, env, meter)?),
+        _ => return Err(internal("Expected an arithmetic operation")),
+    };
+    match &ast.kind {
+        NodeKind::Add(..) => Ok(lhs.wrapping_add(rhs)),
+        NodeKind::Sub(..) => Ok(lhs.wrapping_sub(rhs)),
+        NodeKind::Mul(..) => Ok(lhs.wrapping_mul(rhs)),
+        _ if rhs == 0 => Err(Error::Exception(Exception {
+            kind: ExceptionKind::DivisionByZero,
+            span: Some(ast.span),
+        })),
+        _ => Ok(lhs.wrapping_div(rhs)),
+    }
+}
+
+// Evaluates an equality or a comparison.
+#[inline(never)]
+fn eval_compare(ast: &Node, env: &Env, meter: &Meter) -> Result<Value, Error> {
+    let (op, lhs, rhs) = match &ast.kind {
+        NodeKind::Eql(lhs, rhs) => (None, lhs, rhs),
+        NodeKind::Neql(lhs, rhs) => (None, lhs, rhs),
+        NodeKind::Compare(op, lhs, rhs) => (Some(op), lhs, rhs),
+        _ => return Err(internal("Expected a comparison")),
+    };
+    let (lval, rval) = (eval_expr(lhs, env, meter)?, eval_expr(rhs, env, meter)?);
+    let prim = op.map(|op| match op {
+        CompareOp::Eq | CompareOp::Ne => "equal",
+        _ => "compare",
+    });
+    let ord = compare(&lval, &rval, env.decls(), prim, meter, ast.span)
+        .map_err(|err| locate(err, ast.span))?;
+    Ok(Value::Bool(match (op, &ast.kind) {
+        (None, NodeKind::Eql(..)) | (Some(CompareOp::Eq), _) => ord == Ordering::Equal,
+        (None, _) | (Some(CompareOp::Ne), _) => ord != Ordering::Equal,
+        (Some(CompareOp::Lt), _) => ord == Ordering::Less,
+        (Some(CompareOp::Gt), _) => ord == Ordering::Greater,
+        (Some(CompareOp::Le), _) => ord != Ordering::Greater,
+        (Some(CompareOp::Ge), _) => ord != Ordering::Less,
+    }))
+}
+
+// Evaluates the name of a primitive not shadowed by a binding.
+#[inline(never)]
+fn eval_prim(ast: &Node, name: &str) -> Result<Value, Error> {
+    match prim(name) {
+        Some(prim) => Ok(Value::Fn(Func::Prim(prim, Rc::from([])))),
+        None => Err(Error::Type(TypeError {
+            kind: TypeErrorKind::UnboundValue(name.to_string()),
+            span: Some(ast.span),
+        })),
+    }
+}
+
+#[cold]
+fn internal(message: &str) -> Error {
+    Error::Internal(message.to_string())
+}
+
+// Evaluates an expression in tail position, counted by the meter as
+// eval_expr() does.
+fn eval_tail(ast: &Node, env: &Env, meter: &Meter) -> Result<Tail, Error> {
+    meter.enter(ast.span)?;
+    let tail = match &ast.kind {
+        NodeKind::LocalBind(_)
+        | NodeKind::App(..)
+        | NodeKind::Seq(..)
+        | NodeKind::If(..)
+        | NodeKind::Constraint(..) => eval_tail_node(ast, env, meter),
+        _ => eval_node(ast, env, meter).map(Tail::Value),
+    };
+    meter.leave();
+    tail
+}
+
+fn eval_tail_node(ast: &Node, env: &Env, meter: &Meter) -> Result<Tail, Error> {
+    match &ast.kind {
+        NodeKind::LocalBind(local_bind) => {
+            let LocalBindStruct { bind, scope } = &**local_bind;
+            let (name, value) = eval_bound(bind, env, meter)?;
+            eval_tail(scope, &env.bind(name, value), meter)
+        }
+        NodeKind::App(func, arg) => {
+            let func = eval_expr(func, env, meter)?;
+            let arg = eval_expr(arg, env, meter)?;
+            Ok(Tail::Call(func, arg, ast.span))
+        }
+        NodeKind::Seq(lhs, rhs) => {
+            eval_expr(lhs, env, meter)?;
+            eval_tail(rhs, env, meter)
+        }
+        NodeKind::If(cond, then, other) => match (eval_expr(cond, env, meter)?, other) {
+            (Value::Bool(true), _) => eval_tail(then, env, meter),
+            (Value::Bool(false), Some(other)) => eval_tail(other, env, meter),
+            (Value::Bool(false), None) => Ok(Tail::Value(Value::Unit)),
+            _ => Err(Error::Internal("Expected a bool".to_string())),
         },
-        Node::Div(lhs, rhs) => match (eval_ast(lhs, bounds)?, eval_ast(rhs, bounds)?) {
-            (
-                Output {
-                    name: None,
-                    value: Value::Int(l),
-                },
-                Output {
-                    name: None,
-                    value: Value::Int(r),
-                },
-            ) => Ok(Output {
+        NodeKind::Constraint(expr, _) => eval_tail(expr, env, meter),
+        _ => eval_node(ast, env, meter).map(Tail::Value),
+    }
+}
+
+// Evaluates a binding to its name and value, which is a closure if it takes
+// arguments.
+fn eval_bound(bind: &BindStruct, env: &Env, meter: &Meter) -> Result<(String, Value), Error> {
+    let BindStruct {
+        recursive,
+        name,
+        args,
+        expr,
+    } = bind;
+    let name = match &name.kind {
+        NodeKind::Ident(ident) => ident.clone(),
+        _ => return Err(Error::Internal("Expected a value name".to_string())),
+    };
+    if args.is_empty() {
+        return Ok((name, eval_expr(expr, env, meter)?));
+    }
+    let mut params = Vec::new();
+    for arg in args {
+        let param = match &arg.kind {
+            NodeKind::Constraint(name, _) => name,
+            _ => arg,
+        };
+        match &param.kind {
+            NodeKind::Ident(ident) => params.push(ident.clone()),
+            _ => return Err(Error::Internal("Expected a parameter name".to_string())),
+        }
+    }
+    let value = Value::Fn(Func::Closure(Rc::new(Closure {
+        name: recursive.then(|| name.clone()),
+        params,
+        body: Rc::new(expr.clone()),
+        env: env.clone(),
+    })));
+    meter.alloc(&value, expr.span)?;
+    Ok((name, value))
+}
+
+// Applies a function to an argument, returning a function taking the rest
+// of the arguments if any. Exceptions raised by primitives are located at
+// `span` of the application, as are values allocated.
+//
+// Applications in tail position of closures' bodies are made in a loop
+// rather than recursively, so that tail calls take no stack.
+pub(super) fn apply(
+    mut func: Value,
+    mut arg: Value,
+    mut span: Span,
+    meter: &Meter,
+) -> Result<Value, Error> {
+    loop {
+        let closure = match &func {
+            Value::Fn(Func::Closure(closure)) => closure.clone(),
+            Value::Fn(Func::Code(..)) => return vm::apply(func, arg, span, meter),
+            _ => return apply_prim(func, arg, span, meter),
+        };
+        let Closure {
+            name,
+            params,
+            body,
+            env,
+        } = &*closure;
+        // A recursive function refers to itself in its body.
+        let env = match name {
+            Some(name) => env.bind(name.clone(), Value::Fn(Func::Closure(closure.clone()))),
+            None => env.clone(),
+        };
+        let env = env.bind(params[0].clone(), arg);
+        if params.len() > 1 {
+            let value = Value::Fn(Func::Closure(Rc::new(Closure {
                 name: None,
-                value: Value::Int(l / r),
-            }),
-            _ => Err("This expression has a type other than int".to_string()),
-        },
-        Node::Eql(lhs, rhs) => {
-            if let (
-                Output {
-                    name: None,
-                    value: lval,
-                },
-                Output {
-                    name: None,
-                    value: rval,
-                },
-            ) = (eval_ast(lhs, bounds)?, eval_ast(rhs, bounds)?)
-            {
-                match (lval, rval) {
-                    (Value::Int(l), Value::Int(r)) => {
-                        return Ok(Output {
-                            name: None,
-                            value: Value::Bool(l == r),
-                        })
-                    }
-                    (Value::Bool(l), Value::Bool(r)) => {
-                        return Ok(Output {
-                            name: None,
-                            value: Value::Bool(l == r),
-                        })
-                    }
-                    _ => (),
-                }
-            }
-            Err("Syntax error".to_string())
+                params: params[1..].to_vec(),
+                body: body.clone(),
+                env,
+            })));
+            meter.alloc(&value, span)?;
+            return Ok(value);
         }
-        Node::Neql(lhs, rhs) => {
-            if let (
-                Output {
-                    name: None,
-                    value: lval,
-                },
-                Output {
-                    name: None,
-                    value: rval,
-                },
-            ) = (eval_ast(lhs, bounds)?, eval_ast(rhs, bounds)?)
-            {
-                match (lval, rval) {
-                    (Value::Int(l), Value::Int(r)) => {
-                        return Ok(Output {
-                            name: None,
-                            value: Value::Bool(l != r),
-                        })
-                    }
-                    (Value::Bool(l), Value::Bool(r)) => {
-                        return Ok(Output {
-                            name: None,
-                            value: Value::Bool(l != r),
-                        })
-                    }
-                    _ => (),
+        match eval_tail(body, &env, meter)? {
+            Tail::Value(value) => return Ok(value),
+            Tail::Call(f, a, s) => (func, arg, span) = (f, a, s),
+        }
+    }
+}
+
+// Applies a primitive or native function, which is called once given all
+// its arguments.
+fn apply_prim(func: Value, arg: Value, span: Span, meter: &Meter) -> Result<Value, Error> {
+    let value = match &func {
+        Value::Fn(Func::Prim(prim, args)) => {
+            let args = applied(args, arg);
+            if args.len() < prim.arity {
+                Value::Fn(Func::Prim(prim, args))
+            } else {
+                let value = (prim.apply)(&args).map_err(|err| locate(err, span))?;
+                if !prim.alloc {
+                    return Ok(value);
                 }
+                value
             }
-            Err("Syntax error".to_string())
         }
-        Node::Ident(name) => match bounds.get(name) {
-            Some(value) => Ok(Output {
-                name: None,
-                value: value.clone(),
-            }),
-            None => Err(format!("Unbound value {}", name)),
-        },
-        Node::Bind(bind) => {
-            let BindStruct { name, args, expr } = &**bind;
-            let name = match name {
-                Node::Ident(name) => name.clone(),
-                _ => return Err("Expected a value name".to_string()),
-            };
-            // TODO: args
-            let value = if args.is_empty() {
-                match eval_ast(expr, bounds)? {
-                    Output { name: None, value } => value,
-                    _ => return Err("Syntax error".to_string()),
-                }
+        Value::Fn(Func::Native(native, args)) => {
+            let args = applied(args, arg);
+            if args.len() < native.arity {
+                Value::Fn(Func::Native(native.clone(), args))
             } else {
-                Value::Fn
-            };
-            bounds.bind(name.clone(), value.clone());
-            Ok(Output {
-                name: Some(name),
-                value,
-            })
+                (native.apply)(&args).map_err(|err| locate(err, span))?
+            }
         }
-        Node::LocalBind(local_bind) => {
-            let LocalBindStruct { bind, scope } = &**local_bind;
-            // Eval local binding.
-            let BindStruct { name, args, expr } = &*bind;
-            let name = match name {
-                Node::Ident(name) => name.clone(),
-                _ => return Err("Expected a value name".to_string()),
-            };
-            let value = if args.is_empty() {
-                match eval_ast(expr, bounds)? {
-                    Output { name: None, value } => value,
-                    _ => return Err("Syntax error".to_string()),
+        _ => return Err(Error::Internal("Expected a function".to_string())),
+    };
+    meter.alloc(&value, span)?;
+    Ok(value)
+}
+
+// Locates an exception raised by a primitive at its application.
+pub(super) fn locate(err: Error, span: Span) -> Error {
+    match err {
+        Error::Exception(exn) => Error::Exception(Exception {
+            span: Some(span),
+            ..exn
+        }),
+        err => err,
+    }
+}
+
+// Evaluates an operand of an arithmetic operator, which is an int.
+fn eval_int(node: &Node, env: &Env, meter: &Meter) -> Result<i64, Error> {
+    match eval_expr(node, env, meter)? {
+        Value::Int(i) => Ok(i),
+        _ => Err(Error::Internal("Expected an int".to_string())),
+    }
+}
+
+// Orders values structurally as OCaml's `compare` does. Variant
+// constructors are ordered as declared, those without arguments first, and
+// arrays by their lengths first. Comparing functions raises
+// `Invalid_argument` with the name of the primitive `prim`, or with `None`,
+// as for `==`, tells only whether they are the same function applied to
+// equal arguments. Values are compared a pair at a time rather than
+// recursively, as they can be nested deeper than the stack, and each pair is
+// a step counted by `meter` at `span`, so that comparing cyclic values runs
+// out of fuel or is interrupted.
+pub(super) fn compare(
+    lhs: &Value,
+    rhs: &Value,
+    decls: &TypeDecls,
+    prim: Option<&str>,
+    meter: &Meter,
+    span: Span,
+) -> Result<Ordering, Error> {
+    let mut pairs = vec![(lhs.clone(), rhs.clone())];
+    while let Some((lhs, rhs)) = pairs.pop() {
+        meter.step(span)?;
+        let ord = match (&lhs, &rhs) {
+            (Value::Int(l), Value::Int(r)) => l.cmp(r),
+            (Value::Bool(l), Value::Bool(r)) => l.cmp(r),
+            (Value::Unit, Value::Unit) => Ordering::Equal,
+            (Value::Str(l), Value::Str(r)) => l.cmp(r),
+            (Value::List(l), Value::List(r)) => match (l.uncons(), r.uncons()) {
+                (Some((lhead, ltail)), Some((rhead, rtail))) => {
+                    pairs.push((Value::List(ltail.clone()), Value::List(rtail.clone())));
+                    pairs.push((lhead.clone(), rhead.clone()));
+                    Ordering::Equal
                 }
-            } else {
-                Value::Fn
-            };
-            // Make new bound values from global bound values.
-            let mut bounds_locally = bounds.clone();
-            bounds_locally.bind(name, value);
-            // Eval expresion in scope with local bindings.
-            match eval_ast(scope, &mut bounds_locally)? {
-                Output {
-                    name: None,
-                    value: Value::Int(i),
-                } => Ok(Output {
-                    name: None,
-                    value: Value::Int(i),
-                }),
-                _ => Err("Syntax error".to_string()),
+                (l, r) => l.is_some().cmp(&r.is_some()),
+            },
+            (Value::Tuple(l), Value::Tuple(r)) => push_pairs(&mut pairs, l.iter(), r.iter()),
+            (Value::Record(l), Value::Record(r)) => push_pairs(
+                &mut pairs,
+                l.iter().map(|(_, value)| value),
+                r.iter().map(|(_, value)| value),
+            ),
+            // A reference or an array is equal to itself even if it is
+            // cyclic.
+            (Value::Ref(l), Value::Ref(r)) if Rc::ptr_eq(l, r) => Ordering::Equal,
+            (Value::Ref(l), Value::Ref(r)) => {
+                pairs.push((l.borrow().clone(), r.borrow().clone()));
+                Ordering::Equal
+            }
+            (Value::Array(l), Value::Array(r)) if Rc::ptr_eq(l, r) => Ordering::Equal,
+            (Value::Array(l), Value::Array(r)) => {
+                let (l, r) = (l.borrow(), r.borrow());
+                l.len()
+                    .cmp(&r.len())
+                    .then_with(|| push_pairs(&mut pairs, l.iter(), r.iter()))
             }
+            (Value::Constr(l, larg), Value::Constr(r, rarg)) => {
+                let ord = tag(l, larg.is_some(), decls).cmp(&tag(r, rarg.is_some(), decls));
+                if let (Ordering::Equal, Some(l), Some(r)) = (ord, larg, rarg) {
+                    pairs.push((l.as_ref().clone(), r.as_ref().clone()));
+                }
+                ord
+            }
+            (Value::Fn(l), Value::Fn(r)) if prim.is_none() => match (l, r) {
+                (Func::Closure(l), Func::Closure(r)) if Rc::ptr_eq(l, r) => Ordering::Equal,
+                (Func::Code(l, largs), Func::Code(r, rargs)) if Rc::ptr_eq(l, r) => {
+                    push_args(&mut pairs, largs, rargs)
+                }
+                (Func::Prim(l, largs), Func::Prim(r, rargs)) if l.name == r.name => {
+                    push_args(&mut pairs, largs, rargs)
+                }
+                (Func::Native(l, largs), Func::Native(r, rargs)) if Rc::ptr_eq(l, r) => {
+                    push_args(&mut pairs, largs, rargs)
+                }
+                _ => Ordering::Less,
+            },
+            (Value::Fn(_), _) | (_, Value::Fn(_)) => {
+                return Err(Error::Exception(Exception::invalid_argument(format!(
+                    "{}: functional value",
+                    prim.unwrap_or_default()
+                ))))
+            }
+            _ => {
+                return Err(Error::Internal(
+                    "Expected values of the same type".to_string(),
+                ))
+            }
+        };
+        if ord != Ordering::Equal {
+            return Ok(ord);
         }
     }
+    Ok(Ordering::Equal)
+}
+
+// Pushes pairs of the elements of sequences of the same length to be
+// compared, the first ones first.
+fn push_pairs<'a, L, R>(pairs: &mut Vec<(Value, Value)>, lhs: L, rhs: R) -> Ordering
+where
+    L: DoubleEndedIterator<Item = &'a Value> + ExactSizeIterator,
+    R: DoubleEndedIterator<Item = &'a Value> + ExactSizeIterator,
+{
+    pairs.extend(lhs.zip(rhs).rev().map(|(l, r)| (l.clone(), r.clone())));
+    Ordering::Equal
+}
+
+// Pushes pairs of the arguments applied to the same function, if as many.
+fn push_args(pairs: &mut Vec<(Value, Value)>, lhs: &[Value], rhs: &[Value]) -> Ordering {
+    lhs.len()
+        .cmp(&rhs.len())
+        .then_with(|| push_pairs(pairs, lhs.iter(), rhs.iter()))
+}
+
+// Whether a constructor takes an argument, and its index among the
+// constructors of its type which likewise do or do not.
+pub(super) fn tag(name: &str, has_arg: bool, decls: &TypeDecls) -> (bool, usize) {
+    let index = match decls.constructor(name).map(|(decl, _)| &decl.kind) {
+        Some(TypeDeclKind::Variant(constrs)) => constrs
+            .iter()
+            .filter(|(_, arg)| arg.is_some() == has_arg)
+            .position(|(constr, _)| constr == name),
+        _ => None,
+    };
+    (has_arg, index.unwrap_or_default())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::eval::value::List;
-    use std::collections::HashMap;
+    use crate::eval::span::LineIndex;
+    use crate::eval::types::{Type, TypeDecl};
+    use crate::eval::value::{Func, List};
+    use crate::eval::Globals;
+
+    fn node(kind: NodeKind) -> Node {
+        Node {
+            kind,
+            span: Span::default(),
+        }
+    }
 
     #[test]
     fn eval_int() {
-        let ast = Node::Int(123);
+        let ast = node(NodeKind::Int(123));
         let mut bounds = Bounds::new();
         let expected = Output {
             name: None,
@@ -252,16 +571,22 @@ mod tests {
     #[test]
     fn eval_arithmetic_expr() {
         // 2+3*4+5-6/2
-        let ast = Node::Sub(
-            Box::new(Node::Add(
-                Box::new(Node::Add(
-                    Box::new(Node::Int(2)),
-                    Box::new(Node::Mul(Box::new(Node::Int(3)), Box::new(Node::Int(4)))),
-                )),
-                Box::new(Node::Int(5)),
-            )),
-            Box::new(Node::Div(Box::new(Node::Int(6)), Box::new(Node::Int(2)))),
-        );
+        let ast = node(NodeKind::Sub(
+            Box::new(node(NodeKind::Add(
+                Box::new(node(NodeKind::Add(
+                    Box::new(node(NodeKind::Int(2))),
+                    Box::new(node(NodeKind::Mul(
+                        Box::new(node(NodeKind::Int(3))),
+                        Box::new(node(NodeKind::Int(4))),
+                    ))),
+                ))),
+                Box::new(node(NodeKind::Int(5))),
+            ))),
+            Box::new(node(NodeKind::Div(
+                Box::new(node(NodeKind::Int(6))),
+                Box::new(node(NodeKind::Int(2))),
+            ))),
+        ));
         let mut bounds = Bounds::new();
         let expected = Output {
             name: None,
@@ -274,11 +599,12 @@ mod tests {
     #[test]
     fn eval_global_binding() {
         // let foo = 123
-        let ast = Node::Bind(Box::new(BindStruct {
-            name: Node::Ident("foo".to_string()),
+        let ast = node(NodeKind::Bind(Box::new(BindStruct {
+            recursive: false,
+            name: node(NodeKind::Ident("foo".to_string())),
             args: vec![],
-            expr: Node::Int(123),
-        }));
+            expr: node(NodeKind::Int(123)),
+        })));
         let mut bounds = Bounds::new();
         let expected = Output {
             name: Some("foo".to_string()),
@@ -287,19 +613,20 @@ mod tests {
         let actual = eval_ast(&ast, &mut bounds).unwrap();
         assert_eq!(expected, actual);
         assert_eq!(
-            bounds,
-            Bounds(HashMap::from([("foo".to_string(), Value::Int(123))])),
+            bounds.values,
+            Globals::from([("foo".to_string(), Value::Int(123))])
         );
     }
 
     #[test]
     fn overwrites_existing_global_binding() {
         // let foo = 123
-        let ast = Node::Bind(Box::new(BindStruct {
-            name: Node::Ident("foo".to_string()),
+        let ast = node(NodeKind::Bind(Box::new(BindStruct {
+            recursive: false,
+            name: node(NodeKind::Ident("foo".to_string())),
             args: vec![],
-            expr: Node::Int(987),
-        }));
+            expr: node(NodeKind::Int(987)),
+        })));
         let mut bounds = Bounds::new();
         let expected = Output {
             name: Some("foo".to_string()),
@@ -308,16 +635,19 @@ mod tests {
         let actual = eval_ast(&ast, &mut bounds).unwrap();
         assert_eq!(expected, actual);
         assert_eq!(
-            bounds,
-            Bounds(HashMap::from([("foo".to_string(), Value::Int(987))])),
+            bounds.values,
+            Globals::from([("foo".to_string(), Value::Int(987))])
         );
     }
 
     #[test]
     fn eval_bound_global_value() {
         // foo
-        let ast = Node::Ident("foo".to_string());
-        let mut bounds = Bounds(HashMap::from([("foo".to_string(), Value::Int(123))]));
+        let ast = node(NodeKind::Ident("foo".to_string()));
+        let mut bounds = Bounds {
+            values: Globals::from([("foo".to_string(), Value::Int(123))]),
+            ..Bounds::new()
+        };
         let expected = Output {
             name: None,
             value: Value::Int(123),
@@ -325,25 +655,26 @@ mod tests {
         let actual = eval_ast(&ast, &mut bounds).unwrap();
         assert_eq!(expected, actual);
         assert_eq!(
-            bounds,
-            Bounds(HashMap::from([("foo".to_string(), Value::Int(123))]))
+            bounds.values,
+            Globals::from([("foo".to_string(), Value::Int(123))])
         );
     }
 
     #[test]
     fn eval_local_binding() {
         // let x = 5 in x + 2
-        let ast = Node::LocalBind(Box::new(LocalBindStruct {
+        let ast = node(NodeKind::LocalBind(Box::new(LocalBindStruct {
             bind: BindStruct {
-                name: Node::Ident("x".to_string()),
+                recursive: false,
+                name: node(NodeKind::Ident("x".to_string())),
                 args: vec![],
-                expr: Node::Int(5),
+                expr: node(NodeKind::Int(5)),
             },
-            scope: Node::Add(
-                Box::new(Node::Ident("x".to_string())),
-                Box::new(Node::Int(2)),
-            ),
-        }));
+            scope: node(NodeKind::Add(
+                Box::new(node(NodeKind::Ident("x".to_string()))),
+                Box::new(node(NodeKind::Int(2))),
+            )),
+        })));
         let mut bounds = Bounds::new();
         let expected = Output {
             name: None,
@@ -357,18 +688,22 @@ mod tests {
     #[test]
     fn shadow_global_binding_with_local_binding() {
         // let foo = 5 in foo + 2
-        let ast = Node::LocalBind(Box::new(LocalBindStruct {
+        let ast = node(NodeKind::LocalBind(Box::new(LocalBindStruct {
             bind: BindStruct {
-                name: Node::Ident("foo".to_string()),
+                recursive: false,
+                name: node(NodeKind::Ident("foo".to_string())),
                 args: vec![],
-                expr: Node::Int(5),
+                expr: node(NodeKind::Int(5)),
             },
-            scope: Node::Add(
-                Box::new(Node::Ident("foo".to_string())),
-                Box::new(Node::Int(2)),
-            ),
-        }));
-        let mut bounds = Bounds(HashMap::from([("foo".to_string(), Value::Int(123))]));
+            scope: node(NodeKind::Add(
+                Box::new(node(NodeKind::Ident("foo".to_string()))),
+                Box::new(node(NodeKind::Int(2))),
+            )),
+        })));
+        let mut bounds = Bounds {
+            values: Globals::from([("foo".to_string(), Value::Int(123))]),
+            ..Bounds::new()
+        };
         let expected = Output {
             name: None,
             value: Value::Int(7),
@@ -376,15 +711,15 @@ mod tests {
         let actual = eval_ast(&ast, &mut bounds).unwrap();
         assert_eq!(expected, actual);
         assert_eq!(
-            bounds,
-            Bounds(HashMap::from([("foo".to_string(), Value::Int(123))]))
+            bounds.values,
+            Globals::from([("foo".to_string(), Value::Int(123))])
         );
     }
 
     #[test]
     fn eval_empty_list() {
         // []
-        let ast = Node::List(List::new());
+        let ast = node(NodeKind::List(vec![]));
         let mut bounds = Bounds::new();
         let expected = Output {
             name: None,
@@ -398,14 +733,13 @@ mod tests {
     #[test]
     fn eval_list() {
         // [1; 2; 3]
-        let list = List(
-            Some(1),
-            Some(Box::new(List(
-                Some(2),
-                Some(Box::new(List(Some(3), Some(Box::new(List(None, None)))))),
-            ))),
-        );
-        let ast = Node::List(list.clone());
+        let elems = vec![
+            node(NodeKind::Int(1)),
+            node(NodeKind::Int(2)),
+            node(NodeKind::Int(3)),
+        ];
+        let list = List::from(vec![Value::Int(1), Value::Int(2), Value::Int(3)]);
+        let ast = node(NodeKind::List(elems));
         let mut bounds = Bounds::new();
         let expected = Output {
             name: None,
@@ -416,21 +750,39 @@ mod tests {
         assert_eq!(bounds, Bounds::new());
     }
 
+    #[test]
+    fn eval_list_of_expressions() {
+        // [1 + 2; 3]
+        let ast = node(NodeKind::List(vec![
+            node(NodeKind::Add(
+                Box::new(node(NodeKind::Int(1))),
+                Box::new(node(NodeKind::Int(2))),
+            )),
+            node(NodeKind::Int(3)),
+        ]));
+        let expected = Output {
+            name: None,
+            value: Value::List(List::from(vec![Value::Int(3), Value::Int(3)])),
+        };
+        let actual = eval_ast(&ast, &mut Bounds::new()).unwrap();
+        assert_eq!(expected, actual);
+    }
+
     #[test]
     fn eval_list_binding() {
         // let lst = [1; 2; 3]
-        let list = List(
-            Some(1),
-            Some(Box::new(List(
-                Some(2),
-                Some(Box::new(List(Some(3), Some(Box::new(List(None, None)))))),
-            ))),
-        );
-        let ast = Node::Bind(Box::new(BindStruct {
-            name: Node::Ident("lst".to_string()),
+        let elems = vec![
+            node(NodeKind::Int(1)),
+            node(NodeKind::Int(2)),
+            node(NodeKind::Int(3)),
+        ];
+        let list = List::from(vec![Value::Int(1), Value::Int(2), Value::Int(3)]);
+        let ast = node(NodeKind::Bind(Box::new(BindStruct {
+            recursive: false,
+            name: node(NodeKind::Ident("lst".to_string())),
             args: vec![],
-            expr: Node::List(list.clone()),
-        }));
+            expr: node(NodeKind::List(elems)),
+        })));
         let mut bounds = Bounds::new();
         let expected = Output {
             name: Some("lst".to_string()),
@@ -439,25 +791,39 @@ mod tests {
         let actual = eval_ast(&ast, &mut bounds).unwrap();
         assert_eq!(expected, actual);
         assert_eq!(
-            bounds,
-            Bounds(HashMap::from([("lst".to_string(), Value::List(list))]))
+            bounds.values,
+            Globals::from([("lst".to_string(), Value::List(list))])
         );
     }
 
     #[test]
     fn error_on_arithmetic_operation_for_not_int_pair() {
         // [1; 2] + 3
-        let ast = Node::Add(
-            Box::new(Node::List(List::from(&vec![1, 2]))),
-            Box::new(Node::Int(3)),
-        );
+        let ast = node(NodeKind::Add(
+            Box::new(node(NodeKind::List(vec![
+                node(NodeKind::Int(1)),
+                node(NodeKind::Int(2)),
+            ]))),
+            Box::new(node(NodeKind::Int(3))),
+        ));
         assert!(eval_ast(&ast, &mut Bounds::new()).is_err());
     }
 
+    #[test]
+    fn raise_division_by_zero() {
+        // 1 / 0
+        let ast = node(NodeKind::Div(
+            Box::new(node(NodeKind::Int(1))),
+            Box::new(node(NodeKind::Int(0))),
+        ));
+        let err = eval_ast(&ast, &mut Bounds::new()).unwrap_err();
+        assert_eq!(err.code(), "exception/division-by-zero");
+    }
+
     #[test]
     fn eval_true() {
         // true
-        let ast = Node::Bool(true);
+        let ast = node(NodeKind::Bool(true));
         let mut bounds = Bounds::new();
         let expected = Output {
             name: None,
@@ -470,7 +836,10 @@ mod tests {
     #[test]
     fn eval_equal_1() {
         // 2 == 3
-        let ast = Node::Eql(Box::new(Node::Int(2)), Box::new(Node::Int(3)));
+        let ast = node(NodeKind::Eql(
+            Box::new(node(NodeKind::Int(2))),
+            Box::new(node(NodeKind::Int(3))),
+        ));
         let mut bounds = Bounds::new();
         let expected = Output {
             name: None,
@@ -483,7 +852,10 @@ mod tests {
     #[test]
     fn eval_equal_2() {
         // 3 == 3
-        let ast = Node::Eql(Box::new(Node::Int(3)), Box::new(Node::Int(3)));
+        let ast = node(NodeKind::Eql(
+            Box::new(node(NodeKind::Int(3))),
+            Box::new(node(NodeKind::Int(3))),
+        ));
         let mut bounds = Bounds::new();
         let expected = Output {
             name: None,
@@ -496,7 +868,10 @@ mod tests {
     #[test]
     fn eval_equal_3() {
         // true == false
-        let ast = Node::Eql(Box::new(Node::Bool(true)), Box::new(Node::Bool(false)));
+        let ast = node(NodeKind::Eql(
+            Box::new(node(NodeKind::Bool(true))),
+            Box::new(node(NodeKind::Bool(false))),
+        ));
         let mut bounds = Bounds::new();
         let expected = Output {
             name: None,
@@ -509,7 +884,10 @@ mod tests {
     #[test]
     fn eval_equal_4() {
         // false == false
-        let ast = Node::Eql(Box::new(Node::Bool(false)), Box::new(Node::Bool(false)));
+        let ast = node(NodeKind::Eql(
+            Box::new(node(NodeKind::Bool(false))),
+            Box::new(node(NodeKind::Bool(false))),
+        ));
         let mut bounds = Bounds::new();
         let expected = Output {
             name: None,
@@ -519,10 +897,31 @@ mod tests {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn eval_equal_between_lists() {
+        // [1; 2] == [1; 2]
+        let list = || {
+            node(NodeKind::List(vec![
+                node(NodeKind::Int(1)),
+                node(NodeKind::Int(2)),
+            ]))
+        };
+        let ast = node(NodeKind::Eql(Box::new(list()), Box::new(list())));
+        let expected = Output {
+            name: None,
+            value: Value::Bool(true),
+        };
+        let actual = eval_ast(&ast, &mut Bounds::new()).unwrap();
+        assert_eq!(expected, actual);
+    }
+
     #[test]
     fn eval_not_equal_1() {
         // 2 != 3
-        let ast = Node::Neql(Box::new(Node::Int(2)), Box::new(Node::Int(3)));
+        let ast = node(NodeKind::Neql(
+            Box::new(node(NodeKind::Int(2))),
+            Box::new(node(NodeKind::Int(3))),
+        ));
         let mut bounds = Bounds::new();
         let expected = Output {
             name: None,
@@ -535,7 +934,10 @@ mod tests {
     #[test]
     fn eval_not_equal_2() {
         // 3 != 3
-        let ast = Node::Neql(Box::new(Node::Int(3)), Box::new(Node::Int(3)));
+        let ast = node(NodeKind::Neql(
+            Box::new(node(NodeKind::Int(3))),
+            Box::new(node(NodeKind::Int(3))),
+        ));
         let mut bounds = Bounds::new();
         let expected = Output {
             name: None,
@@ -548,42 +950,159 @@ mod tests {
     #[test]
     fn eval_func_definition() {
         // let square x = x * x
-        let ast = Node::Bind(Box::new(BindStruct {
-            name: Node::Ident("square".to_string()),
-            args: vec![Node::Ident("x".to_string())],
-            expr: Node::Mul(
-                Box::new(Node::Ident("x".to_string())),
-                Box::new(Node::Ident("x".to_string())),
+        let ast = node(NodeKind::Bind(Box::new(BindStruct {
+            recursive: false,
+            name: node(NodeKind::Ident("square".to_string())),
+            args: vec![node(NodeKind::Ident("x".to_string()))],
+            expr: node(NodeKind::Mul(
+                Box::new(node(NodeKind::Ident("x".to_string()))),
+                Box::new(node(NodeKind::Ident("x".to_string()))),
+            )),
+        })));
+        let mut bounds = Bounds::new();
+        let actual = eval_ast(&ast, &mut bounds).unwrap();
+        assert_eq!(actual.name, Some("square".to_string()));
+        match &actual.value {
+            Value::Fn(Func::Closure(closure)) => {
+                assert_eq!(closure.params, vec!["x".to_string()]);
+                assert_eq!(closure.env, Env::default());
+            }
+            value => panic!("Expected a closure: {:?}", value),
+        }
+        assert_eq!(
+            bounds.values,
+            Globals::from([("square".to_string(), actual.value)])
+        );
+    }
+
+    #[test]
+    fn eval_partial_application() {
+        // let sub a b = a - b in sub 5 3
+        let ident = |name: &str| node(NodeKind::Ident(name.to_string()));
+        let app = |func, arg| node(NodeKind::App(Box::new(func), Box::new(arg)));
+        let ast = node(NodeKind::LocalBind(Box::new(LocalBindStruct {
+            bind: BindStruct {
+                recursive: false,
+                name: ident("sub"),
+                args: vec![ident("a"), ident("b")],
+                expr: node(NodeKind::Sub(Box::new(ident("a")), Box::new(ident("b")))),
+            },
+            scope: app(
+                app(ident("sub"), node(NodeKind::Int(5))),
+                node(NodeKind::Int(3)),
             ),
-        }));
+        })));
         let mut bounds = Bounds::new();
         let expected = Output {
-            name: Some("square".to_string()),
-            value: Value::Fn,
+            name: None,
+            value: Value::Int(2),
         };
         let actual = eval_ast(&ast, &mut bounds).unwrap();
         assert_eq!(expected, actual);
-        assert_eq!(
-            bounds,
-            // TODO
-            Bounds(HashMap::from([("square".to_string(), Value::Fn)]))
-        );
+    }
+
+    #[test]
+    fn eval_references() {
+        // let r = ref 1 in (r := !r + 1, r)
+        let ident = |name: &str| node(NodeKind::Ident(name.to_string()));
+        let app = |func, arg| node(NodeKind::App(Box::new(func), Box::new(arg)));
+        let ast = node(NodeKind::LocalBind(Box::new(LocalBindStruct {
+            bind: BindStruct {
+                recursive: false,
+                name: ident("r"),
+                args: vec![],
+                expr: app(ident("ref"), node(NodeKind::Int(1))),
+            },
+            scope: node(NodeKind::Tuple(vec![
+                app(
+                    app(ident(":="), ident("r")),
+                    node(NodeKind::Add(
+                        Box::new(app(ident("!"), ident("r"))),
+                        Box::new(node(NodeKind::Int(1))),
+                    )),
+                ),
+                ident("r"),
+            ])),
+        })));
+        let mut bounds = Bounds::new();
+        let actual = eval_ast(&ast, &mut bounds).unwrap();
+        let expected = Value::Tuple(Rc::new([
+            Value::Unit,
+            Value::Ref(Rc::new(RefCell::new(Value::Int(2)))),
+        ]));
+        assert_eq!(actual.value, expected);
+    }
+
+    #[test]
+    fn eval_sequences_and_failures() {
+        // let r = ref 1 in r := 2; !r
+        let ident = |name: &str| node(NodeKind::Ident(name.to_string()));
+        let app = |func, arg| node(NodeKind::App(Box::new(func), Box::new(arg)));
+        let ast = node(NodeKind::LocalBind(Box::new(LocalBindStruct {
+            bind: BindStruct {
+                recursive: false,
+                name: ident("r"),
+                args: vec![],
+                expr: app(ident("ref"), node(NodeKind::Int(1))),
+            },
+            scope: node(NodeKind::Seq(
+                Box::new(app(app(ident(":="), ident("r")), node(NodeKind::Int(2)))),
+                Box::new(app(ident("!"), ident("r"))),
+            )),
+        })));
+        let mut bounds = Bounds::new();
+        let actual = eval_ast(&ast, &mut bounds).unwrap();
+        assert_eq!(actual.value, Value::Int(2));
+
+        // Exceptions raised by primitives are located at the applications.
+        let index = LineIndex::new("failwith \"boom\"");
+        let span = Span::new(index.pos(0), index.pos(15));
+        let ast = Node {
+            kind: NodeKind::App(
+                Box::new(ident("failwith")),
+                Box::new(node(NodeKind::Str(b"boom".to_vec()))),
+            ),
+            span,
+        };
+        let expected = Error::Exception(Exception {
+            kind: ExceptionKind::Failure("boom".to_string()),
+            span: Some(span),
+        });
+        assert_eq!(eval_ast(&ast, &mut bounds).unwrap_err(), expected);
+    }
+
+    #[test]
+    fn eval_deep_expressions_within_stack() {
+        // 1 + (1 + (1 + ...)) nested deeper than the stack allows
+        let ast = (0..1_000_000).fold(node(NodeKind::Int(0)), |ast, _| {
+            node(NodeKind::Add(
+                Box::new(node(NodeKind::Int(1))),
+                Box::new(ast),
+            ))
+        });
+        let mut bounds = Bounds::new();
+        bounds.meter.reset();
+        let err = eval_ast(&ast, &mut bounds).unwrap_err();
+        assert_eq!(err.code(), "exception/stack-overflow");
+        // Dropping the nodes would recurse as deep.
+        std::mem::forget(ast);
     }
 
     #[test]
     fn eval_local_func_definition() {
         // let square x = x * x in 42
-        let ast = Node::LocalBind(Box::new(LocalBindStruct {
+        let ast = node(NodeKind::LocalBind(Box::new(LocalBindStruct {
             bind: BindStruct {
-                name: Node::Ident("square".to_string()),
-                args: vec![Node::Ident("x".to_string())],
-                expr: Node::Mul(
-                    Box::new(Node::Ident("x".to_string())),
-                    Box::new(Node::Ident("x".to_string())),
-                ),
+                recursive: false,
+                name: node(NodeKind::Ident("square".to_string())),
+                args: vec![node(NodeKind::Ident("x".to_string()))],
+                expr: node(NodeKind::Mul(
+                    Box::new(node(NodeKind::Ident("x".to_string()))),
+                    Box::new(node(NodeKind::Ident("x".to_string()))),
+                )),
             },
-            scope: Node::Int(42),
-        }));
+            scope: node(NodeKind::Int(42)),
+        })));
         let mut bounds = Bounds::new();
         let expected = Output {
             name: None,
@@ -593,4 +1112,39 @@ mod tests {
         assert_eq!(expected, actual);
         assert_eq!(bounds, Bounds::new());
     }
+
+    #[test]
+    fn eval_record_in_declared_order() {
+        // { y = true; x = 1 }.x
+        let record = node(NodeKind::Record(vec![
+            ("y".to_string(), node(NodeKind::Bool(true))),
+            ("x".to_string(), node(NodeKind::Int(1))),
+        ]));
+        let mut bounds = Bounds::new();
+        Rc::make_mut(&mut bounds.decls).declare(TypeDecl {
+            name: "point".to_string(),
+            params: vec![],
+            kind: TypeDeclKind::Record(vec![
+                ("x".to_string(), Type::Int),
+                ("y".to_string(), Type::Bool),
+            ]),
+        });
+        let expected = Output {
+            name: None,
+            value: Value::Record(Rc::new([
+                ("x".to_string(), Value::Int(1)),
+                ("y".to_string(), Value::Bool(true)),
+            ])),
+        };
+        let actual = eval_ast(&record, &mut bounds).unwrap();
+        assert_eq!(expected, actual);
+
+        let field = node(NodeKind::Field(Box::new(record), "x".to_string()));
+        let expected = Output {
+            name: None,
+            value: Value::Int(1),
+        };
+        let actual = eval_ast(&field, &mut bounds).unwrap();
+        assert_eq!(expected, actual);
+    }
 }
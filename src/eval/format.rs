@@ -0,0 +1,211 @@
+// Layout of the toplevel output following OCaml's Format module: text is
+// grouped in boxes with break hints, which are line breaks if the text does
+// not fit in the margin, or spaces otherwise.
+
+// right margin, and the maximum indentation of lines, as in Format
+const MARGIN: usize = 78;
+const MAX_INDENT: usize = 68;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum Doc {
+    Text(String),
+    // `spaces` if not broken, or a new line indented by `offset` from the
+    // enclosing box, like `@;<spaces offset>`
+    Break { spaces: usize, offset: usize },
+    // box whose lines are indented by `indent` from where it is opened,
+    // like `@[<indent>...@]`
+    Box(usize, Vec<Doc>),
+}
+
+impl Doc {
+    pub fn text(text: impl Into<String>) -> Doc {
+        Doc::Text(text.into())
+    }
+
+    // `@ `, a break hint printed as a space if not broken.
+    pub fn space() -> Doc {
+        Doc::Break {
+            spaces: 1,
+            offset: 0,
+        }
+    }
+
+    // Width of the document printed on a single line.
+    fn flat_len(&self) -> usize {
+        match self {
+            Doc::Text(text) => text.chars().count(),
+            Doc::Break { spaces, .. } => *spaces,
+            Doc::Box(_, docs) => docs.iter().map(Doc::flat_len).sum(),
+        }
+    }
+
+    // Lays out the document within the margin.
+    pub fn render(&self) -> String {
+        let mut printer = Printer {
+            out: String::new(),
+            space_left: MARGIN,
+            current_indent: 0,
+            is_new_line: true,
+            boxes: Vec::new(),
+        };
+        printer.print(self);
+        printer.out
+    }
+}
+
+struct Printer {
+    out: String,
+    space_left: usize,
+    // indentation of the current line
+    current_indent: usize,
+    is_new_line: bool,
+    // open boxes, with whether each fits on the rest of the line, and the
+    // column where its lines are indented
+    boxes: Vec<(bool, usize)>,
+}
+
+impl Printer {
+    fn column(&self) -> usize {
+        MARGIN - self.space_left
+    }
+
+    fn print(&mut self, doc: &Doc) {
+        match doc {
+            Doc::Text(text) => {
+                self.out.push_str(text);
+                self.space_left = self.space_left.saturating_sub(text.chars().count());
+                self.is_new_line = false;
+            }
+            // A break outside boxes is never broken.
+            Doc::Break { spaces, .. } => self.print_spaces(*spaces),
+            Doc::Box(indent, docs) => {
+                // A box cannot be opened too far right.
+                if self.column() > MAX_INDENT {
+                    if let Some(&(false, indent)) = self.boxes.last() {
+                        self.new_line(indent);
+                    }
+                }
+                let fits = doc.flat_len() <= self.space_left;
+                self.boxes.push((fits, self.column() + indent));
+                for (idx, doc) in docs.iter().enumerate() {
+                    match doc {
+                        Doc::Break { spaces, offset } => {
+                            // text up to the next break in this box
+                            let size = spaces
+                                + docs[idx + 1..]
+                                    .iter()
+                                    .take_while(|doc| !matches!(doc, Doc::Break { .. }))
+                                    .map(Doc::flat_len)
+                                    .sum::<usize>();
+                            self.print_break(*spaces, *offset, size);
+                        }
+                        doc => self.print(doc),
+                    }
+                }
+                self.boxes.pop();
+            }
+        }
+    }
+
+    // Breaks the line if the text up to the next break, of `size`, does not
+    // fit, or if breaking would not indent the next line further than the
+    // current one (Format's structural boxes).
+    fn print_break(&mut self, spaces: usize, offset: usize, size: usize) {
+        let (fits, indent) = self.boxes[self.boxes.len() - 1];
+        if fits || self.is_new_line {
+            self.print_spaces(spaces);
+        } else if size > self.space_left || self.current_indent > indent + offset {
+            self.new_line(indent + offset);
+        } else {
+            self.print_spaces(spaces);
+        }
+    }
+
+    fn print_spaces(&mut self, spaces: usize) {
+        self.out.push_str(&" ".repeat(spaces));
+        self.space_left = self.space_left.saturating_sub(spaces);
+    }
+
+    fn new_line(&mut self, indent: usize) {
+        let indent = indent.min(MAX_INDENT);
+        self.out.push('\n');
+        self.out.push_str(&" ".repeat(indent));
+        self.current_indent = indent;
+        self.space_left = MARGIN - indent;
+        self.is_new_line = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `[0; 1; ...; n - 1]` in a box like the toplevel's
+    fn list(n: usize) -> Doc {
+        let mut docs = vec![Doc::text("[")];
+        for i in 0..n {
+            if i > 0 {
+                docs.push(Doc::text(";"));
+                docs.push(Doc::space());
+            }
+            docs.push(Doc::text(i.to_string()));
+        }
+        docs.push(Doc::text("]"));
+        Doc::Box(1, docs)
+    }
+
+    #[test]
+    fn render_fitting_box_on_single_line() {
+        let doc = Doc::Box(0, vec![Doc::text("- : int list ="), Doc::space(), list(3)]);
+        assert_eq!(doc.render(), "- : int list = [0; 1; 2]");
+    }
+
+    #[test]
+    fn render_long_box_on_lines() {
+        let doc = Doc::Box(
+            2,
+            vec![Doc::text("val l : int list ="), Doc::space(), list(30)],
+        );
+        assert_eq!(
+            doc.render(),
+            "val l : int list =\n  \
+             [0; 1; 2; 3; 4; 5; 6; 7; 8; 9; 10; 11; 12; 13; 14; 15; 16; 17; 18; 19; 20;\n   \
+             21; 22; 23; 24; 25; 26; 27; 28; 29]"
+        );
+    }
+
+    #[test]
+    fn render_nested_boxes() {
+        // Breaks of an inner box are kept while the outer box is broken.
+        let pair = |n| {
+            Doc::Box(
+                1,
+                vec![
+                    Doc::text("("),
+                    list(n),
+                    Doc::text(","),
+                    Doc::space(),
+                    Doc::text("true)"),
+                ],
+            )
+        };
+        let doc = Doc::Box(
+            1,
+            vec![
+                Doc::text("["),
+                pair(2),
+                Doc::text(";"),
+                Doc::space(),
+                pair(30),
+                Doc::text("]"),
+            ],
+        );
+        assert_eq!(
+            doc.render(),
+            "[([0; 1], true);\n \
+             ([0; 1; 2; 3; 4; 5; 6; 7; 8; 9; 10; 11; 12; 13; 14; 15; 16; 17; 18; 19; 20;\n   \
+             21; 22; 23; 24; 25; 26; 27; 28; 29],\n  \
+             true)]"
+        );
+    }
+}
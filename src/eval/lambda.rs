@@ -0,0 +1,668 @@
+use super::{
+    builtins::prim,
+    bytecode::{show, ArithOp, Block},
+    format::Doc,
+    parser::{BindStruct, CompareOp, LocalBindStruct, Node, NodeKind},
+    span::Span,
+    types::{Type, TypeDeclKind, TypeDecls},
+    value::{Func, List},
+    Value,
+};
+use crate::error::{Error, TypeError, TypeErrorKind};
+use crate::Bounds;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+// Intermediate representation of a phrase, after OCaml's lambda: variables
+// are explicit and unique, functions are lifted out of the phrase with the
+// variables their closures capture, and primitives are applied to operands
+// bound beforehand by `let`. Terms which may fail keep the span they are
+// lowered from, by which errors are located. It is dumped by `-dlambda`, and
+// compiled to bytecode, C or WebAssembly.
+#[derive(Debug)]
+pub(super) struct Program {
+    // name bound by the phrase, if it is a binding
+    pub name: Option<String>,
+    // functions lifted out of the phrase, referred to by their index
+    pub functions: Vec<Function>,
+    pub body: Term,
+}
+
+#[derive(Debug)]
+pub(super) struct Function {
+    // variable the closure is bound to, by which it calls itself if it is
+    // recursive
+    pub name: Ident,
+    pub recursive: bool,
+    pub params: Vec<Ident>,
+    // variables of the enclosing functions used in the body, whose values
+    // are captured in turn when the closure is made
+    pub captured: Vec<Ident>,
+    pub body: Term,
+    // span of the body, where closures are allocated
+    pub span: Span,
+}
+
+// variable named after the source with a stamp unique in the phrase, e.g.
+// `x/3`
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(super) struct Ident {
+    pub name: Rc<str>,
+    pub stamp: usize,
+}
+
+// operand, which takes no evaluation
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum Atom {
+    Var(Ident),
+    Const(Value),   // literal or primitive function
+    Global(String), // value bound by an earlier phrase
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum Prim {
+    Arith(ArithOp),
+    Eq,                 // ==
+    Ne,                 // !=
+    Compare(CompareOp), // =, <>, <, >, <= or >=
+    MakeBlock(Block),
+    Cons,
+    Field(usize), // field of a record, by its declared order
+}
+
+#[derive(Debug, PartialEq)]
+pub(super) enum Term {
+    Atom(Atom),
+    Let(Ident, Box<Term>, Box<Term>),
+    Prim(Prim, Vec<Atom>, Span),
+    // function applied to arguments in turn, each at the span of its
+    // application
+    Apply(Atom, Vec<(Atom, Span)>),
+    // closure of a function lifted, with the values of its captured variables
+    Closure(usize, Vec<Atom>),
+    If(Atom, Box<Term>, Box<Term>),
+    Seq(Box<Term>, Box<Term>),
+}
+
+// Converts a well-typed phrase to the IR. Names not bound locally are
+// global values, or primitives.
+pub(super) fn lower(ast: &Node, bounds: &Bounds) -> Result<Program, Error> {
+    let mut lowerer = Lowerer {
+        globals: &bounds.types,
+        decls: &bounds.decls,
+        stamps: 0,
+        scopes: vec![Scope::default()],
+        functions: Vec::new(),
+    };
+    let (name, body) = match &ast.kind {
+        NodeKind::Bind(bind) => {
+            let (ident, term) = lowerer.bound(bind)?;
+            (Some(ident.name.to_string()), term)
+        }
+        _ => (None, lowerer.term(ast)?),
+    };
+    Ok(Program {
+        name,
+        functions: lowerer.functions,
+        body,
+    })
+}
+
+struct Lowerer<'a> {
+    // types of global values, by which they are known
+    globals: &'a HashMap<String, Type>,
+    decls: &'a TypeDecls,
+    stamps: usize,
+    // functions being lowered, the innermost last
+    scopes: Vec<Scope>,
+    functions: Vec<Function>,
+}
+
+// function being lowered
+#[derive(Default)]
+struct Scope {
+    // variable bound to the closure itself, for `let rec`
+    itself: Option<Ident>,
+    // local names with their variables, the innermost last
+    locals: Vec<(String, Ident)>,
+    captured: Vec<Ident>,
+}
+
+// bindings of operands made before the term using them, in order
+type Binds = Vec<(Ident, Term)>;
+
+impl Lowerer<'_> {
+    fn fresh(&mut self, name: &str) -> Ident {
+        self.stamps += 1;
+        Ident {
+            name: Rc::from(name),
+            stamp: self.stamps,
+        }
+    }
+
+    fn scope(&mut self) -> &mut Scope {
+        self.scopes.last_mut().unwrap()
+    }
+
+    fn term(&mut self, ast: &Node) -> Result<Term, Error> {
+        let mut binds = Binds::new();
+        let term = match &ast.kind {
+            NodeKind::Int(_)
+            | NodeKind::Bool(_)
+            | NodeKind::Unit
+            | NodeKind::Str(_)
+            | NodeKind::Ident(_)
+            | NodeKind::Constr(_, None) => Term::Atom(self.atom(ast, &mut binds)?),
+            NodeKind::List(elems) if elems.is_empty() => Term::Atom(self.atom(ast, &mut binds)?),
+            NodeKind::Array(elems) => self.block(Block::Array, ast, elems.iter(), &mut binds)?,
+            NodeKind::List(elems) => self.block(Block::List, ast, elems.iter(), &mut binds)?,
+            NodeKind::Tuple(elems) => self.block(Block::Tuple, ast, elems.iter(), &mut binds)?,
+            NodeKind::Add(lhs, rhs) => {
+                self.binary(Prim::Arith(ArithOp::Add), ast, lhs, rhs, &mut binds)?
+            }
+            NodeKind::Sub(lhs, rhs) => {
+                self.binary(Prim::Arith(ArithOp::Sub), ast, lhs, rhs, &mut binds)?
+            }
+            NodeKind::Mul(lhs, rhs) => {
+                self.binary(Prim::Arith(ArithOp::Mul), ast, lhs, rhs, &mut binds)?
+            }
+            NodeKind::Div(lhs, rhs) => {
+                self.binary(Prim::Arith(ArithOp::Div), ast, lhs, rhs, &mut binds)?
+            }
+            NodeKind::Eql(lhs, rhs) => self.binary(Prim::Eq, ast, lhs, rhs, &mut binds)?,
+            NodeKind::Neql(lhs, rhs) => self.binary(Prim::Ne, ast, lhs, rhs, &mut binds)?,
+            NodeKind::Compare(op, lhs, rhs) => {
+                self.binary(Prim::Compare(*op), ast, lhs, rhs, &mut binds)?
+            }
+            NodeKind::Cons(head, tail) => self.binary(Prim::Cons, ast, head, tail, &mut binds)?,
+            NodeKind::If(cond, then, other) => {
+                let cond = self.atom(cond, &mut binds)?;
+                let then = self.term(then)?;
+                let other = match other {
+                    Some(other) => self.term(other)?,
+                    None => Term::Atom(Atom::Const(Value::Unit)),
+                };
+                Term::If(cond, Box::new(then), Box::new(other))
+            }
+            NodeKind::App(..) => {
+                // Arguments are collected along the spine, as long as those
+                // evaluated after an application cannot tell it came later.
+                let mut spine = Vec::new();
+                let mut func = ast;
+                while let NodeKind::App(f, arg) = &func.kind {
+                    spine.push((&**arg, func.span));
+                    func = f;
+                }
+                let mut func = self.atom(func, &mut binds)?;
+                let mut args = Vec::new();
+                for (arg, span) in spine.into_iter().rev() {
+                    let mut arg_binds = Binds::new();
+                    let arg = self.atom(arg, &mut arg_binds)?;
+                    if !args.is_empty() && !arg_binds.iter().all(|(_, term)| pure(term)) {
+                        let applied = self.fresh("app");
+                        binds.push((applied.clone(), Term::Apply(func, args)));
+                        (func, args) = (Atom::Var(applied), Vec::new());
+                    }
+                    binds.extend(arg_binds);
+                    args.push((arg, span));
+                }
+                Term::Apply(func, args)
+            }
+            NodeKind::Seq(lhs, rhs) => {
+                Term::Seq(Box::new(self.term(lhs)?), Box::new(self.term(rhs)?))
+            }
+            NodeKind::LocalBind(local_bind) => {
+                let LocalBindStruct { bind, scope } = &**local_bind;
+                let (ident, term) = self.bound(bind)?;
+                let term = float(term, &mut binds);
+                self.scope()
+                    .locals
+                    .push((ident.name.to_string(), ident.clone()));
+                let scope = self.term(scope);
+                self.scope().locals.pop();
+                Term::Let(ident, Box::new(term), Box::new(scope?))
+            }
+            NodeKind::Constraint(expr, _) => self.term(expr)?,
+            NodeKind::Constr(name, Some(arg)) => {
+                let arg = self.atom(arg, &mut binds)?;
+                Term::Prim(
+                    Prim::MakeBlock(Block::Constr(Rc::from(name.as_str()))),
+                    vec![arg],
+                    ast.span,
+                )
+            }
+            NodeKind::Record(fields) => {
+                // Fields are evaluated and stored in the declared order.
+                let names = record_fields(self.decls, &fields[0].0)?;
+                let mut exprs = Vec::new();
+                for name in names.iter() {
+                    match fields.iter().find(|(field, _)| field == name) {
+                        Some((_, expr)) => exprs.push(expr),
+                        None => return Err(Error::Internal("Expected a record field".to_string())),
+                    }
+                }
+                self.block(Block::Record(names), ast, exprs.into_iter(), &mut binds)?
+            }
+            NodeKind::Field(expr, field) => {
+                let index = record_fields(self.decls, field)?
+                    .iter()
+                    .position(|name| name == field);
+                let record = self.atom(expr, &mut binds)?;
+                Term::Prim(
+                    Prim::Field(index.unwrap_or_default()),
+                    vec![record],
+                    ast.span,
+                )
+            }
+            // Global bindings are lowered by lower().
+            NodeKind::Bind(_) => return Err(Error::Internal("Expected an expression".to_string())),
+            NodeKind::TypeDef(_) => {
+                return Err(Error::Internal(
+                    "Found a type definition in an expression".to_string(),
+                ))
+            }
+            NodeKind::Directive(..) => {
+                return Err(Error::Internal(
+                    "Found a directive in an expression".to_string(),
+                ))
+            }
+            NodeKind::External(..) => {
+                return Err(Error::Internal(
+                    "Found an external declaration in an expression".to_string(),
+                ))
+            }
+            NodeKind::Error => {
+                return Err(Error::Internal("Found a node failed to parse".to_string()))
+            }
+        };
+        Ok(binds.into_iter().rev().fold(term, |body, (ident, term)| {
+            Term::Let(ident, Box::new(term), Box::new(body))
+        }))
+    }
+
+    // Lowers an operand, binding it to a variable beforehand unless it is
+    // an atom.
+    fn atom(&mut self, ast: &Node, binds: &mut Binds) -> Result<Atom, Error> {
+        let value = match &ast.kind {
+            NodeKind::Int(i) => Value::Int(*i),
+            NodeKind::Bool(b) => Value::Bool(*b),
+            NodeKind::Unit => Value::Unit,
+            NodeKind::Str(s) => Value::Str(Rc::from(s.as_slice())),
+            NodeKind::List(elems) if elems.is_empty() => Value::List(List::new()),
+            NodeKind::Constr(name, None) => Value::Constr(Rc::from(name.as_str()), None),
+            NodeKind::Ident(name) => return self.ident(name, ast),
+            NodeKind::Constraint(expr, _) => return self.atom(expr, binds),
+            _ => {
+                let term = float(self.term(ast)?, binds);
+                if let Term::Atom(atom) = term {
+                    return Ok(atom);
+                }
+                let ident = self.fresh("tmp");
+                binds.push((ident.clone(), term));
+                return Ok(Atom::Var(ident));
+            }
+        };
+        Ok(Atom::Const(value))
+    }
+
+    // Lowers operands from left to right, and a primitive on them at the
+    // span of `ast`.
+    fn binary(
+        &mut self,
+        prim: Prim,
+        ast: &Node,
+        lhs: &Node,
+        rhs: &Node,
+        binds: &mut Binds,
+    ) -> Result<Term, Error> {
+        let lhs = self.atom(lhs, binds)?;
+        let rhs = self.atom(rhs, binds)?;
+        Ok(Term::Prim(prim, vec![lhs, rhs], ast.span))
+    }
+
+    fn block<'n>(
+        &mut self,
+        block: Block,
+        ast: &Node,
+        elems: impl Iterator<Item = &'n Node>,
+        binds: &mut Binds,
+    ) -> Result<Term, Error> {
+        let mut atoms = Vec::new();
+        for elem in elems {
+            atoms.push(self.atom(elem, binds)?);
+        }
+        Ok(Term::Prim(Prim::MakeBlock(block), atoms, ast.span))
+    }
+
+    // Lowers a bound value, which is a closure of a function lifted if it
+    // takes arguments, with the variable it is bound to.
+    fn bound(&mut self, bind: &BindStruct) -> Result<(Ident, Term), Error> {
+        let BindStruct {
+            recursive,
+            name,
+            args,
+            expr,
+        } = bind;
+        let name = match &name.kind {
+            NodeKind::Ident(ident) => self.fresh(ident),
+            _ => return Err(Error::Internal("Expected a value name".to_string())),
+        };
+        if args.is_empty() {
+            return Ok((name, self.term(expr)?));
+        }
+        let mut params = Vec::new();
+        for arg in args {
+            let param = match &arg.kind {
+                NodeKind::Constraint(name, _) => name,
+                _ => arg,
+            };
+            match &param.kind {
+                NodeKind::Ident(ident) => params.push(self.fresh(ident)),
+                _ => return Err(Error::Internal("Expected a parameter name".to_string())),
+            }
+        }
+        self.scopes.push(Scope {
+            itself: recursive.then(|| name.clone()),
+            locals: params
+                .iter()
+                .map(|param| (param.name.to_string(), param.clone()))
+                .collect(),
+            captured: Vec::new(),
+        });
+        let body = self.term(expr);
+        let scope = self.scopes.pop().unwrap();
+        let captured: Vec<Atom> = scope.captured.iter().cloned().map(Atom::Var).collect();
+        self.functions.push(Function {
+            name: name.clone(),
+            recursive: *recursive,
+            params,
+            captured: scope.captured,
+            body: body?,
+            span: expr.span,
+        });
+        Ok((name, Term::Closure(self.functions.len() - 1, captured)))
+    }
+
+    // Variable of a name, local ones shadowing global values and primitives.
+    fn ident(&mut self, name: &str, ast: &Node) -> Result<Atom, Error> {
+        if let Some(ident) = resolve(&mut self.scopes, name) {
+            return Ok(Atom::Var(ident));
+        }
+        match (self.globals.contains_key(name), prim(name)) {
+            (true, _) => Ok(Atom::Global(name.to_string())),
+            (false, Some(prim)) => Ok(Atom::Const(Value::Fn(Func::Prim(prim, Rc::from([]))))),
+            (false, None) => Err(Error::Type(TypeError {
+                kind: TypeErrorKind::UnboundValue(name.to_string()),
+                span: Some(ast.span),
+            })),
+        }
+    }
+}
+
+// Fields of the record type with a field in the declared order.
+fn record_fields(decls: &TypeDecls, field: &str) -> Result<Rc<[String]>, Error> {
+    match decls.record_of(field).map(|decl| &decl.kind) {
+        Some(TypeDeclKind::Record(fields)) => {
+            Ok(fields.iter().map(|(name, _)| name.clone()).collect())
+        }
+        _ => Err(Error::Internal("Expected a record type".to_string())),
+    }
+}
+
+// Whether a term neither runs effects nor fails, so that it may be evaluated
+// earlier.
+fn pure(term: &Term) -> bool {
+    match term {
+        Term::Atom(_) | Term::Closure(..) => true,
+        Term::Prim(prim, ..) => *prim != Prim::Arith(ArithOp::Div),
+        Term::Let(_, bound, body) => pure(bound) && pure(body),
+        Term::If(_, then, other) => pure(then) && pure(other),
+        Term::Apply(..) | Term::Seq(..) => false,
+    }
+}
+
+// Moves the bindings a term starts with to those made before it, which is
+// safe as variables are unique.
+fn float(mut term: Term, binds: &mut Binds) -> Term {
+    while let Term::Let(ident, bound, body) = term {
+        binds.push((ident, *bound));
+        term = *body;
+    }
+    term
+}
+
+// Finds a local name in the innermost function, capturing it from the
+// enclosing ones if bound there.
+fn resolve(scopes: &mut [Scope], name: &str) -> Option<Ident> {
+    let (scope, outer) = scopes.split_last_mut()?;
+    if let Some((_, ident)) = scope.locals.iter().rev().find(|(local, _)| local == name) {
+        return Some(ident.clone());
+    }
+    if let Some(ident) = scope.itself.iter().find(|ident| &*ident.name == name) {
+        return Some(ident.clone());
+    }
+    if let Some(ident) = scope.captured.iter().find(|ident| &*ident.name == name) {
+        return Some(ident.clone());
+    }
+    let ident = resolve(outer, name)?;
+    scope.captured.push(ident.clone());
+    Some(ident)
+}
+
+impl fmt::Display for Ident {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.name, self.stamp)
+    }
+}
+
+// Laid out as S-expressions like `ocaml -dlambda` does, the functions lifted
+// first. The value of a binding is stored by `setglobal`.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for function in &self.functions {
+            let mut head = vec![
+                Doc::text(format!("(function {}", function.name)),
+                Doc::space(),
+                list(function.params.iter().map(|param| param.to_string())),
+            ];
+            if function.recursive {
+                head.extend([Doc::space(), Doc::text("rec")]);
+            }
+            if !function.captured.is_empty() {
+                let captured = function.captured.iter().map(|ident| ident.to_string());
+                head.extend([Doc::space(), Doc::text("captured"), Doc::space()]);
+                head.push(list(captured));
+            }
+            let doc = Doc::Box(
+                2,
+                vec![
+                    Doc::Box(0, head),
+                    Doc::space(),
+                    self.doc(&function.body),
+                    Doc::text(")"),
+                ],
+            );
+            writeln!(f, "{}", doc.render())?;
+        }
+        let body = self.doc(&self.body);
+        let doc = match &self.name {
+            Some(name) => sexp(&format!("setglobal {}", name), vec![body]),
+            None => body,
+        };
+        write!(f, "{}", doc.render())
+    }
+}
+
+impl Program {
+    fn doc(&self, term: &Term) -> Doc {
+        match term {
+            Term::Atom(atom) => atom_doc(atom),
+            Term::Let(..) => {
+                // Bindings nested in the body are grouped, as in OCaml.
+                let mut binds = Vec::new();
+                let mut body = term;
+                while let Term::Let(ident, term, rest) = body {
+                    if !binds.is_empty() {
+                        binds.push(Doc::space());
+                    }
+                    binds.push(Doc::Box(
+                        1,
+                        vec![
+                            Doc::text(format!("{} =", ident)),
+                            Doc::space(),
+                            self.doc(term),
+                        ],
+                    ));
+                    body = rest;
+                }
+                binds.insert(0, Doc::text("("));
+                binds.push(Doc::text(")"));
+                sexp("let", vec![Doc::Box(0, binds), self.doc(body)])
+            }
+            Term::Prim(prim, args, _) => {
+                let (head, mut docs) = match prim {
+                    Prim::Arith(op) => (
+                        match op {
+                            ArithOp::Add => "+",
+                            ArithOp::Sub => "-",
+                            ArithOp::Mul => "*",
+                            ArithOp::Div => "/",
+                        }
+                        .to_string(),
+                        Vec::new(),
+                    ),
+                    Prim::Eq => ("==".to_string(), Vec::new()),
+                    Prim::Ne => ("!=".to_string(), Vec::new()),
+                    Prim::Compare(op) => (
+                        match op {
+                            CompareOp::Eq => "=",
+                            CompareOp::Ne => "<>",
+                            CompareOp::Lt => "<",
+                            CompareOp::Gt => ">",
+                            CompareOp::Le => "<=",
+                            CompareOp::Ge => ">=",
+                        }
+                        .to_string(),
+                        Vec::new(),
+                    ),
+                    Prim::MakeBlock(Block::Constr(name)) => (name.to_string(), Vec::new()),
+                    Prim::MakeBlock(Block::Record(names)) => (
+                        "record".to_string(),
+                        vec![Doc::text(format!("{{{}}}", names.join("; ")))],
+                    ),
+                    Prim::MakeBlock(block) => (format!("{:?}", block).to_lowercase(), Vec::new()),
+                    Prim::Cons => ("::".to_string(), Vec::new()),
+                    Prim::Field(index) => ("field".to_string(), vec![Doc::text(index.to_string())]),
+                };
+                docs.extend(args.iter().map(atom_doc));
+                sexp(&head, docs)
+            }
+            Term::Apply(func, args) => {
+                let args = args.iter().map(|(arg, _)| arg);
+                let docs = [func].into_iter().chain(args).map(atom_doc).collect();
+                sexp("apply", docs)
+            }
+            Term::Closure(index, captured) => {
+                let name = Doc::text(self.functions[*index].name.to_string());
+                let docs = [name]
+                    .into_iter()
+                    .chain(captured.iter().map(atom_doc))
+                    .collect();
+                sexp("closure", docs)
+            }
+            Term::If(cond, then, other) => {
+                sexp("if", vec![atom_doc(cond), self.doc(then), self.doc(other)])
+            }
+            Term::Seq(lhs, rhs) => sexp("seq", vec![self.doc(lhs), self.doc(rhs)]),
+        }
+    }
+}
+
+// `(head doc ...)`, broken after the head if it does not fit.
+fn sexp(head: &str, docs: Vec<Doc>) -> Doc {
+    let mut sexp = vec![Doc::text(format!("({}", head))];
+    for doc in docs {
+        sexp.push(Doc::space());
+        sexp.push(doc);
+    }
+    sexp.push(Doc::text(")"));
+    Doc::Box(2, sexp)
+}
+
+fn list(items: impl Iterator<Item = String>) -> Doc {
+    Doc::text(format!("({})", items.collect::<Vec<_>>().join(" ")))
+}
+
+fn atom_doc(atom: &Atom) -> Doc {
+    Doc::text(match atom {
+        Atom::Var(ident) => ident.to_string(),
+        Atom::Const(value) => show(value),
+        Atom::Global(name) => format!("(global {})", name),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::lexer::tokenize;
+    use crate::eval::parser::parse;
+
+    fn lowered(input: &str) -> String {
+        let ast = parse(&tokenize(input).unwrap()).unwrap();
+        let mut bounds = Bounds::new();
+        bounds.bind_type("g".to_string(), Type::Int);
+        bounds.bind_type("add".to_string(), Type::Int);
+        lower(&ast, &bounds).unwrap().to_string()
+    }
+
+    #[test]
+    fn lower_let_bound_primitives() {
+        assert_eq!(
+            lowered("1 + g * 2 :: []"),
+            "(let (tmp/1 = (* (global g) 2) tmp/2 = (+ 1 tmp/1)) (:: tmp/2 []))"
+        );
+        assert_eq!(
+            lowered("let x = 1 in let x = x + 1 in (x, print_int x)"),
+            "(let (x/1 = 1 x/2 = (+ x/1 1) tmp/3 = (apply <prim print_int> x/2))\n  \
+             (tuple x/2 tmp/3))"
+        );
+    }
+
+    #[test]
+    fn lower_closures_capturing_variables() {
+        // Variables captured by inner functions are captured by those
+        // enclosing them in turn.
+        assert_eq!(
+            lowered("let f x y = let g z = let h w = x + w in h z in g y"),
+            "(function h/6 (w/7) captured (x/2) (+ x/2 w/7))\n\
+             (function g/4 (z/5) captured (x/2)\n  \
+             (let (h/6 = (closure h/6 x/2)) (apply h/6 z/5)))\n\
+             (function f/1 (x/2 y/3) (let (g/4 = (closure g/4 x/2)) (apply g/4 y/3)))\n\
+             (setglobal f (closure f/1))"
+        );
+        assert_eq!(
+            lowered("let rec loop n = if n = 0 then g else loop (n - 1)"),
+            "(function loop/1 (n/2) rec\n  \
+             (let (tmp/3 = (= n/2 0))\n    \
+             (if tmp/3 (global g) (let (tmp/4 = (- n/2 1)) (apply loop/1 tmp/4)))))\n\
+             (setglobal loop (closure loop/1))"
+        );
+    }
+
+    #[test]
+    fn lower_applications_in_order() {
+        assert_eq!(lowered("add g 1 2"), "(apply (global add) (global g) 1 2)");
+        assert_eq!(
+            lowered("add 1 (2 + g)"),
+            "(let (tmp/1 = (+ 2 (global g))) (apply (global add) 1 tmp/1))"
+        );
+        // Applying `add 1` comes before the argument which may print.
+        assert_eq!(
+            lowered("add 1 (print_int 2; 3)"),
+            "(let\n  \
+             (app/2 = (apply (global add) 1) tmp/1 = (seq (apply <prim print_int> 2) 3))\n  \
+             (apply app/2 tmp/1))"
+        );
+    }
+}
@@ -1,23 +1,90 @@
+use super::span::{LineIndex, Span};
+use crate::error::{LexError, LexErrorKind};
+use std::fmt;
+
+#[derive(Debug)]
+pub(super) struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+// Spans are not compared, so that tokens are compared by their kinds only.
+impl PartialEq for Token {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
 #[derive(Debug, PartialEq)]
-pub(super) enum Token {
+pub(super) enum TokenKind {
     Int(i64),      // integer
+    Str(Vec<u8>),  // string literal, which is a sequence of bytes
     Punct(String), // punctuator
     Kw(KwKind),    // keyword
     Ident(String), // identifier
 }
 
-#[derive(Debug, PartialEq)]
+// Formats a token as it appears in the source.
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::Int(int) => write!(f, "{}", int),
+            TokenKind::Str(s) => write!(f, "\"{}\"", escape(s)),
+            TokenKind::Punct(p) => write!(f, "{}", p),
+            TokenKind::Kw(kind) => {
+                let (kw, _) = KEYWORDS.iter().find(|(_, k)| k == kind).unwrap();
+                write!(f, "{}", kw)
+            }
+            TokenKind::Ident(ident) => write!(f, "{}", ident),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub(super) enum KwKind {
-    Let,   // let
-    In,    // in
-    True,  // true
-    False, // false
+    Let,      // let
+    In,       // in
+    True,     // true
+    False,    // false
+    Type,     // type
+    And,      // and
+    Of,       // of
+    External, // external
+    Rec,      // rec
+    If,       // if
+    Then,     // then
+    Else,     // else
 }
 
-pub(super) fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+pub(super) const KEYWORDS: [(&str, KwKind); 12] = [
+    ("let", KwKind::Let),
+    ("in", KwKind::In),
+    ("true", KwKind::True),
+    ("false", KwKind::False),
+    ("type", KwKind::Type),
+    ("and", KwKind::And),
+    ("of", KwKind::Of),
+    ("external", KwKind::External),
+    ("rec", KwKind::Rec),
+    ("if", KwKind::If),
+    ("then", KwKind::Then),
+    ("else", KwKind::Else),
+];
+
+pub(super) fn tokenize(input: &str) -> Result<Vec<Token>, LexError> {
+    let index = LineIndex::new(input);
+    let span = |start: &str, end: &str| {
+        Span::new(
+            index.pos(input.len() - start.len()),
+            index.pos(input.len() - end.len()),
+        )
+    };
+
     let mut tokens: Vec<Token> = vec![];
     let mut rest = input;
     'outer: while let Some(c) = rest.chars().next() {
+        let start = rest;
+
         // whitespaces
         if c.is_ascii_whitespace() {
             // skip white spaces
@@ -28,25 +95,63 @@ pub(super) fn tokenize(input: &str) -> Result<Vec<Token>, String> {
         // integers
         if c.is_ascii_digit() {
             let int;
-            (int, rest) = take_integer_from(rest)?;
-            tokens.push(Token::Int(int));
+            (int, rest) = take_integer_from(rest).map_err(|kind| LexError {
+                kind,
+                span: span(start, skip_digits(start)),
+            })?;
+            tokens.push(Token {
+                kind: TokenKind::Int(int),
+                span: span(start, rest),
+            });
+            continue;
+        }
+
+        // strings
+        if c == '"' {
+            let s;
+            (s, rest) = take_string_from(&rest[1..]).map_err(|(kind, end)| LexError {
+                kind,
+                span: span(start, end),
+            })?;
+            tokens.push(Token {
+                kind: TokenKind::Str(s),
+                span: span(start, rest),
+            });
+            continue;
+        }
+
+        // comments, which nest
+        if rest.starts_with("(*") {
+            rest = skip_comment(&rest[2..]).ok_or_else(|| LexError {
+                kind: LexErrorKind::UnterminatedComment,
+                span: span(start, &start[2..]),
+            })?;
             continue;
         }
 
         // punctuators
         if c.is_ascii_punctuation() {
-            for p in &["==", "!="] {
+            for p in &[
+                "==", "!=", "<>", "<=", ">=", ";;", "->", "::", ":=", "[|", "|]",
+            ] {
                 if rest.starts_with(p) {
-                    tokens.push(Token::Punct(p.to_string()));
                     rest = &rest[p.len()..];
+                    tokens.push(Token {
+                        kind: TokenKind::Punct(p.to_string()),
+                        span: span(start, rest),
+                    });
                     continue 'outer;
                 }
             }
 
             match c {
-                '+' | '-' | '*' | '/' | '=' | '[' | ']' | ';' | '(' | ')' => {
-                    tokens.push(Token::Punct(c.to_string()));
+                '+' | '-' | '*' | '/' | '=' | '[' | ']' | ';' | '(' | ')' | ':' | ',' | '\''
+                | '|' | '{' | '}' | '.' | '!' | '#' | '<' | '>' => {
                     rest = &rest[1..];
+                    tokens.push(Token {
+                        kind: TokenKind::Punct(c.to_string()),
+                        span: span(start, rest),
+                    });
                     continue;
                 }
                 _ => (),
@@ -55,24 +160,32 @@ pub(super) fn tokenize(input: &str) -> Result<Vec<Token>, String> {
 
         // identifiers and keywords
         if let Ok((ident, r)) = take_identifier_from(rest) {
-            let tok = match &*ident {
-                "let" => Token::Kw(KwKind::Let),
-                "in" => Token::Kw(KwKind::In),
-                "true" => Token::Kw(KwKind::True),
-                "false" => Token::Kw(KwKind::False),
-                _ => Token::Ident(ident),
+            let kind = match KEYWORDS.iter().find(|(kw, _)| *kw == ident) {
+                Some((_, kind)) => TokenKind::Kw(*kind),
+                None => TokenKind::Ident(ident),
             };
-            tokens.push(tok);
             rest = r;
+            tokens.push(Token {
+                kind,
+                span: span(start, rest),
+            });
             continue;
         }
 
-        return Err(format!(r#"Failed to tokenize "{}""#, rest));
+        return Err(LexError {
+            kind: LexErrorKind::IllegalCharacter(c),
+            span: span(start, &rest[c.len_utf8()..]),
+        });
     }
     Ok(tokens)
 }
 
-fn take_integer_from(s: &str) -> Result<(i64, &str), String> {
+// Skips digits and underscores following them, e.g. `10_000`.
+fn skip_digits(s: &str) -> &str {
+    s.trim_start_matches(|c: char| c.is_ascii_digit() || c == '_')
+}
+
+fn take_integer_from(s: &str) -> Result<(i64, &str), LexErrorKind> {
     let mut rest = s;
     let mut int_str = "".to_string();
     loop {
@@ -81,48 +194,129 @@ fn take_integer_from(s: &str) -> Result<(i64, &str), String> {
                 int_str.push(c);
                 rest = &rest[1..];
             }
+            // Underscores separate digits, e.g. `10_000`.
+            Some('_') => rest = &rest[1..],
             _ => break,
         }
     }
     match int_str.parse() {
         Ok(int) => Ok((int, rest)),
-        Err(_) => Err(format!(r#"Failed to parse "{}" into i64"#, s)),
+        Err(_) => Err(LexErrorKind::IntegerOutOfRange(int_str)),
     }
 }
 
-fn take_identifier_from(s: &str) -> Result<(String, &str), String> {
+// Takes a string literal after the opening quote, with escape sequences
+// replaced. On errors, returns the rest of input following the erroneous
+// part.
+fn take_string_from(s: &str) -> Result<(Vec<u8>, &str), (LexErrorKind, &str)> {
+    let mut string = Vec::new();
     let mut rest = s;
-    let mut ident: Option<String> = None;
     loop {
-        match rest.chars().next() {
-            Some(c) if c.is_ascii_alphabetic() => {
-                ident = match ident {
-                    Some(mut ident) => {
-                        ident.push(c);
-                        Some(ident)
+        let c = match rest.chars().next() {
+            Some(c) => c,
+            None => return Err((LexErrorKind::UnterminatedString, rest)),
+        };
+        rest = &rest[c.len_utf8()..];
+        match c {
+            '"' => return Ok((string, rest)),
+            '\\' => {
+                let (escaped, len) = match rest.as_bytes() {
+                    [b'n', ..] => (b'\n', 1),
+                    [b't', ..] => (b'\t', 1),
+                    [b'r', ..] => (b'\r', 1),
+                    [b'b', ..] => (8, 1),
+                    [c @ (b' ' | b'\\' | b'"' | b'\''), ..] => (*c, 1),
+                    // decimal code \ddd
+                    [d1, d2, d3, ..] if [d1, d2, d3].iter().all(|d| d.is_ascii_digit()) => {
+                        match rest[..3].parse::<u8>() {
+                            Ok(code) => (code, 3),
+                            Err(_) => {
+                                let seq = format!("\\{}", &rest[..3]);
+                                return Err((LexErrorKind::IllegalEscape(seq), &rest[3..]));
+                            }
+                        }
+                    }
+                    // hexadecimal code \xhh
+                    [b'x', h1, h2, ..] if h1.is_ascii_hexdigit() && h2.is_ascii_hexdigit() => {
+                        (u8::from_str_radix(&rest[1..3], 16).unwrap(), 3)
+                    }
+                    _ => {
+                        let len = rest.chars().next().map_or(0, char::len_utf8);
+                        let seq = format!("\\{}", &rest[..len]);
+                        return Err((LexErrorKind::IllegalEscape(seq), &rest[len..]));
                     }
-                    None => Some(c.to_string()),
                 };
-                rest = &rest[1..];
+                string.push(escaped);
+                rest = &rest[len..];
             }
-            _ => break,
+            c => string.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+}
+
+// Skips a comment after the opening "(*", returning the rest of input
+// following its closing "*)", or none if it is not terminated. Comments
+// nested in it are skipped, as are string literals, which may contain "*)".
+fn skip_comment(s: &str) -> Option<&str> {
+    let mut depth = 1;
+    let mut rest = s;
+    while depth > 0 {
+        if let Some(r) = rest.strip_prefix("(*") {
+            (depth, rest) = (depth + 1, r);
+        } else if let Some(r) = rest.strip_prefix("*)") {
+            (depth, rest) = (depth - 1, r);
+        } else if let Some(r) = rest.strip_prefix('"') {
+            (_, rest) = take_string_from(r).ok()?;
+        } else {
+            let c = rest.chars().next()?;
+            rest = &rest[c.len_utf8()..];
         }
     }
-    match ident {
-        Some(ident) => Ok((ident, rest)),
-        None => Err("Failed to take an identifier".to_string()),
+    Some(rest)
+}
+
+// Escapes a string as in an OCaml string literal, e.g. `a"b` as `a\"b`.
+// Bytes other than printable ASCII characters are written in decimal.
+pub(crate) fn escape(s: &[u8]) -> String {
+    let mut escaped = String::new();
+    for &byte in s {
+        match byte {
+            b'"' => escaped.push_str("\\\""),
+            b'\\' => escaped.push_str("\\\\"),
+            b'\n' => escaped.push_str("\\n"),
+            b'\t' => escaped.push_str("\\t"),
+            b'\r' => escaped.push_str("\\r"),
+            8 => escaped.push_str("\\b"),
+            b' '..=b'~' => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\{:03}", byte)),
+        }
     }
+    escaped
+}
+
+// Takes an identifier, which begins with a letter or an underscore followed
+// by letters, digits, underscores or single quotes.
+fn take_identifier_from(s: &str) -> Result<(String, &str), String> {
+    if !s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        return Err("Failed to take an identifier".to_string());
+    }
+    let rest = s.trim_start_matches(|c: char| c.is_ascii_alphanumeric() || c == '_' || c == '\'');
+    Ok((s[..s.len() - rest.len()].to_string(), rest))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn kinds(tokens: Vec<Token>) -> Vec<TokenKind> {
+        tokens.into_iter().map(|tok| tok.kind).collect()
+    }
+
     #[test]
     fn tokenizes_integer() {
         let input = "123";
-        let expected = vec![Token::Int(123)];
-        let actual = tokenize(input).unwrap();
+        let expected = vec![TokenKind::Int(123)];
+        let actual = kinds(tokenize(input).unwrap());
         assert_eq!(expected, actual);
     }
 
@@ -130,27 +324,27 @@ mod tests {
     fn tokenizes_arithmetic_expr() {
         let input = "2+3*4+5-6/2+(3-1)*2";
         let expected = vec![
-            Token::Int(2),
-            Token::Punct("+".to_string()),
-            Token::Int(3),
-            Token::Punct("*".to_string()),
-            Token::Int(4),
-            Token::Punct("+".to_string()),
-            Token::Int(5),
-            Token::Punct("-".to_string()),
-            Token::Int(6),
-            Token::Punct("/".to_string()),
-            Token::Int(2),
-            Token::Punct("+".to_string()),
-            Token::Punct("(".to_string()),
-            Token::Int(3),
-            Token::Punct("-".to_string()),
-            Token::Int(1),
-            Token::Punct(")".to_string()),
-            Token::Punct("*".to_string()),
-            Token::Int(2),
+            TokenKind::Int(2),
+            TokenKind::Punct("+".to_string()),
+            TokenKind::Int(3),
+            TokenKind::Punct("*".to_string()),
+            TokenKind::Int(4),
+            TokenKind::Punct("+".to_string()),
+            TokenKind::Int(5),
+            TokenKind::Punct("-".to_string()),
+            TokenKind::Int(6),
+            TokenKind::Punct("/".to_string()),
+            TokenKind::Int(2),
+            TokenKind::Punct("+".to_string()),
+            TokenKind::Punct("(".to_string()),
+            TokenKind::Int(3),
+            TokenKind::Punct("-".to_string()),
+            TokenKind::Int(1),
+            TokenKind::Punct(")".to_string()),
+            TokenKind::Punct("*".to_string()),
+            TokenKind::Int(2),
         ];
-        let actual = tokenize(input).unwrap();
+        let actual = kinds(tokenize(input).unwrap());
         assert_eq!(expected, actual);
     }
 
@@ -158,12 +352,12 @@ mod tests {
     fn tokenizes_global_binding() {
         let input = "let foo = 123";
         let expected = vec![
-            Token::Kw(KwKind::Let),
-            Token::Ident("foo".to_string()),
-            Token::Punct("=".to_string()),
-            Token::Int(123),
+            TokenKind::Kw(KwKind::Let),
+            TokenKind::Ident("foo".to_string()),
+            TokenKind::Punct("=".to_string()),
+            TokenKind::Int(123),
         ];
-        let actual = tokenize(input).unwrap();
+        let actual = kinds(tokenize(input).unwrap());
         assert_eq!(expected, actual);
     }
 
@@ -171,22 +365,25 @@ mod tests {
     fn tokenizes_local_binding() {
         let input = "let x = 5 in x";
         let expected = vec![
-            Token::Kw(KwKind::Let),
-            Token::Ident("x".to_string()),
-            Token::Punct("=".to_string()),
-            Token::Int(5),
-            Token::Kw(KwKind::In),
-            Token::Ident("x".to_string()),
+            TokenKind::Kw(KwKind::Let),
+            TokenKind::Ident("x".to_string()),
+            TokenKind::Punct("=".to_string()),
+            TokenKind::Int(5),
+            TokenKind::Kw(KwKind::In),
+            TokenKind::Ident("x".to_string()),
         ];
-        let actual = tokenize(input).unwrap();
+        let actual = kinds(tokenize(input).unwrap());
         assert_eq!(expected, actual);
     }
 
     #[test]
     fn tokenizes_empty_list() {
         let input = "[]";
-        let expected = vec![Token::Punct("[".to_string()), Token::Punct("]".to_string())];
-        let actual = tokenize(input).unwrap();
+        let expected = vec![
+            TokenKind::Punct("[".to_string()),
+            TokenKind::Punct("]".to_string()),
+        ];
+        let actual = kinds(tokenize(input).unwrap());
         assert_eq!(expected, actual);
     }
 
@@ -194,47 +391,105 @@ mod tests {
     fn tokenizes_list() {
         let input = "[1; 2; 3]";
         let expected = vec![
-            Token::Punct("[".to_string()),
-            Token::Int(1),
-            Token::Punct(";".to_string()),
-            Token::Int(2),
-            Token::Punct(";".to_string()),
-            Token::Int(3),
-            Token::Punct("]".to_string()),
+            TokenKind::Punct("[".to_string()),
+            TokenKind::Int(1),
+            TokenKind::Punct(";".to_string()),
+            TokenKind::Int(2),
+            TokenKind::Punct(";".to_string()),
+            TokenKind::Int(3),
+            TokenKind::Punct("]".to_string()),
         ];
-        let actual = tokenize(input).unwrap();
+        let actual = kinds(tokenize(input).unwrap());
         assert_eq!(expected, actual);
     }
 
     #[test]
     fn tokenizes_true() {
         let input = "true";
-        let expected = vec![Token::Kw(KwKind::True)];
-        let actual = tokenize(input).unwrap();
+        let expected = vec![TokenKind::Kw(KwKind::True)];
+        let actual = kinds(tokenize(input).unwrap());
         assert_eq!(expected, actual);
     }
 
     #[test]
     fn tokenizes_false() {
         let input = "false";
-        let expected = vec![Token::Kw(KwKind::False)];
-        let actual = tokenize(input).unwrap();
+        let expected = vec![TokenKind::Kw(KwKind::False)];
+        let actual = kinds(tokenize(input).unwrap());
         assert_eq!(expected, actual);
     }
 
     #[test]
     fn tokenizes_equal() {
         let input = "2 == 3";
-        let expected = vec![Token::Int(2), Token::Punct("==".to_string()), Token::Int(3)];
-        let actual = tokenize(input).unwrap();
+        let expected = vec![
+            TokenKind::Int(2),
+            TokenKind::Punct("==".to_string()),
+            TokenKind::Int(3),
+        ];
+        let actual = kinds(tokenize(input).unwrap());
         assert_eq!(expected, actual);
     }
 
     #[test]
     fn tokenizes_not_equal() {
         let input = "2 != 3";
-        let expected = vec![Token::Int(2), Token::Punct("!=".to_string()), Token::Int(3)];
-        let actual = tokenize(input).unwrap();
+        let expected = vec![
+            TokenKind::Int(2),
+            TokenKind::Punct("!=".to_string()),
+            TokenKind::Int(3),
+        ];
+        let actual = kinds(tokenize(input).unwrap());
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn tokenizes_cons() {
+        let input = "x::xs : t";
+        let expected = vec![
+            TokenKind::Ident("x".to_string()),
+            TokenKind::Punct("::".to_string()),
+            TokenKind::Ident("xs".to_string()),
+            TokenKind::Punct(":".to_string()),
+            TokenKind::Ident("t".to_string()),
+        ];
+        let actual = kinds(tokenize(input).unwrap());
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn tokenizes_comparisons() {
+        let input = "a = b <> c < d > e <= f >= g";
+        let expected: Vec<_> = [
+            "a", "=", "b", "<>", "c", "<", "d", ">", "e", "<=", "f", ">=", "g",
+        ]
+        .iter()
+        .map(|s| match s.chars().next() {
+            Some(c) if c.is_ascii_alphabetic() => TokenKind::Ident(s.to_string()),
+            _ => TokenKind::Punct(s.to_string()),
+        })
+        .collect();
+        let actual = kinds(tokenize(input).unwrap());
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn tokenizes_conditionals() {
+        let input = "let rec f n = if n then 1_000_000 else 0";
+        let expected = vec![
+            TokenKind::Kw(KwKind::Let),
+            TokenKind::Kw(KwKind::Rec),
+            TokenKind::Ident("f".to_string()),
+            TokenKind::Ident("n".to_string()),
+            TokenKind::Punct("=".to_string()),
+            TokenKind::Kw(KwKind::If),
+            TokenKind::Ident("n".to_string()),
+            TokenKind::Kw(KwKind::Then),
+            TokenKind::Int(1_000_000),
+            TokenKind::Kw(KwKind::Else),
+            TokenKind::Int(0),
+        ];
+        let actual = kinds(tokenize(input).unwrap());
         assert_eq!(expected, actual);
     }
 
@@ -242,15 +497,204 @@ mod tests {
     fn tokenizes_func_definition() {
         let input = "let square x = x * x";
         let expected = vec![
-            Token::Kw(KwKind::Let),
-            Token::Ident("square".to_string()),
-            Token::Ident("x".to_string()),
-            Token::Punct("=".to_string()),
-            Token::Ident("x".to_string()),
-            Token::Punct("*".to_string()),
-            Token::Ident("x".to_string()),
+            TokenKind::Kw(KwKind::Let),
+            TokenKind::Ident("square".to_string()),
+            TokenKind::Ident("x".to_string()),
+            TokenKind::Punct("=".to_string()),
+            TokenKind::Ident("x".to_string()),
+            TokenKind::Punct("*".to_string()),
+            TokenKind::Ident("x".to_string()),
+        ];
+        let actual = kinds(tokenize(input).unwrap());
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn tokenizes_type_annotation() {
+        let input = "(x : 'a -> int * int)";
+        let expected = vec![
+            TokenKind::Punct("(".to_string()),
+            TokenKind::Ident("x".to_string()),
+            TokenKind::Punct(":".to_string()),
+            TokenKind::Punct("'".to_string()),
+            TokenKind::Ident("a".to_string()),
+            TokenKind::Punct("->".to_string()),
+            TokenKind::Ident("int".to_string()),
+            TokenKind::Punct("*".to_string()),
+            TokenKind::Ident("int".to_string()),
+            TokenKind::Punct(")".to_string()),
+        ];
+        let actual = kinds(tokenize(input).unwrap());
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn tokenizes_tuple() {
+        let input = "1, 2";
+        let expected = vec![
+            TokenKind::Int(1),
+            TokenKind::Punct(",".to_string()),
+            TokenKind::Int(2),
+        ];
+        let actual = kinds(tokenize(input).unwrap());
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn tokenizes_type_definition() {
+        let input = "type 'a t = A | B' of int_list2 and u = { x : t }";
+        let expected = vec![
+            TokenKind::Kw(KwKind::Type),
+            TokenKind::Punct("'".to_string()),
+            TokenKind::Ident("a".to_string()),
+            TokenKind::Ident("t".to_string()),
+            TokenKind::Punct("=".to_string()),
+            TokenKind::Ident("A".to_string()),
+            TokenKind::Punct("|".to_string()),
+            TokenKind::Ident("B'".to_string()),
+            TokenKind::Kw(KwKind::Of),
+            TokenKind::Ident("int_list2".to_string()),
+            TokenKind::Kw(KwKind::And),
+            TokenKind::Ident("u".to_string()),
+            TokenKind::Punct("=".to_string()),
+            TokenKind::Punct("{".to_string()),
+            TokenKind::Ident("x".to_string()),
+            TokenKind::Punct(":".to_string()),
+            TokenKind::Ident("t".to_string()),
+            TokenKind::Punct("}".to_string()),
+        ];
+        let actual = kinds(tokenize(input).unwrap());
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn tokenizes_identifiers_with_underscores() {
+        let input = "_ _x a_b'";
+        let expected = vec![
+            TokenKind::Ident("_".to_string()),
+            TokenKind::Ident("_x".to_string()),
+            TokenKind::Ident("a_b'".to_string()),
+        ];
+        let actual = kinds(tokenize(input).unwrap());
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn tokenizes_references() {
+        let input = "r := !r != ()";
+        let expected = vec![
+            TokenKind::Ident("r".to_string()),
+            TokenKind::Punct(":=".to_string()),
+            TokenKind::Punct("!".to_string()),
+            TokenKind::Ident("r".to_string()),
+            TokenKind::Punct("!=".to_string()),
+            TokenKind::Punct("(".to_string()),
+            TokenKind::Punct(")".to_string()),
+        ];
+        let actual = kinds(tokenize(input).unwrap());
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn tokenizes_directive() {
+        let input = "#print_depth 10";
+        let expected = vec![
+            TokenKind::Punct("#".to_string()),
+            TokenKind::Ident("print_depth".to_string()),
+            TokenKind::Int(10),
         ];
-        let actual = tokenize(input).unwrap();
+        let actual = kinds(tokenize(input).unwrap());
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn tokenizes_strings() {
+        let input = r#"["a\"b"; "\t\\\065\x42"; ""]"#;
+        let expected = vec![
+            TokenKind::Punct("[".to_string()),
+            TokenKind::Str(b"a\"b".to_vec()),
+            TokenKind::Punct(";".to_string()),
+            TokenKind::Str(b"\t\\AB".to_vec()),
+            TokenKind::Punct(";".to_string()),
+            TokenKind::Str(Vec::new()),
+            TokenKind::Punct("]".to_string()),
+        ];
+        let actual = kinds(tokenize(input).unwrap());
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn tokenizes_array() {
+        let input = "[|1; 2|]";
+        let expected = vec![
+            TokenKind::Punct("[|".to_string()),
+            TokenKind::Int(1),
+            TokenKind::Punct(";".to_string()),
+            TokenKind::Int(2),
+            TokenKind::Punct("|]".to_string()),
+        ];
+        let actual = kinds(tokenize(input).unwrap());
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn escape_strings() {
+        assert_eq!(escape(b"a\"b\\c\n"), r#"a\"b\\c\n"#);
+        assert_eq!(escape("é\x07".as_bytes()), r#"\195\169\007"#);
+    }
+
+    #[test]
+    fn error_on_strings() {
+        let err = tokenize(r#"1 + "abc"#).unwrap_err();
+        assert_eq!(err.kind, LexErrorKind::UnterminatedString);
+        assert_eq!((err.span.start.offset, err.span.end.offset), (4, 8));
+
+        let err = tokenize(r#""a\qb""#).unwrap_err();
+        assert_eq!(err.kind, LexErrorKind::IllegalEscape("\\q".to_string()));
+        assert_eq!((err.span.start.offset, err.span.end.offset), (0, 4));
+    }
+
+    #[test]
+    fn skips_nested_comments() {
+        let input = "1 (* a (* b *) \"*)\" *) + (**) 2 (*)*)";
+        let expected = vec![
+            TokenKind::Int(1),
+            TokenKind::Punct("+".to_string()),
+            TokenKind::Int(2),
+        ];
+        assert_eq!(expected, kinds(tokenize(input).unwrap()));
+
+        let err = tokenize("1 (* a (* b *)").unwrap_err();
+        assert_eq!(err.kind, LexErrorKind::UnterminatedComment);
+        assert_eq!((err.span.start.offset, err.span.end.offset), (2, 4));
+        let err = tokenize("(* \"*) *)").unwrap_err();
+        assert_eq!(err.kind, LexErrorKind::UnterminatedComment);
+    }
+
+    #[test]
+    fn tokenizes_with_spans() {
+        let input = "let x =\n  42";
+        let spans: Vec<(usize, usize, usize, usize)> = tokenize(input)
+            .unwrap()
+            .iter()
+            .map(|tok| {
+                (
+                    tok.span.start.offset,
+                    tok.span.end.offset,
+                    tok.span.start.line,
+                    tok.span.start.column,
+                )
+            })
+            .collect();
+        assert_eq!(
+            spans,
+            vec![(0, 3, 1, 0), (4, 5, 1, 4), (6, 7, 1, 6), (10, 12, 2, 2)]
+        );
+    }
+
+    #[test]
+    fn error_on_unknown_character_with_span() {
+        let err = tokenize("1 + @").unwrap_err();
+        assert_eq!((err.span.start.offset, err.span.end.offset), (4, 5));
+    }
 }
@@ -0,0 +1,170 @@
+use super::span::Span;
+use super::value::{Func, Value};
+use crate::error::{Error, Exception, ExceptionKind};
+use std::cell::Cell;
+use std::hint::black_box;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Limits on resources used to evaluate a phrase, so that untrusted phrases
+/// cannot exhaust the host. `None` is unlimited. Regardless of the limits,
+/// `Stack_overflow` is raised before the Rust stack runs out.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EvalLimits {
+    /// evaluation steps, i.e. nodes evaluated, or instructions run by the
+    /// bytecode machine
+    pub fuel: Option<u64>,
+    /// levels of nested evaluation, beyond which `Stack_overflow` is raised
+    pub max_depth: Option<usize>,
+    /// words of values allocated, beyond which `Out_of_memory` is raised
+    pub max_alloc: Option<usize>,
+}
+
+// stack left for a step of evaluation, printing values and primitives
+const RED_ZONE: usize = 256 * 1024;
+
+// stack on which a phrase is evaluated, whatever the stack of the thread
+// evaluating it, so that calls not in tail position can nest about as deep
+// as the bytecode machine's
+pub(super) const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+// resources used by the phrase being evaluated in a session
+#[derive(Debug, Default)]
+pub(crate) struct Meter {
+    limits: Cell<EvalLimits>,
+    steps: Cell<u64>,
+    depth: Cell<usize>,
+    words: Cell<usize>,
+    // lowest address the stack may grow to, or 0 if unknown
+    stack_limit: Cell<usize>,
+    // flag set to interrupt evaluation, e.g. on Ctrl-C
+    interrupt: Cell<Option<&'static AtomicBool>>,
+}
+
+// A copy of a meter, e.g. of a session cloned, has the same limits and
+// interrupt flag, which are set apart from the original's from then on.
+impl Clone for Meter {
+    fn clone(&self) -> Self {
+        Self {
+            limits: self.limits.clone(),
+            interrupt: self.interrupt.clone(),
+            ..Self::default()
+        }
+    }
+}
+
+// Meters are compared by their limits, as the usage is of the last phrase.
+impl PartialEq for Meter {
+    fn eq(&self, other: &Self) -> bool {
+        self.limits == other.limits
+    }
+}
+
+impl Meter {
+    pub fn limits(&self) -> EvalLimits {
+        self.limits.get()
+    }
+
+    pub fn set_limits(&self, limits: EvalLimits) {
+        self.limits.set(limits);
+    }
+
+    pub fn set_interrupt(&self, flag: &'static AtomicBool) {
+        self.interrupt.set(Some(flag));
+    }
+
+    // Starts metering a phrase.
+    pub fn reset(&self) {
+        self.steps.set(0);
+        self.depth.set(0);
+        self.words.set(0);
+        self.stack_limit.set(stack_limit());
+    }
+
+    // Counts a step into a node at `span`, to be left by `leave()` unless
+    // failed.
+    pub fn enter(&self, span: Span) -> Result<(), Error> {
+        self.step(span)?;
+        let depth = self.depth.get() + 1;
+        if self.limits.get().max_depth.is_some_and(|max| depth > max)
+            || stack_pointer() < self.stack_limit.get()
+        {
+            return Err(exception(ExceptionKind::StackOverflow, span));
+        }
+        self.depth.set(depth);
+        Ok(())
+    }
+
+    // Counts a step at `span` which is not nested, e.g. an instruction of
+    // the bytecode machine.
+    pub fn step(&self, span: Span) -> Result<(), Error> {
+        if let Some(flag) = self.interrupt.get() {
+            if flag.swap(false, Ordering::SeqCst) {
+                return Err(exception(ExceptionKind::Interrupted, span));
+            }
+        }
+        let steps = self.steps.get() + 1;
+        if let Some(fuel) = self.limits.get().fuel.filter(|fuel| steps > *fuel) {
+            return Err(Error::OutOfFuel(fuel));
+        }
+        self.steps.set(steps);
+        Ok(())
+    }
+
+    pub fn leave(&self) {
+        self.depth.set(self.depth.get() - 1);
+    }
+
+    // Counts a value allocated by the node at `span`.
+    pub fn alloc(&self, value: &Value, span: Span) -> Result<(), Error> {
+        self.alloc_words(words(value), span)
+    }
+
+    // Counts words allocated by the node at `span`, e.g. a cell consed onto a
+    // list which is otherwise shared.
+    pub fn alloc_words(&self, words: usize, span: Span) -> Result<(), Error> {
+        let words = self.words.get() + words;
+        if self.limits.get().max_alloc.is_some_and(|max| words > max) {
+            return Err(exception(ExceptionKind::OutOfMemory, span));
+        }
+        self.words.set(words);
+        Ok(())
+    }
+}
+
+fn exception(kind: ExceptionKind, span: Span) -> Error {
+    Error::Exception(Exception {
+        kind,
+        span: Some(span),
+    })
+}
+
+// Words of the block made for a value, as an OCaml value would take
+// including its header. Values inside it are counted when they are made.
+fn words(value: &Value) -> usize {
+    match value {
+        Value::Int(_) | Value::Bool(_) | Value::Unit | Value::Constr(_, None) => 0,
+        Value::Str(s) => 1 + s.len() / 8 + 1,
+        Value::List(list) => list.iter().count() * 3,
+        Value::Tuple(elems) => 1 + elems.len(),
+        Value::Array(elems) => 1 + elems.borrow().len(),
+        Value::Record(fields) => 1 + fields.len(),
+        Value::Ref(_) | Value::Constr(_, Some(_)) => 2,
+        Value::Fn(Func::Closure(closure)) => 2 + closure.params.len(),
+        Value::Fn(Func::Code(closure, args)) => 2 + closure.captured.len() + args.len(),
+        Value::Fn(Func::Prim(_, args)) | Value::Fn(Func::Native(_, args)) => 2 + args.len(),
+    }
+}
+
+// Address of the top of the stack, which grows downwards.
+fn stack_pointer() -> usize {
+    let marker = 0u8;
+    black_box(&marker) as *const u8 as usize
+}
+
+// Lowest address of the current stack with the red zone, or 0 if unknown.
+fn stack_limit() -> usize {
+    match stacker::remaining_stack() {
+        Some(left) => (stack_pointer() - left).saturating_add(RED_ZONE),
+        None => 0,
+    }
+}
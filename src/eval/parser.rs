@@ -1,130 +1,799 @@
-use super::lexer::{KwKind, Token};
-use super::value::List;
-
-#[derive(Debug, PartialEq)]
-pub(super) enum Node {
-    Int(i64),                        // integer
-    Bool(bool),                      // boolean
-    List(List),                      // list
-    Add(Box<Node>, Box<Node>),       // +
-    Sub(Box<Node>, Box<Node>),       // -
-    Mul(Box<Node>, Box<Node>),       // *
-    Div(Box<Node>, Box<Node>),       // /
-    Eql(Box<Node>, Box<Node>),       // ==
-    Neql(Box<Node>, Box<Node>),      // !=
-    Ident(String),                   // identifier
-    Bind(Box<BindStruct>),           // global binding
-    LocalBind(Box<LocalBindStruct>), // local binding
-}
-
-#[derive(Debug, PartialEq)]
+use super::lexer::{KwKind, Token, TokenKind};
+use super::span::Span;
+use crate::error::{ParseError, ParseErrorKind};
+
+#[derive(Clone, Debug)]
+pub(super) struct Node {
+    pub kind: NodeKind,
+    pub span: Span,
+}
+
+// Spans are not compared, so that nodes are compared by their structures
+// only.
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum NodeKind {
+    Int(i64),                                    // integer
+    Bool(bool),                                  // boolean
+    Unit,                                        // ()
+    Str(Vec<u8>),                                // string
+    List(Vec<Node>),                             // list
+    Array(Vec<Node>),                            // array
+    Tuple(Vec<Node>),                            // tuple of two or more elements
+    Add(Box<Node>, Box<Node>),                   // +
+    Sub(Box<Node>, Box<Node>),                   // -
+    Mul(Box<Node>, Box<Node>),                   // *
+    Div(Box<Node>, Box<Node>),                   // /
+    Eql(Box<Node>, Box<Node>),                   // ==
+    Neql(Box<Node>, Box<Node>),                  // !=
+    Compare(CompareOp, Box<Node>, Box<Node>),    // =, <>, <, >, <= or >=
+    Cons(Box<Node>, Box<Node>),                  // x :: xs
+    If(Box<Node>, Box<Node>, Option<Box<Node>>), // if c then e1 (else e2)?
+    Ident(String),                               // identifier
+    App(Box<Node>, Box<Node>),                   // function applied to an argument
+    Seq(Box<Node>, Box<Node>),                   // e1; e2
+    Bind(Box<BindStruct>),                       // global binding
+    LocalBind(Box<LocalBindStruct>),             // local binding
+    Constraint(Box<Node>, TypeExpr),             // (expr : type)
+    Constr(String, Option<Box<Node>>),           // variant constructor with its argument
+    Record(Vec<(String, Node)>),                 // { field = expr; ... }
+    Field(Box<Node>, String),                    // expr.field
+    TypeDef(Vec<TypeDefStruct>),                 // type definitions joined with `and`
+    Directive(String, Option<Box<Node>>),        // #name with an optional literal argument
+    External(String, TypeExpr, String),          // external name : type = "primitive"
+    Error,                                       // part which failed to parse
+}
+
+// structural comparison, which orders values as OCaml's `compare` does
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) enum CompareOp {
+    Eq, // =
+    Ne, // <>
+    Lt, // <
+    Gt, // >
+    Le, // <=
+    Ge, // >=
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub(super) struct BindStruct {
+    pub recursive: bool, // let rec
     pub name: Node,
-    pub args: Vec<Node>,
+    pub args: Vec<Node>, // identifiers, or constraints on them
     pub expr: Node,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub(super) struct LocalBindStruct {
     pub bind: BindStruct,
     pub scope: Node, // expression node in scope, followed by `in`
 }
 
-pub(super) fn parse(tokens: &[Token]) -> Result<Node, String> {
-    let (node, rest) = parse_expr(tokens)?;
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct TypeDefStruct {
+    pub name: String,
+    pub params: Vec<String>, // names of type parameters without quotes
+    pub repr: TypeRepr,
+}
+
+// right-hand side of a type definition
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum TypeRepr {
+    Abstract,                                 // type t
+    Alias(TypeExpr),                          // type t = int list
+    Variant(Vec<(String, Option<TypeExpr>)>), // type t = A | B of int
+    Record(Vec<(String, TypeExpr)>),          // type t = { x : int }
+}
+
+// type expression in annotations
+#[derive(Clone, Debug)]
+pub(super) struct TypeExpr {
+    pub kind: TypeExprKind,
+    pub span: Span,
+}
+
+impl PartialEq for TypeExpr {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum TypeExprKind {
+    Var(String),                         // 'a
+    Constr(String, Vec<TypeExpr>),       // type constructor applied to arguments
+    Arrow(Box<TypeExpr>, Box<TypeExpr>), // t -> u
+    Tuple(Vec<TypeExpr>),                // t * u
+}
+
+impl TypeExpr {
+    fn new(kind: TypeExprKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+impl Node {
+    fn new(kind: NodeKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+
+    // Makes a binary operator node spanning from `lhs` to `rhs`.
+    fn binary(op: fn(Box<Node>, Box<Node>) -> NodeKind, lhs: Node, rhs: Node) -> Self {
+        let span = lhs.span.to(rhs.span);
+        Self::new(op(Box::new(lhs), Box::new(rhs)), span)
+    }
+}
+
+// parsed node and the rest of tokens
+type ParseResult<'a> = Result<(Node, &'a [Token]), ParseError>;
+type TypeResult<'a> = Result<(TypeExpr, &'a [Token]), ParseError>;
+// parameters and the return type of a binding, and the rest of tokens
+type HeaderResult<'a> = Result<(Vec<Node>, Option<TypeExpr>, &'a [Token]), ParseError>;
+
+fn peek(tokens: &[Token]) -> Option<&TokenKind> {
+    tokens.first().map(|tok| &tok.kind)
+}
+
+// Span of the next token, used to report where an error occurred.
+fn next_span(tokens: &[Token]) -> Span {
+    tokens.first().map_or(Span::EOF, |tok| tok.span)
+}
+
+// Makes an error that `expected` is required in place of the next token.
+fn expected(expected: &str, tokens: &[Token]) -> ParseError {
+    ParseError {
+        kind: ParseErrorKind::Expected {
+            expected: expected.to_string(),
+            found: tokens.first().map(|tok| tok.kind.to_string()),
+        },
+        span: next_span(tokens),
+    }
+}
+
+fn is_punct(tokens: &[Token], punct: &str) -> bool {
+    matches!(peek(tokens), Some(TokenKind::Punct(p)) if p == punct)
+}
+
+// Skips tokens up to a synchronizing token, i.e. ";;", "in", ")", "]", "|]",
+// "}" or "|", from which parsing can be resumed after an error.
+fn synchronize(tokens: &[Token]) -> &[Token] {
+    skip_to(tokens, &[])
+}
+
+// Returns whether the next token is a synchronizing one, or no tokens left.
+fn at_sync_point(tokens: &[Token]) -> bool {
+    synchronize(tokens).len() == tokens.len()
+}
+
+// Skips tokens up to a synchronizing token or one of `puncts`.
+// Parenthesized and bracketed tokens are skipped as a whole.
+fn skip_to<'a>(tokens: &'a [Token], puncts: &[&str]) -> &'a [Token] {
+    let mut depth = 0;
+    for (idx, tok) in tokens.iter().enumerate() {
+        match &tok.kind {
+            TokenKind::Punct(p) if ["(", "[", "[|", "{"].contains(&&**p) => depth += 1,
+            TokenKind::Punct(p) if [")", "]", "|]", "}"].contains(&&**p) && depth > 0 => depth -= 1,
+            TokenKind::Punct(p)
+                if depth == 0
+                    && ([";;", ")", "]", "|]", "}", "|"].contains(&&**p)
+                        || puncts.contains(&&**p)) =>
+            {
+                return &tokens[idx..]
+            }
+            TokenKind::Kw(KwKind::In) if depth == 0 => return &tokens[idx..],
+            _ => (),
+        }
+    }
+    &tokens[tokens.len()..]
+}
+
+// Records an error, unless another error has been reported at the same
+// token, to avoid cascading errors.
+fn report(errors: &mut Vec<ParseError>, err: ParseError) {
+    if errors.last().map(|last| last.span) != Some(err.span) {
+        errors.push(err);
+    }
+}
+
+// Parses with `parse_fn`. On failure, records the error and resumes from the
+// next synchronizing token, returning an error node in place of the part
+// which failed to parse.
+fn recover<'a>(
+    tokens: &'a [Token],
+    errors: &mut Vec<ParseError>,
+    parse_fn: fn(&'a [Token], &mut Vec<ParseError>) -> ParseResult<'a>,
+) -> (Node, &'a [Token]) {
+    match parse_fn(tokens, errors) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            // Resume from the token where the error occurred.
+            let failed_at = tokens
+                .iter()
+                .position(|tok| tok.span.start.offset >= err.span.start.offset)
+                .unwrap_or(tokens.len());
+            report(errors, err);
+            let rest = synchronize(&tokens[failed_at..]);
+            let skipped = tokens.len() - rest.len();
+            let span = match skipped {
+                0 => next_span(tokens),
+                _ => tokens[0].span.to(tokens[skipped - 1].span),
+            };
+            (Node::new(NodeKind::Error, span), rest)
+        }
+    }
+}
+
+// Parses an expression, a type definition or a toplevel directive, reporting
+// all syntax errors found in it.
+pub(super) fn parse(tokens: &[Token]) -> Result<Node, Vec<ParseError>> {
+    let mut errors = Vec::new();
+    let (node, rest) = match peek(tokens) {
+        Some(TokenKind::Kw(KwKind::Type)) => recover(tokens, &mut errors, parse_type_def),
+        Some(TokenKind::Kw(KwKind::External)) => recover(tokens, &mut errors, parse_external),
+        Some(TokenKind::Punct(p)) if p == "#" => recover(tokens, &mut errors, parse_directive),
+        _ => recover(tokens, &mut errors, parse_seq),
+    };
+
+    // tokens remaining after the expression
+    if !rest.is_empty() {
+        report(&mut errors, expected(r#"";;""#, rest));
+    }
+
+    if errors.is_empty() {
+        Ok(node)
+    } else {
+        Err(errors)
+    }
+}
 
+// Parses tokens as a whole type expression, e.g. `int -> int list`.
+pub(super) fn parse_type_expr(tokens: &[Token]) -> Result<TypeExpr, ParseError> {
+    let (ty, rest) = parse_type(tokens)?;
     if !rest.is_empty() {
-        return Err(format!("Found extra token: {:?}", rest));
+        return Err(expected("end of type", rest));
+    }
+    Ok(ty)
+}
+
+// Finds where definitions begin in tokens which are not separated by ";;",
+// e.g. `let x = 1 let y = x`, returning the indices of the tokens beginning
+// the second one onwards.
+pub(super) fn definition_starts(tokens: &[Token]) -> Vec<usize> {
+    let mut starts = Vec::new();
+    let mut rest = tokens;
+    loop {
+        let parse_fn = match peek(rest) {
+            Some(TokenKind::Kw(KwKind::Type)) => parse_type_def,
+            Some(TokenKind::Kw(KwKind::Let)) => parse_expr,
+            Some(TokenKind::Kw(KwKind::External)) => parse_external,
+            _ => break,
+        };
+        let mut errors = Vec::new();
+        match parse_fn(rest, &mut errors) {
+            Ok((_, r)) if errors.is_empty() && r.len() < rest.len() => rest = r,
+            _ => break,
+        }
+        if !matches!(
+            peek(rest),
+            Some(TokenKind::Kw(KwKind::Type | KwKind::Let | KwKind::External))
+        ) {
+            break;
+        }
+        starts.push(tokens.len() - rest.len());
+    }
+    starts
+}
+
+// Returns whether an identifier is a variant constructor, which begins with
+// an uppercase letter.
+fn is_constructor(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_uppercase())
+}
+
+// <type-def> ::= "type" <type-binding> ("and" <type-binding>)*
+fn parse_type_def<'a>(tokens: &'a [Token], _errors: &mut Vec<ParseError>) -> ParseResult<'a> {
+    if !matches!(peek(tokens), Some(TokenKind::Kw(KwKind::Type))) {
+        return Err(expected(r#""type""#, tokens));
+    }
+    let mut rest = &tokens[1..];
+    let mut defs = Vec::new();
+    loop {
+        let def;
+        (def, rest) = parse_type_binding(rest)?;
+        defs.push(def);
+        match peek(rest) {
+            Some(TokenKind::Kw(KwKind::And)) => rest = &rest[1..],
+            _ => break,
+        }
+    }
+    let last = tokens.len() - rest.len() - 1;
+    let span = tokens[0].span.to(tokens[last].span);
+    Ok((Node::new(NodeKind::TypeDef(defs), span), rest))
+}
+
+// <directive> ::= "#" identifier (integer | string | identifier | "true" | "false")?
+fn parse_directive<'a>(tokens: &'a [Token], _errors: &mut Vec<ParseError>) -> ParseResult<'a> {
+    if !is_punct(tokens, "#") {
+        return Err(expected(r##""#""##, tokens));
+    }
+    let name = match peek(&tokens[1..]) {
+        Some(TokenKind::Ident(name)) => name.clone(),
+        _ => return Err(expected("a directive name", &tokens[1..])),
+    };
+    let mut rest = &tokens[2..];
+    let arg = match peek(rest) {
+        Some(TokenKind::Int(i)) => Some(NodeKind::Int(*i)),
+        Some(TokenKind::Str(s)) => Some(NodeKind::Str(s.clone())),
+        Some(TokenKind::Ident(name)) => Some(NodeKind::Ident(name.clone())),
+        Some(TokenKind::Kw(KwKind::True)) => Some(NodeKind::Bool(true)),
+        Some(TokenKind::Kw(KwKind::False)) => Some(NodeKind::Bool(false)),
+        _ => None,
+    };
+    let arg = arg.map(|kind| {
+        let node = Node::new(kind, rest[0].span);
+        rest = &rest[1..];
+        Box::new(node)
+    });
+    let last = tokens.len() - rest.len() - 1;
+    let span = tokens[0].span.to(tokens[last].span);
+    Ok((Node::new(NodeKind::Directive(name, arg), span), rest))
+}
+
+// <external> ::= "external" identifier ":" <type> "=" string
+fn parse_external<'a>(tokens: &'a [Token], _errors: &mut Vec<ParseError>) -> ParseResult<'a> {
+    if !matches!(peek(tokens), Some(TokenKind::Kw(KwKind::External))) {
+        return Err(expected(r#""external""#, tokens));
+    }
+    let name = match peek(&tokens[1..]) {
+        Some(TokenKind::Ident(name)) if !is_constructor(name) => name.clone(),
+        _ => return Err(expected("a value name", &tokens[1..])),
+    };
+    if !is_punct(&tokens[2..], ":") {
+        return Err(expected(r#"":""#, &tokens[2..]));
+    }
+    let (ty, rest) = parse_type(&tokens[3..])?;
+    if !is_punct(rest, "=") {
+        return Err(expected(r#""=""#, rest));
+    }
+    let prim = match peek(&rest[1..]) {
+        Some(TokenKind::Str(s)) => String::from_utf8_lossy(s).into_owned(),
+        _ => return Err(expected("a primitive name", &rest[1..])),
+    };
+    let span = tokens[0].span.to(rest[1].span);
+    Ok((
+        Node::new(NodeKind::External(name, ty, prim), span),
+        &rest[2..],
+    ))
+}
+
+// <type-binding> ::= <type-params> identifier ("=" <type-repr>)?
+// <type-repr> ::= <type> | <variant> | <record-decl>
+fn parse_type_binding(tokens: &[Token]) -> Result<(TypeDefStruct, &[Token]), ParseError> {
+    let (params, mut rest) = parse_type_params(tokens)?;
+    let name = match peek(rest) {
+        Some(TokenKind::Ident(name)) if !is_constructor(name) => name.clone(),
+        _ => return Err(expected("a type name", rest)),
+    };
+    rest = &rest[1..];
+
+    let repr = if is_punct(rest, "=") {
+        rest = &rest[1..];
+        let repr;
+        (repr, rest) = match peek(rest) {
+            Some(TokenKind::Punct(p)) if p == "{" => parse_record_decl(rest)?,
+            Some(TokenKind::Punct(p)) if p == "|" => parse_variant(rest)?,
+            Some(TokenKind::Ident(name)) if is_constructor(name) => parse_variant(rest)?,
+            _ => {
+                let (ty, rest) = parse_type(rest)?;
+                (TypeRepr::Alias(ty), rest)
+            }
+        };
+        repr
+    } else {
+        TypeRepr::Abstract
+    };
+    Ok((TypeDefStruct { name, params, repr }, rest))
+}
+
+// <type-params> ::= ("'" identifier | "(" "'" identifier ("," "'" identifier)* ")")?
+fn parse_type_params(tokens: &[Token]) -> Result<(Vec<String>, &[Token]), ParseError> {
+    fn param(tokens: &[Token]) -> Result<(String, &[Token]), ParseError> {
+        if !is_punct(tokens, "'") {
+            return Err(expected("a type parameter", tokens));
+        }
+        match peek(&tokens[1..]) {
+            Some(TokenKind::Ident(name)) => Ok((name.clone(), &tokens[2..])),
+            _ => Err(expected("a type variable name", &tokens[1..])),
+        }
+    }
+
+    if is_punct(tokens, "'") {
+        let (name, rest) = param(tokens)?;
+        return Ok((vec![name], rest));
+    }
+    if !is_punct(tokens, "(") {
+        return Ok((vec![], tokens));
+    }
+    let mut params = Vec::new();
+    let mut rest = &tokens[1..];
+    loop {
+        let name;
+        (name, rest) = param(rest)?;
+        params.push(name);
+        if !is_punct(rest, ",") {
+            break;
+        }
+        rest = &rest[1..];
+    }
+    if !is_punct(rest, ")") {
+        return Err(expected(r#"")""#, rest));
+    }
+    Ok((params, &rest[1..]))
+}
+
+// <variant> ::= "|"? <constr-decl> ("|" <constr-decl>)*
+// <constr-decl> ::= constructor ("of" <type>)?
+fn parse_variant(tokens: &[Token]) -> Result<(TypeRepr, &[Token]), ParseError> {
+    let mut rest = tokens;
+    if is_punct(rest, "|") {
+        rest = &rest[1..];
+    }
+    let mut constrs = Vec::new();
+    loop {
+        let name = match peek(rest) {
+            Some(TokenKind::Ident(name)) if is_constructor(name) => name.clone(),
+            _ => return Err(expected("a constructor", rest)),
+        };
+        rest = &rest[1..];
+        let arg = match peek(rest) {
+            Some(TokenKind::Kw(KwKind::Of)) => {
+                let ty;
+                (ty, rest) = parse_type(&rest[1..])?;
+                Some(ty)
+            }
+            _ => None,
+        };
+        constrs.push((name, arg));
+        if !is_punct(rest, "|") {
+            break;
+        }
+        rest = &rest[1..];
+    }
+    Ok((TypeRepr::Variant(constrs), rest))
+}
+
+// <record-decl> ::= "{" identifier ":" <type> (";" identifier ":" <type>)* ";"? "}"
+fn parse_record_decl(tokens: &[Token]) -> Result<(TypeRepr, &[Token]), ParseError> {
+    let mut rest = &tokens[1..];
+    let mut fields = Vec::new();
+    loop {
+        let name = match peek(rest) {
+            Some(TokenKind::Ident(name)) if !is_constructor(name) => name.clone(),
+            _ => return Err(expected("a field name", rest)),
+        };
+        rest = &rest[1..];
+        if !is_punct(rest, ":") {
+            return Err(expected(r#"":""#, rest));
+        }
+        let ty;
+        (ty, rest) = parse_type(&rest[1..])?;
+        fields.push((name, ty));
+
+        let has_semicolon = is_punct(rest, ";");
+        if has_semicolon {
+            rest = &rest[1..];
+        }
+        if is_punct(rest, "}") {
+            return Ok((TypeRepr::Record(fields), &rest[1..]));
+        }
+        if !has_semicolon {
+            return Err(expected(r#"";" or "}""#, rest));
+        }
     }
+}
 
-    Ok(node)
+// <seq> ::= <expr> (";" <seq>)?
+fn parse_seq<'a>(tokens: &'a [Token], errors: &mut Vec<ParseError>) -> ParseResult<'a> {
+    let (lhs, rest) = parse_expr(tokens, errors)?;
+    if !is_punct(rest, ";") {
+        return Ok((lhs, rest));
+    }
+    let (rhs, rest) = parse_seq(&rest[1..], errors)?;
+    Ok((Node::binary(NodeKind::Seq, lhs, rhs), rest))
 }
 
+// Expression without sequences, e.g. an element of a list.
+//
 // <expr> ::= <bind>
-fn parse_expr(tokens: &[Token]) -> Result<(Node, &[Token]), String> {
-    parse_bind(tokens)
+fn parse_expr<'a>(tokens: &'a [Token], errors: &mut Vec<ParseError>) -> ParseResult<'a> {
+    parse_bind(tokens, errors)
 }
 
-// <bind> ::= "let" identifier (identifier)* "=" <add> ("in" <expr>)?
-//          | <add>
-fn parse_bind(tokens: &[Token]) -> Result<(Node, &[Token]), String> {
-    match tokens.get(0) {
-        Some(Token::Kw(KwKind::Let)) => {
+// `let () = e` is the expression `(e : unit)`, and `let () = e in e'` is
+// `(e : unit); e'`.
+//
+// <bind> ::= "let" "rec"? identifier <bind-header> <seq> ("in" <seq>)?
+//          | "let" "(" ")" "=" <seq> ("in" <seq>)?
+//          | <if>
+//          | <assign>
+fn parse_bind<'a>(tokens: &'a [Token], errors: &mut Vec<ParseError>) -> ParseResult<'a> {
+    match peek(tokens) {
+        Some(TokenKind::Kw(KwKind::Let))
+            if is_punct(&tokens[1..], "(") && is_punct(&tokens[2..], ")") =>
+        {
+            let unit = TypeExpr {
+                kind: TypeExprKind::Constr("unit".to_string(), Vec::new()),
+                span: tokens[1].span.to(tokens[2].span),
+            };
+            if !is_punct(&tokens[3..], "=") {
+                return Err(expected(r#""=""#, &tokens[3..]));
+            }
+            let (expr, rest) = recover(&tokens[4..], errors, parse_seq);
+            let span = tokens[0].span.to(expr.span);
+            let expr = Node::new(NodeKind::Constraint(Box::new(expr), unit), span);
+            match peek(rest) {
+                Some(TokenKind::Kw(KwKind::In)) => {
+                    let (scope, rest) = recover(&rest[1..], errors, parse_seq);
+                    Ok((Node::binary(NodeKind::Seq, expr, scope), rest))
+                }
+                _ => Ok((expr, rest)),
+            }
+        }
+        Some(TokenKind::Kw(KwKind::Let)) => {
+            let start = tokens[0].span;
             let mut rest = &tokens[1..];
-            let ident = match rest.get(0) {
-                Some(Token::Ident(ident)) => {
+            let recursive = matches!(peek(rest), Some(TokenKind::Kw(KwKind::Rec)));
+            if recursive {
+                rest = &rest[1..];
+            }
+            let ident = match peek(rest) {
+                Some(TokenKind::Ident(ident)) => {
+                    let ident = Node::new(NodeKind::Ident(ident.clone()), rest[0].span);
                     rest = &rest[1..];
-                    ident.clone()
+                    ident
+                }
+                _ => {
+                    report(errors, expected("an identifier", rest));
+                    Node::new(NodeKind::Error, next_span(rest))
                 }
-                _ => return Err("Expected an identifier".to_string()),
             };
 
-            let args = {
-                let mut args: Vec<Node> = Vec::new();
-                loop {
-                    match rest.get(0) {
-                        Some(Token::Punct(p)) if p == "=" => {
-                            rest = &rest[1..];
-                            break;
-                        }
-                        Some(Token::Ident(arg)) => {
-                            args.push(Node::Ident(arg.clone()));
-                            rest = &rest[1..];
-                        }
-                        _ => return Err("Syntax error".to_string()),
-                    };
+            let (args, ret) = match parse_bind_header(rest) {
+                Ok((args, ret, r)) => {
+                    rest = r;
+                    (args, ret)
+                }
+                Err(err) => {
+                    report(errors, err);
+                    // Resume from the right-hand side, if any.
+                    rest = skip_to(rest, &["="]);
+                    if is_punct(rest, "=") {
+                        rest = &rest[1..];
+                    }
+                    (Vec::new(), None)
                 }
-                args
             };
 
-            let rhs;
-            (rhs, rest) = parse_add(rest)?;
+            let mut rhs;
+            (rhs, rest) = recover(rest, errors, parse_seq);
+            if let Some(ty) = ret {
+                // `let f x : t = e` is `let f x = (e : t)`.
+                let span = rhs.span;
+                rhs = Node::new(NodeKind::Constraint(Box::new(rhs), ty), span);
+            }
 
-            match rest.get(0) {
-                Some(Token::Kw(KwKind::In)) => {
+            match peek(rest) {
+                Some(TokenKind::Kw(KwKind::In)) => {
                     let expr;
-                    (expr, rest) = parse_expr(&rest[1..])?;
+                    (expr, rest) = recover(&rest[1..], errors, parse_seq);
+                    let span = start.to(expr.span);
+                    Ok((
+                        Node::new(
+                            NodeKind::LocalBind(Box::new(LocalBindStruct {
+                                bind: BindStruct {
+                                    recursive,
+                                    name: ident,
+                                    args,
+                                    expr: rhs,
+                                },
+                                scope: expr,
+                            })),
+                            span,
+                        ),
+                        rest,
+                    ))
+                }
+                _ => {
+                    let span = start.to(rhs.span);
                     Ok((
-                        Node::LocalBind(Box::new(LocalBindStruct {
-                            bind: BindStruct {
-                                name: Node::Ident(ident),
+                        Node::new(
+                            NodeKind::Bind(Box::new(BindStruct {
+                                recursive,
+                                name: ident,
                                 args,
                                 expr: rhs,
-                            },
-                            scope: expr,
-                        })),
+                            })),
+                            span,
+                        ),
                         rest,
                     ))
                 }
-                _ => Ok((
-                    Node::Bind(Box::new(BindStruct {
-                        name: Node::Ident(ident),
-                        args,
-                        expr: rhs,
-                    })),
-                    rest,
-                )),
             }
         }
-        _ => parse_add(tokens),
+        Some(TokenKind::Kw(KwKind::If)) => parse_if(tokens, errors),
+        _ => parse_assign(tokens, errors),
+    }
+}
+
+// <if> ::= "if" <seq> "then" <expr> ("else" <expr>)?
+fn parse_if<'a>(tokens: &'a [Token], errors: &mut Vec<ParseError>) -> ParseResult<'a> {
+    let (cond, rest) = parse_seq(&tokens[1..], errors)?;
+    if !matches!(peek(rest), Some(TokenKind::Kw(KwKind::Then))) {
+        return Err(expected(r#""then""#, rest));
+    }
+    let (then, rest) = parse_expr(&rest[1..], errors)?;
+    let (other, rest) = match peek(rest) {
+        Some(TokenKind::Kw(KwKind::Else)) => {
+            let (other, rest) = parse_expr(&rest[1..], errors)?;
+            (Some(Box::new(other)), rest)
+        }
+        _ => (None, rest),
+    };
+    let span = tokens[0]
+        .span
+        .to(other.as_ref().map_or(then.span, |other| other.span));
+    Ok((
+        Node::new(NodeKind::If(Box::new(cond), Box::new(then), other), span),
+        rest,
+    ))
+}
+
+// Parses parameters and the return type of a binding, followed by "=".
+//
+// <bind-header> ::= <param>* (":" <type>)? "="
+fn parse_bind_header(tokens: &[Token]) -> HeaderResult<'_> {
+    let mut rest = tokens;
+    let mut args = Vec::new();
+    while !is_punct(rest, "=") && !is_punct(rest, ":") {
+        let arg;
+        (arg, rest) = parse_param(rest)?;
+        args.push(arg);
+    }
+
+    let mut ret = None;
+    if is_punct(rest, ":") {
+        let ty;
+        (ty, rest) = parse_type(&rest[1..])?;
+        ret = Some(ty);
+    }
+
+    if !is_punct(rest, "=") {
+        return Err(expected(r#""=""#, rest));
+    }
+    Ok((args, ret, &rest[1..]))
+}
+
+// <param> ::= identifier | "(" identifier ":" <type> ")"
+fn parse_param(tokens: &[Token]) -> ParseResult<'_> {
+    match peek(tokens) {
+        Some(TokenKind::Ident(name)) => Ok((
+            Node::new(NodeKind::Ident(name.clone()), tokens[0].span),
+            &tokens[1..],
+        )),
+        Some(TokenKind::Punct(p)) if p == "(" => {
+            let name = match peek(&tokens[1..]) {
+                Some(TokenKind::Ident(name)) => {
+                    Node::new(NodeKind::Ident(name.clone()), tokens[1].span)
+                }
+                _ => return Err(expected("an identifier", &tokens[1..])),
+            };
+            let rest = &tokens[2..];
+            if !is_punct(rest, ":") {
+                return Err(expected(r#"":""#, rest));
+            }
+            let (ty, rest) = parse_type(&rest[1..])?;
+            if !is_punct(rest, ")") {
+                return Err(expected(r#"")""#, rest));
+            }
+            let span = tokens[0].span.to(rest[0].span);
+            Ok((
+                Node::new(NodeKind::Constraint(Box::new(name), ty), span),
+                &rest[1..],
+            ))
+        }
+        _ => Err(expected(r#""=""#, tokens)),
+    }
+}
+
+// `a := b` is the application `(:=) a b`.
+//
+// <assign> ::= <tuple> (":=" <assign>)?
+fn parse_assign<'a>(tokens: &'a [Token], errors: &mut Vec<ParseError>) -> ParseResult<'a> {
+    let (lhs, rest) = parse_tuple(tokens, errors)?;
+    if !is_punct(rest, ":=") {
+        return Ok((lhs, rest));
+    }
+    let op = Node::new(NodeKind::Ident(":=".to_string()), rest[0].span);
+    let (rhs, rest) = parse_assign(&rest[1..], errors)?;
+    let app = Node::binary(NodeKind::App, op, lhs);
+    Ok((Node::binary(NodeKind::App, app, rhs), rest))
+}
+
+// <tuple> ::= <compare> ("," <compare>)*
+fn parse_tuple<'a>(tokens: &'a [Token], errors: &mut Vec<ParseError>) -> ParseResult<'a> {
+    let (node, mut rest) = parse_compare(tokens, errors)?;
+    if !is_punct(rest, ",") {
+        return Ok((node, rest));
+    }
+
+    let mut elems = vec![node];
+    while is_punct(rest, ",") {
+        let elem;
+        (elem, rest) = parse_compare(&rest[1..], errors)?;
+        elems.push(elem);
+    }
+    let span = elems[0].span.to(elems[elems.len() - 1].span);
+    Ok((Node::new(NodeKind::Tuple(elems), span), rest))
+}
+
+// Comparisons are left-associative, e.g. `a < b = c` is `(a < b) = c`.
+//
+// <compare> ::= <cons> (("=" | "<>" | "<" | ">" | "<=" | ">=") <cons>)*
+fn parse_compare<'a>(tokens: &'a [Token], errors: &mut Vec<ParseError>) -> ParseResult<'a> {
+    let (mut node, mut rest) = parse_cons(tokens, errors)?;
+
+    while let Some(TokenKind::Punct(p)) = peek(rest) {
+        let op = match &**p {
+            "=" => CompareOp::Eq,
+            "<>" => CompareOp::Ne,
+            "<" => CompareOp::Lt,
+            ">" => CompareOp::Gt,
+            "<=" => CompareOp::Le,
+            ">=" => CompareOp::Ge,
+            _ => break,
+        };
+        let rhs;
+        (rhs, rest) = parse_cons(&rest[1..], errors)?;
+        let span = node.span.to(rhs.span);
+        node = Node::new(NodeKind::Compare(op, Box::new(node), Box::new(rhs)), span);
+    }
+
+    Ok((node, rest))
+}
+
+// <cons> ::= <add> ("::" <cons>)?
+fn parse_cons<'a>(tokens: &'a [Token], errors: &mut Vec<ParseError>) -> ParseResult<'a> {
+    let (node, rest) = parse_add(tokens, errors)?;
+
+    match peek(rest) {
+        Some(TokenKind::Punct(p)) if p == "::" => {
+            let (tail, rest) = parse_cons(&rest[1..], errors)?;
+            Ok((Node::binary(NodeKind::Cons, node, tail), rest))
+        }
+        _ => Ok((node, rest)),
     }
 }
 
 // <add> ::= <mul> (("+" | "-") <mul>)*
-fn parse_add(tokens: &[Token]) -> Result<(Node, &[Token]), String> {
-    let (mut node, mut rest) = parse_mul(tokens)?;
+fn parse_add<'a>(tokens: &'a [Token], errors: &mut Vec<ParseError>) -> ParseResult<'a> {
+    let (mut node, mut rest) = parse_mul(tokens, errors)?;
 
-    while let Some(Token::Punct(p)) = rest.get(0) {
+    while let Some(TokenKind::Punct(p)) = peek(rest) {
         match &**p {
             "+" => {
                 let rhs;
-                (rhs, rest) = parse_mul(&rest[1..])?;
-                node = Node::Add(Box::new(node), Box::new(rhs));
+                (rhs, rest) = parse_mul(&rest[1..], errors)?;
+                node = Node::binary(NodeKind::Add, node, rhs);
             }
             "-" => {
                 let rhs;
-                (rhs, rest) = parse_mul(&rest[1..])?;
-                node = Node::Sub(Box::new(node), Box::new(rhs));
+                (rhs, rest) = parse_mul(&rest[1..], errors)?;
+                node = Node::binary(NodeKind::Sub, node, rhs);
             }
             _ => break,
         }
@@ -134,20 +803,20 @@ fn parse_add(tokens: &[Token]) -> Result<(Node, &[Token]), String> {
 }
 
 // <mul> ::= <equal> ("*" | "/" <equal>)*
-fn parse_mul(tokens: &[Token]) -> Result<(Node, &[Token]), String> {
-    let (mut node, mut rest) = parse_equal(tokens)?;
+fn parse_mul<'a>(tokens: &'a [Token], errors: &mut Vec<ParseError>) -> ParseResult<'a> {
+    let (mut node, mut rest) = parse_equal(tokens, errors)?;
 
-    while let Some(Token::Punct(p)) = rest.get(0) {
+    while let Some(TokenKind::Punct(p)) = peek(rest) {
         match &**p {
             "*" => {
                 let rhs;
-                (rhs, rest) = parse_equal(&rest[1..])?;
-                node = Node::Mul(Box::new(node), Box::new(rhs));
+                (rhs, rest) = parse_equal(&rest[1..], errors)?;
+                node = Node::binary(NodeKind::Mul, node, rhs);
             }
             "/" => {
                 let rhs;
-                (rhs, rest) = parse_equal(&rest[1..])?;
-                node = Node::Div(Box::new(node), Box::new(rhs));
+                (rhs, rest) = parse_equal(&rest[1..], errors)?;
+                node = Node::binary(NodeKind::Div, node, rhs);
             }
             _ => break,
         }
@@ -156,22 +825,22 @@ fn parse_mul(tokens: &[Token]) -> Result<(Node, &[Token]), String> {
     Ok((node, rest))
 }
 
-// <equal> ::= <primary> (("==" | "!=") <primary>)*
-fn parse_equal(tokens: &[Token]) -> Result<(Node, &[Token]), String> {
-    let (mut node, mut rest) = parse_primary(tokens)?;
+// <equal> ::= <app> (("==" | "!=") <app>)*
+fn parse_equal<'a>(tokens: &'a [Token], errors: &mut Vec<ParseError>) -> ParseResult<'a> {
+    let (mut node, mut rest) = parse_app(tokens, errors)?;
 
-    while let Some(Token::Punct(p)) = rest.get(0) {
+    while let Some(TokenKind::Punct(p)) = peek(rest) {
         if p == "==" {
             let rhs;
-            (rhs, rest) = parse_primary(&rest[1..])?;
-            node = Node::Eql(Box::new(node), Box::new(rhs));
+            (rhs, rest) = parse_app(&rest[1..], errors)?;
+            node = Node::binary(NodeKind::Eql, node, rhs);
             continue;
         }
 
         if p == "!=" {
             let rhs;
-            (rhs, rest) = parse_primary(&rest[1..])?;
-            node = Node::Neql(Box::new(node), Box::new(rhs));
+            (rhs, rest) = parse_app(&rest[1..], errors)?;
+            node = Node::binary(NodeKind::Neql, node, rhs);
             continue;
         }
 
@@ -181,71 +850,304 @@ fn parse_equal(tokens: &[Token]) -> Result<(Node, &[Token]), String> {
     Ok((node, rest))
 }
 
-// <primary> ::= <int> | <boolean> | <val-name> | <list> | "(" <expr> ")"
-fn parse_primary(tokens: &[Token]) -> Result<(Node, &[Token]), String> {
-    match tokens.get(0) {
-        Some(Token::Int(int)) => Ok((Node::Int(*int), &tokens[1..])),
-        Some(Token::Kw(KwKind::True)) => Ok((Node::Bool(true), &tokens[1..])),
-        Some(Token::Kw(KwKind::False)) => Ok((Node::Bool(false), &tokens[1..])),
-        Some(Token::Ident(name)) => Ok((Node::Ident(name.clone()), &tokens[1..])),
-        Some(Token::Punct(p)) if p == "[" => parse_list(tokens),
-        Some(Token::Punct(p)) if p == "(" => {
-            let (expr, rest) = parse_expr(&tokens[1..])?;
-            match rest.get(0) {
-                Some(Token::Punct(p)) if p == ")" => Ok((expr, &rest[1..])),
-                _ => Err("expected )".to_string()),
+// Applications are curried, e.g. `f x y` is `(f x) y`.
+//
+// <app> ::= <access> <access>*
+fn parse_app<'a>(tokens: &'a [Token], errors: &mut Vec<ParseError>) -> ParseResult<'a> {
+    let (mut node, mut rest) = parse_access(tokens, errors)?;
+    while starts_primary(rest) {
+        let arg;
+        (arg, rest) = parse_access(rest, errors)?;
+        node = Node::binary(NodeKind::App, node, arg);
+    }
+    Ok((node, rest))
+}
+
+// <access> ::= <primary> ("." identifier)*
+fn parse_access<'a>(tokens: &'a [Token], errors: &mut Vec<ParseError>) -> ParseResult<'a> {
+    let (mut node, mut rest) = parse_primary(tokens, errors)?;
+    while is_punct(rest, ".") {
+        match peek(&rest[1..]) {
+            Some(TokenKind::Ident(field)) if !is_constructor(field) => {
+                let span = node.span.to(rest[1].span);
+                node = Node::new(NodeKind::Field(Box::new(node), field.clone()), span);
+                rest = &rest[2..];
             }
+            _ => return Err(expected("a field name", &rest[1..])),
         }
-        _ => Err("Failed to parse a primary".to_string()),
+    }
+    Ok((node, rest))
+}
+
+// Returns whether the next token begins a primary expression, e.g. an
+// argument of a function or a constructor.
+fn starts_primary(tokens: &[Token]) -> bool {
+    match peek(tokens) {
+        Some(TokenKind::Int(_) | TokenKind::Str(_) | TokenKind::Ident(_)) => true,
+        Some(TokenKind::Kw(kw)) => matches!(kw, KwKind::True | KwKind::False),
+        Some(TokenKind::Punct(p)) => ["(", "[", "[|", "{", "!"].contains(&&**p),
+        None => false,
     }
 }
 
-// <list> ::= "[" (<int> (";" <int>)*)? "]"
-fn parse_list(tokens: &[Token]) -> Result<(Node, &[Token]), String> {
-    let mut rest = match tokens.get(0) {
-        Some(Token::Punct(p)) if p == "[" => &tokens[1..],
-        _ => return Err("Require [ to parse a list".to_string()),
+// `!e` is the application `(!) e`.
+//
+// <primary> ::= <int> | <string> | <boolean> | <val-name>
+//             | constructor <access>? | <list> | <record> | "!" <primary>
+//             | "(" ")" | "(" <expr> (":" <type>)? ")"
+fn parse_primary<'a>(tokens: &'a [Token], errors: &mut Vec<ParseError>) -> ParseResult<'a> {
+    let span = next_span(tokens);
+    match peek(tokens) {
+        Some(TokenKind::Int(int)) => Ok((Node::new(NodeKind::Int(*int), span), &tokens[1..])),
+        Some(TokenKind::Kw(KwKind::True)) => {
+            Ok((Node::new(NodeKind::Bool(true), span), &tokens[1..]))
+        }
+        Some(TokenKind::Kw(KwKind::False)) => {
+            Ok((Node::new(NodeKind::Bool(false), span), &tokens[1..]))
+        }
+        Some(TokenKind::Ident(name)) if is_constructor(name) => {
+            let rest = &tokens[1..];
+            if !starts_primary(rest) {
+                return Ok((Node::new(NodeKind::Constr(name.clone(), None), span), rest));
+            }
+            let (arg, rest) = parse_access(rest, errors)?;
+            let span = span.to(arg.span);
+            Ok((
+                Node::new(NodeKind::Constr(name.clone(), Some(Box::new(arg))), span),
+                rest,
+            ))
+        }
+        Some(TokenKind::Ident(name)) => {
+            Ok((Node::new(NodeKind::Ident(name.clone()), span), &tokens[1..]))
+        }
+        Some(TokenKind::Str(s)) => Ok((Node::new(NodeKind::Str(s.clone()), span), &tokens[1..])),
+        Some(TokenKind::Punct(p)) if p == "[" || p == "[|" => parse_list(tokens, errors),
+        Some(TokenKind::Punct(p)) if p == "{" => parse_record(tokens, errors),
+        Some(TokenKind::Punct(p)) if p == "!" => {
+            let op = Node::new(NodeKind::Ident("!".to_string()), span);
+            let (arg, rest) = parse_primary(&tokens[1..], errors)?;
+            Ok((Node::binary(NodeKind::App, op, arg), rest))
+        }
+        Some(TokenKind::Punct(p)) if p == "(" && is_punct(&tokens[1..], ")") => Ok((
+            Node::new(NodeKind::Unit, span.to(tokens[1].span)),
+            &tokens[2..],
+        )),
+        Some(TokenKind::Punct(p)) if p == "(" => {
+            let (mut expr, mut rest) = recover(&tokens[1..], errors, parse_seq);
+            if is_punct(rest, ":") {
+                let ty;
+                (ty, rest) = parse_type(&rest[1..])?;
+                let span = expr.span.to(ty.span);
+                expr = Node::new(NodeKind::Constraint(Box::new(expr), ty), span);
+            }
+            if is_punct(rest, ")") {
+                expr.span = span.to(rest[0].span);
+                Ok((expr, &rest[1..]))
+            } else {
+                // Continue as if the parenthesis were closed.
+                report(errors, expected(r#"")""#, rest));
+                Ok((expr, rest))
+            }
+        }
+        _ => Err(expected("an expression", tokens)),
+    }
+}
+
+// <list> ::= "[" (<expr> (";" <expr>)*)? "]"
+//          | "[|" (<expr> (";" <expr>)*)? "|]"
+fn parse_list<'a>(tokens: &'a [Token], errors: &mut Vec<ParseError>) -> ParseResult<'a> {
+    let start = next_span(tokens);
+    let (close, kind): (_, fn(Vec<Node>) -> NodeKind) = match peek(tokens) {
+        Some(TokenKind::Punct(p)) if p == "[" => ("]", NodeKind::List),
+        Some(TokenKind::Punct(p)) if p == "[|" => ("|]", NodeKind::Array),
+        _ => return Err(expected(r#""[""#, tokens)),
     };
-    let list = {
-        let mut lst: Vec<i64> = Vec::new();
-        let mut is_first = true;
-        loop {
-            match rest.get(0) {
-                Some(Token::Punct(p)) if p == "]" => {
-                    rest = &rest[1..];
+    let mut rest = &tokens[1..];
+    let mut end = start;
+    let mut elems: Vec<Node> = Vec::new();
+    let mut is_first = true;
+    loop {
+        if is_punct(rest, close) {
+            end = rest[0].span;
+            rest = &rest[1..];
+            break;
+        }
+        // skip ;
+        if !is_first {
+            if is_punct(rest, ";") {
+                rest = &rest[1..];
+            } else {
+                report(errors, expected(&format!(r#"";" or "{}""#, close), rest));
+                if at_sync_point(rest) {
                     break;
                 }
-                _ => (),
             }
-            // skip ;
-            if !is_first {
-                match rest.get(0) {
-                    Some(Token::Punct(p)) if p == ";" => rest = &rest[1..],
-                    _ => return Err("; is required as a delimiter".to_string()),
+        }
+        match parse_expr(rest, errors) {
+            Ok((elem, r)) => {
+                elems.push(elem);
+                rest = r;
+            }
+            Err(err) => {
+                report(errors, err);
+                // Skip to the next element.
+                rest = skip_to(rest, &[";"]);
+                if at_sync_point(rest) && !is_punct(rest, close) {
+                    break;
                 }
             }
-            match rest.get(0) {
-                Some(Token::Int(int)) => {
-                    lst.push(*int);
-                    rest = &rest[1..];
-                    is_first = false;
+        }
+        is_first = false;
+    }
+    Ok((Node::new(kind(elems), start.to(end)), rest))
+}
+
+// <record> ::= "{" identifier "=" <expr> (";" identifier "=" <expr>)* ";"? "}"
+fn parse_record<'a>(tokens: &'a [Token], errors: &mut Vec<ParseError>) -> ParseResult<'a> {
+    let start = next_span(tokens);
+    let mut rest = &tokens[1..];
+    let mut fields = Vec::new();
+    loop {
+        let name = match peek(rest) {
+            Some(TokenKind::Ident(name)) if !is_constructor(name) => name.clone(),
+            _ => return Err(expected("a field name", rest)),
+        };
+        rest = &rest[1..];
+        if !is_punct(rest, "=") {
+            return Err(expected(r#""=""#, rest));
+        }
+        let expr;
+        (expr, rest) = parse_expr(&rest[1..], errors)?;
+        fields.push((name, expr));
+
+        let has_semicolon = is_punct(rest, ";");
+        if has_semicolon {
+            rest = &rest[1..];
+        }
+        if is_punct(rest, "}") {
+            let span = start.to(rest[0].span);
+            return Ok((Node::new(NodeKind::Record(fields), span), &rest[1..]));
+        }
+        if !has_semicolon {
+            return Err(expected(r#"";" or "}""#, rest));
+        }
+    }
+}
+
+// <type> ::= <tuple-type> ("->" <type>)?
+fn parse_type(tokens: &[Token]) -> TypeResult<'_> {
+    let (ty, rest) = parse_tuple_type(tokens)?;
+    if is_punct(rest, "->") {
+        let (ret, rest) = parse_type(&rest[1..])?;
+        let span = ty.span.to(ret.span);
+        return Ok((
+            TypeExpr::new(TypeExprKind::Arrow(Box::new(ty), Box::new(ret)), span),
+            rest,
+        ));
+    }
+    Ok((ty, rest))
+}
+
+// <tuple-type> ::= <app-type> ("*" <app-type>)*
+fn parse_tuple_type(tokens: &[Token]) -> TypeResult<'_> {
+    let (ty, mut rest) = parse_app_type(tokens)?;
+    if !is_punct(rest, "*") {
+        return Ok((ty, rest));
+    }
+
+    let mut elems = vec![ty];
+    while is_punct(rest, "*") {
+        let elem;
+        (elem, rest) = parse_app_type(&rest[1..])?;
+        elems.push(elem);
+    }
+    let span = elems[0].span.to(elems[elems.len() - 1].span);
+    Ok((TypeExpr::new(TypeExprKind::Tuple(elems), span), rest))
+}
+
+// <app-type> ::= <atom-type> identifier*
+//              | "(" <type> ("," <type>)+ ")" identifier+
+// <atom-type> ::= "'" identifier | identifier | "(" <type> ")"
+fn parse_app_type(tokens: &[Token]) -> TypeResult<'_> {
+    let start = next_span(tokens);
+    let (mut ty, mut rest) = match peek(tokens) {
+        Some(TokenKind::Punct(p)) if p == "'" => match peek(&tokens[1..]) {
+            Some(TokenKind::Ident(name)) => (
+                TypeExpr::new(TypeExprKind::Var(name.clone()), start.to(tokens[1].span)),
+                &tokens[2..],
+            ),
+            _ => return Err(expected("a type variable name", &tokens[1..])),
+        },
+        Some(TokenKind::Ident(name)) => (
+            TypeExpr::new(TypeExprKind::Constr(name.clone(), vec![]), start),
+            &tokens[1..],
+        ),
+        Some(TokenKind::Punct(p)) if p == "(" => {
+            let mut args = Vec::new();
+            let mut rest = &tokens[1..];
+            loop {
+                let arg;
+                (arg, rest) = parse_type(rest)?;
+                args.push(arg);
+                if !is_punct(rest, ",") {
+                    break;
+                }
+                rest = &rest[1..];
+            }
+            if !is_punct(rest, ")") {
+                return Err(expected(r#"")""#, rest));
+            }
+            let span = start.to(rest[0].span);
+            rest = &rest[1..];
+            if args.len() == 1 {
+                let mut ty = args.remove(0);
+                ty.span = span;
+                (ty, rest)
+            } else {
+                // Multiple arguments must be applied to a type constructor.
+                match peek(rest) {
+                    Some(TokenKind::Ident(name)) => (
+                        TypeExpr::new(
+                            TypeExprKind::Constr(name.clone(), args),
+                            start.to(rest[0].span),
+                        ),
+                        &rest[1..],
+                    ),
+                    _ => return Err(expected("a type constructor", rest)),
                 }
-                _ => return Err("Failed to parse a list".to_string()),
             }
         }
-        List::from(&lst)
+        _ => return Err(expected("a type", tokens)),
     };
-    Ok((Node::List(list), rest))
+
+    while let Some(TokenKind::Ident(name)) = peek(rest) {
+        let span = ty.span.to(rest[0].span);
+        ty = TypeExpr::new(TypeExprKind::Constr(name.clone(), vec![ty]), span);
+        rest = &rest[1..];
+    }
+    Ok((ty, rest))
 }
 
 #[cfg(test)]
 mod tests {
+    use super::super::lexer::tokenize;
     use super::*;
 
+    fn tok(kind: TokenKind) -> Token {
+        Token {
+            kind,
+            span: Span::default(),
+        }
+    }
+
+    fn node(kind: NodeKind) -> Node {
+        Node::new(kind, Span::default())
+    }
+
     #[test]
     fn parses_integer() {
-        let tokens = vec![Token::Int(42)];
-        let expected = Node::Int(42);
+        let tokens = vec![tok(TokenKind::Int(42))];
+        let expected = node(NodeKind::Int(42));
         let actual = parse(&tokens).unwrap();
         assert_eq!(expected, actual);
     }
@@ -254,42 +1156,51 @@ mod tests {
     fn parses_arithmetic_expr() {
         // 2+3*4+5-6/2+(3-1)*2
         let tokens = vec![
-            Token::Int(2),
-            Token::Punct("+".to_string()),
-            Token::Int(3),
-            Token::Punct("*".to_string()),
-            Token::Int(4),
-            Token::Punct("+".to_string()),
-            Token::Int(5),
-            Token::Punct("-".to_string()),
-            Token::Int(6),
-            Token::Punct("/".to_string()),
-            Token::Int(2),
-            Token::Punct("+".to_string()),
-            Token::Punct("(".to_string()),
-            Token::Int(3),
-            Token::Punct("-".to_string()),
-            Token::Int(1),
-            Token::Punct(")".to_string()),
-            Token::Punct("*".to_string()),
-            Token::Int(2),
+            tok(TokenKind::Int(2)),
+            tok(TokenKind::Punct("+".to_string())),
+            tok(TokenKind::Int(3)),
+            tok(TokenKind::Punct("*".to_string())),
+            tok(TokenKind::Int(4)),
+            tok(TokenKind::Punct("+".to_string())),
+            tok(TokenKind::Int(5)),
+            tok(TokenKind::Punct("-".to_string())),
+            tok(TokenKind::Int(6)),
+            tok(TokenKind::Punct("/".to_string())),
+            tok(TokenKind::Int(2)),
+            tok(TokenKind::Punct("+".to_string())),
+            tok(TokenKind::Punct("(".to_string())),
+            tok(TokenKind::Int(3)),
+            tok(TokenKind::Punct("-".to_string())),
+            tok(TokenKind::Int(1)),
+            tok(TokenKind::Punct(")".to_string())),
+            tok(TokenKind::Punct("*".to_string())),
+            tok(TokenKind::Int(2)),
         ];
-        let expected = Node::Add(
-            Box::new(Node::Sub(
-                Box::new(Node::Add(
-                    Box::new(Node::Add(
-                        Box::new(Node::Int(2)),
-                        Box::new(Node::Mul(Box::new(Node::Int(3)), Box::new(Node::Int(4)))),
-                    )),
-                    Box::new(Node::Int(5)),
-                )),
-                Box::new(Node::Div(Box::new(Node::Int(6)), Box::new(Node::Int(2)))),
-            )),
-            Box::new(Node::Mul(
-                Box::new(Node::Sub(Box::new(Node::Int(3)), Box::new(Node::Int(1)))),
-                Box::new(Node::Int(2)),
-            )),
-        );
+        let expected = node(NodeKind::Add(
+            Box::new(node(NodeKind::Sub(
+                Box::new(node(NodeKind::Add(
+                    Box::new(node(NodeKind::Add(
+                        Box::new(node(NodeKind::Int(2))),
+                        Box::new(node(NodeKind::Mul(
+                            Box::new(node(NodeKind::Int(3))),
+                            Box::new(node(NodeKind::Int(4))),
+                        ))),
+                    ))),
+                    Box::new(node(NodeKind::Int(5))),
+                ))),
+                Box::new(node(NodeKind::Div(
+                    Box::new(node(NodeKind::Int(6))),
+                    Box::new(node(NodeKind::Int(2))),
+                ))),
+            ))),
+            Box::new(node(NodeKind::Mul(
+                Box::new(node(NodeKind::Sub(
+                    Box::new(node(NodeKind::Int(3))),
+                    Box::new(node(NodeKind::Int(1))),
+                ))),
+                Box::new(node(NodeKind::Int(2))),
+            ))),
+        ));
         let actual = parse(&tokens).unwrap();
         assert_eq!(expected, actual);
     }
@@ -298,16 +1209,17 @@ mod tests {
     fn parses_global_binding() {
         // let foo = 123
         let tokens = vec![
-            Token::Kw(KwKind::Let),
-            Token::Ident("foo".to_string()),
-            Token::Punct("=".to_string()),
-            Token::Int(123),
+            tok(TokenKind::Kw(KwKind::Let)),
+            tok(TokenKind::Ident("foo".to_string())),
+            tok(TokenKind::Punct("=".to_string())),
+            tok(TokenKind::Int(123)),
         ];
-        let expected = Node::Bind(Box::new(BindStruct {
-            name: Node::Ident("foo".to_string()),
+        let expected = node(NodeKind::Bind(Box::new(BindStruct {
+            recursive: false,
+            name: node(NodeKind::Ident("foo".to_string())),
             args: vec![],
-            expr: Node::Int(123),
-        }));
+            expr: node(NodeKind::Int(123)),
+        })));
         let actual = parse(&tokens).unwrap();
         assert_eq!(expected, actual);
     }
@@ -315,8 +1227,8 @@ mod tests {
     #[test]
     fn parses_global_value_eval() {
         // foo
-        let tokens = vec![Token::Ident("foo".to_string())];
-        let expected = Node::Ident("foo".to_string());
+        let tokens = vec![tok(TokenKind::Ident("foo".to_string()))];
+        let expected = node(NodeKind::Ident("foo".to_string()));
         let actual = parse(&tokens).unwrap();
         assert_eq!(expected, actual);
     }
@@ -325,26 +1237,27 @@ mod tests {
     fn parses_local_binding() {
         // let x = 5 in x + 2
         let tokens = vec![
-            Token::Kw(KwKind::Let),
-            Token::Ident("x".to_string()),
-            Token::Punct("=".to_string()),
-            Token::Int(5),
-            Token::Kw(KwKind::In),
-            Token::Ident("x".to_string()),
-            Token::Punct("+".to_string()),
-            Token::Int(2),
+            tok(TokenKind::Kw(KwKind::Let)),
+            tok(TokenKind::Ident("x".to_string())),
+            tok(TokenKind::Punct("=".to_string())),
+            tok(TokenKind::Int(5)),
+            tok(TokenKind::Kw(KwKind::In)),
+            tok(TokenKind::Ident("x".to_string())),
+            tok(TokenKind::Punct("+".to_string())),
+            tok(TokenKind::Int(2)),
         ];
-        let expected = Node::LocalBind(Box::new(LocalBindStruct {
+        let expected = node(NodeKind::LocalBind(Box::new(LocalBindStruct {
             bind: BindStruct {
-                name: Node::Ident("x".to_string()),
+                recursive: false,
+                name: node(NodeKind::Ident("x".to_string())),
                 args: vec![],
-                expr: Node::Int(5),
+                expr: node(NodeKind::Int(5)),
             },
-            scope: Node::Add(
-                Box::new(Node::Ident("x".to_string())),
-                Box::new(Node::Int(2)),
-            ),
-        }));
+            scope: node(NodeKind::Add(
+                Box::new(node(NodeKind::Ident("x".to_string()))),
+                Box::new(node(NodeKind::Int(2))),
+            )),
+        })));
         let actual = parse(&tokens).unwrap();
         assert_eq!(expected, actual);
     }
@@ -352,8 +1265,11 @@ mod tests {
     #[test]
     fn parses_empty_list() {
         // []
-        let tokens = vec![Token::Punct("[".to_string()), Token::Punct("]".to_string())];
-        let expected = Node::List(List::new());
+        let tokens = vec![
+            tok(TokenKind::Punct("[".to_string())),
+            tok(TokenKind::Punct("]".to_string())),
+        ];
+        let expected = node(NodeKind::List(vec![]));
         let actual = parse(&tokens).unwrap();
         assert_eq!(expected, actual);
     }
@@ -362,53 +1278,79 @@ mod tests {
     fn parses_list() {
         // [1; 2; 3]
         let tokens = vec![
-            Token::Punct("[".to_string()),
-            Token::Int(1),
-            Token::Punct(";".to_string()),
-            Token::Int(2),
-            Token::Punct(";".to_string()),
-            Token::Int(3),
-            Token::Punct("]".to_string()),
+            tok(TokenKind::Punct("[".to_string())),
+            tok(TokenKind::Int(1)),
+            tok(TokenKind::Punct(";".to_string())),
+            tok(TokenKind::Int(2)),
+            tok(TokenKind::Punct(";".to_string())),
+            tok(TokenKind::Int(3)),
+            tok(TokenKind::Punct("]".to_string())),
         ];
-        let expected = Node::List(List(
-            Some(1),
-            Some(Box::new(List(
-                Some(2),
-                Some(Box::new(List(Some(3), Some(Box::new(List(None, None)))))),
-            ))),
-        ));
+        let expected = node(NodeKind::List(vec![
+            node(NodeKind::Int(1)),
+            node(NodeKind::Int(2)),
+            node(NodeKind::Int(3)),
+        ]));
+        let actual = parse(&tokens).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn parses_list_of_expressions() {
+        let tokens = tokenize("[1 + 2; x]").unwrap();
+        let expected = node(NodeKind::List(vec![
+            node(NodeKind::Add(
+                Box::new(node(NodeKind::Int(1))),
+                Box::new(node(NodeKind::Int(2))),
+            )),
+            node(NodeKind::Ident("x".to_string())),
+        ]));
         let actual = parse(&tokens).unwrap();
         assert_eq!(expected, actual);
     }
 
     #[test]
     fn parses_true() {
-        let tokens = vec![Token::Kw(KwKind::True)];
-        let expected = Node::Bool(true);
+        let tokens = vec![tok(TokenKind::Kw(KwKind::True))];
+        let expected = node(NodeKind::Bool(true));
         let actual = parse(&tokens).unwrap();
         assert_eq!(expected, actual);
     }
 
     #[test]
     fn parses_false() {
-        let tokens = vec![Token::Kw(KwKind::False)];
-        let expected = Node::Bool(false);
+        let tokens = vec![tok(TokenKind::Kw(KwKind::False))];
+        let expected = node(NodeKind::Bool(false));
         let actual = parse(&tokens).unwrap();
         assert_eq!(expected, actual);
     }
 
     #[test]
     fn parses_equal() {
-        let tokens = vec![Token::Int(2), Token::Punct("==".to_string()), Token::Int(3)];
-        let expected = Node::Eql(Box::new(Node::Int(2)), Box::new(Node::Int(3)));
+        let tokens = vec![
+            tok(TokenKind::Int(2)),
+            tok(TokenKind::Punct("==".to_string())),
+            tok(TokenKind::Int(3)),
+        ];
+        let expected = node(NodeKind::Eql(
+            Box::new(node(NodeKind::Int(2))),
+            Box::new(node(NodeKind::Int(3))),
+        ));
         let actual = parse(&tokens).unwrap();
         assert_eq!(expected, actual);
     }
 
     #[test]
     fn parses_not_equal() {
-        let tokens = vec![Token::Int(2), Token::Punct("!=".to_string()), Token::Int(3)];
-        let expected = Node::Neql(Box::new(Node::Int(2)), Box::new(Node::Int(3)));
+        let tokens = vec![
+            tok(TokenKind::Int(2)),
+            tok(TokenKind::Punct("!=".to_string())),
+            tok(TokenKind::Int(3)),
+        ];
+        let expected = node(NodeKind::Neql(
+            Box::new(node(NodeKind::Int(2))),
+            Box::new(node(NodeKind::Int(3))),
+        ));
         let actual = parse(&tokens).unwrap();
         assert_eq!(expected, actual);
     }
@@ -417,22 +1359,23 @@ mod tests {
     fn parses_func_definition() {
         // let square x = x * x
         let tokens = vec![
-            Token::Kw(KwKind::Let),
-            Token::Ident("square".to_string()),
-            Token::Ident("x".to_string()),
-            Token::Punct("=".to_string()),
-            Token::Ident("x".to_string()),
-            Token::Punct("*".to_string()),
-            Token::Ident("x".to_string()),
+            tok(TokenKind::Kw(KwKind::Let)),
+            tok(TokenKind::Ident("square".to_string())),
+            tok(TokenKind::Ident("x".to_string())),
+            tok(TokenKind::Punct("=".to_string())),
+            tok(TokenKind::Ident("x".to_string())),
+            tok(TokenKind::Punct("*".to_string())),
+            tok(TokenKind::Ident("x".to_string())),
         ];
-        let expected = Node::Bind(Box::new(BindStruct {
-            name: Node::Ident("square".to_string()),
-            args: vec![Node::Ident("x".to_string())],
-            expr: Node::Mul(
-                Box::new(Node::Ident("x".to_string())),
-                Box::new(Node::Ident("x".to_string())),
-            ),
-        }));
+        let expected = node(NodeKind::Bind(Box::new(BindStruct {
+            recursive: false,
+            name: node(NodeKind::Ident("square".to_string())),
+            args: vec![node(NodeKind::Ident("x".to_string()))],
+            expr: node(NodeKind::Mul(
+                Box::new(node(NodeKind::Ident("x".to_string()))),
+                Box::new(node(NodeKind::Ident("x".to_string()))),
+            )),
+        })));
         let actual = parse(&tokens).unwrap();
         assert_eq!(expected, actual);
     }
@@ -441,33 +1384,34 @@ mod tests {
     fn parses_func_definition_with_multi_args() {
         // let calc a b c = a * b + c
         let tokens = vec![
-            Token::Kw(KwKind::Let),
-            Token::Ident("calc".to_string()),
-            Token::Ident("a".to_string()),
-            Token::Ident("b".to_string()),
-            Token::Ident("c".to_string()),
-            Token::Punct("=".to_string()),
-            Token::Ident("a".to_string()),
-            Token::Punct("*".to_string()),
-            Token::Ident("b".to_string()),
-            Token::Punct("+".to_string()),
-            Token::Ident("c".to_string()),
+            tok(TokenKind::Kw(KwKind::Let)),
+            tok(TokenKind::Ident("calc".to_string())),
+            tok(TokenKind::Ident("a".to_string())),
+            tok(TokenKind::Ident("b".to_string())),
+            tok(TokenKind::Ident("c".to_string())),
+            tok(TokenKind::Punct("=".to_string())),
+            tok(TokenKind::Ident("a".to_string())),
+            tok(TokenKind::Punct("*".to_string())),
+            tok(TokenKind::Ident("b".to_string())),
+            tok(TokenKind::Punct("+".to_string())),
+            tok(TokenKind::Ident("c".to_string())),
         ];
-        let expected = Node::Bind(Box::new(BindStruct {
-            name: Node::Ident("calc".to_string()),
+        let expected = node(NodeKind::Bind(Box::new(BindStruct {
+            recursive: false,
+            name: node(NodeKind::Ident("calc".to_string())),
             args: vec![
-                Node::Ident("a".to_string()),
-                Node::Ident("b".to_string()),
-                Node::Ident("c".to_string()),
+                node(NodeKind::Ident("a".to_string())),
+                node(NodeKind::Ident("b".to_string())),
+                node(NodeKind::Ident("c".to_string())),
             ],
-            expr: Node::Add(
-                Box::new(Node::Mul(
-                    Box::new(Node::Ident("a".to_string())),
-                    Box::new(Node::Ident("b".to_string())),
-                )),
-                Box::new(Node::Ident("c".to_string())),
-            ),
-        }));
+            expr: node(NodeKind::Add(
+                Box::new(node(NodeKind::Mul(
+                    Box::new(node(NodeKind::Ident("a".to_string()))),
+                    Box::new(node(NodeKind::Ident("b".to_string()))),
+                ))),
+                Box::new(node(NodeKind::Ident("c".to_string()))),
+            )),
+        })));
         let actual = parse(&tokens).unwrap();
         assert_eq!(expected, actual);
     }
@@ -476,28 +1420,570 @@ mod tests {
     fn parses_local_func_definition() {
         // let square x = x * x in 42
         let tokens = vec![
-            Token::Kw(KwKind::Let),
-            Token::Ident("square".to_string()),
-            Token::Ident("x".to_string()),
-            Token::Punct("=".to_string()),
-            Token::Ident("x".to_string()),
-            Token::Punct("*".to_string()),
-            Token::Ident("x".to_string()),
-            Token::Kw(KwKind::In),
-            Token::Int(42),
+            tok(TokenKind::Kw(KwKind::Let)),
+            tok(TokenKind::Ident("square".to_string())),
+            tok(TokenKind::Ident("x".to_string())),
+            tok(TokenKind::Punct("=".to_string())),
+            tok(TokenKind::Ident("x".to_string())),
+            tok(TokenKind::Punct("*".to_string())),
+            tok(TokenKind::Ident("x".to_string())),
+            tok(TokenKind::Kw(KwKind::In)),
+            tok(TokenKind::Int(42)),
         ];
-        let expected = Node::LocalBind(Box::new(LocalBindStruct {
+        let expected = node(NodeKind::LocalBind(Box::new(LocalBindStruct {
+            bind: BindStruct {
+                recursive: false,
+                name: node(NodeKind::Ident("square".to_string())),
+                args: vec![node(NodeKind::Ident("x".to_string()))],
+                expr: node(NodeKind::Mul(
+                    Box::new(node(NodeKind::Ident("x".to_string()))),
+                    Box::new(node(NodeKind::Ident("x".to_string()))),
+                )),
+            },
+            scope: node(NodeKind::Int(42)),
+        })));
+        let actual = parse(&tokens).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    fn ty(kind: TypeExprKind) -> TypeExpr {
+        TypeExpr::new(kind, Span::default())
+    }
+
+    fn constr(name: &str, args: Vec<TypeExpr>) -> TypeExpr {
+        ty(TypeExprKind::Constr(name.to_string(), args))
+    }
+
+    #[test]
+    fn parses_tuple() {
+        let tokens = tokenize("1, x").unwrap();
+        let expected = node(NodeKind::Tuple(vec![
+            node(NodeKind::Int(1)),
+            node(NodeKind::Ident("x".to_string())),
+        ]));
+        let actual = parse(&tokens).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn parses_type_constraint() {
+        let tokens = tokenize("([] : int list)").unwrap();
+        let expected = node(NodeKind::Constraint(
+            Box::new(node(NodeKind::List(vec![]))),
+            constr("list", vec![constr("int", vec![])]),
+        ));
+        let actual = parse(&tokens).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn parses_annotated_func_definition() {
+        let tokens = tokenize("let f (x : 'a) y : int = y").unwrap();
+        let expected = node(NodeKind::Bind(Box::new(BindStruct {
+            recursive: false,
+            name: node(NodeKind::Ident("f".to_string())),
+            args: vec![
+                node(NodeKind::Constraint(
+                    Box::new(node(NodeKind::Ident("x".to_string()))),
+                    ty(TypeExprKind::Var("a".to_string())),
+                )),
+                node(NodeKind::Ident("y".to_string())),
+            ],
+            expr: node(NodeKind::Constraint(
+                Box::new(node(NodeKind::Ident("y".to_string()))),
+                constr("int", vec![]),
+            )),
+        })));
+        let actual = parse(&tokens).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn parses_type_expressions() {
+        // Arrows are right associative, and bind looser than tuples, which
+        // bind looser than type constructors.
+        let tokens = tokenize("(f : int * 'a list -> (bool, int) t list -> int)").unwrap();
+        let expected = node(NodeKind::Constraint(
+            Box::new(node(NodeKind::Ident("f".to_string()))),
+            ty(TypeExprKind::Arrow(
+                Box::new(ty(TypeExprKind::Tuple(vec![
+                    constr("int", vec![]),
+                    constr("list", vec![ty(TypeExprKind::Var("a".to_string()))]),
+                ]))),
+                Box::new(ty(TypeExprKind::Arrow(
+                    Box::new(constr(
+                        "list",
+                        vec![constr(
+                            "t",
+                            vec![constr("bool", vec![]), constr("int", vec![])],
+                        )],
+                    )),
+                    Box::new(constr("int", vec![])),
+                ))),
+            )),
+        ));
+        let actual = parse(&tokens).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn parses_type_definitions() {
+        let tokens = tokenize(
+            "type 'a tree = Leaf | Node of 'a tree * 'a \
+             and ('k, 'v) assoc = ('k * 'v) list and t and p = { x : int; y : t; }",
+        )
+        .unwrap();
+        let var = |name: &str| ty(TypeExprKind::Var(name.to_string()));
+        let expected = node(NodeKind::TypeDef(vec![
+            TypeDefStruct {
+                name: "tree".to_string(),
+                params: vec!["a".to_string()],
+                repr: TypeRepr::Variant(vec![
+                    ("Leaf".to_string(), None),
+                    (
+                        "Node".to_string(),
+                        Some(ty(TypeExprKind::Tuple(vec![
+                            constr("tree", vec![var("a")]),
+                            var("a"),
+                        ]))),
+                    ),
+                ]),
+            },
+            TypeDefStruct {
+                name: "assoc".to_string(),
+                params: vec!["k".to_string(), "v".to_string()],
+                repr: TypeRepr::Alias(constr(
+                    "list",
+                    vec![ty(TypeExprKind::Tuple(vec![var("k"), var("v")]))],
+                )),
+            },
+            TypeDefStruct {
+                name: "t".to_string(),
+                params: vec![],
+                repr: TypeRepr::Abstract,
+            },
+            TypeDefStruct {
+                name: "p".to_string(),
+                params: vec![],
+                repr: TypeRepr::Record(vec![
+                    ("x".to_string(), constr("int", vec![])),
+                    ("y".to_string(), constr("t", vec![])),
+                ]),
+            },
+        ]));
+        let actual = parse(&tokens).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn parses_constructors() {
+        let tokens = tokenize("Some (Some 1), None").unwrap();
+        let expected = node(NodeKind::Tuple(vec![
+            node(NodeKind::Constr(
+                "Some".to_string(),
+                Some(Box::new(node(NodeKind::Constr(
+                    "Some".to_string(),
+                    Some(Box::new(node(NodeKind::Int(1)))),
+                )))),
+            )),
+            node(NodeKind::Constr("None".to_string(), None)),
+        ]));
+        let actual = parse(&tokens).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn parses_directives() {
+        let tokens = tokenize("#print_length 20").unwrap();
+        let expected = node(NodeKind::Directive(
+            "print_length".to_string(),
+            Some(Box::new(node(NodeKind::Int(20)))),
+        ));
+        let actual = parse(&tokens).unwrap();
+        assert_eq!(expected, actual);
+
+        let tokens = tokenize("#help").unwrap();
+        let expected = node(NodeKind::Directive("help".to_string(), None));
+        let actual = parse(&tokens).unwrap();
+        assert_eq!(expected, actual);
+
+        let tokens = tokenize("# 1").unwrap();
+        let err = parse(&tokens).unwrap_err();
+        assert_eq!(
+            err[0].to_string(),
+            "Syntax error: expected a directive name, found \"1\""
+        );
+    }
+
+    #[test]
+    fn parses_externals() {
+        let tokens = tokenize(r#"external get : string -> string = "http_get""#).unwrap();
+        let expected = node(NodeKind::External(
+            "get".to_string(),
+            ty(TypeExprKind::Arrow(
+                Box::new(constr("string", vec![])),
+                Box::new(constr("string", vec![])),
+            )),
+            "http_get".to_string(),
+        ));
+        let actual = parse(&tokens).unwrap();
+        assert_eq!(expected, actual);
+
+        let tokens = tokenize("external get : string -> string = http_get").unwrap();
+        let err = parse(&tokens).unwrap_err();
+        assert_eq!(
+            err[0].to_string(),
+            "Syntax error: expected a primitive name, found \"http_get\""
+        );
+    }
+
+    #[test]
+    fn parses_strings_and_arrays() {
+        let tokens = tokenize(r#"[| "a"; "b\n" |], [||]"#).unwrap();
+        let expected = node(NodeKind::Tuple(vec![
+            node(NodeKind::Array(vec![
+                node(NodeKind::Str(b"a".to_vec())),
+                node(NodeKind::Str(b"b\n".to_vec())),
+            ])),
+            node(NodeKind::Array(vec![])),
+        ]));
+        let actual = parse(&tokens).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn parses_records_and_field_access() {
+        let tokens = tokenize("{ x = 1; y = p.y.z } .x").unwrap();
+        let ident = |name: &str| Box::new(node(NodeKind::Ident(name.to_string())));
+        let expected = node(NodeKind::Field(
+            Box::new(node(NodeKind::Record(vec![
+                ("x".to_string(), node(NodeKind::Int(1))),
+                (
+                    "y".to_string(),
+                    node(NodeKind::Field(
+                        Box::new(node(NodeKind::Field(ident("p"), "y".to_string()))),
+                        "z".to_string(),
+                    )),
+                ),
+            ]))),
+            "x".to_string(),
+        ));
+        let actual = parse(&tokens).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn parses_applications() {
+        // Applications bind tighter than operators and looser than field
+        // accesses and `!`.
+        let tokens = tokenize("f x.y !r () + g (h 1)").unwrap();
+        let ident = |name: &str| node(NodeKind::Ident(name.to_string()));
+        let app = |func, arg| node(NodeKind::App(Box::new(func), Box::new(arg)));
+        let expected = node(NodeKind::Add(
+            Box::new(app(
+                app(
+                    app(
+                        ident("f"),
+                        node(NodeKind::Field(Box::new(ident("x")), "y".to_string())),
+                    ),
+                    app(ident("!"), ident("r")),
+                ),
+                node(NodeKind::Unit),
+            )),
+            Box::new(app(ident("g"), app(ident("h"), node(NodeKind::Int(1))))),
+        ));
+        let actual = parse(&tokens).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn parses_assignments() {
+        // `:=` is right associative, and binds looser than tuples.
+        let tokens = tokenize("r := s := 1, 2").unwrap();
+        let ident = |name: &str| node(NodeKind::Ident(name.to_string()));
+        let assign = |lhs, rhs| {
+            let app = node(NodeKind::App(Box::new(ident(":=")), Box::new(lhs)));
+            node(NodeKind::App(Box::new(app), Box::new(rhs)))
+        };
+        let expected = assign(
+            ident("r"),
+            assign(
+                ident("s"),
+                node(NodeKind::Tuple(vec![
+                    node(NodeKind::Int(1)),
+                    node(NodeKind::Int(2)),
+                ])),
+            ),
+        );
+        let actual = parse(&tokens).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn parses_sequences() {
+        // Sequences are right associative, and end lists' elements.
+        let tokens = tokenize("let x = a; [b; c] in d; e").unwrap();
+        let ident = |name: &str| node(NodeKind::Ident(name.to_string()));
+        let seq = |lhs, rhs| node(NodeKind::Seq(Box::new(lhs), Box::new(rhs)));
+        let expected = node(NodeKind::LocalBind(Box::new(LocalBindStruct {
             bind: BindStruct {
-                name: Node::Ident("square".to_string()),
-                args: vec![Node::Ident("x".to_string())],
-                expr: Node::Mul(
-                    Box::new(Node::Ident("x".to_string())),
-                    Box::new(Node::Ident("x".to_string())),
+                recursive: false,
+                name: ident("x"),
+                args: vec![],
+                expr: seq(
+                    ident("a"),
+                    node(NodeKind::List(vec![ident("b"), ident("c")])),
+                ),
+            },
+            scope: seq(ident("d"), ident("e")),
+        })));
+        let actual = parse(&tokens).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn parses_comparisons() {
+        // Comparisons bind looser than arithmetic and tighter than tuples.
+        let tokens = tokenize("a + 1 < b = c, d <> e").unwrap();
+        let ident = |name: &str| node(NodeKind::Ident(name.to_string()));
+        let cmp = |op, lhs, rhs| node(NodeKind::Compare(op, Box::new(lhs), Box::new(rhs)));
+        let expected = node(NodeKind::Tuple(vec![
+            cmp(
+                CompareOp::Eq,
+                cmp(
+                    CompareOp::Lt,
+                    node(NodeKind::Add(
+                        Box::new(ident("a")),
+                        Box::new(node(NodeKind::Int(1))),
+                    )),
+                    ident("b"),
                 ),
+                ident("c"),
+            ),
+            cmp(CompareOp::Ne, ident("d"), ident("e")),
+        ]));
+        let actual = parse(&tokens).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn parses_cons() {
+        // Cons is right-associative, binding looser than arithmetic and
+        // tighter than comparisons.
+        let tokens = tokenize("a + 1 :: b :: c = d").unwrap();
+        let ident = |name: &str| node(NodeKind::Ident(name.to_string()));
+        let cons = |lhs, rhs| node(NodeKind::Cons(Box::new(lhs), Box::new(rhs)));
+        let expected = node(NodeKind::Compare(
+            CompareOp::Eq,
+            Box::new(cons(
+                node(NodeKind::Add(
+                    Box::new(ident("a")),
+                    Box::new(node(NodeKind::Int(1))),
+                )),
+                cons(ident("b"), ident("c")),
+            )),
+            Box::new(ident("d")),
+        ));
+        let actual = parse(&tokens).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn parses_conditionals() {
+        // Branches end sequences.
+        let tokens = tokenize("if a then if b then c else d; e").unwrap();
+        let ident = |name: &str| node(NodeKind::Ident(name.to_string()));
+        let cond =
+            |c, t, e: Option<Node>| node(NodeKind::If(Box::new(c), Box::new(t), e.map(Box::new)));
+        let expected = node(NodeKind::Seq(
+            Box::new(cond(
+                ident("a"),
+                cond(ident("b"), ident("c"), Some(ident("d"))),
+                None,
+            )),
+            Box::new(ident("e")),
+        ));
+        let actual = parse(&tokens).unwrap();
+        assert_eq!(expected, actual);
+
+        let tokens = tokenize("if a b").unwrap();
+        let err = parse(&tokens).unwrap_err();
+        assert_eq!(
+            err[0].to_string(),
+            r#"Syntax error: expected "then", found end of input"#
+        );
+    }
+
+    #[test]
+    fn parses_recursive_bindings() {
+        // let rec f n = f n in f
+        let tokens = tokenize("let rec f n = f n in f").unwrap();
+        let ident = |name: &str| node(NodeKind::Ident(name.to_string()));
+        let expected = node(NodeKind::LocalBind(Box::new(LocalBindStruct {
+            bind: BindStruct {
+                recursive: true,
+                name: ident("f"),
+                args: vec![ident("n")],
+                expr: node(NodeKind::App(Box::new(ident("f")), Box::new(ident("n")))),
             },
-            scope: Node::Int(42),
-        }));
+            scope: ident("f"),
+        })));
         let actual = parse(&tokens).unwrap();
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn parses_unit_bindings() {
+        // let () = a in b
+        let tokens = tokenize("let () = a in b").unwrap();
+        let ident = |name: &str| node(NodeKind::Ident(name.to_string()));
+        let unit = TypeExpr {
+            kind: TypeExprKind::Constr("unit".to_string(), vec![]),
+            span: Span::default(),
+        };
+        let expected = node(NodeKind::Seq(
+            Box::new(node(NodeKind::Constraint(Box::new(ident("a")), unit))),
+            Box::new(ident("b")),
+        ));
+        let actual = parse(&tokens).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn split_definitions() {
+        let tokens = tokenize("let x = 1 type t = A let y = let z = 1 in z 1 let w = 2").unwrap();
+        assert_eq!(definition_starts(&tokens), vec![4, 8, 18]);
+        let tokens = tokenize("1 let x = 1").unwrap();
+        assert!(definition_starts(&tokens).is_empty());
+        let tokens = tokenize(r#"external f : int -> int = "f" let x = f 1"#).unwrap();
+        assert_eq!(definition_starts(&tokens), vec![8]);
+    }
+
+    #[test]
+    fn parses_with_spans() {
+        let tokens = tokenize("let x = (1 + 2) * y").unwrap();
+        let offsets = |span: Span| (span.start.offset, span.end.offset);
+        let ast = parse(&tokens).unwrap();
+        assert_eq!(offsets(ast.span), (0, 19));
+        let bind = match ast.kind {
+            NodeKind::Bind(bind) => bind,
+            _ => panic!("expected a binding"),
+        };
+        assert_eq!(offsets(bind.name.span), (4, 5));
+        assert_eq!(offsets(bind.expr.span), (8, 19));
+        match bind.expr.kind {
+            NodeKind::Mul(lhs, rhs) => {
+                assert_eq!(offsets(lhs.span), (8, 15));
+                assert_eq!(offsets(rhs.span), (18, 19));
+            }
+            _ => panic!("expected a multiplication"),
+        }
+    }
+
+    #[test]
+    fn error_with_span_of_unexpected_token() {
+        let tokens = tokenize("let x 1 = 2").unwrap();
+        let errs = parse(&tokens).unwrap_err();
+        assert_eq!(errs.len(), 1);
+        assert_eq!((errs[0].span.start.offset, errs[0].span.end.offset), (6, 7));
+    }
+
+    // Messages and start offsets of errors found in `input`.
+    fn errors_in(input: &str) -> Vec<(String, usize)> {
+        parse(&tokenize(input).unwrap())
+            .unwrap_err()
+            .iter()
+            .map(|err| (err.to_string(), err.span.start.offset))
+            .collect()
+    }
+
+    #[test]
+    fn reports_extra_tokens_as_source_text() {
+        assert_eq!(
+            errors_in("1 )"),
+            vec![(r#"Syntax error: expected ";;", found ")""#.to_string(), 2)]
+        );
+    }
+
+    #[test]
+    fn recovers_from_errors_in_list() {
+        assert_eq!(
+            errors_in("[1; *; 3; / 2]"),
+            vec![
+                (
+                    r#"Syntax error: expected an expression, found "*""#.to_string(),
+                    4
+                ),
+                (
+                    r#"Syntax error: expected an expression, found "/""#.to_string(),
+                    10
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn recovers_from_errors_at_synchronizing_tokens() {
+        assert_eq!(
+            errors_in("let x = 1 + in let y = ( * 2) in [1 let z = 2 in z) + y"),
+            vec![
+                (
+                    r#"Syntax error: expected an expression, found "in""#.to_string(),
+                    12
+                ),
+                (
+                    r#"Syntax error: expected an expression, found "*""#.to_string(),
+                    25
+                ),
+                (
+                    r#"Syntax error: expected ";" or "]", found "let""#.to_string(),
+                    36
+                ),
+                (
+                    r#"Syntax error: expected ";" or "]", found ")""#.to_string(),
+                    50
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn recovers_from_errors_in_binding_header() {
+        assert_eq!(
+            errors_in("let 1 = 2 in x"),
+            vec![(
+                r#"Syntax error: expected an identifier, found "1""#.to_string(),
+                4
+            )]
+        );
+    }
+
+    #[test]
+    fn error_in_type_expression() {
+        assert_eq!(
+            errors_in("let f (x : int *) = x"),
+            vec![(
+                r#"Syntax error: expected a type, found ")""#.to_string(),
+                16
+            )]
+        );
+        assert_eq!(
+            errors_in("(x : (int, bool))"),
+            vec![(
+                r#"Syntax error: expected a type constructor, found ")""#.to_string(),
+                16
+            )]
+        );
+    }
+
+    #[test]
+    fn reports_end_of_input() {
+        assert_eq!(
+            errors_in("(1 + 2"),
+            vec![(
+                r#"Syntax error: expected ")", found end of input"#.to_string(),
+                usize::MAX
+            )]
+        );
+    }
 }
@@ -0,0 +1,367 @@
+use super::format::Doc;
+use super::lexer::escape;
+use super::types::{Type, TypeDeclKind, TypeDecls};
+use super::value::Value;
+use std::rc::Rc;
+
+// Limits on how much of a value the toplevel prints, set by the
+// `#print_depth` and `#print_length` directives. Parts beyond them are
+// elided as `...`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Limits {
+    pub depth: usize,  // levels of nested values
+    pub length: usize, // values printed in total
+}
+
+impl Default for Limits {
+    // the defaults of the OCaml toplevel
+    fn default() -> Self {
+        Limits {
+            depth: 100,
+            length: 100,
+        }
+    }
+}
+
+// Formats a value as the toplevel prints it, walking it alongside its type,
+// e.g. `Some (-1)` of type `int option`, `{x = 1; y = [true]}` of a record
+// type, or `<fun>` of a function type.
+pub(super) fn show_value(value: &Value, ty: &Type, decls: &TypeDecls, limits: &Limits) -> Doc {
+    let mut printer = Printer {
+        decls,
+        steps: limits.length,
+        path: Vec::new(),
+    };
+    printer.show(value, ty, limits.depth, false)
+}
+
+struct Printer<'a> {
+    decls: &'a TypeDecls,
+    // number of values which can still be printed
+    steps: usize,
+    // mutable values being printed, to find cycles through them
+    path: Vec<*const ()>,
+}
+
+impl Printer<'_> {
+    // Shows a value within `depth` levels of nesting.
+    //
+    // `is_arg` tells whether the value is an argument of a constructor,
+    // which is parenthesized if it is a negative integer or a constructor
+    // applied to an argument.
+    fn show(&mut self, value: &Value, ty: &Type, depth: usize, is_arg: bool) -> Doc {
+        if self.exhausted(depth) {
+            return Doc::text("...");
+        }
+        self.steps -= 1;
+
+        let mut ty = ty.resolve();
+        while let Some(expanded) = self.decls.expand(&ty) {
+            ty = expanded.resolve();
+        }
+
+        match (value, &ty) {
+            // Values of unknown types are not inspected.
+            (_, Type::Var(_) | Type::Gen(_)) => Doc::text("<poly>"),
+            (Value::Fn(_), _) => Doc::text("<fun>"),
+            (Value::Int(i), Type::Int) if is_arg && *i < 0 => Doc::text(format!("({})", i)),
+            (Value::Int(i), Type::Int) => Doc::text(i.to_string()),
+            (Value::Bool(b), Type::Bool) => Doc::text(b.to_string()),
+            (Value::Unit, Type::Con(name, _)) if name == "unit" => Doc::text("()"),
+            (Value::Str(s), Type::Con(name, _)) if name == "string" => {
+                Doc::text(format!("\"{}\"", escape(s)))
+            }
+            (Value::List(list), Type::List(elem)) => {
+                let elems = self.limited(list.iter(), depth - 1, |printer, v, depth| {
+                    printer.show(v, elem, depth, false)
+                });
+                sequence(1, "[", ";", "]", elems)
+            }
+            (Value::Tuple(values), Type::Tuple(tys)) => {
+                let elems = self.limited(
+                    values.iter().zip(tys),
+                    depth - 1,
+                    |printer, (v, ty), depth| printer.show(v, ty, depth, false),
+                );
+                sequence(1, "(", ",", ")", elems)
+            }
+            (Value::Array(values), Type::Con(name, args)) if name == "array" => {
+                let ptr = Rc::as_ptr(values) as *const ();
+                if self.path.contains(&ptr) {
+                    return Doc::text("<cycle>");
+                }
+                self.path.push(ptr);
+                let elems = self.limited(values.borrow().iter(), depth - 1, |printer, v, depth| {
+                    printer.show(v, &args[0], depth, false)
+                });
+                self.path.pop();
+                sequence(2, "[|", ";", "|]", elems)
+            }
+            // `ref` is a record with a mutable field `contents`.
+            (Value::Ref(cell), Type::Con(name, args)) if name == "ref" => {
+                let ptr = Rc::as_ptr(cell) as *const ();
+                if self.path.contains(&ptr) {
+                    return Doc::text("<cycle>");
+                }
+                self.path.push(ptr);
+                let field = self.field("contents", &cell.borrow(), &args[0], depth - 1);
+                self.path.pop();
+                sequence(1, "{", ";", "}", vec![field])
+            }
+            (Value::Record(values), Type::Con(name, args)) => {
+                let fields = match self.decls.get(name).map(|decl| &decl.kind) {
+                    Some(TypeDeclKind::Record(fields)) => fields,
+                    _ => return Doc::text("<abstr>"),
+                };
+                let fields = self.limited(
+                    values.iter().zip(fields),
+                    depth - 1,
+                    |printer, ((name, v), (_, ty)), depth| {
+                        printer.field(name, v, &ty.subst(args), depth)
+                    },
+                );
+                sequence(1, "{", ";", "}", fields)
+            }
+            (Value::Constr(constr, arg), Type::Con(name, args)) => {
+                let constrs = match self.decls.get(name).map(|decl| &decl.kind) {
+                    Some(TypeDeclKind::Variant(constrs)) => constrs,
+                    _ => return Doc::text("<abstr>"),
+                };
+                let arg_ty = match constrs.iter().find(|(name, _)| **name == **constr) {
+                    Some((_, arg_ty)) => arg_ty.as_ref().map(|ty| ty.subst(args)),
+                    None => return Doc::text("<abstr>"),
+                };
+                let (arg, arg_ty) = match (arg, arg_ty) {
+                    (Some(arg), Some(arg_ty)) => (arg, arg_ty),
+                    _ => return Doc::text(constr.to_string()),
+                };
+                let arg = self.show(arg, &arg_ty, depth - 1, true);
+                let doc = Doc::Box(1, vec![Doc::text(constr.to_string()), Doc::space(), arg]);
+                if is_arg {
+                    Doc::Box(1, vec![Doc::text("("), doc, Doc::text(")")])
+                } else {
+                    doc
+                }
+            }
+            // Values of abstract types are not shown.
+            _ => Doc::text("<abstr>"),
+        }
+    }
+
+    // Returns whether no more values can be printed at `depth`.
+    fn exhausted(&self, depth: usize) -> bool {
+        depth == 0 || self.steps == 0
+    }
+
+    // Shows items with `show` until the limits are exhausted, when a single
+    // `...` ends them. This never walks the rest of long lists.
+    fn limited<T>(
+        &mut self,
+        items: impl Iterator<Item = T>,
+        depth: usize,
+        mut show: impl FnMut(&mut Self, T, usize) -> Doc,
+    ) -> Vec<Doc> {
+        let mut docs = Vec::new();
+        for item in items {
+            if self.exhausted(depth) {
+                docs.push(Doc::text("..."));
+                break;
+            }
+            docs.push(show(self, item, depth));
+        }
+        docs
+    }
+
+    // `name = value` in a record
+    fn field(&mut self, name: &str, value: &Value, ty: &Type, depth: usize) -> Doc {
+        Doc::Box(
+            1,
+            vec![
+                Doc::text(name),
+                Doc::space(),
+                Doc::text("="),
+                Doc::space(),
+                self.show(value, ty, depth, false),
+            ],
+        )
+    }
+}
+
+// Elements between `open` and `close`, separated by `sep` and break hints.
+fn sequence(indent: usize, open: &str, sep: &str, close: &str, elems: Vec<Doc>) -> Doc {
+    let mut docs = vec![Doc::text(open)];
+    for (idx, elem) in elems.into_iter().enumerate() {
+        if idx > 0 {
+            docs.push(Doc::text(sep));
+            docs.push(Doc::space());
+        }
+        docs.push(elem);
+    }
+    docs.push(Doc::text(close));
+    Doc::Box(indent, docs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::builtins::{array, reference, string, unit};
+    use crate::eval::lexer::tokenize;
+    use crate::eval::parser::{parse, NodeKind};
+    use crate::eval::typing::declare_types;
+    use crate::eval::value::List;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // Declarations of the types defined by `input`.
+    fn declared(input: &str) -> TypeDecls {
+        let mut decls = TypeDecls::default();
+        match parse(&tokenize(input).unwrap()).unwrap().kind {
+            NodeKind::TypeDef(defs) => {
+                for decl in declare_types(&defs, &decls).unwrap() {
+                    decls.declare(decl);
+                }
+            }
+            kind => panic!("Expected a type definition: {:?}", kind),
+        }
+        decls
+    }
+
+    fn show(value: &Value, ty: &Type, decls: &TypeDecls) -> String {
+        show_value(value, ty, decls, &Limits::default()).render()
+    }
+
+    fn list(ty: Type) -> Type {
+        Type::List(Box::new(ty))
+    }
+
+    fn con(name: &str, args: Vec<Type>) -> Type {
+        Type::Con(name.to_string(), args)
+    }
+
+    fn constr(name: &str, arg: Option<Value>) -> Value {
+        Value::Constr(name.into(), arg.map(Rc::new))
+    }
+
+    #[test]
+    fn show_tuples_and_lists() {
+        let decls = TypeDecls::default();
+        let value = Value::Tuple(Rc::new([
+            Value::Int(1),
+            Value::List(List::from(vec![Value::Bool(false)])),
+        ]));
+        let ty = Type::Tuple(vec![Type::Int, list(Type::Bool)]);
+        assert_eq!(show(&value, &ty, &decls), "(1, [false])");
+
+        let value = Value::List(List::from(vec![
+            Value::List(List::from(vec![Value::Bool(true)])),
+            Value::List(List::new()),
+        ]));
+        assert_eq!(
+            show(&value, &list(list(Type::Bool)), &decls),
+            "[[true]; []]"
+        );
+    }
+
+    #[test]
+    fn show_strings_and_arrays() {
+        let decls = TypeDecls::default();
+        let value = Value::Str(Rc::new(*b"a\"b\n"));
+        assert_eq!(show(&value, &string(), &decls), r#""a\"b\n""#);
+
+        let value = Value::Array(Rc::new(RefCell::new(vec![Value::Int(1), Value::Int(2)])));
+        assert_eq!(show(&value, &array(Type::Int), &decls), "[|1; 2|]");
+    }
+
+    #[test]
+    fn show_variants() {
+        let decls = declared("type 'a tree = Leaf | Node of 'a tree * 'a * 'a tree and 'a option = None | Some of 'a");
+        let leaf = || constr("Leaf", None);
+        let value = constr(
+            "Node",
+            Some(Value::Tuple(Rc::new([leaf(), Value::Int(1), leaf()]))),
+        );
+        let ty = con("tree", vec![Type::Int]);
+        assert_eq!(show(&value, &ty, &decls), "Node (Leaf, 1, Leaf)");
+
+        // Arguments which are constructors with arguments, or negative
+        // integers, are parenthesized.
+        let value = constr("Some", Some(constr("Some", Some(Value::Int(1)))));
+        let ty = con("option", vec![con("option", vec![Type::Int])]);
+        assert_eq!(show(&value, &ty, &decls), "Some (Some 1)");
+        let value = constr("Some", Some(Value::Int(-1)));
+        let ty = con("option", vec![Type::Int]);
+        assert_eq!(show(&value, &ty, &decls), "Some (-1)");
+    }
+
+    #[test]
+    fn show_records_and_references() {
+        let decls = declared("type 'a point = { x : int; y : 'a }");
+        let value = Value::Record(Rc::new([
+            ("x".to_string(), Value::Int(1)),
+            ("y".to_string(), Value::Bool(true)),
+        ]));
+        let ty = con("point", vec![Type::Bool]);
+        assert_eq!(show(&value, &ty, &decls), "{x = 1; y = true}");
+
+        let value = Value::Ref(Rc::new(RefCell::new(Value::Unit)));
+        assert_eq!(show(&value, &reference(unit()), &decls), "{contents = ()}");
+    }
+
+    #[test]
+    fn show_values_of_unknown_types() {
+        let decls = declared("type t");
+        let value = Value::List(List::from(vec![Value::Int(1)]));
+        assert_eq!(show(&value, &list(Type::Gen(0)), &decls), "[<poly>]");
+        assert_eq!(show(&Value::Unit, &con("t", vec![]), &decls), "<abstr>");
+    }
+
+    #[test]
+    fn show_values_within_limits() {
+        let decls = TypeDecls::default();
+        let limits = Limits {
+            depth: 3,
+            length: 5,
+        };
+        let elems: Vec<Value> = (0..1000).map(Value::Int).collect();
+        let value = Value::List(List::from(elems));
+        assert_eq!(
+            show_value(&value, &list(Type::Int), &decls, &limits).render(),
+            "[0; 1; 2; 3; ...]"
+        );
+
+        let nested = |v| Value::List(List::from(vec![v]));
+        let value = nested(nested(nested(Value::Int(1))));
+        let ty = list(list(list(Type::Int)));
+        assert_eq!(
+            show_value(&value, &ty, &decls, &limits).render(),
+            "[[[...]]]"
+        );
+    }
+
+    #[test]
+    fn show_cyclic_values() {
+        // let r = ref Leaf in r := Node r
+        let decls = declared("type t = Leaf | Node of t ref");
+        let cell = Rc::new(RefCell::new(constr("Leaf", None)));
+        *cell.borrow_mut() = constr("Node", Some(Value::Ref(cell.clone())));
+        let value = Value::Ref(cell);
+        assert_eq!(
+            show(&value, &reference(con("t", vec![])), &decls),
+            "{contents = Node <cycle>}"
+        );
+    }
+
+    #[test]
+    fn show_long_values_on_lines() {
+        let decls = TypeDecls::default();
+        let elems: Vec<Value> = (0..30).map(Value::Int).collect();
+        let value = Value::Tuple(Rc::new([Value::List(List::from(elems)), Value::Bool(true)]));
+        let ty = Type::Tuple(vec![list(Type::Int), Type::Bool]);
+        assert_eq!(
+            show(&value, &ty, &decls),
+            "([0; 1; 2; 3; 4; 5; 6; 7; 8; 9; 10; 11; 12; 13; 14; 15; 16; 17; 18; 19; 20;\n  \
+             21; 22; 23; 24; 25; 26; 27; 28; 29],\n \
+             true)"
+        );
+    }
+}
@@ -0,0 +1,458 @@
+/* Runtime of programs compiled to C by rcaml, which precedes their code.
+
+   Values are words: ints are tagged by their lowest bit, as in OCaml, so
+   they have 63 bits, and other values are pointers to blocks of words
+   allocated by malloc() with a header. Blocks are freed by a mark-and-sweep
+   collector, which finds the values in use from frames of local values
+   linked by the compiled functions. Helpers which a program may not use are
+   inline, so that compilers do not warn of them. */
+
+#include <inttypes.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+typedef intptr_t value;
+
+#define Val_int(n) ((value)(((uintptr_t)(n) << 1) | 1))
+#define Int_val(v) ((v) >> 1)
+#define Is_int(v) ((v)&1)
+#define Val_unit Val_int(0)
+#define Val_false Val_int(0)
+#define Val_true Val_int(1)
+#define Val_bool(b) ((b) ? Val_true : Val_false)
+#define Field(v, i) (((value *)(v))[i])
+
+/* Tags of blocks other than those of constructors with an argument, which
+   are numbered from 0 in their declared order, as are tuples, records,
+   lists, arrays and references by 0. */
+#define Closure_tag 247
+#define Pap_tag 248
+#define String_tag 252
+
+struct header {
+  struct header *next;
+  size_t size;
+  unsigned char tag;
+  unsigned char marked;
+};
+
+#define Hd(v) (((struct header *)(v)) - 1)
+#define Tag(v) (Hd(v)->tag)
+#define Size(v) (Hd(v)->size)
+
+/* A closure holds its code, its arity and the values it captured. A partial
+   application holds a closure and the arguments applied so far. */
+typedef value (*code)(value self, value *args);
+#define Code(v) ((code)Field(v, 0))
+#define Arity(v) Int_val(Field(v, 1))
+
+/* A string holds its length and its bytes, followed by a NUL. */
+#define String_len(v) Int_val(Field(v, 0))
+#define String_bytes(v) ((char *)&Field(v, 1))
+
+/* Local values of a function, linked from the innermost. */
+struct frame {
+  struct frame *prev;
+  size_t len;
+  value *values;
+};
+
+static struct frame *rc_frames;
+
+#define RC_ENTER(n)                                                            \
+  value v[n];                                                                  \
+  struct frame rc_frame = {rc_frames, n, v};                                   \
+  rc_check_stack((char *)v);                                                   \
+  rc_clear(v, n);                                                              \
+  rc_frames = &rc_frame
+#define RC_RETURN(e)                                                           \
+  do {                                                                         \
+    value rc_result = (e);                                                     \
+    rc_frames = rc_frame.prev;                                                 \
+    return rc_result;                                                          \
+  } while (0)
+
+static void rc_clear(value *values, size_t len) {
+  size_t i;
+  for (i = 0; i < len; i++)
+    values[i] = Val_unit;
+}
+
+static void rc_fatal(const char *exn) {
+  fflush(stdout);
+  fprintf(stderr, "Fatal error: exception %s\n", exn);
+  exit(2);
+}
+
+/* Stack_overflow is raised once the stack grows by RC_STACK_SIZE bytes from
+   where the runtime started, rather than crashing on the limit of the
+   system, e.g. 8MB on Linux. */
+#ifndef RC_STACK_SIZE
+#define RC_STACK_SIZE (6 << 20)
+#endif
+
+static uintptr_t rc_stack_base;
+
+static void rc_check_stack(char *top) {
+  uintptr_t here = (uintptr_t)top;
+  uintptr_t used = here < rc_stack_base ? rc_stack_base - here : here - rc_stack_base;
+  if (used > RC_STACK_SIZE)
+    rc_fatal("Stack_overflow");
+}
+
+/* Memory */
+
+/* words allocated before the first collection, after which twice those
+   live are */
+#ifndef RC_HEAP_SIZE
+#define RC_HEAP_SIZE (1 << 20)
+#endif
+
+static struct header *rc_heap;
+/* words allocated since the last collection, and how many trigger the next */
+static size_t rc_allocated, rc_threshold = RC_HEAP_SIZE;
+static value *rc_marks;
+static size_t rc_marks_len, rc_marks_cap;
+
+static void rc_push_mark(value v) {
+  if (Is_int(v) || v == 0 || Hd(v)->marked)
+    return;
+  Hd(v)->marked = 1;
+  if (rc_marks_len == rc_marks_cap) {
+    rc_marks_cap = rc_marks_cap ? 2 * rc_marks_cap : 1024;
+    rc_marks = realloc(rc_marks, rc_marks_cap * sizeof(value));
+    if (!rc_marks)
+      rc_fatal("Out_of_memory");
+  }
+  rc_marks[rc_marks_len++] = v;
+}
+
+static void rc_collect(void) {
+  struct frame *frame;
+  struct header **link = &rc_heap;
+  size_t i, live = 0;
+  for (frame = rc_frames; frame; frame = frame->prev)
+    for (i = 0; i < frame->len; i++)
+      rc_push_mark(frame->values[i]);
+  while (rc_marks_len > 0) {
+    value v = rc_marks[--rc_marks_len];
+    if (Tag(v) == String_tag)
+      continue;
+    /* The code of a closure is not a value. */
+    for (i = Tag(v) == Closure_tag ? 1 : 0; i < Size(v); i++)
+      rc_push_mark(Field(v, i));
+  }
+  while (*link) {
+    struct header *hd = *link;
+    if (hd->marked) {
+      hd->marked = 0;
+      live += hd->size + 3;
+      link = &hd->next;
+    } else {
+      *link = hd->next;
+      free(hd);
+    }
+  }
+  rc_allocated = 0;
+  if (rc_threshold < 2 * live)
+    rc_threshold = 2 * live;
+}
+
+/* Allocates a block whose fields are set to unit, or left uninitialized
+   for a string. */
+static value rc_alloc(size_t size, unsigned char tag) {
+  struct header *hd;
+  value v;
+  size_t i;
+  if (rc_allocated > rc_threshold)
+    rc_collect();
+  hd = malloc(sizeof(struct header) + size * sizeof(value));
+  if (!hd)
+    rc_fatal("Out_of_memory");
+  hd->next = rc_heap;
+  hd->size = size;
+  hd->tag = tag;
+  hd->marked = 0;
+  rc_heap = hd;
+  rc_allocated += size + 3;
+  v = (value)(hd + 1);
+  if (tag != String_tag)
+    for (i = 0; i < size; i++)
+      Field(v, i) = Val_unit;
+  return v;
+}
+
+static inline value rc_string(const char *bytes, size_t len) {
+  value v = rc_alloc(1 + len / sizeof(value) + 1, String_tag);
+  Field(v, 0) = Val_int(len);
+  memcpy(String_bytes(v), bytes, len);
+  String_bytes(v)[len] = 0;
+  return v;
+}
+
+static value rc_closure(code code, intptr_t arity, size_t captured) {
+  value v = rc_alloc(2 + captured, Closure_tag);
+  Field(v, 0) = (value)code;
+  Field(v, 1) = Val_int(arity);
+  return v;
+}
+
+static inline value rc_cons(value head, value tail) {
+  value v;
+  value roots[2];
+  struct frame frame = {rc_frames, 2, roots};
+  roots[0] = head;
+  roots[1] = tail;
+  rc_frames = &frame;
+  v = rc_alloc(2, 0);
+  Field(v, 0) = roots[0];
+  Field(v, 1) = roots[1];
+  rc_frames = frame.prev;
+  return v;
+}
+
+/* Application */
+
+/* Applies a function to `n` arguments in turn. Arguments are copied by the
+   functions called before they allocate. */
+static inline value rc_apply(value f, intptr_t n, value *args) {
+  value roots[3];
+  struct frame frame = {rc_frames, 3, roots};
+  struct frame arg_frame = {&frame, (size_t)n, args};
+  intptr_t i;
+  roots[0] = f;
+  roots[1] = roots[2] = Val_unit;
+  rc_frames = &arg_frame;
+  while (n > 0) {
+    value clo = Tag(roots[0]) == Pap_tag ? Field(roots[0], 0) : roots[0];
+    intptr_t held = Tag(roots[0]) == Pap_tag ? (intptr_t)Size(roots[0]) - 1 : 0;
+    intptr_t arity = Arity(clo);
+    if (held + n < arity) {
+      value pap = rc_alloc(1 + held + n, Pap_tag);
+      Field(pap, 0) = Tag(roots[0]) == Pap_tag ? Field(roots[0], 0) : roots[0];
+      for (i = 0; i < held; i++)
+        Field(pap, 1 + i) = Field(roots[0], 1 + i);
+      for (i = 0; i < n; i++)
+        Field(pap, 1 + held + i) = args[i];
+      roots[0] = pap;
+      break;
+    }
+    if (held == 0) {
+      roots[0] = Code(clo)(clo, args);
+    } else {
+      roots[1] = rc_alloc(arity, 0);
+      roots[2] = Field(roots[0], 0);
+      for (i = 0; i < held; i++)
+        Field(roots[1], i) = Field(roots[0], 1 + i);
+      for (i = held; i < arity; i++)
+        Field(roots[1], i) = args[i - held];
+      roots[0] = Code(roots[2])(roots[2], &Field(roots[1], 0));
+    }
+    args += arity - held;
+    n -= arity - held;
+    arg_frame.values = args;
+    arg_frame.len = (size_t)n;
+  }
+  rc_frames = frame.prev;
+  return roots[0];
+}
+
+/* Comparison */
+
+static void rc_functional(const char *prim) {
+  char exn[64];
+  sprintf(exn, "Invalid_argument(\"%s: functional value\")", prim);
+  rc_fatal(exn);
+}
+
+/* Orders values as `compare` does, raising on functions. Immediate values,
+   e.g. constant constructors, come before blocks. */
+static inline int rc_compare(value a, value b, const char *prim) {
+  size_t i;
+  for (;;) {
+    if (Is_int(a) && Is_int(b))
+      return a < b ? -1 : a > b;
+    if (Is_int(a))
+      return -1;
+    if (Is_int(b))
+      return 1;
+    if (Tag(a) == Closure_tag || Tag(a) == Pap_tag || Tag(b) == Closure_tag ||
+        Tag(b) == Pap_tag)
+      rc_functional(prim);
+    if (Tag(a) == String_tag) {
+      size_t la = String_len(a), lb = String_len(b);
+      int c = memcmp(String_bytes(a), String_bytes(b), la < lb ? la : lb);
+      if (c != 0)
+        return c < 0 ? -1 : 1;
+      return la < lb ? -1 : la > lb;
+    }
+    if (Tag(a) != Tag(b))
+      return Tag(a) < Tag(b) ? -1 : 1;
+    if (Size(a) != Size(b))
+      return Size(a) < Size(b) ? -1 : 1;
+    if (Size(a) == 0)
+      return 0;
+    for (i = 0; i + 1 < Size(a); i++) {
+      int c = rc_compare(Field(a, i), Field(b, i), prim);
+      if (c != 0)
+        return c;
+    }
+    /* The last field, e.g. the tail of a list, is compared in turn. */
+    a = Field(a, i);
+    b = Field(b, i);
+  }
+}
+
+/* Whether values are equal as `==` tells, functions being equal only to
+   themselves. */
+static inline int rc_equal(value a, value b) {
+  size_t i;
+  for (;;) {
+    if (a == b)
+      return 1;
+    if (Is_int(a) || Is_int(b) || Tag(a) != Tag(b) || Size(a) != Size(b))
+      return 0;
+    if (Tag(a) == Closure_tag || Tag(a) == Pap_tag)
+      return 0;
+    if (Tag(a) == String_tag)
+      return String_len(a) == String_len(b) &&
+             memcmp(String_bytes(a), String_bytes(b), String_len(a)) == 0;
+    if (Size(a) == 0)
+      return 1;
+    for (i = 0; i + 1 < Size(a); i++)
+      if (!rc_equal(Field(a, i), Field(b, i)))
+        return 0;
+    a = Field(a, i);
+    b = Field(b, i);
+  }
+}
+
+/* Arithmetic, wrapping around on overflow */
+
+#define rc_add(a, b) ((value)((uintptr_t)(a) + (uintptr_t)(b)-1))
+#define rc_sub(a, b) ((value)((uintptr_t)(a) - (uintptr_t)(b) + 1))
+#define rc_mul(a, b) Val_int((uintptr_t)Int_val(a) * (uintptr_t)Int_val(b))
+
+static inline value rc_div(value a, value b) {
+  if (Int_val(b) == 0)
+    rc_fatal("Division_by_zero");
+  if (Int_val(b) == -1)
+    return Val_int(-(uintptr_t)Int_val(a));
+  return Val_int(Int_val(a) / Int_val(b));
+}
+
+/* Primitives */
+
+static value rc_ref(value x) {
+  value roots[1];
+  struct frame frame = {rc_frames, 1, roots};
+  value v;
+  roots[0] = x;
+  rc_frames = &frame;
+  v = rc_alloc(1, 0);
+  Field(v, 0) = roots[0];
+  rc_frames = frame.prev;
+  return v;
+}
+
+#define rc_deref(r) Field(r, 0)
+
+static value rc_assign(value r, value x) {
+  Field(r, 0) = x;
+  return Val_unit;
+}
+
+static value rc_print_string(value s) {
+  fwrite(String_bytes(s), 1, String_len(s), stdout);
+  return Val_unit;
+}
+
+static value rc_print_endline(value s) {
+  rc_print_string(s);
+  putchar('\n');
+  return Val_unit;
+}
+
+static value rc_print_int(value n) {
+  printf("%" PRIdPTR, Int_val(n));
+  return Val_unit;
+}
+
+static value rc_print_newline(value u) {
+  (void)u;
+  putchar('\n');
+  fflush(stdout);
+  return Val_unit;
+}
+
+/* Raises Failure with the message escaped as OCaml prints it. */
+static value rc_failwith(value s) {
+  char *exn = malloc(4 * String_len(s) + 16);
+  char *p = exn;
+  size_t i;
+  if (!exn)
+    rc_fatal("Out_of_memory");
+  p += sprintf(p, "Failure(\"");
+  for (i = 0; i < (size_t)String_len(s); i++) {
+    unsigned char c = String_bytes(s)[i];
+    switch (c) {
+    case '"': p += sprintf(p, "\\\""); break;
+    case '\\': p += sprintf(p, "\\\\"); break;
+    case '\n': p += sprintf(p, "\\n"); break;
+    case '\t': p += sprintf(p, "\\t"); break;
+    case '\r': p += sprintf(p, "\\r"); break;
+    case '\b': p += sprintf(p, "\\b"); break;
+    default:
+      if (c >= ' ' && c <= '~')
+        *p++ = (char)c;
+      else
+        p += sprintf(p, "\\%03d", c);
+    }
+  }
+  sprintf(p, "\")");
+  rc_fatal(exn);
+  return Val_unit;
+}
+
+static value rc_code_ref(value self, value *args) { (void)self; return rc_ref(args[0]); }
+static value rc_code_deref(value self, value *args) { (void)self; return rc_deref(args[0]); }
+static value rc_code_assign(value self, value *args) { (void)self; return rc_assign(args[0], args[1]); }
+static value rc_code_print_string(value self, value *args) { (void)self; return rc_print_string(args[0]); }
+static value rc_code_print_endline(value self, value *args) { (void)self; return rc_print_endline(args[0]); }
+static value rc_code_print_int(value self, value *args) { (void)self; return rc_print_int(args[0]); }
+static value rc_code_print_newline(value self, value *args) { (void)self; return rc_print_newline(args[0]); }
+static value rc_code_failwith(value self, value *args) { (void)self; return rc_failwith(args[0]); }
+
+/* Closures of the primitives, used as values */
+enum {
+  RC_PRIM_REF,
+  RC_PRIM_DEREF,
+  RC_PRIM_ASSIGN,
+  RC_PRIM_PRINT_STRING,
+  RC_PRIM_PRINT_ENDLINE,
+  RC_PRIM_PRINT_INT,
+  RC_PRIM_PRINT_NEWLINE,
+  RC_PRIM_FAILWITH,
+  RC_PRIMS
+};
+
+static value rc_prims[RC_PRIMS];
+static struct frame rc_prims_frame = {NULL, RC_PRIMS, rc_prims};
+
+/* Starts the runtime, with global values held by `globals`. */
+static void rc_init(struct frame *globals) {
+  char base;
+  rc_stack_base = (uintptr_t)&base;
+  rc_prims_frame.prev = globals;
+  rc_frames = &rc_prims_frame;
+  rc_prims[RC_PRIM_REF] = rc_closure(rc_code_ref, 1, 0);
+  rc_prims[RC_PRIM_DEREF] = rc_closure(rc_code_deref, 1, 0);
+  rc_prims[RC_PRIM_ASSIGN] = rc_closure(rc_code_assign, 2, 0);
+  rc_prims[RC_PRIM_PRINT_STRING] = rc_closure(rc_code_print_string, 1, 0);
+  rc_prims[RC_PRIM_PRINT_ENDLINE] = rc_closure(rc_code_print_endline, 1, 0);
+  rc_prims[RC_PRIM_PRINT_INT] = rc_closure(rc_code_print_int, 1, 0);
+  rc_prims[RC_PRIM_PRINT_NEWLINE] = rc_closure(rc_code_print_newline, 1, 0);
+  rc_prims[RC_PRIM_FAILWITH] = rc_closure(rc_code_failwith, 1, 0);
+}
@@ -0,0 +1,403 @@
+  ;; Runtime of programs compiled to WebAssembly by rcaml, spliced into their
+  ;; modules after the imports.
+  ;;
+  ;; Values are i64 words laid out as by the C backend: ints are tagged by
+  ;; their lowest bit, so they have 63 bits, and other values are addresses
+  ;; of blocks of words, preceded by a header holding size << 8 | tag.
+  ;; Blocks are allocated from the end of the static data, and never freed.
+  ;; Memory below 1MB is used by the runtime: its messages below 1024, and
+  ;; the stack of arguments passed to functions applied by $apply above.
+  ;;
+  ;; A closure holds the index of its code in the table, its arity and the
+  ;; values it captured, and is called as (self, arg1, ..., argN). A partial
+  ;; application holds a closure and the arguments applied so far. A string
+  ;; holds its length and its bytes.
+  ;;
+  ;; The host prints bytes by "print", and is told the uncaught exception by
+  ;; "raise", e.g. `Failure("boom")`, after which the program traps.
+
+  (global $sp (mut i32) (i32.const 1048576))
+
+  (data (i32.const 48) "\n")
+  (data (i32.const 64) "Out_of_memory")
+  (data (i32.const 80) "Stack_overflow")
+  (data (i32.const 96) "Division_by_zero")
+  (data (i32.const 128) "Invalid_argument(\22compare: functional value\22)")
+  (data (i32.const 192) "Invalid_argument(\22equal: functional value\22)")
+  (data (i32.const 240) "Failure(\22")
+
+  (func $fail (param $exn i32) (param $len i32)
+    (call $raise (local.get $exn) (local.get $len))
+    (unreachable))
+
+  ;; Memory
+
+  ;; Allocates a block whose fields are set to unit, or left as they are for
+  ;; a string, growing the memory as needed.
+  (func $alloc (param $size i32) (param $tag i32) (result i64)
+    (local $p i32) (local $end i32) (local $limit i32) (local $pages i32)
+    (local.set $p (global.get $hp))
+    (local.set $end
+      (i32.add (local.get $p) (i32.shl (i32.add (local.get $size) (i32.const 1)) (i32.const 3))))
+    (if (i32.lt_u (local.get $end) (local.get $p))
+      (then (call $fail (i32.const 64) (i32.const 13))))
+    (local.set $limit (i32.shl (memory.size) (i32.const 16)))
+    (if (i32.gt_u (local.get $end) (local.get $limit))
+      (then
+        ;; The memory is at least doubled, to grow it rarely.
+        (local.set $pages
+          (i32.add (i32.shr_u (i32.sub (local.get $end) (local.get $limit)) (i32.const 16))
+            (i32.const 1)))
+        (if (i32.lt_u (local.get $pages) (memory.size))
+          (then (local.set $pages (memory.size))))
+        (if (i32.lt_s (memory.grow (local.get $pages)) (i32.const 0))
+          (then
+            (if (i32.lt_s (memory.grow
+                  (i32.add (i32.shr_u (i32.sub (local.get $end) (local.get $limit)) (i32.const 16))
+                    (i32.const 1)))
+                (i32.const 0))
+              (then (call $fail (i32.const 64) (i32.const 13))))))))
+    (i64.store (local.get $p)
+      (i64.or (i64.shl (i64.extend_i32_u (local.get $size)) (i64.const 8))
+        (i64.extend_i32_u (local.get $tag))))
+    (global.set $hp (local.get $end))
+    (local.set $p (i32.add (local.get $p) (i32.const 8)))
+    (if (i32.ne (local.get $tag) (i32.const 252))
+      (then (call $fill (local.get $p) (local.get $size))))
+    (i64.extend_i32_u (local.get $p)))
+
+  (func $fill (param $p i32) (param $n i32)
+    (block $done
+      (loop $next
+        (br_if $done (i32.eqz (local.get $n)))
+        (i64.store (local.get $p) (i64.const 1))
+        (local.set $p (i32.add (local.get $p) (i32.const 8)))
+        (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+        (br $next))))
+
+  ;; Copies n words.
+  (func $copy (param $dst i32) (param $src i32) (param $n i32)
+    (block $done
+      (loop $next
+        (br_if $done (i32.eqz (local.get $n)))
+        (i64.store (local.get $dst) (i64.load (local.get $src)))
+        (local.set $dst (i32.add (local.get $dst) (i32.const 8)))
+        (local.set $src (i32.add (local.get $src) (i32.const 8)))
+        (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+        (br $next))))
+
+  (func $field (param $v i64) (param $i i32) (result i64)
+    (i64.load (i32.add (i32.wrap_i64 (local.get $v)) (i32.shl (local.get $i) (i32.const 3)))))
+
+  (func $tag (param $v i64) (result i32)
+    (i32.wrap_i64
+      (i64.and (i64.load (i32.sub (i32.wrap_i64 (local.get $v)) (i32.const 8))) (i64.const 255))))
+
+  (func $size (param $v i64) (result i32)
+    (i32.wrap_i64
+      (i64.shr_u (i64.load (i32.sub (i32.wrap_i64 (local.get $v)) (i32.const 8))) (i64.const 8))))
+
+  (func $is_int (param $v i64) (result i32)
+    (i32.wrap_i64 (i64.and (local.get $v) (i64.const 1))))
+
+  (func $is_function (param $v i64) (result i32)
+    (i32.or (i32.eq (call $tag (local.get $v)) (i32.const 247))
+      (i32.eq (call $tag (local.get $v)) (i32.const 248))))
+
+  (func $bool (param $b i32) (result i64)
+    (i64.or (i64.shl (i64.extend_i32_u (local.get $b)) (i64.const 1)) (i64.const 1)))
+
+  (func $closure (param $code i32) (param $arity i32) (param $captured i32) (result i64)
+    (local $v i64)
+    (local.set $v (call $alloc (i32.add (local.get $captured) (i32.const 2)) (i32.const 247)))
+    (i64.store (i32.wrap_i64 (local.get $v)) (i64.extend_i32_u (local.get $code)))
+    (i64.store offset=8 (i32.wrap_i64 (local.get $v))
+      (i64.or (i64.shl (i64.extend_i32_u (local.get $arity)) (i64.const 1)) (i64.const 1)))
+    (local.get $v))
+
+  (func $cons (param $head i64) (param $tail i64) (result i64)
+    (local $v i64)
+    (local.set $v (call $alloc (i32.const 2) (i32.const 0)))
+    (i64.store (i32.wrap_i64 (local.get $v)) (local.get $head))
+    (i64.store offset=8 (i32.wrap_i64 (local.get $v)) (local.get $tail))
+    (local.get $v))
+
+  ;; Application
+
+  ;; Reserves n words of the stack of arguments, returning their address.
+  (func $push (param $n i32) (result i32)
+    (local $sp i32)
+    (local.set $sp (i32.sub (global.get $sp) (i32.shl (local.get $n) (i32.const 3))))
+    (if (i32.lt_u (local.get $sp) (i32.const 1024))
+      (then (call $fail (i32.const 80) (i32.const 14))))
+    (global.set $sp (local.get $sp))
+    (local.get $sp))
+
+  (func $pop (param $n i32)
+    (global.set $sp (i32.add (global.get $sp) (i32.shl (local.get $n) (i32.const 3)))))
+
+  ;; Applies a function to n arguments in turn, calling closures by $enter
+  ;; once given as many arguments as they take.
+  (func $apply (param $f i64) (param $n i32) (param $args i32) (result i64)
+    (local $clo i64) (local $held i32) (local $arity i32) (local $taken i32) (local $v i64)
+    (block $done
+      (loop $next
+        (br_if $done (i32.eqz (local.get $n)))
+        (if (i32.eq (call $tag (local.get $f)) (i32.const 248))
+          (then
+            (local.set $clo (call $field (local.get $f) (i32.const 0)))
+            (local.set $held (i32.sub (call $size (local.get $f)) (i32.const 1))))
+          (else
+            (local.set $clo (local.get $f))
+            (local.set $held (i32.const 0))))
+        (local.set $arity
+          (i32.wrap_i64 (i64.shr_s (call $field (local.get $clo) (i32.const 1)) (i64.const 1))))
+        (if (i32.lt_u (i32.add (local.get $held) (local.get $n)) (local.get $arity))
+          (then
+            (local.set $v
+              (call $alloc (i32.add (i32.add (local.get $held) (local.get $n)) (i32.const 1))
+                (i32.const 248)))
+            (i64.store (i32.wrap_i64 (local.get $v)) (local.get $clo))
+            (call $copy (i32.add (i32.wrap_i64 (local.get $v)) (i32.const 8))
+              (i32.add (i32.wrap_i64 (local.get $f)) (i32.const 8)) (local.get $held))
+            (call $copy
+              (i32.add (i32.wrap_i64 (local.get $v))
+                (i32.shl (i32.add (local.get $held) (i32.const 1)) (i32.const 3)))
+              (local.get $args) (local.get $n))
+            (return (local.get $v))))
+        (local.set $taken (i32.sub (local.get $arity) (local.get $held)))
+        (if (i32.eqz (local.get $held))
+          (then (local.set $f (call $enter (local.get $clo) (local.get $args))))
+          (else
+            ;; The arguments held are joined with those given.
+            (local.set $v (call $alloc (local.get $arity) (i32.const 0)))
+            (call $copy (i32.wrap_i64 (local.get $v))
+              (i32.add (i32.wrap_i64 (local.get $f)) (i32.const 8)) (local.get $held))
+            (call $copy
+              (i32.add (i32.wrap_i64 (local.get $v)) (i32.shl (local.get $held) (i32.const 3)))
+              (local.get $args) (local.get $taken))
+            (local.set $f (call $enter (local.get $clo) (i32.wrap_i64 (local.get $v))))))
+        (local.set $args (i32.add (local.get $args) (i32.shl (local.get $taken) (i32.const 3))))
+        (local.set $n (i32.sub (local.get $n) (local.get $taken)))
+        (br $next)))
+    (local.get $f))
+
+  ;; Comparison
+
+  (func $functional (param $prim i32)
+    (if (i32.eqz (local.get $prim))
+      (then (call $fail (i32.const 128) (i32.const 45))))
+    (call $fail (i32.const 192) (i32.const 43)))
+
+  (func $compare_strings (param $a i64) (param $b i64) (result i32)
+    (local $la i32) (local $lb i32) (local $i i32) (local $ca i32) (local $cb i32)
+    (local.set $la (i32.wrap_i64 (i64.shr_s (call $field (local.get $a) (i32.const 0)) (i64.const 1))))
+    (local.set $lb (i32.wrap_i64 (i64.shr_s (call $field (local.get $b) (i32.const 0)) (i64.const 1))))
+    (block $done
+      (loop $next
+        (br_if $done
+          (i32.or (i32.ge_u (local.get $i) (local.get $la)) (i32.ge_u (local.get $i) (local.get $lb))))
+        (local.set $ca
+          (i32.load8_u offset=8 (i32.add (i32.wrap_i64 (local.get $a)) (local.get $i))))
+        (local.set $cb
+          (i32.load8_u offset=8 (i32.add (i32.wrap_i64 (local.get $b)) (local.get $i))))
+        (if (i32.ne (local.get $ca) (local.get $cb))
+          (then
+            (return (select (i32.const -1) (i32.const 1) (i32.lt_u (local.get $ca) (local.get $cb))))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (i32.sub (i32.gt_u (local.get $la) (local.get $lb)) (i32.lt_u (local.get $la) (local.get $lb))))
+
+  ;; Orders values as `compare` does, raising on functions as the primitive
+  ;; prim, 0 for compare and 1 for equal. Immediate values, e.g. constant
+  ;; constructors, come before blocks.
+  (func $compare (param $a i64) (param $b i64) (param $prim i32) (result i32)
+    (local $n i32) (local $i i32) (local $c i32)
+    (loop $next
+      (if (i32.and (call $is_int (local.get $a)) (call $is_int (local.get $b)))
+        (then
+          (return (i32.sub (i64.gt_s (local.get $a) (local.get $b))
+            (i64.lt_s (local.get $a) (local.get $b))))))
+      (if (call $is_int (local.get $a)) (then (return (i32.const -1))))
+      (if (call $is_int (local.get $b)) (then (return (i32.const 1))))
+      (if (i32.or (call $is_function (local.get $a)) (call $is_function (local.get $b)))
+        (then (call $functional (local.get $prim))))
+      (if (i32.eq (call $tag (local.get $a)) (i32.const 252))
+        (then (return (call $compare_strings (local.get $a) (local.get $b)))))
+      (if (i32.ne (call $tag (local.get $a)) (call $tag (local.get $b)))
+        (then
+          (return (select (i32.const -1) (i32.const 1)
+            (i32.lt_u (call $tag (local.get $a)) (call $tag (local.get $b)))))))
+      (local.set $n (call $size (local.get $a)))
+      (if (i32.ne (local.get $n) (call $size (local.get $b)))
+        (then
+          (return (select (i32.const -1) (i32.const 1)
+            (i32.lt_u (local.get $n) (call $size (local.get $b)))))))
+      (if (i32.eqz (local.get $n)) (then (return (i32.const 0))))
+      (local.set $i (i32.const 0))
+      (block $last
+        (loop $fields
+          (br_if $last (i32.ge_u (i32.add (local.get $i) (i32.const 1)) (local.get $n)))
+          (local.set $c
+            (call $compare (call $field (local.get $a) (local.get $i))
+              (call $field (local.get $b) (local.get $i)) (local.get $prim)))
+          (if (local.get $c) (then (return (local.get $c))))
+          (local.set $i (i32.add (local.get $i) (i32.const 1)))
+          (br $fields)))
+      ;; The last field, e.g. the tail of a list, is compared in turn.
+      (local.set $a (call $field (local.get $a) (local.get $i)))
+      (local.set $b (call $field (local.get $b) (local.get $i)))
+      (br $next))
+    (unreachable))
+
+  ;; Whether values are equal as `==` tells, functions being equal only to
+  ;; themselves.
+  (func $equal (param $a i64) (param $b i64) (result i32)
+    (local $n i32) (local $i i32)
+    (loop $next
+      (if (i64.eq (local.get $a) (local.get $b)) (then (return (i32.const 1))))
+      (if (i32.or (call $is_int (local.get $a)) (call $is_int (local.get $b)))
+        (then (return (i32.const 0))))
+      (local.set $n (call $size (local.get $a)))
+      (if (i32.or (i32.ne (call $tag (local.get $a)) (call $tag (local.get $b)))
+          (i32.ne (local.get $n) (call $size (local.get $b))))
+        (then (return (i32.const 0))))
+      (if (call $is_function (local.get $a)) (then (return (i32.const 0))))
+      (if (i32.eq (call $tag (local.get $a)) (i32.const 252))
+        (then (return (i32.eqz (call $compare_strings (local.get $a) (local.get $b))))))
+      (if (i32.eqz (local.get $n)) (then (return (i32.const 1))))
+      (local.set $i (i32.const 0))
+      (block $last
+        (loop $fields
+          (br_if $last (i32.ge_u (i32.add (local.get $i) (i32.const 1)) (local.get $n)))
+          (if (i32.eqz
+              (call $equal (call $field (local.get $a) (local.get $i))
+                (call $field (local.get $b) (local.get $i))))
+            (then (return (i32.const 0))))
+          (local.set $i (i32.add (local.get $i) (i32.const 1)))
+          (br $fields)))
+      (local.set $a (call $field (local.get $a) (local.get $i)))
+      (local.set $b (call $field (local.get $b) (local.get $i)))
+      (br $next))
+    (unreachable))
+
+  ;; Arithmetic, wrapping around on overflow
+
+  (func $div (param $a i64) (param $b i64) (result i64)
+    (if (i64.eq (local.get $b) (i64.const 1))
+      (then (call $fail (i32.const 96) (i32.const 16))))
+    (i64.or
+      (i64.shl
+        (i64.div_s (i64.shr_s (local.get $a) (i64.const 1)) (i64.shr_s (local.get $b) (i64.const 1)))
+        (i64.const 1))
+      (i64.const 1)))
+
+  ;; Primitives
+
+  (func $ref (param $x i64) (result i64)
+    (local $v i64)
+    (local.set $v (call $alloc (i32.const 1) (i32.const 0)))
+    (i64.store (i32.wrap_i64 (local.get $v)) (local.get $x))
+    (local.get $v))
+
+  (func $deref (param $r i64) (result i64)
+    (i64.load (i32.wrap_i64 (local.get $r))))
+
+  (func $assign (param $r i64) (param $x i64) (result i64)
+    (i64.store (i32.wrap_i64 (local.get $r)) (local.get $x))
+    (i64.const 1))
+
+  (func $print_string (param $s i64) (result i64)
+    (call $print (i32.add (i32.wrap_i64 (local.get $s)) (i32.const 8))
+      (i32.wrap_i64 (i64.shr_s (call $field (local.get $s) (i32.const 0)) (i64.const 1))))
+    (i64.const 1))
+
+  (func $print_endline (param $s i64) (result i64)
+    (drop (call $print_string (local.get $s)))
+    (call $print (i32.const 48) (i32.const 1))
+    (i64.const 1))
+
+  ;; Prints the digits from the end of the bytes below 48.
+  (func $print_int (param $n i64) (result i64)
+    (local $m i64) (local $p i32)
+    (local.set $m (i64.shr_s (local.get $n) (i64.const 1)))
+    (if (i64.lt_s (local.get $m) (i64.const 0))
+      (then (local.set $m (i64.sub (i64.const 0) (local.get $m)))))
+    (local.set $p (i32.const 48))
+    (loop $digits
+      (local.set $p (i32.sub (local.get $p) (i32.const 1)))
+      (i32.store8 (local.get $p)
+        (i32.add (i32.const 48) (i32.wrap_i64 (i64.rem_u (local.get $m) (i64.const 10)))))
+      (local.set $m (i64.div_u (local.get $m) (i64.const 10)))
+      (br_if $digits (i64.ne (local.get $m) (i64.const 0))))
+    (if (i64.lt_s (local.get $n) (i64.const 0))
+      (then
+        (local.set $p (i32.sub (local.get $p) (i32.const 1)))
+        (i32.store8 (local.get $p) (i32.const 45))))
+    (call $print (local.get $p) (i32.sub (i32.const 48) (local.get $p)))
+    (i64.const 1))
+
+  (func $print_newline (param $u i64) (result i64)
+    (call $print (i32.const 48) (i32.const 1))
+    (i64.const 1))
+
+  (func $put (param $p i32) (param $byte i32) (result i32)
+    (i32.store8 (local.get $p) (local.get $byte))
+    (i32.add (local.get $p) (i32.const 1)))
+
+  ;; Raises Failure with the message escaped as OCaml prints it.
+  (func $failwith (param $s i64) (result i64)
+    (local $len i32) (local $exn i32) (local $p i32) (local $i i32) (local $c i32)
+    (local.set $len (i32.wrap_i64 (i64.shr_s (call $field (local.get $s) (i32.const 0)) (i64.const 1))))
+    (local.set $exn
+      (i32.wrap_i64
+        (call $alloc (i32.add (i32.shr_u (local.get $len) (i32.const 1)) (i32.const 3))
+          (i32.const 252))))
+    (call $copy (local.get $exn) (i32.const 240) (i32.const 2))
+    (local.set $p (i32.add (local.get $exn) (i32.const 9)))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+        (local.set $c (i32.load8_u offset=8 (i32.add (i32.wrap_i64 (local.get $s)) (local.get $i))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (block $escaped
+          (block $backslash
+            (br_if $backslash (i32.or (i32.eq (local.get $c) (i32.const 34))
+              (i32.eq (local.get $c) (i32.const 92))))
+            (if (i32.eq (local.get $c) (i32.const 10))
+              (then (local.set $c (i32.const 110)) (br $backslash)))
+            (if (i32.eq (local.get $c) (i32.const 9))
+              (then (local.set $c (i32.const 116)) (br $backslash)))
+            (if (i32.eq (local.get $c) (i32.const 13))
+              (then (local.set $c (i32.const 114)) (br $backslash)))
+            (if (i32.eq (local.get $c) (i32.const 8))
+              (then (local.set $c (i32.const 98)) (br $backslash)))
+            (if (i32.and (i32.ge_u (local.get $c) (i32.const 32)) (i32.le_u (local.get $c) (i32.const 126)))
+              (then (local.set $p (call $put (local.get $p) (local.get $c))) (br $escaped)))
+            ;; \ddd in decimal
+            (local.set $p (call $put (local.get $p) (i32.const 92)))
+            (local.set $p
+              (call $put (local.get $p) (i32.add (i32.const 48) (i32.div_u (local.get $c) (i32.const 100)))))
+            (local.set $p
+              (call $put (local.get $p)
+                (i32.add (i32.const 48) (i32.rem_u (i32.div_u (local.get $c) (i32.const 10)) (i32.const 10)))))
+            (local.set $p
+              (call $put (local.get $p) (i32.add (i32.const 48) (i32.rem_u (local.get $c) (i32.const 10)))))
+            (br $escaped))
+          (local.set $p (call $put (local.get $p) (i32.const 92)))
+          (local.set $p (call $put (local.get $p) (local.get $c))))
+        (br $next)))
+    (local.set $p (call $put (local.get $p) (i32.const 34)))
+    (local.set $p (call $put (local.get $p) (i32.const 41)))
+    (call $fail (local.get $exn) (i32.sub (local.get $p) (local.get $exn)))
+    (unreachable))
+
+  ;; Code of the closures of the primitives, at the start of the table
+  (func $code_ref (type $code1) (call $ref (local.get 1)))
+  (func $code_deref (type $code1) (call $deref (local.get 1)))
+  (func $code_assign (type $code2) (call $assign (local.get 1) (local.get 2)))
+  (func $code_print_string (type $code1) (call $print_string (local.get 1)))
+  (func $code_print_endline (type $code1) (call $print_endline (local.get 1)))
+  (func $code_print_int (type $code1) (call $print_int (local.get 1)))
+  (func $code_print_newline (type $code1) (call $print_newline (local.get 1)))
+  (func $code_failwith (type $code1) (call $failwith (local.get 1)))
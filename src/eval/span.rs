@@ -0,0 +1,112 @@
+/// position in the source
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Pos {
+    /// byte offset from the beginning of the source
+    pub offset: usize,
+    /// 1-based line number
+    pub line: usize,
+    /// 0-based byte offset from the beginning of the line
+    pub column: usize,
+}
+
+/// range of a token or a node in the source
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Span {
+    pub start: Pos,
+    pub end: Pos,
+}
+
+impl Span {
+    // Span for the end of input, resolved to the end of the source when
+    // rendered.
+    pub const EOF: Span = Span {
+        start: Pos {
+            offset: usize::MAX,
+            line: usize::MAX,
+            column: usize::MAX,
+        },
+        end: Pos {
+            offset: usize::MAX,
+            line: usize::MAX,
+            column: usize::MAX,
+        },
+    };
+
+    pub fn new(start: Pos, end: Pos) -> Self {
+        Self { start, end }
+    }
+
+    // Makes a span covering from this span to `other`.
+    pub fn to(self, other: Span) -> Self {
+        Self {
+            start: self.start,
+            end: other.end,
+        }
+    }
+}
+
+// Beginning offsets of lines in a source, to look up line and column
+// numbers from byte offsets.
+pub(crate) struct LineIndex(Vec<usize>);
+
+impl LineIndex {
+    pub fn new(source: &str) -> Self {
+        let mut starts = vec![0];
+        starts.extend(source.match_indices('\n').map(|(idx, _)| idx + 1));
+        Self(starts)
+    }
+
+    pub fn pos(&self, offset: usize) -> Pos {
+        let line = match self.0.binary_search(&offset) {
+            Ok(idx) => idx,
+            Err(idx) => idx - 1,
+        };
+        Pos {
+            offset,
+            line: line + 1,
+            column: offset - self.0[line],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn look_up_position() {
+        let index = LineIndex::new("let x = 1 in\nx +\n\n  2");
+        assert_eq!(
+            index.pos(0),
+            Pos {
+                offset: 0,
+                line: 1,
+                column: 0
+            }
+        );
+        assert_eq!(
+            index.pos(4),
+            Pos {
+                offset: 4,
+                line: 1,
+                column: 4
+            }
+        );
+        assert_eq!(
+            index.pos(13),
+            Pos {
+                offset: 13,
+                line: 2,
+                column: 0
+            }
+        );
+        assert_eq!(
+            index.pos(20),
+            Pos {
+                offset: 20,
+                line: 4,
+                column: 2
+            }
+        );
+    }
+}
@@ -0,0 +1,596 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+// type of an expression
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Type {
+    Int,                      // int
+    Bool,                     // bool
+    List(Box<Type>),          // list of elements
+    Tuple(Vec<Type>),         // tuple of two or more elements
+    Fn(Box<Type>, Box<Type>), // function from an argument to a result
+    Con(String, Vec<Type>),   // declared type applied to arguments
+    Var(TypeVar),             // type variable to be inferred
+    Gen(usize),               // quantified type variable of a generalized type
+}
+
+// Type variable shared among the types it occurs in, so that it is
+// instantiated in all of them at once by linking it to a type.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct TypeVar(Rc<RefCell<VarState>>);
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum VarState {
+    // `level` is the depth of let-bindings where the variable was made, to
+    // tell whether it can be generalized. `weak` numbers a weak variable,
+    // which is never generalized, once it is printed as `'_weak1`, ...
+    Unbound { level: usize, weak: Option<usize> },
+    Link(Type),
+}
+
+impl TypeVar {
+    pub fn new(level: usize) -> Self {
+        Self(Rc::new(RefCell::new(VarState::Unbound {
+            level,
+            weak: None,
+        })))
+    }
+
+    // Returns whether both are the same variable, rather than equal ones.
+    pub fn same(&self, other: &TypeVar) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    // Level of the variable, or None if it has been instantiated.
+    pub fn level(&self) -> Option<usize> {
+        match &*self.0.borrow() {
+            VarState::Unbound { level, .. } => Some(*level),
+            VarState::Link(_) => None,
+        }
+    }
+
+    pub fn set_level(&self, level: usize) {
+        if let VarState::Unbound { level: l, .. } = &mut *self.0.borrow_mut() {
+            *l = level;
+        }
+    }
+
+    // Number of the weak variable, if it has been printed as one.
+    pub fn weak(&self) -> Option<usize> {
+        match &*self.0.borrow() {
+            VarState::Unbound { weak, .. } => *weak,
+            VarState::Link(_) => None,
+        }
+    }
+
+    pub fn set_weak(&self, num: usize) {
+        if let VarState::Unbound { weak, .. } = &mut *self.0.borrow_mut() {
+            *weak = Some(num);
+        }
+    }
+
+    pub fn link(&self, ty: Type) {
+        // A weak variable unified with another one keeps its name.
+        if let (Some(num), Type::Var(var)) = (self.weak(), &ty) {
+            if var.weak().is_none() {
+                var.set_weak(num);
+            }
+        }
+        *self.0.borrow_mut() = VarState::Link(ty);
+    }
+
+    pub fn state(&self) -> VarState {
+        self.0.borrow().clone()
+    }
+
+    // Undoes instantiation of the variable since `state()` returned `state`.
+    pub fn restore(&self, state: VarState) {
+        *self.0.borrow_mut() = state;
+    }
+
+    fn key(&self) -> usize {
+        Rc::as_ptr(&self.0) as usize
+    }
+}
+
+impl Type {
+    pub fn fresh(level: usize) -> Self {
+        Type::Var(TypeVar::new(level))
+    }
+
+    // Follows links of instantiated type variables, returning the type which
+    // is not an instantiated variable.
+    pub fn resolve(&self) -> Type {
+        if let Type::Var(TypeVar(var)) = self {
+            if let VarState::Link(ty) = &*var.borrow() {
+                return ty.resolve();
+            }
+        }
+        self.clone()
+    }
+
+    // Makes a type by applying `f` to the types directly inside this type.
+    pub fn map(&self, f: &mut dyn FnMut(&Type) -> Type) -> Type {
+        match self {
+            Type::List(elem) => Type::List(Box::new(f(elem))),
+            Type::Tuple(elems) => Type::Tuple(elems.iter().map(&mut *f).collect()),
+            Type::Fn(arg, ret) => Type::Fn(Box::new(f(arg)), Box::new(f(ret))),
+            Type::Con(name, args) => Type::Con(name.clone(), args.iter().map(&mut *f).collect()),
+            Type::Int | Type::Bool | Type::Var(_) | Type::Gen(_) => self.clone(),
+        }
+    }
+
+    // Types directly inside this type.
+    pub fn children(&self) -> Vec<&Type> {
+        match self {
+            Type::List(elem) => vec![elem],
+            Type::Tuple(elems) | Type::Con(_, elems) => elems.iter().collect(),
+            Type::Fn(arg, ret) => vec![arg, ret],
+            Type::Int | Type::Bool | Type::Var(_) | Type::Gen(_) => vec![],
+        }
+    }
+
+    // Type variables not instantiated in this type.
+    pub fn vars(&self) -> Vec<TypeVar> {
+        fn walk(ty: &Type, vars: &mut Vec<TypeVar>) {
+            match ty.resolve() {
+                Type::Var(var) if !vars.iter().any(|v| v.same(&var)) => vars.push(var),
+                ty => ty.children().into_iter().for_each(|t| walk(t, vars)),
+            }
+        }
+        let mut vars = Vec::new();
+        walk(self, &mut vars);
+        vars
+    }
+
+    // Replaces quantified type variables with `args`, e.g. parameters of a
+    // declared type with its arguments.
+    pub fn subst(&self, args: &[Type]) -> Type {
+        match self.resolve() {
+            Type::Gen(idx) => args[idx].clone(),
+            ty => ty.map(&mut |t| t.subst(args)),
+        }
+    }
+}
+
+// declared type, whose parameters are referred to as Gen(0), Gen(1), ... in
+// its definition
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct TypeDecl {
+    pub name: String,
+    pub params: Vec<String>, // names of parameters without quotes
+    pub kind: TypeDeclKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum TypeDeclKind {
+    Abstract,                             // type t
+    Alias(Type),                          // type t = int list
+    Variant(Vec<(String, Option<Type>)>), // type t = A | B of int
+    Record(Vec<(String, Type)>),          // type t = { x : int }
+}
+
+// how a type changes with a type in it, e.g. `'a list` is covariant in 'a
+// and `'a -> int` is contravariant in 'a
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Variance {
+    Covariant,
+    Contravariant,
+    Invariant,
+}
+
+impl Variance {
+    // Variance of a position with variance `inner` inside this position.
+    fn then(self, inner: Variance) -> Variance {
+        match (self, inner) {
+            (Variance::Invariant, _) | (_, Variance::Invariant) => Variance::Invariant,
+            (v1, v2) if v1 == v2 => Variance::Covariant,
+            _ => Variance::Contravariant,
+        }
+    }
+
+    // Variance of a type occurring at positions with both variances.
+    fn join(self, other: Variance) -> Variance {
+        if self == other {
+            self
+        } else {
+            Variance::Invariant
+        }
+    }
+}
+
+// declared types with their constructors and record fields
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct TypeDecls {
+    decls: HashMap<String, TypeDecl>,
+    constructors: HashMap<String, String>, // constructor to type name
+    fields: HashMap<String, String>,       // record field to type name
+}
+
+impl TypeDecls {
+    pub fn get(&self, name: &str) -> Option<&TypeDecl> {
+        self.decls.get(name)
+    }
+
+    // Declares a type, shadowing the one with the same name and constructors
+    // and fields of other types with the same names.
+    pub fn declare(&mut self, decl: TypeDecl) {
+        match &decl.kind {
+            TypeDeclKind::Variant(constrs) => {
+                for (constr, _) in constrs {
+                    self.constructors.insert(constr.clone(), decl.name.clone());
+                }
+            }
+            TypeDeclKind::Record(fields) => {
+                for (field, _) in fields {
+                    self.fields.insert(field.clone(), decl.name.clone());
+                }
+            }
+            TypeDeclKind::Abstract | TypeDeclKind::Alias(_) => (),
+        }
+        self.decls.insert(decl.name.clone(), decl);
+    }
+
+    // Type declaring a variant constructor, with the type of its argument.
+    pub fn constructor(&self, constr: &str) -> Option<(&TypeDecl, Option<&Type>)> {
+        let decl = self.decls.get(self.constructors.get(constr)?)?;
+        match &decl.kind {
+            TypeDeclKind::Variant(constrs) => constrs
+                .iter()
+                .find(|(name, _)| name == constr)
+                .map(|(_, arg)| (decl, arg.as_ref())),
+            _ => None,
+        }
+    }
+
+    // Record type declaring a field.
+    pub fn record_of(&self, field: &str) -> Option<&TypeDecl> {
+        let decl = self.decls.get(self.fields.get(field)?)?;
+        match &decl.kind {
+            TypeDeclKind::Record(fields) if fields.iter().any(|(name, _)| name == field) => {
+                Some(decl)
+            }
+            _ => None,
+        }
+    }
+
+    // Variances of the parameters of a type. Types whose definitions are
+    // unknown, e.g. abstract types and `ref`, are invariant. `visiting` holds
+    // types whose variances are being computed, which are assumed covariant
+    // where they recursively occur.
+    fn variance_of(&self, name: &str, visiting: &mut Vec<String>) -> Vec<Variance> {
+        let decl = match self.decls.get(name) {
+            Some(decl) => decl,
+            None => return Vec::new(),
+        };
+        if visiting.iter().any(|v| v == name) {
+            return vec![Variance::Covariant; decl.params.len()];
+        }
+        let tys: Vec<&Type> = match &decl.kind {
+            TypeDeclKind::Abstract => return vec![Variance::Invariant; decl.params.len()],
+            TypeDeclKind::Alias(ty) => vec![ty],
+            TypeDeclKind::Variant(constrs) => {
+                constrs.iter().filter_map(|(_, arg)| arg.as_ref()).collect()
+            }
+            TypeDeclKind::Record(fields) => fields.iter().map(|(_, ty)| ty).collect(),
+        };
+
+        visiting.push(name.to_string());
+        let mut variances: Vec<Option<Variance>> = vec![None; decl.params.len()];
+        for ty in tys {
+            self.walk_variance(ty, Variance::Covariant, visiting, &mut |ty, variance| {
+                if let Type::Gen(idx) = ty {
+                    let v = &mut variances[*idx];
+                    *v = Some(v.map_or(variance, |v| v.join(variance)));
+                }
+            });
+        }
+        visiting.pop();
+        // Parameters not occurring in the definition can vary freely.
+        variances
+            .into_iter()
+            .map(|v| v.unwrap_or(Variance::Covariant))
+            .collect()
+    }
+
+    // Calls `f` with each type variable in `ty`, either uninstantiated or
+    // quantified, and the variance of the position where it occurs.
+    pub fn occurrences(&self, ty: &Type, f: &mut dyn FnMut(&Type, Variance)) {
+        self.walk_variance(ty, Variance::Covariant, &mut Vec::new(), f)
+    }
+
+    fn walk_variance(
+        &self,
+        ty: &Type,
+        variance: Variance,
+        visiting: &mut Vec<String>,
+        f: &mut dyn FnMut(&Type, Variance),
+    ) {
+        match ty.resolve() {
+            ty @ (Type::Var(_) | Type::Gen(_)) => f(&ty, variance),
+            Type::Fn(arg, ret) => {
+                let contra = variance.then(Variance::Contravariant);
+                self.walk_variance(&arg, contra, visiting, f);
+                self.walk_variance(&ret, variance, visiting, f);
+            }
+            Type::Con(name, args) => {
+                let variances = self.variance_of(&name, visiting);
+                for (idx, arg) in args.iter().enumerate() {
+                    let v = variances.get(idx).copied().unwrap_or(Variance::Invariant);
+                    self.walk_variance(arg, variance.then(v), visiting, f);
+                }
+            }
+            ty => {
+                for child in ty.children() {
+                    self.walk_variance(child, variance, visiting, f);
+                }
+            }
+        }
+    }
+
+    // Expands a type abbreviation at the head of `ty`, if any.
+    pub fn expand(&self, ty: &Type) -> Option<Type> {
+        match ty.resolve() {
+            Type::Con(name, args) => match &self.decls.get(&name)?.kind {
+                TypeDeclKind::Alias(def) => Some(def.subst(&args)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+impl TypeDecl {
+    // Formats the declaration as `type` or `and` clauses in the toplevel
+    // output, e.g. `type 'a tree = Leaf | Node of 'a tree * 'a * 'a tree`.
+    pub fn show(&self, keyword: &str) -> String {
+        let mut names = TypeNames::default();
+        for (idx, param) in self.params.iter().enumerate() {
+            names.0.insert(VarKey::Gen(idx), format!("'{}", param));
+        }
+        let params: Vec<String> = self.params.iter().map(|p| format!("'{}", p)).collect();
+        let head = match params.len() {
+            0 => format!("{} {}", keyword, self.name),
+            1 => format!("{} {} {}", keyword, params[0], self.name),
+            _ => format!("{} ({}) {}", keyword, params.join(", "), self.name),
+        };
+        match &self.kind {
+            TypeDeclKind::Abstract => head,
+            TypeDeclKind::Alias(ty) => format!("{} = {}", head, names.show(ty)),
+            TypeDeclKind::Variant(constrs) => {
+                let constrs: Vec<String> = constrs
+                    .iter()
+                    .map(|(constr, arg)| match arg {
+                        Some(arg) => format!("{} of {}", constr, names.show_prec(arg, 1)),
+                        None => constr.clone(),
+                    })
+                    .collect();
+                format!("{} = {}", head, constrs.join(" | "))
+            }
+            TypeDeclKind::Record(fields) => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(field, ty)| format!("{} : {};", field, names.show(ty)))
+                    .collect();
+                format!("{} = {{ {} }}", head, fields.join(" "))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Eq, Hash, PartialEq)]
+enum VarKey {
+    Var(usize),
+    Gen(usize),
+}
+
+// Names type variables 'a, 'b, ... in the order of appearance. Names are
+// shared among types printed with the same instance, e.g. the types in a
+// type error.
+#[derive(Default)]
+pub(crate) struct TypeNames(HashMap<VarKey, String>);
+
+impl TypeNames {
+    pub fn show(&mut self, ty: &Type) -> String {
+        self.show_prec(ty, 0)
+    }
+
+    // `prec` is 0 at the top, 1 at the left of an arrow, 2 in a tuple, and
+    // 3 as an argument of a type constructor, e.g. `list`.
+    fn show_prec(&mut self, ty: &Type, prec: u8) -> String {
+        match ty.resolve() {
+            Type::Int => "int".to_string(),
+            Type::Bool => "bool".to_string(),
+            Type::List(elem) => format!("{} list", self.show_prec(&elem, 3)),
+            Type::Tuple(elems) => {
+                let elems: Vec<String> = elems.iter().map(|e| self.show_prec(e, 2)).collect();
+                paren(elems.join(" * "), prec > 1)
+            }
+            Type::Fn(arg, ret) => {
+                let arrow = format!("{} -> {}", self.show_prec(&arg, 1), self.show_prec(&ret, 0));
+                paren(arrow, prec > 0)
+            }
+            Type::Con(name, args) => match args.len() {
+                0 => name,
+                1 => format!("{} {}", self.show_prec(&args[0], 3), name),
+                _ => {
+                    let args: Vec<String> = args.iter().map(|a| self.show_prec(a, 0)).collect();
+                    format!("({}) {}", args.join(", "), name)
+                }
+            },
+            Type::Var(var) => match var.weak() {
+                Some(num) => format!("'_weak{}", num),
+                None => self.name(VarKey::Var(var.key())),
+            },
+            Type::Gen(idx) => self.name(VarKey::Gen(idx)),
+        }
+    }
+
+    fn name(&mut self, key: VarKey) -> String {
+        let count = self.0.len();
+        self.0.entry(key).or_insert_with(|| var_name(count)).clone()
+    }
+}
+
+fn paren(s: String, enclose: bool) -> String {
+    if enclose {
+        format!("({})", s)
+    } else {
+        s
+    }
+}
+
+// 'a, 'b, ..., 'z, 'a1, 'b1, ...
+fn var_name(idx: usize) -> String {
+    let letter = (b'a' + (idx % 26) as u8) as char;
+    match idx / 26 {
+        0 => format!("'{}", letter),
+        n => format!("'{}{}", letter, n),
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", TypeNames::default().show(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(elem: Type) -> Type {
+        Type::List(Box::new(elem))
+    }
+
+    fn func(arg: Type, ret: Type) -> Type {
+        Type::Fn(Box::new(arg), Box::new(ret))
+    }
+
+    #[test]
+    fn print_basic_types() {
+        assert_eq!(Type::Int.to_string(), "int");
+        assert_eq!(list(list(Type::Bool)).to_string(), "bool list list");
+    }
+
+    #[test]
+    fn print_arrows_with_parentheses() {
+        let ty = func(
+            func(Type::Int, Type::Bool),
+            func(list(func(Type::Int, Type::Int)), Type::Int),
+        );
+        assert_eq!(ty.to_string(), "(int -> bool) -> (int -> int) list -> int");
+    }
+
+    #[test]
+    fn print_tuples_with_parentheses() {
+        let pair = Type::Tuple(vec![Type::Int, Type::Bool]);
+        let ty = Type::Tuple(vec![
+            pair.clone(),
+            list(pair.clone()),
+            func(Type::Int, pair.clone()),
+        ]);
+        assert_eq!(
+            ty.to_string(),
+            "(int * bool) * (int * bool) list * (int -> int * bool)"
+        );
+    }
+
+    #[test]
+    fn print_declared_types() {
+        let assoc = Type::Con("assoc".to_string(), vec![Type::Int, list(Type::Bool)]);
+        let tree = Type::Con("tree".to_string(), vec![func(Type::Int, Type::Int)]);
+        assert_eq!(
+            list(Type::Tuple(vec![assoc, tree])).to_string(),
+            "((int, bool list) assoc * (int -> int) tree) list"
+        );
+    }
+
+    #[test]
+    fn print_type_declarations() {
+        let tree = TypeDecl {
+            name: "tree".to_string(),
+            params: vec!["a".to_string()],
+            kind: TypeDeclKind::Variant(vec![
+                ("Leaf".to_string(), None),
+                (
+                    "Node".to_string(),
+                    Some(Type::Tuple(vec![
+                        Type::Con("tree".to_string(), vec![Type::Gen(0)]),
+                        Type::Gen(0),
+                        Type::Con("tree".to_string(), vec![Type::Gen(0)]),
+                    ])),
+                ),
+            ]),
+        };
+        assert_eq!(
+            tree.show("type"),
+            "type 'a tree = Leaf | Node of 'a tree * 'a * 'a tree"
+        );
+
+        let assoc = TypeDecl {
+            name: "assoc".to_string(),
+            params: vec!["k".to_string(), "v".to_string()],
+            kind: TypeDeclKind::Alias(list(Type::Tuple(vec![Type::Gen(0), Type::Gen(1)]))),
+        };
+        assert_eq!(assoc.show("and"), "and ('k, 'v) assoc = ('k * 'v) list");
+
+        let point = TypeDecl {
+            name: "point".to_string(),
+            params: vec![],
+            kind: TypeDeclKind::Record(vec![
+                ("x".to_string(), Type::Int),
+                ("y".to_string(), Type::Int),
+            ]),
+        };
+        assert_eq!(point.show("type"), "type point = { x : int; y : int; }");
+    }
+
+    #[test]
+    fn expand_type_abbreviations() {
+        let mut decls = TypeDecls::default();
+        decls.declare(TypeDecl {
+            name: "pair".to_string(),
+            params: vec!["a".to_string()],
+            kind: TypeDeclKind::Alias(Type::Tuple(vec![Type::Gen(0), Type::Gen(0)])),
+        });
+        let ty = Type::Con("pair".to_string(), vec![Type::Bool]);
+        assert_eq!(
+            decls.expand(&ty),
+            Some(Type::Tuple(vec![Type::Bool, Type::Bool]))
+        );
+        assert_eq!(decls.expand(&Type::Int), None);
+    }
+
+    #[test]
+    fn print_variables_in_order_of_appearance() {
+        let var = Type::fresh(0);
+        let ty = func(Type::Gen(3), func(var.clone(), list(Type::Gen(3))));
+        assert_eq!(ty.to_string(), "'a -> 'b -> 'a list");
+    }
+
+    #[test]
+    fn print_instantiated_variables() {
+        let var = TypeVar::new(0);
+        var.link(list(Type::Int));
+        assert_eq!(list(Type::Var(var)).to_string(), "int list list");
+    }
+
+    #[test]
+    fn share_names_among_types() {
+        let var = Type::fresh(0);
+        let mut names = TypeNames::default();
+        assert_eq!(names.show(&Type::fresh(0)), "'a");
+        assert_eq!(names.show(&list(var.clone())), "'b list");
+        assert_eq!(names.show(&var), "'b");
+    }
+
+    #[test]
+    fn name_many_variables() {
+        assert_eq!(var_name(0), "'a");
+        assert_eq!(var_name(25), "'z");
+        assert_eq!(var_name(27), "'b1");
+    }
+}
@@ -0,0 +1,1240 @@
+use super::builtins::{array, prim, reference, string, unit};
+use super::parser::{BindStruct, Node, NodeKind, TypeDefStruct, TypeExpr, TypeExprKind, TypeRepr};
+use super::span::Span;
+use super::types::{Type, TypeDecl, TypeDeclKind, TypeDecls, TypeNames, TypeVar, Variance};
+use crate::error::{Error, TypeClash, TypeError, TypeErrorKind};
+use crate::Bounds;
+use std::collections::HashMap;
+
+// Infers the generalized type of an expression, or the type of the bound
+// value for a global binding. Type variables left in it are weak ones.
+pub(super) fn infer(ast: &Node, bounds: &Bounds) -> Result<Type, Error> {
+    let mut typer = Typer {
+        bounds,
+        locals: Vec::new(),
+        level: 0,
+        type_vars: HashMap::new(),
+    };
+    // Weak type variables of global bindings are restored if the input is
+    // ill-typed, as it is never evaluated.
+    let weak: Vec<_> = bounds
+        .weakly_typed
+        .iter()
+        .flat_map(|name| bounds.types[name].vars())
+        .map(|var| (var.clone(), var.state()))
+        .collect();
+    let result = match &ast.kind {
+        NodeKind::Bind(bind) => typer.infer_bind(bind).map(|(_, ty)| ty),
+        _ => {
+            typer.level += 1;
+            let ty = typer.infer(ast);
+            typer.level -= 1;
+            ty.map(|ty| typer.generalize(&ty, is_nonexpansive(ast)))
+        }
+    };
+    if result.is_err() {
+        for (var, state) in weak {
+            var.restore(state);
+        }
+    }
+    result
+}
+
+// Hindley-Milner type inference, generalizing let-bound values.
+struct Typer<'a> {
+    bounds: &'a Bounds,
+    // types of local bindings and arguments, the innermost last
+    locals: Vec<(String, Type)>,
+    // depth of let-bindings being inferred; type variables made deeper than
+    // the current level are generalized at the binding
+    level: usize,
+    // named type variables in annotations, which denote the same types
+    // throughout an input
+    type_vars: HashMap<String, Type>,
+}
+
+impl Typer<'_> {
+    fn infer(&mut self, node: &Node) -> Result<Type, Error> {
+        match &node.kind {
+            NodeKind::Int(_) => Ok(Type::Int),
+            NodeKind::Bool(_) => Ok(Type::Bool),
+            NodeKind::Unit => Ok(unit()),
+            NodeKind::Str(_) => Ok(string()),
+            NodeKind::List(elems) => {
+                let elem = Type::fresh(self.level);
+                for e in elems {
+                    self.check(e, &elem)?;
+                }
+                Ok(Type::List(Box::new(elem)))
+            }
+            NodeKind::Array(elems) => {
+                let elem = Type::fresh(self.level);
+                for e in elems {
+                    self.check(e, &elem)?;
+                }
+                Ok(array(elem))
+            }
+            NodeKind::Tuple(elems) => {
+                let mut tys = Vec::new();
+                for e in elems {
+                    tys.push(self.infer(e)?);
+                }
+                Ok(Type::Tuple(tys))
+            }
+            NodeKind::Add(lhs, rhs)
+            | NodeKind::Sub(lhs, rhs)
+            | NodeKind::Mul(lhs, rhs)
+            | NodeKind::Div(lhs, rhs) => {
+                self.check(lhs, &Type::Int)?;
+                self.check(rhs, &Type::Int)?;
+                Ok(Type::Int)
+            }
+            // 'a -> 'a -> bool
+            NodeKind::Eql(lhs, rhs) | NodeKind::Neql(lhs, rhs) | NodeKind::Compare(_, lhs, rhs) => {
+                let ty = self.infer(lhs)?;
+                self.check(rhs, &ty)?;
+                Ok(Type::Bool)
+            }
+            // 'a -> 'a list -> 'a list
+            NodeKind::Cons(head, tail) => {
+                let ty = Type::List(Box::new(self.infer(head)?));
+                self.check(tail, &ty)?;
+                Ok(ty)
+            }
+            // `if c then e` is of type unit.
+            NodeKind::If(cond, then, other) => {
+                self.check(cond, &Type::Bool)?;
+                match other {
+                    Some(other) => {
+                        let ty = self.infer(then)?;
+                        self.check(other, &ty)?;
+                        Ok(ty)
+                    }
+                    None => {
+                        self.check(then, &unit())?;
+                        Ok(unit())
+                    }
+                }
+            }
+            NodeKind::Ident(name) => match self.lookup(name) {
+                Some(ty) => Ok(self.instantiate(&ty)),
+                None => Err(Error::Type(TypeError {
+                    kind: TypeErrorKind::UnboundValue(name.clone()),
+                    span: Some(node.span),
+                })),
+            },
+            NodeKind::App(func, arg) => {
+                let func_ty = self.infer(func)?;
+                let (param, ret) = match self.arrow(&func_ty) {
+                    Some(arrow) => arrow,
+                    None => {
+                        let ty = TypeNames::default().show(&func_ty);
+                        return Err(type_error(TypeErrorKind::NotAFunction(ty), func.span));
+                    }
+                };
+                self.check(arg, &param)?;
+                Ok(ret)
+            }
+            // The value of `e1` is discarded, of which OCaml only warns
+            // unless it is unit.
+            NodeKind::Seq(lhs, rhs) => {
+                self.infer(lhs)?;
+                self.infer(rhs)
+            }
+            NodeKind::Bind(bind) => self.infer_bind(bind).map(|(_, ty)| ty),
+            NodeKind::LocalBind(local_bind) => {
+                let (name, ty) = self.infer_bind(&local_bind.bind)?;
+                self.locals.push((name, ty));
+                let ty = self.infer(&local_bind.scope);
+                self.locals.pop();
+                ty
+            }
+            NodeKind::Constraint(expr, texpr) => {
+                let ty = self.type_of(texpr)?;
+                self.check(expr, &ty)?;
+                Ok(ty)
+            }
+            NodeKind::Constr(name, arg) => {
+                let bounds = self.bounds;
+                let (decl, arg_ty) = bounds.decls.constructor(name).ok_or_else(|| {
+                    type_error(TypeErrorKind::UnboundConstructor(name.clone()), node.span)
+                })?;
+                let args = self.fresh_args(decl);
+                match (arg, arg_ty) {
+                    (Some(arg), Some(arg_ty)) => self.check(arg, &arg_ty.subst(&args))?,
+                    (None, None) => (),
+                    _ => {
+                        return Err(type_error(
+                            TypeErrorKind::ConstructorArity {
+                                name: name.clone(),
+                                expected: arg_ty.is_some() as usize,
+                                actual: arg.is_some() as usize,
+                            },
+                            node.span,
+                        ))
+                    }
+                }
+                Ok(Type::Con(decl.name.clone(), args))
+            }
+            NodeKind::Record(fields) => {
+                let bounds = self.bounds;
+                let decl = record_of(&bounds.decls, &fields[0].0, node.span)?;
+                let decl_fields = match &decl.kind {
+                    TypeDeclKind::Record(decl_fields) => decl_fields,
+                    _ => return Err(Error::Internal("Expected a record type".to_string())),
+                };
+                let args = self.fresh_args(decl);
+                for (field, expr) in fields {
+                    match decl_fields.iter().find(|(name, _)| name == field) {
+                        Some((_, ty)) => self.check(expr, &ty.subst(&args))?,
+                        None => {
+                            let owner = record_of(&bounds.decls, field, node.span)?;
+                            return Err(type_error(
+                                TypeErrorKind::MixedFields {
+                                    field: field.clone(),
+                                    types: Box::new((owner.name.clone(), decl.name.clone())),
+                                },
+                                node.span,
+                            ));
+                        }
+                    }
+                }
+                let missing: Vec<String> = decl_fields
+                    .iter()
+                    .filter(|(name, _)| fields.iter().all(|(field, _)| field != name))
+                    .map(|(name, _)| name.clone())
+                    .collect();
+                if !missing.is_empty() {
+                    return Err(type_error(TypeErrorKind::MissingFields(missing), node.span));
+                }
+                Ok(Type::Con(decl.name.clone(), args))
+            }
+            NodeKind::Field(expr, field) => {
+                let bounds = self.bounds;
+                let decl = record_of(&bounds.decls, field, node.span)?;
+                let args = self.fresh_args(decl);
+                self.check(expr, &Type::Con(decl.name.clone(), args.clone()))?;
+                match &decl.kind {
+                    TypeDeclKind::Record(fields) => {
+                        let (_, ty) = fields.iter().find(|(name, _)| name == field).unwrap();
+                        Ok(ty.subst(&args))
+                    }
+                    _ => Err(Error::Internal("Expected a record type".to_string())),
+                }
+            }
+            // Type definitions are checked by declare_types().
+            NodeKind::TypeDef(_) => Err(Error::Internal(
+                "Found a type definition in an expression".to_string(),
+            )),
+            NodeKind::Directive(..) => Err(Error::Internal(
+                "Found a directive in an expression".to_string(),
+            )),
+            // External declarations are checked by external_type().
+            NodeKind::External(..) => Err(Error::Internal(
+                "Found an external declaration in an expression".to_string(),
+            )),
+            // parse() never returns nodes containing errors.
+            NodeKind::Error => Err(Error::Internal("Found a node failed to parse".to_string())),
+        }
+    }
+
+    // Infers the type of `node`, which must be compatible with `expected`.
+    fn check(&mut self, node: &Node, expected: &Type) -> Result<(), Error> {
+        let actual = self.infer(node)?;
+        unify(&actual, expected, &self.bounds.decls).map_err(|trace| {
+            Error::Type(TypeError {
+                kind: mismatch(&actual, expected, trace),
+                span: Some(node.span),
+            })
+        })
+    }
+
+    // Infers the generalized type of a bound value, which is a function if
+    // it takes arguments. A recursive function is monomorphic in its body.
+    fn infer_bind(&mut self, bind: &BindStruct) -> Result<(String, Type), Error> {
+        let BindStruct {
+            recursive,
+            name,
+            args,
+            expr,
+        } = bind;
+        let name = ident(name)?;
+        if *recursive && args.is_empty() {
+            return Err(type_error(TypeErrorKind::IllegalLetRec, expr.span));
+        }
+
+        self.level += 1;
+        let this = Type::fresh(self.level);
+        if *recursive {
+            self.locals.push((name.clone(), this.clone()));
+        }
+        let mut params = Vec::new();
+        for arg in args {
+            let (name, ty) = match &arg.kind {
+                NodeKind::Constraint(name, texpr) => (ident(name)?, self.type_of(texpr)?),
+                _ => (ident(arg)?, Type::fresh(self.level)),
+            };
+            self.locals.push((name, ty.clone()));
+            params.push(ty);
+        }
+        let ret = self.infer(expr);
+        self.locals
+            .truncate(self.locals.len() - params.len() - *recursive as usize);
+
+        self.level -= 1;
+
+        let mut ty = ret?;
+        for param in params.into_iter().rev() {
+            ty = Type::Fn(Box::new(param), Box::new(ty));
+        }
+        if *recursive {
+            unify(&ty, &this, &self.bounds.decls).map_err(|trace| {
+                Error::Type(TypeError {
+                    kind: mismatch(&ty, &this, trace),
+                    span: Some(expr.span),
+                })
+            })?;
+        }
+        // Functions are values, which are fully generalized.
+        let nonexpansive = !args.is_empty() || is_nonexpansive(expr);
+        Ok((name, self.generalize(&ty, nonexpansive)))
+    }
+
+    // Converts a type expression in an annotation to a type.
+    fn type_of(&mut self, texpr: &TypeExpr) -> Result<Type, Error> {
+        let level = self.level;
+        let type_vars = &mut self.type_vars;
+        convert(texpr, &self.bounds.decls, &mut |name, _| {
+            Ok(type_vars
+                .entry(name.to_string())
+                .or_insert_with(|| Type::fresh(level))
+                .clone())
+        })
+    }
+
+    // Fresh type variables as arguments of a declared type.
+    fn fresh_args(&self, decl: &TypeDecl) -> Vec<Type> {
+        decl.params
+            .iter()
+            .map(|_| Type::fresh(self.level))
+            .collect()
+    }
+
+    // Parameter and result types of a function type, instantiating a type
+    // variable as a function type if needed.
+    fn arrow(&self, ty: &Type) -> Option<(Type, Type)> {
+        match ty.resolve() {
+            Type::Fn(param, ret) => Some((*param, *ret)),
+            Type::Var(var) => {
+                let (param, ret) = (Type::fresh(self.level), Type::fresh(self.level));
+                bind_var(
+                    &var,
+                    &Type::Fn(Box::new(param.clone()), Box::new(ret.clone())),
+                )
+                .ok()?;
+                Some((param, ret))
+            }
+            ty => self.arrow(&self.bounds.decls.expand(&ty)?),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<Type> {
+        match self.locals.iter().rev().find(|(local, _)| local == name) {
+            Some((_, ty)) => Some(ty.clone()),
+            None => match self.bounds.get_type(name) {
+                Some(ty) => Some(ty.clone()),
+                None => prim(name).map(|prim| (prim.ty)()),
+            },
+        }
+    }
+
+    // Replaces type variables made in the binding being left with quantified
+    // ones. Unless the bound expression is nonexpansive, only variables
+    // occurring at covariant positions are generalized (relaxed value
+    // restriction), and the others are kept at the current level, becoming
+    // weak ones at the toplevel.
+    fn generalize(&self, ty: &Type, nonexpansive: bool) -> Type {
+        if !nonexpansive {
+            self.bounds.decls.occurrences(ty, &mut |ty, variance| {
+                if let Type::Var(var) = ty {
+                    if variance != Variance::Covariant
+                        && var.level().is_some_and(|l| l > self.level)
+                    {
+                        var.set_level(self.level);
+                    }
+                }
+            });
+        }
+
+        fn walk(ty: &Type, level: usize, gens: &mut Vec<TypeVar>) -> Type {
+            match ty.resolve() {
+                Type::Var(var) if var.level().is_some_and(|l| l > level) => {
+                    let idx = match gens.iter().position(|gen| gen.same(&var)) {
+                        Some(idx) => idx,
+                        None => {
+                            gens.push(var);
+                            gens.len() - 1
+                        }
+                    };
+                    Type::Gen(idx)
+                }
+                ty => ty.map(&mut |t| walk(t, level, gens)),
+            }
+        }
+        walk(ty, self.level, &mut Vec::new())
+    }
+
+    // Replaces quantified type variables with fresh ones.
+    fn instantiate(&self, ty: &Type) -> Type {
+        fn walk(ty: &Type, level: usize, vars: &mut HashMap<usize, Type>) -> Type {
+            match ty.resolve() {
+                Type::Gen(idx) => vars
+                    .entry(idx)
+                    .or_insert_with(|| Type::fresh(level))
+                    .clone(),
+                ty => ty.map(&mut |t| walk(t, level, vars)),
+            }
+        }
+        walk(ty, self.level, &mut HashMap::new())
+    }
+}
+
+// Returns whether evaluating an expression makes no mutable values, e.g.
+// references, without applying functions.
+fn is_nonexpansive(node: &Node) -> bool {
+    match &node.kind {
+        NodeKind::Int(_)
+        | NodeKind::Bool(_)
+        | NodeKind::Unit
+        | NodeKind::Str(_)
+        | NodeKind::Ident(_) => true,
+        // Arrays are mutable, except for the empty one.
+        NodeKind::Array(elems) => elems.is_empty(),
+        NodeKind::List(elems) | NodeKind::Tuple(elems) => elems.iter().all(is_nonexpansive),
+        NodeKind::Constr(_, arg) => arg.as_deref().is_none_or(is_nonexpansive),
+        NodeKind::Record(fields) => fields.iter().all(|(_, expr)| is_nonexpansive(expr)),
+        NodeKind::Field(expr, _) | NodeKind::Constraint(expr, _) => is_nonexpansive(expr),
+        NodeKind::Cons(lhs, rhs) | NodeKind::Seq(lhs, rhs) => {
+            is_nonexpansive(lhs) && is_nonexpansive(rhs)
+        }
+        NodeKind::If(cond, then, other) => {
+            is_nonexpansive(cond)
+                && is_nonexpansive(then)
+                && other.as_deref().is_none_or(is_nonexpansive)
+        }
+        NodeKind::LocalBind(local_bind) => {
+            let BindStruct { args, expr, .. } = &local_bind.bind;
+            (!args.is_empty() || is_nonexpansive(expr)) && is_nonexpansive(&local_bind.scope)
+        }
+        _ => false,
+    }
+}
+
+// Checks type definitions joined with `and`, returning the declarations.
+pub(super) fn declare_types(
+    defs: &[TypeDefStruct],
+    decls: &TypeDecls,
+) -> Result<Vec<TypeDecl>, Error> {
+    // Declare the types first, so that they can refer to each other.
+    let mut scope = decls.clone();
+    for def in defs {
+        scope.declare(TypeDecl {
+            name: def.name.clone(),
+            params: def.params.clone(),
+            kind: TypeDeclKind::Abstract,
+        });
+    }
+
+    let mut declared = Vec::new();
+    for def in defs {
+        let mut ty = |texpr: &TypeExpr| {
+            convert(texpr, &scope, &mut |name, span| match def
+                .params
+                .iter()
+                .position(|param| param == name)
+            {
+                Some(idx) => Ok(Type::Gen(idx)),
+                None => Err(type_error(
+                    TypeErrorKind::UnboundTypeVariable(name.to_string()),
+                    span,
+                )),
+            })
+        };
+        let kind = match &def.repr {
+            TypeRepr::Abstract => TypeDeclKind::Abstract,
+            TypeRepr::Alias(texpr) => TypeDeclKind::Alias(ty(texpr)?),
+            TypeRepr::Variant(constrs) => {
+                let mut decl_constrs = Vec::new();
+                for (name, arg) in constrs {
+                    decl_constrs.push((name.clone(), arg.as_ref().map(&mut ty).transpose()?));
+                }
+                TypeDeclKind::Variant(decl_constrs)
+            }
+            TypeRepr::Record(fields) => {
+                let mut decl_fields = Vec::new();
+                for (name, texpr) in fields {
+                    decl_fields.push((name.clone(), ty(texpr)?));
+                }
+                TypeDeclKind::Record(decl_fields)
+            }
+        };
+        declared.push(TypeDecl {
+            name: def.name.clone(),
+            params: def.params.clone(),
+            kind,
+        });
+    }
+
+    // Abbreviations must be expanded to types other than themselves.
+    for (def, decl) in defs.iter().zip(&declared) {
+        if let (TypeRepr::Alias(texpr), TypeDeclKind::Alias(ty)) = (&def.repr, &decl.kind) {
+            if refers_to(ty, &decl.name, &declared, &mut Vec::new()) {
+                return Err(type_error(
+                    TypeErrorKind::CyclicAbbreviation(decl.name.clone()),
+                    texpr.span,
+                ));
+            }
+        }
+    }
+    Ok(declared)
+}
+
+// Returns whether `ty` refers to the type `name`, expanding abbreviations
+// among `decls` which have not been `expanded` yet.
+fn refers_to(ty: &Type, name: &str, decls: &[TypeDecl], expanded: &mut Vec<String>) -> bool {
+    if let Type::Con(con, _) = ty {
+        if con == name {
+            return true;
+        }
+        if !expanded.contains(con) {
+            expanded.push(con.clone());
+            let alias = decls.iter().find_map(|decl| match &decl.kind {
+                TypeDeclKind::Alias(def) if &decl.name == con => Some(def),
+                _ => None,
+            });
+            if alias.is_some_and(|def| refers_to(def, name, decls, expanded)) {
+                return true;
+            }
+        }
+    }
+    ty.children()
+        .into_iter()
+        .any(|child| refers_to(child, name, decls, expanded))
+}
+
+// Converts a type expression given from outside of OCaml, quantifying its
+// type variables in order of appearance.
+pub(super) fn type_scheme(texpr: &TypeExpr, decls: &TypeDecls) -> Result<Type, Error> {
+    let mut names: Vec<String> = Vec::new();
+    convert(texpr, decls, &mut |name, _| {
+        let idx = match names.iter().position(|n| n == name) {
+            Some(idx) => idx,
+            None => {
+                names.push(name.to_string());
+                names.len() - 1
+            }
+        };
+        Ok(Type::Gen(idx))
+    })
+}
+
+// Converts the declared type of an external, which must be an instance of
+// the type of the primitive it is bound to.
+pub(super) fn external_type(
+    texpr: &TypeExpr,
+    prim: &str,
+    prim_ty: &Type,
+    decls: &TypeDecls,
+) -> Result<Type, Error> {
+    fn instance(ty: &Type, vars: &mut HashMap<usize, Type>) -> Type {
+        match ty {
+            Type::Gen(idx) => vars.entry(*idx).or_insert_with(|| Type::fresh(0)).clone(),
+            ty => ty.map(&mut |t| instance(t, vars)),
+        }
+    }
+
+    let declared = type_scheme(texpr, decls)?;
+    let mut vars = HashMap::new();
+    let decl_inst = instance(&declared, &mut vars);
+    let prim_inst = instance(prim_ty, &mut HashMap::new());
+    // Type variables of the declared type must remain distinct variables.
+    let mut instantiated: Vec<TypeVar> = Vec::new();
+    let is_instance = unify(&prim_inst, &decl_inst, decls).is_ok()
+        && vars.values().all(|ty| match ty.resolve() {
+            Type::Var(var) if !instantiated.iter().any(|v| v.same(&var)) => {
+                instantiated.push(var);
+                true
+            }
+            _ => false,
+        });
+    if !is_instance {
+        return Err(type_error(
+            TypeErrorKind::PrimitiveType {
+                name: prim.to_string(),
+                types: Box::new((prim_ty.to_string(), declared.to_string())),
+            },
+            texpr.span,
+        ));
+    }
+    Ok(declared)
+}
+
+// Converts a type expression to a type, with `var` to convert type
+// variables.
+fn convert(
+    texpr: &TypeExpr,
+    decls: &TypeDecls,
+    var: &mut dyn FnMut(&str, Span) -> Result<Type, Error>,
+) -> Result<Type, Error> {
+    match &texpr.kind {
+        TypeExprKind::Var(name) => var(name, texpr.span),
+        TypeExprKind::Constr(name, args) => {
+            let mut tys = Vec::new();
+            for arg in args {
+                tys.push(convert(arg, decls, var)?);
+            }
+            let arity = match name.as_str() {
+                "int" | "bool" | "unit" | "string" => 0,
+                "list" | "ref" | "array" => 1,
+                _ => match decls.get(name) {
+                    Some(decl) => decl.params.len(),
+                    None => {
+                        return Err(type_error(
+                            TypeErrorKind::UnboundTypeConstructor(name.clone()),
+                            texpr.span,
+                        ))
+                    }
+                },
+            };
+            if tys.len() != arity {
+                return Err(type_error(
+                    TypeErrorKind::TypeArity {
+                        name: name.clone(),
+                        expected: arity,
+                        actual: tys.len(),
+                    },
+                    texpr.span,
+                ));
+            }
+            Ok(match name.as_str() {
+                "int" => Type::Int,
+                "bool" => Type::Bool,
+                "list" => Type::List(Box::new(tys.remove(0))),
+                "unit" => unit(),
+                "string" => string(),
+                "ref" => reference(tys.remove(0)),
+                "array" => array(tys.remove(0)),
+                _ => Type::Con(name.clone(), tys),
+            })
+        }
+        TypeExprKind::Arrow(arg, ret) => Ok(Type::Fn(
+            Box::new(convert(arg, decls, var)?),
+            Box::new(convert(ret, decls, var)?),
+        )),
+        TypeExprKind::Tuple(elems) => {
+            let mut tys = Vec::new();
+            for e in elems {
+                tys.push(convert(e, decls, var)?);
+            }
+            Ok(Type::Tuple(tys))
+        }
+    }
+}
+
+fn record_of<'a>(decls: &'a TypeDecls, field: &str, span: Span) -> Result<&'a TypeDecl, Error> {
+    decls
+        .record_of(field)
+        .ok_or_else(|| type_error(TypeErrorKind::UnboundField(field.to_string()), span))
+}
+
+fn type_error(kind: TypeErrorKind, span: Span) -> Error {
+    Error::Type(TypeError {
+        kind,
+        span: Some(span),
+    })
+}
+
+fn ident(node: &Node) -> Result<String, Error> {
+    match &node.kind {
+        NodeKind::Ident(ident) => Ok(ident.clone()),
+        _ => Err(Error::Internal("Expected a value name".to_string())),
+    }
+}
+
+// reason why two types failed to unify
+#[derive(Debug)]
+enum Clash {
+    Incompatible(Type, Type), // nested types which differ
+    Occurs(Type, Type),       // type variable and a type containing it
+}
+
+// Makes two types equal by instantiating type variables in them. On
+// failure, returns the clashes from the innermost types.
+// Abbreviations are expanded with `decls` if needed.
+fn unify(t1: &Type, t2: &Type, decls: &TypeDecls) -> Result<(), Vec<Clash>> {
+    let (t1, t2) = (t1.resolve(), t2.resolve());
+    let result = match (&t1, &t2) {
+        (Type::Int, Type::Int) | (Type::Bool, Type::Bool) => Ok(()),
+        (Type::List(e1), Type::List(e2)) => unify(e1, e2, decls),
+        (Type::Tuple(es1), Type::Tuple(es2)) if es1.len() == es2.len() => es1
+            .iter()
+            .zip(es2)
+            .try_for_each(|(e1, e2)| unify(e1, e2, decls)),
+        (Type::Fn(a1, r1), Type::Fn(a2, r2)) => {
+            unify(a1, a2, decls).and_then(|_| unify(r1, r2, decls))
+        }
+        (Type::Con(n1, as1), Type::Con(n2, as2)) if n1 == n2 => as1
+            .iter()
+            .zip(as2)
+            .try_for_each(|(a1, a2)| unify(a1, a2, decls)),
+        (Type::Var(v1), Type::Var(v2)) if v1.same(v2) => Ok(()),
+        (Type::Var(var), ty) | (ty, Type::Var(var)) => return bind_var(var, ty),
+        _ => {
+            if let Some(t1) = decls.expand(&t1) {
+                return unify(&t1, &t2, decls);
+            }
+            if let Some(t2) = decls.expand(&t2) {
+                return unify(&t1, &t2, decls);
+            }
+            Err(Vec::new())
+        }
+    };
+    result.map_err(|mut trace| {
+        trace.push(Clash::Incompatible(t1.clone(), t2.clone()));
+        trace
+    })
+}
+
+fn bind_var(var: &TypeVar, ty: &Type) -> Result<(), Vec<Clash>> {
+    // level of an unbound variable returned by resolve()
+    let level = var.level().unwrap_or_default();
+    if occurs(var, level, ty) {
+        return Err(vec![Clash::Occurs(Type::Var(var.clone()), ty.clone())]);
+    }
+    var.link(ty.clone());
+    Ok(())
+}
+
+// Returns whether `var` occurs in `ty`, lowering levels of the other
+// variables in `ty` to `level` so that they are generalized no deeper than
+// `var`.
+fn occurs(var: &TypeVar, level: usize, ty: &Type) -> bool {
+    match ty.resolve() {
+        Type::Var(other) if other.same(var) => true,
+        Type::Var(other) => {
+            if let Some(l) = other.level() {
+                other.set_level(l.min(level));
+            }
+            false
+        }
+        Type::List(elem) => occurs(var, level, &elem),
+        Type::Tuple(elems) | Type::Con(_, elems) => elems.iter().any(|e| occurs(var, level, e)),
+        Type::Fn(arg, ret) => occurs(var, level, &arg) || occurs(var, level, &ret),
+        Type::Int | Type::Bool | Type::Gen(_) => false,
+    }
+}
+
+fn mismatch(actual: &Type, expected: &Type, mut trace: Vec<Clash>) -> TypeErrorKind {
+    let mut names = TypeNames::default();
+    let (actual, expected) = (names.show(actual), names.show(expected));
+    // The outermost clash is the one between `actual` and `expected`, unless
+    // it is an occurrence of a variable.
+    if let Some(Clash::Incompatible(..)) = trace.last() {
+        trace.pop();
+    }
+    let trace = trace
+        .iter()
+        .rev()
+        .map(|clash| match clash {
+            Clash::Incompatible(t1, t2) => TypeClash::Incompatible(names.show(t1), names.show(t2)),
+            Clash::Occurs(var, ty) => TypeClash::Occurs(names.show(var), names.show(ty)),
+        })
+        .collect();
+    TypeErrorKind::Mismatch {
+        types: Box::new((actual, expected)),
+        trace,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{lexer::tokenize, parser::parse};
+    use super::*;
+    use std::rc::Rc;
+
+    fn type_of(input: &str) -> Result<String, Error> {
+        type_in(&Bounds::new(), input)
+    }
+
+    fn type_in(bounds: &Bounds, input: &str) -> Result<String, Error> {
+        let ast = parse(&tokenize(input).unwrap()).unwrap();
+        infer(&ast, bounds).map(|ty| ty.to_string())
+    }
+
+    fn declare(input: &str) -> Result<Vec<TypeDecl>, Error> {
+        match parse(&tokenize(input).unwrap()).unwrap().kind {
+            NodeKind::TypeDef(defs) => declare_types(&defs, &TypeDecls::default()),
+            kind => panic!("Expected a type definition: {:?}", kind),
+        }
+    }
+
+    // Bounds with the types declared by `input`.
+    fn declared(input: &str) -> Bounds {
+        let mut bounds = Bounds::new();
+        for decl in declare(input).unwrap() {
+            Rc::make_mut(&mut bounds.decls).declare(decl);
+        }
+        bounds
+    }
+
+    // Message and offsets of the type error in `input`.
+    fn error_in(input: &str) -> (String, usize, usize) {
+        let err = type_of(input).unwrap_err();
+        let span = err.span().unwrap();
+        (err.to_string(), span.start.offset, span.end.offset)
+    }
+
+    #[test]
+    fn infer_basic_types() {
+        assert_eq!(type_of("1 + 2 * 3").unwrap(), "int");
+        assert_eq!(type_of("1 == 2").unwrap(), "bool");
+        assert_eq!(type_of("[[true]; []]").unwrap(), "bool list list");
+        assert_eq!(type_of("[]").unwrap(), "'a list");
+    }
+
+    #[test]
+    fn infer_function_types() {
+        assert_eq!(type_of("let square x = x * x").unwrap(), "int -> int");
+        assert_eq!(type_of("let f a b = a == b").unwrap(), "'a -> 'a -> bool");
+        assert_eq!(
+            type_of("let pair a b = [a; b]").unwrap(),
+            "'a -> 'a -> 'a list"
+        );
+    }
+
+    #[test]
+    fn infer_recursive_functions() {
+        assert_eq!(
+            type_of("let rec fact n = if n <= 1 then 1 else n * fact (n - 1)").unwrap(),
+            "int -> int"
+        );
+        assert_eq!(
+            type_of("let rec loop x = loop x in loop").unwrap(),
+            "'a -> 'b"
+        );
+        // A recursive function is not generalized in its own body.
+        assert_eq!(
+            error_in("let rec f x = f 1; f true").0,
+            "This expression has type bool but an expression was expected of type int"
+        );
+        assert_eq!(
+            error_in("let rec x = 1"),
+            (
+                "This kind of expression is not allowed as right-hand side of `let rec'"
+                    .to_string(),
+                12,
+                13
+            )
+        );
+    }
+
+    #[test]
+    fn infer_conditionals_and_comparisons() {
+        assert_eq!(
+            type_of("if 1 < 2 then [] else [true]").unwrap(),
+            "bool list"
+        );
+        assert_eq!(type_of("let f a b = a <> b").unwrap(), "'a -> 'a -> bool");
+        assert_eq!(type_of("if true then ()").unwrap(), "unit");
+        assert_eq!(
+            error_in("if 1 then 2 else 3"),
+            (
+                "This expression has type int but an expression was expected of type bool"
+                    .to_string(),
+                3,
+                4
+            )
+        );
+        assert_eq!(
+            error_in("if true then 1"),
+            (
+                "This expression has type int but an expression was expected of type unit"
+                    .to_string(),
+                13,
+                14
+            )
+        );
+    }
+
+    #[test]
+    fn infer_cons() {
+        assert_eq!(type_of("1 :: 2 :: []").unwrap(), "int list");
+        assert_eq!(
+            type_of("let f x xs = x :: xs").unwrap(),
+            "'a -> 'a list -> 'a list"
+        );
+        assert_eq!(type_of("[] :: []").unwrap(), "'a list list");
+        assert_eq!(
+            error_in("true :: [1]"),
+            (
+                "This expression has type int list but an expression was expected of type bool list\n       Type int is not compatible with type bool"
+                    .to_string(),
+                8,
+                11
+            )
+        );
+    }
+
+    #[test]
+    fn generalize_local_bindings() {
+        assert_eq!(
+            type_of("let id x = x in [id; id]").unwrap(),
+            "('a -> 'a) list"
+        );
+        assert_eq!(
+            type_of("let nil = [] in [nil == [1]; nil == [true]]").unwrap(),
+            "bool list"
+        );
+    }
+
+    #[test]
+    fn infer_tuple_types() {
+        assert_eq!(type_of("1, (true, [])").unwrap(), "int * (bool * 'a list)");
+        assert_eq!(
+            type_of("let swap a b = b, a").unwrap(),
+            "'a -> 'b -> 'b * 'a"
+        );
+    }
+
+    #[test]
+    fn narrow_types_with_annotations() {
+        assert_eq!(type_of("([] : bool list)").unwrap(), "bool list");
+        assert_eq!(type_of("let f (x : int) y = y").unwrap(), "int -> 'a -> 'a");
+        assert_eq!(
+            type_of("let f x : int list = [x]").unwrap(),
+            "int -> int list"
+        );
+        // Named type variables are unified as other type variables.
+        assert_eq!(
+            type_of("let f (x : 'a) : 'a = x + 1").unwrap(),
+            "int -> int"
+        );
+        assert_eq!(
+            type_of("let f (x : 'a) (y : 'b) = [x; y]").unwrap(),
+            "'a -> 'a -> 'a list"
+        );
+    }
+
+    #[test]
+    fn error_on_annotated_expression() {
+        assert_eq!(
+            error_in("let f (x : bool) = x + 1"),
+            (
+                "This expression has type bool but an expression was expected of type int"
+                    .to_string(),
+                19,
+                20
+            )
+        );
+        assert_eq!(
+            error_in("(1, 2 : int * bool)").0,
+            concat!(
+                "This expression has type int * int but an expression was expected of type int * bool\n",
+                "       Type int is not compatible with type bool",
+            )
+        );
+    }
+
+    #[test]
+    fn error_on_unknown_types() {
+        assert_eq!(
+            error_in("(1 : integer)"),
+            ("Unbound type constructor integer".to_string(), 5, 12)
+        );
+        assert_eq!(
+            error_in("([] : (int, int) list)"),
+            (
+                "The type constructor list expects 1 argument(s),\n       but is here applied to 2 argument(s)".to_string(),
+                6,
+                21
+            )
+        );
+    }
+
+    #[test]
+    fn infer_application_types() {
+        assert_eq!(
+            type_of("let add a b = a + b in add 1").unwrap(),
+            "int -> int"
+        );
+        assert_eq!(type_of("ref [true]").unwrap(), "bool list ref");
+        assert_eq!(type_of("let r = ref 1 in r := !r + 1").unwrap(), "unit");
+        assert_eq!(
+            type_of("let apply f x = f x in apply").unwrap(),
+            "('a -> 'b) -> 'a -> 'b"
+        );
+    }
+
+    #[test]
+    fn infer_sequences_and_printing() {
+        assert_eq!(
+            type_of("let f s = print_string s; print_int 1 in f").unwrap(),
+            "string -> unit"
+        );
+        assert_eq!(type_of("print_newline (); true").unwrap(), "bool");
+        assert_eq!(type_of("let () = print_endline \"a\" in 1").unwrap(), "int");
+        assert_eq!(type_of("failwith \"a\"").unwrap(), "'a");
+        assert!(type_of("let () = 1 in 2").is_err());
+    }
+
+    #[test]
+    fn apply_value_restriction() {
+        // uninstantiated, i.e. weak, type variables in the type of `input`
+        let weak_vars = |bounds: &Bounds, input: &str| {
+            let ast = parse(&tokenize(input).unwrap()).unwrap();
+            infer(&ast, bounds).unwrap().vars().len()
+        };
+        let bounds = declared(
+            "type 'a box = Box of 'a and 'a sink = Sink of ('a -> unit) and 'a cell = 'a ref",
+        );
+        let prelude = "let id x = x in let ignore x = () in ";
+        let cases = [
+            ("ref []", 1),
+            ("let f x = ref x", 0),
+            ("id []", 0),
+            ("id id", 1),
+            ("id [], ref []", 1),
+            ("id (Box [])", 0),
+            ("id (Sink ignore)", 1),
+            ("(ref [] : 'a cell)", 1),
+            ("let r = ref [] in id []", 0),
+            ("let r = ref [] in r", 1),
+        ];
+        for (input, expected) in cases {
+            let input = format!("{}{}", prelude, input);
+            assert_eq!(weak_vars(&bounds, &input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn error_on_application() {
+        assert_eq!(
+            error_in("let f x = x + 1 in f 1 2"),
+            (
+                "This expression has type int\n       This is not a function; it cannot be applied."
+                    .to_string(),
+                19,
+                22
+            )
+        );
+        assert_eq!(
+            error_in("let f x = x + 1 in f true"),
+            (
+                "This expression has type bool but an expression was expected of type int"
+                    .to_string(),
+                21,
+                25
+            )
+        );
+        assert_eq!(
+            error_in("!1"),
+            (
+                "This expression has type int but an expression was expected of type 'a ref"
+                    .to_string(),
+                1,
+                2
+            )
+        );
+    }
+
+    #[test]
+    fn declare_types_referring_to_each_other() {
+        let decls = declare("type 'a tree = Leaf | Node of 'a forest and 'a forest = 'a tree list")
+            .unwrap();
+        let shown: Vec<String> = decls.iter().map(|decl| decl.show("type")).collect();
+        assert_eq!(
+            shown,
+            vec![
+                "type 'a tree = Leaf | Node of 'a forest",
+                "type 'a forest = 'a tree list",
+            ]
+        );
+    }
+
+    #[test]
+    fn error_on_type_declarations() {
+        let error = |input| {
+            let err = declare(input).unwrap_err();
+            let span = err.span().unwrap();
+            (err.to_string(), span.start.offset, span.end.offset)
+        };
+        assert_eq!(
+            error("type 'a t = 'b list"),
+            (
+                "The type variable 'b is unbound in this type declaration.".to_string(),
+                12,
+                14
+            )
+        );
+        assert_eq!(
+            error("type t = u * int and u = t list"),
+            ("The type abbreviation t is cyclic".to_string(), 9, 16)
+        );
+        assert_eq!(
+            error("type t = T of u"),
+            ("Unbound type constructor u".to_string(), 14, 15)
+        );
+    }
+
+    #[test]
+    fn infer_declared_types() {
+        let bounds = declared(
+            "type 'a tree = Leaf | Node of 'a tree * 'a * 'a tree \
+             and ('k, 'v) assoc = ('k * 'v) list \
+             and 'a box = { item : 'a; size : int }",
+        );
+        assert_eq!(type_in(&bounds, "Leaf").unwrap(), "'a tree");
+        assert_eq!(
+            type_in(&bounds, "Node (Leaf, [1], Node (Leaf, [], Leaf))").unwrap(),
+            "int list tree"
+        );
+        assert_eq!(
+            type_in(&bounds, "let f (x : (int, bool) assoc) = [(1, true)] == x").unwrap(),
+            "(int, bool) assoc -> bool"
+        );
+        assert_eq!(
+            type_in(&bounds, "{ size = 1; item = Leaf }").unwrap(),
+            "'a tree box"
+        );
+        assert_eq!(
+            type_in(&bounds, "let item b = b.item").unwrap(),
+            "'a box -> 'a"
+        );
+    }
+
+    #[test]
+    fn error_on_declared_types() {
+        let bounds = declared(
+            "type 'a tree = Leaf | Node of 'a tree * 'a * 'a tree \
+             and p = { x : int; y : int } and q = { z : int }",
+        );
+        let error = |input| {
+            let err = type_in(&bounds, input).unwrap_err();
+            let span = err.span().unwrap();
+            (err.to_string(), span.start.offset, span.end.offset)
+        };
+        assert_eq!(
+            error("(Node (Leaf, 1, Leaf) : bool tree)"),
+            (
+                "This expression has type int tree but an expression was expected of type bool tree\n       Type int is not compatible with type bool"
+                    .to_string(),
+                1,
+                21
+            )
+        );
+        assert_eq!(
+            error("Leaf 1"),
+            (
+                "The constructor Leaf expects 0 argument(s),\n       but is applied here to 1 argument(s)".to_string(),
+                0,
+                6
+            )
+        );
+        assert_eq!(
+            error("Empty"),
+            ("Unbound constructor Empty".to_string(), 0, 5)
+        );
+        assert_eq!(
+            error("{ x = 1 }"),
+            ("Some record fields are undefined: y".to_string(), 0, 9)
+        );
+        assert_eq!(
+            error("{ x = 1; z = 2 }"),
+            (
+                "The record field z belongs to the type q\n       but is mixed here with fields of type p".to_string(),
+                0,
+                16
+            )
+        );
+        assert_eq!(
+            error("Leaf.w"),
+            ("Unbound record field w".to_string(), 0, 6)
+        );
+    }
+
+    #[test]
+    fn error_on_operand() {
+        assert_eq!(
+            error_in("1 + true"),
+            (
+                "This expression has type bool but an expression was expected of type int"
+                    .to_string(),
+                4,
+                8
+            )
+        );
+    }
+
+    #[test]
+    fn error_on_comparison_between_different_types() {
+        assert_eq!(
+            error_in("true == 1"),
+            (
+                "This expression has type int but an expression was expected of type bool"
+                    .to_string(),
+                8,
+                9
+            )
+        );
+    }
+
+    #[test]
+    fn error_on_list_element() {
+        assert_eq!(
+            error_in("[1; 2; (true)]"),
+            (
+                "This expression has type bool but an expression was expected of type int"
+                    .to_string(),
+                7,
+                13
+            )
+        );
+    }
+
+    #[test]
+    fn error_with_unification_trace() {
+        assert_eq!(
+            error_in("[1] == [true]").0,
+            concat!(
+                "This expression has type bool list but an expression was expected of type int list\n",
+                "       Type bool is not compatible with type int",
+            )
+        );
+        assert_eq!(
+            error_in("let f x = x + 1 in [f] == [let g y = y == true in g]").0,
+            concat!(
+                "This expression has type (bool -> bool) list but an expression was expected of type (int -> int) list\n",
+                "       Type bool -> bool is not compatible with type int -> int\n",
+                "       Type bool is not compatible with type int",
+            )
+        );
+    }
+
+    #[test]
+    fn error_on_recursive_type() {
+        assert_eq!(
+            error_in("let f x = [x] == x").0,
+            concat!(
+                "This expression has type 'a but an expression was expected of type 'a list\n",
+                "       The type variable 'a occurs inside 'a list",
+            )
+        );
+    }
+
+    #[test]
+    fn error_on_unbound_value() {
+        let err = type_of("let x = 1 in x + y").unwrap_err();
+        assert_eq!(err.code(), "type/unbound-value");
+        assert_eq!(err.to_string(), "Unbound value y");
+    }
+}
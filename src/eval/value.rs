@@ -1,107 +1,323 @@
-// value with its type
+use super::builtins::{Native, Prim};
+use super::bytecode::Lambda;
+use super::env::Env;
+use super::parser::Node;
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
+
+// Value of an expression. Values are immutable, other than references and
+// arrays, and share what they are made of by reference counting, so cloning
+// one takes constant time.
 #[derive(Clone, Debug, PartialEq)]
 pub(crate) enum Value {
-    Int(i64),   // int
-    Bool(bool), // bool
-    List(List), // list
-    Fn,         // function
+    Int(i64),                // int
+    Bool(bool),              // bool
+    Unit,                    // ()
+    Str(Rc<[u8]>),           // string
+    List(List),              // list
+    Tuple(Rc<[Value]>),      // tuple
+    Fn(Func),                // function
+    Ref(Rc<RefCell<Value>>), // mutable reference shared by its copies
+    // mutable array shared by its copies
+    Array(Rc<RefCell<Vec<Value>>>),
+    // variant constructor with an optional argument
+    Constr(Rc<str>, Option<Rc<Value>>),
+    // record with fields in the declared order
+    Record(Rc<[(String, Value)]>),
+}
+
+// function value, applied to one argument at a time
+#[derive(Clone, Debug)]
+pub(crate) enum Func {
+    Closure(Rc<Closure>),
+    // function compiled to bytecode with the arguments applied so far
+    Code(Rc<CodeClosure>, Rc<[Value]>),
+    // primitive with the arguments applied so far
+    Prim(&'static Prim, Rc<[Value]>),
+    // native function with the arguments applied so far
+    Native(Rc<Native>, Rc<[Value]>),
+}
+
+// Functions are equal only if they are the same value.
+impl PartialEq for Func {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Func::Closure(c1), Func::Closure(c2)) => Rc::ptr_eq(c1, c2),
+            (Func::Code(c1, args1), Func::Code(c2, args2)) => Rc::ptr_eq(c1, c2) && args1 == args2,
+            (Func::Prim(p1, args1), Func::Prim(p2, args2)) => p1.name == p2.name && args1 == args2,
+            (Func::Native(n1, args1), Func::Native(n2, args2)) => {
+                Rc::ptr_eq(n1, n2) && args1 == args2
+            }
+            _ => false,
+        }
+    }
+}
+
+// Values are dropped one by one rather than recursively, since constructors,
+// tuples, references and applications can nest them deeper than the stack:
+// the values held only by each are taken out before it is dropped.
+impl Drop for Value {
+    fn drop(&mut self) {
+        let mut values = Vec::new();
+        self.take_values(&mut values);
+        while let Some(mut value) = values.pop() {
+            value.take_values(&mut values);
+        }
+    }
+}
+
+impl Value {
+    // Moves the values held only by this one to `values`.
+    fn take_values(&mut self, values: &mut Vec<Value>) {
+        match self {
+            Value::Int(_) | Value::Bool(_) | Value::Unit | Value::Str(_) => {}
+            Value::List(list) => list.take_heads(values),
+            Value::Tuple(elems) => values.append(&mut take_all(elems)),
+            Value::Fn(func) => values.append(&mut func.take_values()),
+            Value::Ref(r) => {
+                if let Some(r) = Rc::get_mut(r) {
+                    values.push(mem::replace(r.get_mut(), Value::Unit));
+                }
+            }
+            Value::Array(array) => {
+                if let Some(array) = Rc::get_mut(array) {
+                    values.append(array.get_mut());
+                }
+            }
+            Value::Constr(_, arg) => {
+                if let Some(arg) = arg.as_mut().and_then(Rc::get_mut) {
+                    values.push(mem::replace(arg, Value::Unit));
+                }
+            }
+            Value::Record(fields) => {
+                if let Some(fields) = Rc::get_mut(fields) {
+                    values.extend(
+                        fields
+                            .iter_mut()
+                            .map(|(_, value)| mem::replace(value, Value::Unit)),
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl Func {
+    // Takes the values held only by this function. Those of closures not
+    // compiled are dropped by their environments.
+    fn take_values(&mut self) -> Vec<Value> {
+        match self {
+            Func::Closure(_) => Vec::new(),
+            Func::Code(closure, args) => {
+                let mut values = take_all(args);
+                if let Some(closure) = Rc::get_mut(closure) {
+                    values.append(&mut closure.captured);
+                }
+                values
+            }
+            Func::Prim(_, args) | Func::Native(_, args) => take_all(args),
+        }
+    }
+}
+
+fn take_all(values: &mut Rc<[Value]>) -> Vec<Value> {
+    match Rc::get_mut(values) {
+        Some(values) => values
+            .iter_mut()
+            .map(|value| mem::replace(value, Value::Unit))
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+// Arguments applied so far followed by another.
+pub(super) fn applied(args: &[Value], arg: Value) -> Rc<[Value]> {
+    args.iter().cloned().chain([arg]).collect()
+}
+
+// function defined in OCaml, with the environment where it was defined
+#[derive(Debug)]
+pub(crate) struct Closure {
+    // name bound to the closure itself in its body, for `let rec`
+    pub(super) name: Option<String>,
+    pub(super) params: Vec<String>, // parameters yet to be applied
+    pub(super) body: Rc<Node>,
+    pub(super) env: Env,
+}
+
+// function compiled to bytecode, with the values it captured from where it
+// was defined
+#[derive(Debug)]
+pub(crate) struct CodeClosure {
+    pub(super) lambda: Rc<Lambda>,
+    pub(super) captured: Vec<Value>,
 }
 
 /* list */
-use std::fmt;
+// Immutable list, whose tails are shared by the lists consed onto them, so
+// that consing and cloning take constant time.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct List(Option<Rc<Cons>>);
 
-#[derive(Clone, Debug, PartialEq)]
-pub(crate) struct List(pub Option<i64>, pub Option<Box<List>>);
+#[derive(Debug)]
+struct Cons {
+    head: Value,
+    tail: List,
+}
 
 impl List {
     pub fn new() -> Self {
-        Self(None, None)
+        Self(None)
+    }
+
+    // Makes a list of `head` followed by the elements of this one.
+    pub fn cons(&self, head: Value) -> Self {
+        Self(Some(Rc::new(Cons {
+            head,
+            tail: self.clone(),
+        })))
     }
 
-    pub fn cons(&mut self, head: i64) {
-        self.1 = Some(Box::new(self.clone()));
-        self.0 = Some(head);
+    // First element and the list of the others, unless this one is empty.
+    pub fn uncons(&self) -> Option<(&Value, &List)> {
+        self.0.as_deref().map(|cons| (&cons.head, &cons.tail))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Value> {
+        std::iter::successors(self.0.as_deref(), |cons| cons.tail.0.as_deref())
+            .map(|cons| &cons.head)
     }
 }
 
-impl From<&Vec<i64>> for List {
-    fn from(list: &Vec<i64>) -> Self {
-        let mut lst = List::new();
-        for ele in list.iter().rev() {
-            lst.cons(*ele);
+// Lists are compared element by element rather than recursively, as they can
+// be longer than the stack is deep.
+impl PartialEq for List {
+    fn eq(&self, other: &Self) -> bool {
+        let (mut lhs, mut rhs) = (self.iter(), other.iter());
+        loop {
+            match (lhs.next(), rhs.next()) {
+                (Some(l), Some(r)) if l == r => continue,
+                (None, None) => return true,
+                _ => return false,
+            }
         }
-        lst
     }
 }
 
-impl fmt::Display for List {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "[")?;
-        let mut iter = self;
-        let mut is_first = true;
-        while let List(Some(head), Some(rest)) = iter {
-            if !is_first {
-                write!(f, "; ")?;
-            }
-            write!(f, "{}", head)?;
-            iter = rest;
-            is_first = false;
+// Cells are dropped one by one for the same reason, up to the first one
+// shared with another list.
+impl Drop for List {
+    fn drop(&mut self) {
+        let mut next = self.0.take();
+        while let Some(cons) = next {
+            next = match Rc::try_unwrap(cons) {
+                Ok(mut cons) => cons.tail.0.take(),
+                Err(_) => None,
+            };
+        }
+    }
+}
+
+impl List {
+    // Moves the heads of the cells held only by this list to `values`, up to
+    // the first one shared with another list.
+    fn take_heads(&mut self, values: &mut Vec<Value>) {
+        let mut next = self.0.take();
+        while let Some(cons) = next {
+            next = match Rc::try_unwrap(cons) {
+                Ok(mut cons) => {
+                    values.push(mem::replace(&mut cons.head, Value::Unit));
+                    cons.tail.0.take()
+                }
+                Err(_) => None,
+            };
         }
-        write!(f, "]")
+    }
+}
+
+impl From<Vec<Value>> for List {
+    fn from(list: Vec<Value>) -> Self {
+        list.into_iter()
+            .rev()
+            .fold(List::new(), |list, elem| list.cons(elem))
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::super::builtins::prim;
     use super::*;
 
     #[test]
     fn new_empty_list() {
-        assert_eq!(List(None, None), List::new(),);
+        assert_eq!(List(None), List::new());
+        assert_eq!(List::new().iter().count(), 0);
     }
 
     #[test]
     fn cons_to_list() {
-        let mut lst = List::new();
-        assert_eq!(List(None, None), lst,);
+        let lst = List::new().cons(Value::Int(1));
+        assert_eq!(lst, List::from(vec![Value::Int(1)]));
 
-        lst.cons(1);
-        assert_eq!(List(Some(1), Some(Box::new(List(None, None))),), lst,);
+        let consed = lst.cons(Value::Int(2));
+        assert_eq!(consed, List::from(vec![Value::Int(2), Value::Int(1)]));
+        assert_eq!(lst, List::from(vec![Value::Int(1)]));
+    }
 
-        lst.cons(2);
-        assert_eq!(
-            List(
-                Some(2),
-                Some(Box::new(List(Some(1), Some(Box::new(List(None, None))),),)),
-            ),
-            lst,
-        );
+    #[test]
+    fn cons_shares_tail() {
+        let tail = List::from(vec![Value::Int(1), Value::Int(2)]);
+        let lst = tail.cons(Value::Int(0));
+        let shared = &lst.0.as_ref().unwrap().tail;
+        assert!(Rc::ptr_eq(
+            shared.0.as_ref().unwrap(),
+            tail.0.as_ref().unwrap()
+        ));
+        assert_eq!(Rc::strong_count(tail.0.as_ref().unwrap()), 2);
+        drop(lst);
+        assert_eq!(Rc::strong_count(tail.0.as_ref().unwrap()), 1);
+    }
+
+    #[test]
+    fn iterate_list() {
+        let list = List::from(vec![Value::Int(1), Value::Int(2)]);
+        let elems: Vec<&Value> = list.iter().collect();
+        assert_eq!(elems, vec![&Value::Int(1), &Value::Int(2)]);
+        assert_eq!(List::new().iter().count(), 0);
     }
 
     #[test]
     fn from_collection() {
-        assert_eq!(List(None, None), List::from(&vec![]));
+        assert_eq!(List::new(), List::from(vec![]));
+        let list = List::from(vec![Value::Int(1), Value::Int(2), Value::Int(3)]);
         assert_eq!(
-            List(
-                Some(1),
-                Some(Box::new(List(
-                    Some(2),
-                    Some(Box::new(List(Some(3), Some(Box::new(List(None, None))),),)),
-                ),)),
-            ),
-            List::from(&vec![1, 2, 3]),
+            list,
+            List::new()
+                .cons(Value::Int(3))
+                .cons(Value::Int(2))
+                .cons(Value::Int(1)),
         );
+        assert_ne!(list, List::from(vec![Value::Int(1), Value::Int(2)]));
     }
 
     #[test]
-    fn convert_to_string() {
-        let list = List(
-            Some(1),
-            Some(Box::new(List(
-                Some(2),
-                Some(Box::new(List(Some(3), Some(Box::new(List(None, None)))))),
-            ))),
-        );
-        assert_eq!(list.to_string(), "[1; 2; 3]",);
+    fn compare_and_drop_long_lists() {
+        let long = || (0..1_000_000).fold(List::new(), |list, i| list.cons(Value::Int(i)));
+        assert_eq!(long(), long());
+    }
+
+    #[test]
+    fn drop_deeply_nested_values() {
+        let nest = |wrap: &dyn Fn(Value) -> Value| {
+            (0..1_000_000).fold(Value::Unit, |value, _| wrap(value));
+        };
+        nest(&|value| Value::Constr("S".into(), Some(Rc::new(value))));
+        nest(&|value| Value::Tuple(Rc::from([Value::Int(0), value])));
+        nest(&|value| Value::Ref(Rc::new(RefCell::new(value))));
+        nest(&|value| Value::Array(Rc::new(RefCell::new(vec![value]))));
+        nest(&|value| Value::Record(Rc::from([("next".to_string(), value)])));
+        nest(&|value| Value::List(List::new().cons(value)));
+        nest(&|value| Value::Fn(Func::Prim(prim("ref").unwrap(), Rc::from([value]))));
     }
 }
@@ -0,0 +1,379 @@
+use super::{
+    bytecode::{compile, ArithOp, Block, Instr, Var},
+    eval_ast::{self, compare, locate, Output},
+    lambda::lower,
+    meter::Meter,
+    parser::{CompareOp, Node},
+    span::Span,
+    value::{applied, CodeClosure, Func, List},
+    Value,
+};
+use crate::error::{Error, Exception, ExceptionKind};
+use crate::Bounds;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::mem;
+use std::rc::Rc;
+
+// values on the stack of the machine, counting a frame as 3, beyond which
+// `Stack_overflow` is raised, as the machine does not take the Rust stack
+const STACK_LIMIT: usize = 256 * 1024;
+
+const FRAME_SIZE: usize = 3;
+
+// Evaluates a well-typed AST by lowering it to the IR compiled to bytecode,
+// which is run by the machine. Instructions run and frames are counted by
+// the meter.
+pub(super) fn eval(ast: &Node, bounds: &mut Bounds) -> Result<Output, Error> {
+    let program = lower(ast, bounds)?;
+    let lambda = compile(&program, ast.span, &bounds.values, &bounds.decls)?;
+    let name = program.name;
+    let closure = Rc::new(CodeClosure {
+        lambda,
+        captured: Vec::new(),
+    });
+    let value = Machine::new(&bounds.meter).run(closure, Vec::new(), ast.span)?;
+    if let Some(name) = &name {
+        bounds.bind(name.clone(), value.clone());
+    }
+    Ok(Output { name, value })
+}
+
+// Applies a function to an argument, running the machine if it is compiled
+// to bytecode and given all its arguments.
+pub(super) fn apply(func: Value, arg: Value, span: Span, meter: &Meter) -> Result<Value, Error> {
+    let (closure, args) = match &func {
+        Value::Fn(Func::Code(closure, args)) => (closure.clone(), applied(args, arg)),
+        _ => return eval_ast::apply(func, arg, span, meter),
+    };
+    if args.len() < closure.lambda.arity {
+        let value = Value::Fn(Func::Code(closure, args));
+        meter.alloc(&value, span)?;
+        return Ok(value);
+    }
+    Machine::new(meter).run(closure, args.to_vec(), span)
+}
+
+struct Machine<'a> {
+    accu: Value,
+    stack: Vec<Value>,
+    // frames of the callers of the function running
+    frames: Vec<Frame>,
+    meter: &'a Meter,
+}
+
+// function running, or returned to
+struct Frame {
+    closure: Rc<CodeClosure>,
+    pc: usize,
+    // index of the first argument on the stack
+    base: usize,
+}
+
+impl<'a> Machine<'a> {
+    fn new(meter: &'a Meter) -> Self {
+        Self {
+            accu: Value::Unit,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            meter,
+        }
+    }
+
+    // Runs a closure given all its arguments until it returns. Frames left
+    // by an exception are left in the meter as well.
+    fn run(
+        &mut self,
+        closure: Rc<CodeClosure>,
+        args: Vec<Value>,
+        span: Span,
+    ) -> Result<Value, Error> {
+        self.meter.enter(span)?;
+        self.stack.extend(args);
+        let frame = Frame {
+            closure,
+            pc: 0,
+            base: 0,
+        };
+        let value = self.exec(frame);
+        for _ in 0..=self.frames.len() {
+            self.meter.leave();
+        }
+        value
+    }
+
+    fn exec(&mut self, mut frame: Frame) -> Result<Value, Error> {
+        let mut lambda = frame.closure.lambda.clone();
+        loop {
+            let (instr, span) = (&lambda.code[frame.pc], lambda.spans[frame.pc]);
+            self.meter.step(span)?;
+            frame.pc += 1;
+            match instr {
+                Instr::Const(value) => self.accu = value.clone(),
+                Instr::Load(Var::Local(slot)) => self.accu = self.stack[frame.base + slot].clone(),
+                Instr::Load(Var::Captured(index)) => {
+                    self.accu = frame.closure.captured[*index].clone()
+                }
+                Instr::Load(Var::Itself) => {
+                    self.accu = Value::Fn(Func::Code(frame.closure.clone(), Rc::from([])))
+                }
+                Instr::Push => self.stack.push(self.accu.clone()),
+                Instr::Pop(n) => self.stack.truncate(self.stack.len() - n),
+                Instr::Closure(lambda) => {
+                    let captured = lambda
+                        .captures
+                        .iter()
+                        .map(|var| match var {
+                            Var::Local(slot) => self.stack[frame.base + slot].clone(),
+                            Var::Captured(index) => frame.closure.captured[*index].clone(),
+                            Var::Itself => {
+                                Value::Fn(Func::Code(frame.closure.clone(), Rc::from([])))
+                            }
+                        })
+                        .collect();
+                    let closure = Rc::new(CodeClosure {
+                        lambda: lambda.clone(),
+                        captured,
+                    });
+                    self.accu = Value::Fn(Func::Code(closure, Rc::from([])));
+                    self.meter.alloc(&self.accu, span)?;
+                }
+                Instr::Apply | Instr::TailApply => {
+                    let tail = matches!(instr, Instr::TailApply);
+                    let func = self.stack.pop().unwrap();
+                    let arg = self.take_accu();
+                    let (closure, args) = match &func {
+                        Value::Fn(Func::Code(closure, args))
+                            if args.len() + 1 == closure.lambda.arity =>
+                        {
+                            (closure.clone(), args.clone())
+                        }
+                        // partial applications, and functions not compiled
+                        _ => {
+                            self.accu = apply(func, arg, span, self.meter)?;
+                            if tail {
+                                match self.ret(&frame) {
+                                    Some(caller) => frame = caller,
+                                    None => return Ok(self.take_accu()),
+                                }
+                                lambda = frame.closure.lambda.clone();
+                            }
+                            continue;
+                        }
+                    };
+                    if tail {
+                        self.stack.truncate(frame.base);
+                        frame = Frame {
+                            closure,
+                            pc: 0,
+                            base: frame.base,
+                        };
+                    } else {
+                        self.meter.enter(span)?;
+                        let base = self.stack.len();
+                        let callee = Frame {
+                            closure,
+                            pc: 0,
+                            base,
+                        };
+                        self.frames.push(mem::replace(&mut frame, callee));
+                    }
+                    self.stack.extend(args.iter().cloned());
+                    self.stack.push(arg);
+                    if self.stack.len() + self.frames.len() * FRAME_SIZE > STACK_LIMIT {
+                        return Err(exception(ExceptionKind::StackOverflow, span));
+                    }
+                    lambda = frame.closure.lambda.clone();
+                }
+                Instr::Return => {
+                    match self.ret(&frame) {
+                        Some(caller) => frame = caller,
+                        None => return Ok(self.take_accu()),
+                    }
+                    lambda = frame.closure.lambda.clone();
+                }
+                Instr::Branch(to) => frame.pc = *to,
+                Instr::BranchIfNot(to) => {
+                    if matches!(self.accu, Value::Bool(false)) {
+                        frame.pc = *to;
+                    }
+                }
+                Instr::Arith(op) => {
+                    let (l, r) = match (self.stack.pop(), &self.accu) {
+                        (Some(Value::Int(l)), Value::Int(r)) => (l, *r),
+                        _ => return Err(Error::Internal("Expected an int".to_string())),
+                    };
+                    self.accu = Value::Int(match op {
+                        ArithOp::Add => l.wrapping_add(r),
+                        ArithOp::Sub => l.wrapping_sub(r),
+                        ArithOp::Mul => l.wrapping_mul(r),
+                        ArithOp::Div if r == 0 => {
+                            return Err(exception(ExceptionKind::DivisionByZero, span))
+                        }
+                        ArithOp::Div => l.wrapping_div(r),
+                    });
+                }
+                Instr::Eq | Instr::Ne => {
+                    let lhs = self.stack.pop().unwrap();
+                    let ord = compare(&lhs, &self.accu, &lambda.decls, None, self.meter, span)?;
+                    self.accu = Value::Bool((ord == Ordering::Equal) == matches!(instr, Instr::Eq));
+                }
+                Instr::Compare(op) => {
+                    let lhs = self.stack.pop().unwrap();
+                    let prim = match op {
+                        CompareOp::Eq | CompareOp::Ne => "equal",
+                        _ => "compare",
+                    };
+                    let ord = compare(
+                        &lhs,
+                        &self.accu,
+                        &lambda.decls,
+                        Some(prim),
+                        self.meter,
+                        span,
+                    )
+                    .map_err(|err| locate(err, span))?;
+                    self.accu = Value::Bool(match op {
+                        CompareOp::Eq => ord == Ordering::Equal,
+                        CompareOp::Ne => ord != Ordering::Equal,
+                        CompareOp::Lt => ord == Ordering::Less,
+                        CompareOp::Gt => ord == Ordering::Greater,
+                        CompareOp::Le => ord != Ordering::Greater,
+                        CompareOp::Ge => ord != Ordering::Less,
+                    });
+                }
+                Instr::MakeBlock(block, len) => {
+                    let mut values = Vec::with_capacity(*len);
+                    if *len > 0 {
+                        values.extend(self.stack.drain(self.stack.len() + 1 - len..));
+                        values.push(self.take_accu());
+                    }
+                    self.accu = match block {
+                        Block::Tuple => Value::Tuple(values.into()),
+                        Block::List => Value::List(List::from(values)),
+                        Block::Array => Value::Array(Rc::new(RefCell::new(values))),
+                        Block::Constr(name) => {
+                            Value::Constr(name.clone(), values.pop().map(Rc::new))
+                        }
+                        Block::Record(names) => {
+                            Value::Record(names.iter().cloned().zip(values).collect())
+                        }
+                    };
+                    self.meter.alloc(&self.accu, span)?;
+                }
+                Instr::Cons => {
+                    let head = self.stack.pop().unwrap();
+                    self.accu = match &self.accu {
+                        Value::List(list) => Value::List(list.cons(head)),
+                        _ => return Err(Error::Internal("Expected a list".to_string())),
+                    };
+                    self.meter.alloc_words(3, span)?;
+                }
+                Instr::Field(index) => {
+                    self.accu = match &self.accu {
+                        Value::Record(fields) => fields[*index].1.clone(),
+                        _ => return Err(Error::Internal("Expected a record".to_string())),
+                    };
+                }
+            }
+        }
+    }
+
+    // Returns from a frame to its caller, if it has one within the run.
+    fn ret(&mut self, frame: &Frame) -> Option<Frame> {
+        self.stack.truncate(frame.base);
+        let caller = self.frames.pop()?;
+        self.meter.leave();
+        Some(caller)
+    }
+
+    fn take_accu(&mut self) -> Value {
+        mem::replace(&mut self.accu, Value::Unit)
+    }
+}
+
+fn exception(kind: ExceptionKind, span: Span) -> Error {
+    Error::Exception(Exception {
+        kind,
+        span: Some(span),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::Backend;
+
+    fn bounds() -> Bounds {
+        Bounds {
+            backend: Backend::Bytecode,
+            ..Bounds::new()
+        }
+    }
+
+    fn eval(input: &str, bounds: &mut Bounds) -> String {
+        let input = &format!("{};;", input);
+        match crate::eval::eval(input, bounds) {
+            Ok(output) => output,
+            Err(err) => err.render(input),
+        }
+    }
+
+    #[test]
+    fn eval_partial_applications() {
+        let mut bounds = bounds();
+        eval("let add3 x y z = x + y + z", &mut bounds);
+        eval("let f = add3 1", &mut bounds);
+        assert_eq!(
+            eval("let g = f 2", &mut bounds),
+            "val g : int -> int = <fun>"
+        );
+        assert_eq!(
+            eval("(g 3, f 0 0, add3 1 1 1)", &mut bounds),
+            "- : int * int * int = (6, 1, 3)"
+        );
+    }
+
+    #[test]
+    fn eval_deep_recursion_off_the_rust_stack() {
+        // The machine keeps its frames on the heap, so a small thread stack
+        // does not limit recursion.
+        let (deep, overflow) = std::thread::Builder::new()
+            .stack_size(512 * 1024)
+            .spawn(|| {
+                let mut bounds = bounds();
+                eval(
+                    "let rec deep n = if n = 0 then 0 else 1 + deep (n - 1)",
+                    &mut bounds,
+                );
+                let deep = eval("deep 50_000", &mut bounds);
+                (deep, eval("deep 1_000_000", &mut bounds))
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(deep, "- : int = 50000");
+        assert_eq!(overflow, "Exception: Stack_overflow.");
+    }
+
+    #[test]
+    fn eval_with_max_depth() {
+        let mut bounds = bounds();
+        bounds.meter.set_limits(crate::EvalLimits {
+            max_depth: Some(10),
+            ..Default::default()
+        });
+        eval(
+            "let rec deep n = if n = 0 then 0 else 1 + deep (n - 1)",
+            &mut bounds,
+        );
+        eval(
+            "let rec loop n = if n = 0 then 0 else loop (n - 1)",
+            &mut bounds,
+        );
+        assert_eq!(eval("deep 5", &mut bounds), "- : int = 5");
+        assert_eq!(eval("deep 20", &mut bounds), "Exception: Stack_overflow.");
+        // Tail calls take no depth.
+        assert_eq!(eval("loop 1000", &mut bounds), "- : int = 0");
+    }
+}
@@ -0,0 +1,515 @@
+use super::{
+    bytecode::ArithOp,
+    emit::{bound_function, function_names, indented, prim_name, Emitter, Global, Language},
+    lambda::{Prim, Program},
+    parser::CompareOp,
+    types::TypeDecls,
+};
+use crate::error::Error;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+// runtime spliced into the module after the imports
+const RUNTIME: &str = include_str!("runtime.wat");
+
+// address of the static data, above the stack of arguments
+const DATA: usize = 1 << 20;
+
+// primitives whose closures the runtime has code for, in the order of the
+// table
+const PRIMS: [&str; 8] = [
+    "ref",
+    "!",
+    ":=",
+    "print_string",
+    "print_endline",
+    "print_int",
+    "print_newline",
+    "failwith",
+];
+
+// WebAssembly module in the text format compiled from phrases in turn, e.g.
+// those of a file compiled by `rcaml compile -o out.wat`. Global values are
+// held by globals, constants are static blocks, and each phrase is run by a
+// function called by the export `main` in turn.
+#[derive(Default)]
+pub(crate) struct WasmProgram {
+    // what each global holds, for comments
+    slots: Vec<String>,
+    // global values in scope by their names
+    globals: HashMap<String, Global>,
+    // static blocks from `DATA`, and the addresses of strings and of the
+    // closures of primitives among them
+    data: Vec<u8>,
+    strings: HashMap<Vec<u8>, usize>,
+    prims: HashMap<&'static str, usize>,
+    // initial values of the globals of externals
+    constants: HashMap<usize, usize>,
+    // functions, and those of closures in the order of the table
+    functions: Vec<String>,
+    table: Vec<String>,
+    // greatest number of parameters a function takes
+    max_arity: usize,
+    // names of the functions of the phrases
+    phrases: Vec<String>,
+}
+
+impl WasmProgram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Compiles a phrase lowered to the IR, binding the name it binds.
+    pub(super) fn phrase(&mut self, program: &Program, decls: &TypeDecls) -> Result<(), Error> {
+        let base = self.table.len();
+        let names = function_names(base, program);
+        self.table.extend(names.iter().cloned());
+        for (index, function) in program.functions.iter().enumerate() {
+            let arity = function.params.len();
+            self.max_arity = self.max_arity.max(arity);
+            let mut emitter = Emitter::for_function(self, program, &names, base, decls, index);
+            emitter.indent = 2;
+            emitter.term(&function.body, None)?;
+            let mut code = format!("  (func ${} (type $code{})", names[index], arity);
+            for i in 0..=arity {
+                write!(code, " (param $v{} i64)", i).unwrap();
+            }
+            code += " (result i64)\n";
+            code += &locals(arity + 1, emitter.slots);
+            if emitter.tail_calls {
+                code += "    (loop $top\n";
+                code += &indented(&emitter.code, 1);
+                code += "    )\n";
+            } else {
+                code += &emitter.code;
+            }
+            code += "    (unreachable))\n";
+            self.functions.push(code);
+        }
+
+        let name = format!("phrase{}", self.phrases.len());
+        let mut emitter = Emitter::new(self, program, &names, base, decls);
+        emitter.indent = 2;
+        let result = emitter.slot();
+        emitter.term(&program.body, Some(result))?;
+        let (slots, body) = (emitter.slots, emitter.code);
+        let mut code = format!("  (func ${}\n", name);
+        code += &locals(0, slots);
+        code += &body;
+        if let Some(bound) = &program.name {
+            let slot = self.slot(bound);
+            writeln!(code, "    (global.set $g{} {})", slot, local(result)).unwrap();
+            let function = bound_function(program, &names);
+            self.globals
+                .insert(bound.clone(), Global { slot, function });
+        }
+        code.pop();
+        code += ")\n";
+        self.functions.push(code);
+        self.phrases.push(name);
+        Ok(())
+    }
+
+    // Binds a name to a primitive, by `external`.
+    pub(super) fn external(&mut self, name: &str, prim: &str) -> Result<(), Error> {
+        let closure = self.prim(prim)?;
+        let slot = self.slot(name);
+        self.constants.insert(slot, closure);
+        self.globals.insert(
+            name.to_string(),
+            Global {
+                slot,
+                function: None,
+            },
+        );
+        Ok(())
+    }
+
+    // Text of the whole module with the runtime.
+    pub fn finish(&self) -> String {
+        let mut out = "(module\n".to_string();
+        out += "  (import \"rcaml\" \"print\" (func $print (param i32 i32)))\n";
+        out += "  (import \"rcaml\" \"raise\" (func $raise (param i32 i32)))\n";
+        // Primitives take up to two arguments.
+        for arity in 1..=self.max_arity.max(2) {
+            write!(out, "  (type $code{} (func", arity).unwrap();
+            for _ in 0..=arity {
+                out += " (param i64)";
+            }
+            out += " (result i64)))\n";
+        }
+        let end = DATA + self.data.len();
+        writeln!(out, "  (memory (export \"memory\") {})", end / 65536 + 1).unwrap();
+        writeln!(out, "  (global $hp (mut i32) (i32.const {}))", end).unwrap();
+        writeln!(out, "  (table {} funcref)", PRIMS.len() + self.table.len()).unwrap();
+        out += "  (elem (i32.const 0) func";
+        for prim in PRIMS {
+            write!(out, " $code_{}", prim_name(prim).unwrap()).unwrap();
+        }
+        for name in &self.table {
+            write!(out, " ${}", name).unwrap();
+        }
+        out += ")\n\n";
+        out += RUNTIME;
+
+        out += "\n  ;; Program\n\n";
+        for (index, what) in self.slots.iter().enumerate() {
+            let value = self.constants.get(&index).map_or(1, |address| *address);
+            writeln!(
+                out,
+                "  (global $g{} (mut i64) (i64.const {})) ;; {}",
+                index, value, what
+            )
+            .unwrap();
+        }
+        writeln!(
+            out,
+            "  (data (i32.const {}) \"{}\")\n",
+            DATA,
+            wat_string(&self.data)
+        )
+        .unwrap();
+        out += &self.enter();
+        for function in &self.functions {
+            out += "\n";
+            out += function;
+        }
+        out += "\n  (func (export \"main\")";
+        for phrase in &self.phrases {
+            write!(out, "\n    (call ${})", phrase).unwrap();
+        }
+        out += "))\n";
+        out
+    }
+
+    // Function calling the code of a closure with the arguments at `args`,
+    // as many as it takes.
+    fn enter(&self) -> String {
+        let mut out =
+            "  (func $enter (param $clo i64) (param $args i32) (result i64)\n".to_string();
+        out += "    (local $code i32) (local $arity i64)\n";
+        out += "    (local.set $code (i32.wrap_i64 (i64.load (i32.wrap_i64 (local.get $clo)))))\n";
+        out += "    (local.set $arity (i64.load offset=8 (i32.wrap_i64 (local.get $clo))))\n";
+        for arity in 1..=self.max_arity.max(2) {
+            writeln!(
+                out,
+                "    (if (i64.eq (local.get $arity) (i64.const {}))",
+                tagged(arity as i64)
+            )
+            .unwrap();
+            write!(
+                out,
+                "      (then (return (call_indirect (type $code{}) (local.get $clo)",
+                arity
+            )
+            .unwrap();
+            for i in 0..arity {
+                write!(out, " (i64.load offset={} (local.get $args))", 8 * i).unwrap();
+            }
+            out += " (local.get $code)))))\n";
+        }
+        out += "    (unreachable))\n";
+        out
+    }
+
+    fn slot(&mut self, what: &str) -> usize {
+        self.slots.push(what.to_string());
+        self.slots.len() - 1
+    }
+
+    // Adds a static block, returning its address.
+    fn block(&mut self, tag: u8, fields: &[i64], bytes: &[u8]) -> usize {
+        let size = fields.len() + bytes.len().div_ceil(8);
+        let header = ((size as i64) << 8) | tag as i64;
+        self.data.extend(header.to_le_bytes());
+        let address = DATA + self.data.len();
+        for field in fields {
+            self.data.extend(field.to_le_bytes());
+        }
+        self.data.extend(bytes);
+        self.data.resize(self.data.len().next_multiple_of(8), 0);
+        address
+    }
+
+    // Address of a string constant.
+    fn string_address(&mut self, bytes: &[u8]) -> usize {
+        if let Some(address) = self.strings.get(bytes) {
+            return *address;
+        }
+        // A NUL follows the bytes, as in C.
+        let mut with_nul = bytes.to_vec();
+        with_nul.push(0);
+        let address = self.block(252, &[tagged(bytes.len() as i64)], &with_nul);
+        self.strings.insert(bytes.to_vec(), address);
+        address
+    }
+
+    // Address of the closure of a primitive.
+    fn prim(&mut self, prim: &str) -> Result<usize, Error> {
+        let name = prim_name(prim)?;
+        if let Some(address) = self.prims.get(name) {
+            return Ok(*address);
+        }
+        // The runtime has code for every primitive compiled.
+        let index = PRIMS.iter().position(|known| *known == prim).unwrap();
+        let arity = if prim == ":=" { 2 } else { 1 };
+        let address = self.block(247, &[index as i64, tagged(arity)], &[]);
+        self.prims.insert(name, address);
+        Ok(address)
+    }
+}
+
+impl Language for WasmProgram {
+    fn globals(&self) -> &HashMap<String, Global> {
+        &self.globals
+    }
+
+    // Values are held by the locals `$v0`, `$v1`, ... of a function.
+    fn local(slot: usize) -> String {
+        local(slot)
+    }
+
+    fn global(slot: usize) -> String {
+        format!("(global.get $g{})", slot)
+    }
+
+    fn set(slot: usize, expr: &str) -> String {
+        format!("(local.set $v{} {})", slot, expr)
+    }
+
+    fn ret(expr: &str) -> String {
+        format!("(return {})", expr)
+    }
+
+    fn int(i: i64) -> String {
+        format!("(i64.const {})", tagged(i))
+    }
+
+    fn string(&mut self, bytes: &[u8]) -> String {
+        format!("(i64.const {})", self.string_address(bytes))
+    }
+
+    fn prim_closure(&mut self, prim: &str) -> Result<String, Error> {
+        Ok(format!("(i64.const {})", self.prim(prim)?))
+    }
+
+    fn alloc(size: usize, tag: usize) -> String {
+        format!("(call $alloc (i32.const {}) (i32.const {}))", size, tag)
+    }
+
+    // Closures are called by their index in the table, after the
+    // primitives.
+    fn closure(function: usize, _: &str, arity: usize, captured: usize) -> String {
+        format!(
+            "(call $closure (i32.const {}) (i32.const {}) (i32.const {}))",
+            PRIMS.len() + function,
+            arity,
+            captured
+        )
+    }
+
+    fn field(block: &str, index: usize) -> String {
+        format!("(i64.load offset={} {})", 8 * index, address(block))
+    }
+
+    fn set_field(block: &str, index: usize, value: &str) -> String {
+        format!(
+            "(i64.store offset={} {} {})",
+            8 * index,
+            address(block),
+            value
+        )
+    }
+
+    fn prim(prim: &Prim, args: &[String]) -> String {
+        prim_expr(prim, args)
+    }
+
+    fn call_prim(name: &str, args: &[String]) -> String {
+        format!("(call ${} {})", name, args.join(" "))
+    }
+
+    fn call(name: &str, closure: &str, args: &[String]) -> String {
+        format!("(call ${} {} {})", name, closure, args.join(" "))
+    }
+
+    // The arguments are passed on the stack of arguments to $apply.
+    fn apply(closure: &str, args: &[String]) -> (Vec<String>, String, Vec<String>) {
+        let n = args.len();
+        let mut before = vec![format!("(local.set $argv (call $push (i32.const {})))", n)];
+        for (i, arg) in args.iter().enumerate() {
+            before.push(format!(
+                "(i64.store offset={} (local.get $argv) {})",
+                8 * i,
+                arg
+            ));
+        }
+        let expr = format!(
+            "(call $apply {} (i32.const {}) (local.get $argv))",
+            closure, n
+        );
+        (before, expr, vec![format!("(call $pop (i32.const {}))", n)])
+    }
+
+    fn branch(cond: &str, then: &str, other: &str) -> String {
+        format!(
+            "(if (i64.ne {} (i64.const 1))\n  (then\n{}  )\n  (else\n{}  ))\n",
+            cond,
+            indented(then, 2),
+            indented(other, 2)
+        )
+    }
+
+    const JUMP: &'static str = "(br $top)";
+}
+
+fn prim_expr(prim: &Prim, args: &[String]) -> String {
+    let compare = |prim: u8, test: &str| {
+        format!(
+            "(call $bool (i32.{} (call $compare {} {} (i32.const {})) (i32.const 0)))",
+            test, args[0], args[1], prim
+        )
+    };
+    match prim {
+        Prim::Arith(ArithOp::Add) => {
+            format!("(i64.sub (i64.add {} {}) (i64.const 1))", args[0], args[1])
+        }
+        Prim::Arith(ArithOp::Sub) => {
+            format!("(i64.add (i64.sub {} {}) (i64.const 1))", args[0], args[1])
+        }
+        Prim::Arith(ArithOp::Mul) => format!(
+            "(i64.or (i64.shl (i64.mul (i64.shr_s {} (i64.const 1)) (i64.shr_s {} (i64.const 1))) (i64.const 1)) (i64.const 1))",
+            args[0], args[1]
+        ),
+        Prim::Arith(ArithOp::Div) => format!("(call $div {} {})", args[0], args[1]),
+        Prim::Eq => format!("(call $bool (call $equal {} {}))", args[0], args[1]),
+        Prim::Ne => format!("(call $bool (i32.eqz (call $equal {} {})))", args[0], args[1]),
+        Prim::Compare(op) => match op {
+            CompareOp::Eq => compare(1, "eq"),
+            CompareOp::Ne => compare(1, "ne"),
+            CompareOp::Lt => compare(0, "lt_s"),
+            CompareOp::Gt => compare(0, "gt_s"),
+            CompareOp::Le => compare(0, "le_s"),
+            CompareOp::Ge => compare(0, "ge_s"),
+        },
+        Prim::Cons => format!("(call $cons {} {})", args[0], args[1]),
+        Prim::Field(index) => format!("(i64.load offset={} {})", 8 * index, address(&args[0])),
+        // Blocks are made by statements filling their fields.
+        Prim::MakeBlock(_) => unreachable!("Blocks are made by Emitter::term()"),
+    }
+}
+
+fn local(slot: usize) -> String {
+    format!("(local.get $v{})", slot)
+}
+
+// Address in memory of a block value.
+fn address(value: &str) -> String {
+    format!("(i32.wrap_i64 {})", value)
+}
+
+// Declarations of the locals `$v{from}` up to `$v{to}`, and of `$argv`.
+fn locals(from: usize, to: usize) -> String {
+    let mut out = "   ".to_string();
+    for slot in from..to {
+        write!(out, " (local $v{} i64)", slot).unwrap();
+    }
+    out + " (local $argv i32)\n"
+}
+
+// Word of an int, tagged by its lowest bit.
+fn tagged(i: i64) -> i64 {
+    i.wrapping_shl(1) | 1
+}
+
+// Text of a string of bytes, escaping what is not printable.
+fn wat_string(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' => write!(text, "\\{:02x}", byte).unwrap(),
+            b' '..=b'~' => text.push(byte as char),
+            _ => write!(text, "\\{:02x}", byte).unwrap(),
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::{compile_phrase, Compiled, Target};
+    use crate::Bounds;
+
+    fn compiled(source: &str) -> String {
+        let mut bounds = Bounds::new();
+        let mut program = Compiled::new(Target::Wasm);
+        for input in source.split_inclusive(";;") {
+            compile_phrase(input, &mut bounds, &mut program).unwrap();
+        }
+        let wat = program.finish();
+        // The module is well formed.
+        wat::parse_str(&wat).unwrap();
+        wat
+    }
+
+    // Text of a function, e.g. `(func $f0_fact ...)`.
+    fn function<'a>(wat: &'a str, name: &str) -> &'a str {
+        let start = wat.find(&format!("(func ${} ", name)).unwrap();
+        let len = wat[start..].find("\n\n").unwrap_or(wat.len() - start);
+        &wat[start..start + len]
+    }
+
+    #[test]
+    fn compile_self_tail_calls_to_loops() {
+        let wat = compiled("let rec loop i acc = if i = 0 then acc else loop (i - 1) (acc + i);;");
+        let body = function(&wat, "f0_loop");
+        assert!(body.contains("(loop $top\n"));
+        assert!(body.contains("(br $top)"));
+        assert!(!body.contains("$apply"));
+
+        let wat = compiled("let rec fact n = if n = 0 then 1 else n * fact (n - 1);;");
+        let body = function(&wat, "f0_fact");
+        assert!(!body.contains("(loop $top"));
+        assert!(body.contains("(local.set $v4 (call $f0_fact (local.get $v0) (local.get $v3)))"));
+    }
+
+    #[test]
+    fn compile_known_calls_directly() {
+        let wat = compiled(concat!(
+            "let add x y = x + y;;",
+            "let () = print_int (add 1 2);;",
+            "let inc = add 1;;",
+            "let f x = let g y = x + y in g 1;;",
+        ));
+        assert!(wat.contains("(call $f0_add (global.get $g0) (i64.const 3) (i64.const 5))"));
+        assert!(wat.contains("(call $print_int (local.get $v1))"));
+        assert!(wat.contains("(call $apply (global.get $g0) (i32.const 1) (local.get $argv))"));
+        assert!(
+            function(&wat, "f2_f").contains("(return (call $f1_g (local.get $v2) (i64.const 3)))")
+        );
+        // Closures are called by their index in the table, after the
+        // primitives.
+        assert!(wat.contains(" $code_failwith $f0_add "));
+        assert!(wat.contains("(call $closure (i32.const 8) (i32.const 2) (i32.const 0))"));
+    }
+
+    #[test]
+    fn compile_constants() {
+        let wat = compiled(concat!(
+            "type t = A | B of int | C | D of bool;;",
+            "let x = (\"a\\\"b\\n\", \"a\\\"b\\n\", C, D true, 4611686018427387903, ref);;",
+        ));
+        // Strings and closures of primitives are static blocks after the
+        // stack of arguments.
+        assert!(wat.contains(concat!(
+            "(data (i32.const 1048576) \"",
+            "\\fc\\02\\00\\00\\00\\00\\00\\00\\09\\00\\00\\00\\00\\00\\00\\00a\\22b\\0a\\00\\00\\00\\00",
+            "\\f7\\02\\00\\00\\00\\00\\00\\00\\00\\00\\00\\00\\00\\00\\00\\00\\03\\00\\00\\00\\00\\00\\00\\00\")"
+        )));
+        assert_eq!(wat.matches("(i64.const 1048584)").count(), 2);
+        assert!(wat.contains("(i64.const 1048608)"));
+        assert!(wat.contains("(call $alloc (i32.const 1) (i32.const 1))"));
+        assert!(wat.contains("(i64.const 3)"));
+        assert!(wat.contains("(i64.const 9223372036854775807)"));
+    }
+}
@@ -1,48 +1,268 @@
+mod error;
 mod eval;
+mod options;
 mod read;
+mod session;
+mod signal;
+mod toplevel;
 
-use eval::{eval, Value};
-use read::{read, ReadError};
-use std::collections::HashMap;
+pub use error::{
+    DirectiveError, DirectiveErrorKind, Error, Exception, ExceptionKind, LexError, LexErrorKind,
+    ParseError, ParseErrorKind, TypeClash, TypeError, TypeErrorKind, UsedError,
+};
+use eval::{keywords, prims, Globals, Limits, Meter, Native, Target, Type, TypeDecls};
+pub use eval::{Backend, EvalLimits, Pos, Span};
+use options::{Options, USAGE};
+use read::{Candidate, ReadError, Reader};
+pub use session::{EvalOutput, FromValue, IntoValue, Session, Value};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+use toplevel::{compile_script, run, run_inputs, run_script, Control};
 
-// global bound values
+// global bound values and their types
 #[derive(Clone, Debug, PartialEq)]
-struct Bounds(HashMap<String, Value>);
+struct Bounds {
+    // shared with environments of closures, persistently
+    values: Globals,
+    types: HashMap<String, Type>,
+    // names whose types have weak type variables, restored by typing if an
+    // input is ill-typed
+    weakly_typed: HashSet<String>,
+    decls: Rc<TypeDecls>,
+    // number of weak type variables named so far
+    weak_vars: usize,
+    // how much of values is printed
+    limits: Limits,
+    // functions registered by embedders, by their primitive names
+    natives: HashMap<String, Rc<Native>>,
+    // resources used by evaluation
+    meter: Meter,
+    backend: Backend,
+    // whether the toplevel prints the IR of phrases, by `-dlambda`
+    dlambda: bool,
+}
 
 impl Bounds {
     fn new() -> Self {
-        Self(HashMap::new())
+        Self {
+            values: Globals::default(),
+            types: HashMap::new(),
+            weakly_typed: HashSet::new(),
+            decls: Rc::new(TypeDecls::default()),
+            weak_vars: 0,
+            limits: Limits::default(),
+            natives: HashMap::new(),
+            meter: Meter::default(),
+            backend: Backend::default(),
+            dlambda: false,
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<&eval::Value> {
+        self.values.get(name)
+    }
+
+    fn get_type(&self, name: &str) -> Option<&Type> {
+        self.types.get(name)
+    }
+
+    fn bind(&mut self, name: String, value: eval::Value) {
+        self.values.insert(name, value);
+    }
+
+    fn bind_type(&mut self, name: String, ty: Type) {
+        if ty.vars().is_empty() {
+            self.weakly_typed.remove(&name);
+        } else {
+            self.weakly_typed.insert(name.clone());
+        }
+        self.types.insert(name, ty);
+    }
+}
+
+// Words offered by tab completion: bound names and primitives not shadowed
+// by them with their types, and keywords. Primitives named by operators,
+// e.g. `!`, are not words to complete.
+fn completion_candidates(bounds: &Bounds) -> Vec<Candidate> {
+    let names = bounds.types.iter().map(|(name, ty)| Candidate {
+        name: name.clone(),
+        detail: Some(ty.to_string()),
+    });
+    let prims = prims()
+        .filter(|(name, _)| name.starts_with(|c: char| c.is_ascii_alphabetic()))
+        .filter(|(name, _)| !bounds.types.contains_key(*name))
+        .map(|(name, ty)| Candidate {
+            name: name.to_string(),
+            detail: Some(ty.to_string()),
+        });
+    let kws = keywords().map(|kw| Candidate {
+        name: kw.to_string(),
+        detail: None,
+    });
+    names.chain(prims).chain(kws).collect()
+}
+
+// Bounds for phrases evaluated as the options tell.
+fn bounds_with(options: &Options) -> Bounds {
+    Bounds {
+        backend: options.backend,
+        dlambda: options.dlambda,
+        ..Bounds::new()
     }
+}
 
-    fn get(&self, name: &str) -> Option<&Value> {
-        let map = &self.0;
-        map.get(name)
+// Runs rcaml with the arguments following the program name, returning the
+// exit status.
+pub fn run_cli(args: impl IntoIterator<Item = String>) -> i32 {
+    let options = match Options::parse(args) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("{}\n{}", msg, USAGE);
+            return 2;
+        }
+    };
+    if options.help {
+        println!("{}", USAGE);
+        return 0;
+    }
+    if let (Some(path), Some(output)) = (&options.file, &options.output) {
+        return compile_file(path, output);
+    }
+    if !options.exprs.is_empty() {
+        return run_exprs(&options.exprs, &options);
     }
+    if let Some(path) = &options.file {
+        return run_file(path, &options);
+    }
+    match repl(&options) {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
+    }
+}
+
+// Runs a source file, e.g. `rcaml file.ml`, returning the exit status.
+fn run_file(path: &str, options: &Options) -> i32 {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(_) => {
+            eprintln!("Cannot find file {}.", path);
+            return 2;
+        }
+    };
+    run_script(&source, path, &mut bounds_with(options), &mut io::stderr())
+}
 
-    fn bind(&mut self, name: String, value: Value) {
-        let map = &mut self.0;
-        map.insert(name, value);
+// Compiles a source file to C, e.g. by `rcaml compile file.ml -o out.c`, or
+// to WebAssembly text if the output is a `.wat` file, returning the exit
+// status.
+fn compile_file(path: &str, output: &str) -> i32 {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(_) => {
+            eprintln!("Cannot find file {}.", path);
+            return 2;
+        }
+    };
+    let target = match Path::new(output).extension() {
+        Some(ext) if ext == "wat" => Target::Wasm,
+        _ => Target::C,
+    };
+    let compiled =
+        match compile_script(&source, path, target, &mut Bounds::new(), &mut io::stderr()) {
+            Some(compiled) => compiled,
+            None => return 2,
+        };
+    match fs::write(output, compiled) {
+        Ok(()) => 0,
+        Err(_) => {
+            eprintln!("Cannot write file {}.", output);
+            2
+        }
     }
 }
 
-pub fn repl() -> Result<(), String> {
-    let mut bounds = Bounds::new();
+// Runs expressions given by `rcaml -e`, printing their results, and returns
+// the exit status.
+fn run_exprs(exprs: &[String], options: &Options) -> i32 {
+    run_inputs(
+        exprs,
+        &mut bounds_with(options),
+        &mut io::stdout(),
+        &mut io::stderr(),
+    )
+}
+
+// Starts the interactive toplevel, prompting for inputs if stdin is a
+// terminal, unless suppressed by `rcaml -q`.
+fn repl(options: &Options) -> Result<(), Error> {
+    let mut bounds = bounds_with(options);
+    let mut reader = Reader::new(!options.no_prompt);
+    // Ctrl-C interrupts evaluation rather than the process.
+    signal::catch_interrupts().map_err(|err| Error::Internal(err.to_string()))?;
+    bounds.meter.set_interrupt(&signal::INTERRUPTED);
 
     loop {
-        let input = match read() {
+        let input = match reader.read(&|| completion_candidates(&bounds)) {
             Ok(input) => input,
-            Err(ReadError::CtrlD) => {
-                println!();
-                break;
+            Err(ReadError::CtrlD) => break,
+            Err(ReadError::Interrupted) => {
+                println!("Interrupted.");
+                continue;
+            }
+            Err(ReadError::Unknown) => {
+                return Err(Error::Internal("failed to read an input".to_string()))
             }
-            Err(ReadError::Unknown) => return Err("failed to read an input".to_string()),
         };
 
-        match eval(&input, &mut bounds) {
-            Ok(output) => println!("{}", output),
-            Err(err) => println!("Error: {}", err),
+        match run(&input, &mut bounds, &mut io::stdout()) {
+            Ok(Control::Continue) => (),
+            Ok(Control::Quit) => break,
+            Err(err) => println!("{}", err.render(&input)),
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detail_of(candidates: &[Candidate], name: &str) -> Option<Option<String>> {
+        candidates
+            .iter()
+            .find(|cand| cand.name == name)
+            .map(|cand| cand.detail.clone())
+    }
+
+    #[test]
+    fn complete_primitives_with_types() {
+        let mut bounds = Bounds::new();
+        let candidates = completion_candidates(&bounds);
+        assert_eq!(
+            detail_of(&candidates, "print_int"),
+            Some(Some("int -> unit".to_string()))
+        );
+        assert_eq!(
+            detail_of(&candidates, "ref"),
+            Some(Some("'a -> 'a ref".to_string()))
+        );
+        assert_eq!(detail_of(&candidates, "let"), Some(None));
+        assert_eq!(detail_of(&candidates, "!"), None);
+
+        // Bindings shadow primitives.
+        run("let ref = 1;;", &mut bounds, &mut io::sink()).unwrap();
+        let candidates = completion_candidates(&bounds);
+        assert_eq!(
+            candidates.iter().filter(|cand| cand.name == "ref").count(),
+            1
+        );
+        assert_eq!(detail_of(&candidates, "ref"), Some(Some("int".to_string())));
+    }
+}
@@ -1,12 +1,6 @@
-use rcaml::repl;
+use std::env;
 use std::process;
 
 fn main() {
-    match repl() {
-        Ok(_) => process::exit(0),
-        Err(err) => {
-            eprintln!("{}", err);
-            process::exit(1);
-        }
-    }
+    process::exit(rcaml::run_cli(env::args().skip(1)));
 }
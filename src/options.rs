@@ -0,0 +1,200 @@
+use crate::Backend;
+use std::path::Path;
+
+// command-line options of rcaml
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Options {
+    // expressions given by `-e`, evaluated in turn instead of the toplevel
+    pub exprs: Vec<String>,
+    // whether the toplevel suppresses prompts, by `-q` or `--no-prompt`
+    pub no_prompt: bool,
+    // source file run in batch mode
+    pub file: Option<String>,
+    // by `--backend=ast` or `--backend=bytecode`
+    pub backend: Backend,
+    // whether the IR of phrases is printed, by `-dlambda`
+    pub dlambda: bool,
+    // file the source file is compiled to, by `compile file.ml -o out.c`, or
+    // `-o out.wat` for WebAssembly
+    pub output: Option<String>,
+    // whether the usage is printed instead, by `-h` or `--help`
+    pub help: bool,
+}
+
+pub(crate) const USAGE: &str = "\
+Usage: rcaml [-q | --no-prompt] [--backend=ast|bytecode] [-dlambda] [-e expr... | file.ml]
+       rcaml compile file.ml [-o out.c | -o out.wat]
+       rcaml -h | --help";
+
+impl Options {
+    // Parses arguments following the program name. Expressions are run
+    // instead of a file, so both cannot be given. Asking for help ignores
+    // the other options.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options::default();
+        let mut args = args.into_iter().peekable();
+        if args.peek().map(String::as_str) == Some("compile") {
+            args.next();
+            return Self::parse_compile(args);
+        }
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(Self::help()),
+                "-e" => match args.next() {
+                    Some(expr) => options.exprs.push(expr),
+                    None => return Err("Option -e requires an expression".to_string()),
+                },
+                "-q" | "--no-prompt" => options.no_prompt = true,
+                "-dlambda" => options.dlambda = true,
+                _ if arg.starts_with("--backend=") => {
+                    options.backend = match &arg["--backend=".len()..] {
+                        "ast" => Backend::Ast,
+                        "bytecode" => Backend::Bytecode,
+                        name => return Err(format!("Unknown backend {}", name)),
+                    }
+                }
+                _ if arg.starts_with('-') => return Err(format!("Unknown option {}", arg)),
+                // Arguments after the file are left to the program.
+                _ => {
+                    options.file = Some(arg);
+                    break;
+                }
+            }
+        }
+        match &options.file {
+            Some(file) if !options.exprs.is_empty() => {
+                Err(format!("Option -e cannot be used with file {}", file))
+            }
+            _ => Ok(options),
+        }
+    }
+
+    // Parses arguments following `compile`. The output is named after the
+    // source file unless given by `-o`.
+    fn parse_compile(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(Self::help()),
+                "-o" => match args.next() {
+                    Some(output) => options.output = Some(output),
+                    None => return Err("Option -o requires a file name".to_string()),
+                },
+                _ if arg.starts_with('-') => return Err(format!("Unknown option {}", arg)),
+                _ if options.file.is_some() => return Err(format!("Unexpected argument {}", arg)),
+                _ => options.file = Some(arg),
+            }
+        }
+        let file = options.file.as_ref().ok_or("No file to compile")?;
+        if options.output.is_none() {
+            let output = Path::new(file).with_extension("c");
+            options.output = Some(output.to_string_lossy().into_owned());
+        }
+        Ok(options)
+    }
+
+    fn help() -> Options {
+        Options {
+            help: true,
+            ..Options::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parse_options() {
+        assert_eq!(parse(&[]).unwrap(), Options::default());
+        assert_eq!(
+            parse(&["-q", "-e", "1 + 2", "-e", "3"]).unwrap(),
+            Options {
+                exprs: vec!["1 + 2".to_string(), "3".to_string()],
+                no_prompt: true,
+                file: None,
+                backend: Backend::Ast,
+                dlambda: false,
+                output: None,
+                help: false,
+            }
+        );
+        assert_eq!(
+            parse(&["--no-prompt", "a.ml", "-e"]).unwrap(),
+            Options {
+                exprs: vec![],
+                no_prompt: true,
+                file: Some("a.ml".to_string()),
+                backend: Backend::Ast,
+                dlambda: false,
+                output: None,
+                help: false,
+            }
+        );
+        assert_eq!(
+            parse(&["--backend=bytecode", "-e", "1"]).unwrap().backend,
+            Backend::Bytecode
+        );
+        assert!(parse(&["-dlambda"]).unwrap().dlambda);
+    }
+
+    #[test]
+    fn parse_help() {
+        let help = Options {
+            help: true,
+            ..Options::default()
+        };
+        assert_eq!(parse(&["-h"]).unwrap(), help);
+        assert_eq!(parse(&["-q", "--help", "a.ml"]).unwrap(), help);
+        // Options after it are not checked.
+        assert_eq!(parse(&["--help", "-x"]).unwrap(), help);
+        assert_eq!(parse(&["compile", "-h"]).unwrap(), help);
+        // Arguments after the file are left to the program.
+        assert!(!parse(&["a.ml", "-h"]).unwrap().help);
+    }
+
+    #[test]
+    fn parse_compile_options() {
+        let options = parse(&["compile", "a.ml", "-o", "b.c"]).unwrap();
+        assert_eq!(
+            (options.file.as_deref(), options.output.as_deref()),
+            (Some("a.ml"), Some("b.c"))
+        );
+        let options = parse(&["compile", "dir/a.ml"]).unwrap();
+        assert_eq!(options.output.as_deref(), Some("dir/a.c"));
+        // A file named compile is run as any other file.
+        assert_eq!(parse(&["-q", "compile"]).unwrap().output, None);
+
+        assert_eq!(parse(&["compile"]).unwrap_err(), "No file to compile");
+        assert_eq!(
+            parse(&["compile", "a.ml", "-o"]).unwrap_err(),
+            "Option -o requires a file name"
+        );
+        assert_eq!(
+            parse(&["compile", "a.ml", "b.ml"]).unwrap_err(),
+            "Unexpected argument b.ml"
+        );
+    }
+
+    #[test]
+    fn error_on_options() {
+        assert_eq!(
+            parse(&["-e"]).unwrap_err(),
+            "Option -e requires an expression"
+        );
+        assert_eq!(parse(&["-x"]).unwrap_err(), "Unknown option -x");
+        assert_eq!(
+            parse(&["--backend=native"]).unwrap_err(),
+            "Unknown backend native"
+        );
+        assert_eq!(
+            parse(&["-e", "1 + 2", "a.ml"]).unwrap_err(),
+            "Option -e cannot be used with file a.ml"
+        );
+    }
+}
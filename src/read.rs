@@ -1,14 +1,83 @@
+mod complete;
+mod editor;
+mod history;
+mod terminal;
+
+use crate::signal::take_interrupt;
+pub(super) use complete::Candidate;
+use history::History;
 use std::io;
 use std::io::Write;
 
+const PROMPT: &str = "# ";
+
 pub(super) enum ReadError {
     CtrlD,
+    Interrupted, // Ctrl-C, discarding the input
     Unknown,
 }
 
-pub(super) fn read() -> Result<String, ReadError> {
-    print!("# ");
-    io::stdout().flush().expect("failed to flush");
+pub(super) struct Reader {
+    history: History,
+    // Line editing is enabled only when stdin is a terminal.
+    is_tty: bool,
+    // empty if prompts are suppressed
+    prompt: &'static str,
+}
+
+impl Reader {
+    // Prompts are shown only if `prompt` is true and stdin is a terminal, so
+    // that piped inputs produce only the outputs.
+    pub fn new(prompt: bool) -> Self {
+        let is_tty = terminal::is_tty();
+        let history = if is_tty {
+            History::load(history::default_path())
+        } else {
+            History::new()
+        };
+        let prompt = if prompt && is_tty { PROMPT } else { "" };
+        Self {
+            history,
+            is_tty,
+            prompt,
+        }
+    }
+
+    // Reads an input. `candidates` makes the words offered by tab completion,
+    // only once they are asked for.
+    pub fn read(&mut self, candidates: &dyn Fn() -> Vec<Candidate>) -> Result<String, ReadError> {
+        let input = self.read_input(candidates);
+        // End the line of the prompt at the end of input.
+        if let (Err(ReadError::CtrlD), false) = (&input, self.prompt.is_empty()) {
+            println!();
+        }
+        input
+    }
+
+    fn read_input(&mut self, candidates: &dyn Fn() -> Vec<Candidate>) -> Result<String, ReadError> {
+        // Ctrl-C before the prompt is not for this input.
+        take_interrupt();
+        if !self.is_tty {
+            let input = read_line(self.prompt);
+            // Reads are resumed after Ctrl-C, which discards the input.
+            if input.is_ok() && take_interrupt() {
+                return Err(ReadError::Interrupted);
+            }
+            return input;
+        }
+
+        let input = editor::edit_line(self.prompt, &self.history, candidates)?;
+        self.history.push(&input);
+        Ok(input.trim().to_string())
+    }
+}
+
+// Reads a line without line editing.
+fn read_line(prompt: &str) -> Result<String, ReadError> {
+    if !prompt.is_empty() {
+        print!("{}", prompt);
+        io::stdout().flush().expect("failed to flush");
+    }
 
     let mut input = String::new();
     match io::stdin().read_line(&mut input) {
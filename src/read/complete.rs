@@ -0,0 +1,116 @@
+// A word to complete at the prompt, such as a bound name or a keyword.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Candidate {
+    pub name: String,
+    // Shown next to the name when listing candidates, e.g. its type.
+    pub detail: Option<String>,
+}
+
+// Returns whether `c` can be a part of a word to be completed. Dots are
+// included so that qualified names like `List.map` are completed as a whole.
+pub(super) fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '\'' || c == '.'
+}
+
+// Finds candidates starting with `prefix`, sorted by name. Also returns the
+// longest prefix shared by all of them.
+pub(super) fn complete<'a>(
+    prefix: &str,
+    candidates: &'a [Candidate],
+) -> (String, Vec<&'a Candidate>) {
+    let mut matches: Vec<&Candidate> = candidates
+        .iter()
+        .filter(|cand| cand.name.starts_with(prefix))
+        .collect();
+    matches.sort_by(|a, b| a.name.cmp(&b.name));
+    matches.dedup_by(|a, b| a.name == b.name);
+
+    let common = match matches.first() {
+        Some(first) => matches.iter().fold(first.name.clone(), |common, cand| {
+            common
+                .chars()
+                .zip(cand.name.chars())
+                .take_while(|(a, b)| a == b)
+                .map(|(a, _)| a)
+                .collect()
+        }),
+        None => prefix.to_string(),
+    };
+
+    (common, matches)
+}
+
+// Formats candidates one per line, aligning their details.
+pub(super) fn format_candidates(candidates: &[&Candidate]) -> String {
+    let width = candidates
+        .iter()
+        .map(|cand| cand.name.chars().count())
+        .max()
+        .unwrap_or(0);
+    candidates
+        .iter()
+        .map(|cand| match &cand.detail {
+            Some(detail) => format!("{:width$} : {}", cand.name, detail, width = width),
+            None => cand.name.clone(),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(name: &str, detail: Option<&str>) -> Candidate {
+        Candidate {
+            name: name.to_string(),
+            detail: detail.map(|d| d.to_string()),
+        }
+    }
+
+    #[test]
+    fn complete_unique_candidate() {
+        let candidates = vec![
+            candidate("foo", Some("int")),
+            candidate("bar", Some("bool")),
+        ];
+        let (common, matches) = complete("f", &candidates);
+        assert_eq!(common, "foo");
+        assert_eq!(matches, vec![&candidates[0]]);
+    }
+
+    #[test]
+    fn complete_common_prefix_of_candidates() {
+        let candidates = vec![
+            candidate("lst_two", Some("int list")),
+            candidate("let", None),
+            candidate("lst_one", Some("int list")),
+        ];
+        let (common, matches) = complete("ls", &candidates);
+        assert_eq!(common, "lst_");
+        assert_eq!(matches, vec![&candidates[2], &candidates[0]]);
+
+        let (common, matches) = complete("l", &candidates);
+        assert_eq!(common, "l");
+        assert_eq!(matches.len(), 3);
+    }
+
+    #[test]
+    fn complete_no_candidates() {
+        let candidates = vec![candidate("foo", Some("int"))];
+        let (common, matches) = complete("x", &candidates);
+        assert_eq!(common, "x");
+        assert!(matches.is_empty());
+    }
+
+    #[test]
+    fn format_candidates_with_details() {
+        let candidates = [
+            candidate("in", None),
+            candidate("square", Some("...")),
+            candidate("x", Some("int")),
+        ];
+        let refs: Vec<&Candidate> = candidates.iter().collect();
+        assert_eq!(format_candidates(&refs), "in\nsquare : ...\nx      : int");
+    }
+}
@@ -0,0 +1,456 @@
+use super::complete::{complete, format_candidates, is_word_char, Candidate};
+use super::history::History;
+use super::terminal::{read_byte, RawMode};
+use super::ReadError;
+use std::io::{self, Write};
+
+#[derive(Debug, PartialEq)]
+enum Key {
+    Char(char),
+    Ctrl(char), // Ctrl + alphabet, e.g. Ctrl('a') for Ctrl-A
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Esc,
+    Unknown,
+}
+
+// Reads a key from the terminal. Returns None on EOF.
+fn read_key() -> io::Result<Option<Key>> {
+    let byte = match read_byte()? {
+        Some(byte) => byte,
+        None => return Ok(None),
+    };
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7f | 0x08 => Key::Backspace,
+        0x1b => read_escape_sequence()?,
+        0x01..=0x1a => Key::Ctrl((b'a' + byte - 1) as char),
+        0x00..=0x1f => Key::Unknown,
+        _ => match read_utf8_char(byte)? {
+            Some(c) => Key::Char(c),
+            None => Key::Unknown,
+        },
+    };
+    Ok(Some(key))
+}
+
+// Decodes ANSI escape sequences following ESC, such as "ESC [ A".
+fn read_escape_sequence() -> io::Result<Key> {
+    match read_byte()? {
+        Some(b'[') | Some(b'O') => (),
+        _ => return Ok(Key::Esc),
+    }
+    let key = match read_byte()? {
+        Some(b'A') => Key::Up,
+        Some(b'B') => Key::Down,
+        Some(b'C') => Key::Right,
+        Some(b'D') => Key::Left,
+        Some(b'H') => Key::Home,
+        Some(b'F') => Key::End,
+        // "ESC [ n ~"
+        Some(n @ b'0'..=b'9') => {
+            let mut param = vec![n];
+            loop {
+                match read_byte()? {
+                    Some(b'~') => break,
+                    Some(b) if b.is_ascii_digit() || b == b';' => param.push(b),
+                    _ => return Ok(Key::Unknown),
+                }
+            }
+            match &*param {
+                b"1" | b"7" => Key::Home,
+                b"3" => Key::Delete,
+                b"4" | b"8" => Key::End,
+                _ => Key::Unknown,
+            }
+        }
+        _ => Key::Unknown,
+    };
+    Ok(key)
+}
+
+fn read_utf8_char(first: u8) -> io::Result<Option<char>> {
+    let len = match first {
+        0x00..=0x7f => 1,
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return Ok(None),
+    };
+    let mut bytes = vec![first];
+    while bytes.len() < len {
+        match read_byte()? {
+            Some(b) => bytes.push(b),
+            None => return Ok(None),
+        }
+    }
+    Ok(std::str::from_utf8(&bytes)
+        .ok()
+        .and_then(|s| s.chars().next()))
+}
+
+// A line being edited, with a cursor position in characters.
+#[derive(Debug, PartialEq)]
+struct LineBuffer {
+    chars: Vec<char>,
+    cursor: usize,
+}
+
+impl LineBuffer {
+    fn new() -> Self {
+        Self {
+            chars: Vec::new(),
+            cursor: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.chars.is_empty()
+    }
+
+    // Replaces the whole line, moving the cursor to the end.
+    fn set(&mut self, line: &str) {
+        self.chars = line.chars().collect();
+        self.cursor = self.chars.len();
+    }
+
+    fn insert(&mut self, c: char) {
+        self.chars.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    fn insert_str(&mut self, s: &str) {
+        for c in s.chars() {
+            self.insert(c);
+        }
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    fn move_left(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    fn move_right(&mut self) {
+        self.cursor = (self.cursor + 1).min(self.chars.len());
+    }
+
+    fn move_home(&mut self) {
+        self.cursor = 0;
+    }
+
+    fn move_end(&mut self) {
+        self.cursor = self.chars.len();
+    }
+
+    // Ctrl-K
+    fn kill_to_end(&mut self) {
+        self.chars.truncate(self.cursor);
+    }
+
+    // Ctrl-U
+    fn kill_to_start(&mut self) {
+        self.chars.drain(..self.cursor);
+        self.cursor = 0;
+    }
+
+    // Ctrl-W: deletes the word before the cursor, along with whitespaces
+    // following it.
+    fn kill_word_backward(&mut self) {
+        let mut start = self.cursor;
+        while start > 0 && self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        while start > 0 && !self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        self.chars.drain(start..self.cursor);
+        self.cursor = start;
+    }
+
+    // Returns the (possibly empty) word which ends at the cursor.
+    fn word_before_cursor(&self) -> String {
+        let mut start = self.cursor;
+        while start > 0 && is_word_char(self.chars[start - 1]) {
+            start -= 1;
+        }
+        self.chars[start..self.cursor].iter().collect()
+    }
+
+    fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+}
+
+// State of reverse incremental search (Ctrl-R).
+struct Search {
+    query: String,
+    // Index of the history entry currently matched
+    found: Option<usize>,
+}
+
+// Reads a line from the terminal in raw mode, with line editing, history
+// and completion support. `candidates` are made once Tab is first pressed.
+pub(super) fn edit_line(
+    prompt: &str,
+    history: &History,
+    candidates: &dyn Fn() -> Vec<Candidate>,
+) -> Result<String, ReadError> {
+    let _raw_mode = RawMode::enable().map_err(|_| ReadError::Unknown)?;
+    let mut stdout = io::stdout();
+
+    let mut line = LineBuffer::new();
+    // Index of the history entry being shown. history.len() for a new line.
+    let mut hist_idx = history.len();
+    // Line being written before moving through history.
+    let mut draft = String::new();
+    let mut search: Option<Search> = None;
+    let mut completions: Option<Vec<Candidate>> = None;
+
+    loop {
+        match &search {
+            Some(search) => render_search(&mut stdout, search, history),
+            None => render(&mut stdout, prompt, &line),
+        }
+        .map_err(|_| ReadError::Unknown)?;
+
+        let key = match read_key() {
+            Ok(Some(key)) => key,
+            // Ctrl-C discards the line.
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                writeln!(stdout).map_err(|_| ReadError::Unknown)?;
+                return Err(ReadError::Interrupted);
+            }
+            Err(_) => return Err(ReadError::Unknown),
+            Ok(None) if line.is_empty() => return Err(ReadError::CtrlD),
+            Ok(None) => Key::Enter,
+        };
+
+        if let Some(s) = &mut search {
+            match key {
+                Key::Char(c) => {
+                    s.query.push(c);
+                    s.found =
+                        history.search_backward(&s.query, s.found.map_or(history.len(), |i| i + 1));
+                    continue;
+                }
+                Key::Backspace => {
+                    s.query.pop();
+                    s.found = history.search_backward(&s.query, history.len());
+                    continue;
+                }
+                Key::Ctrl('r') => {
+                    let before = s.found.unwrap_or(history.len());
+                    if let Some(found) = history.search_backward(&s.query, before) {
+                        s.found = Some(found);
+                    }
+                    continue;
+                }
+                // cancel
+                Key::Ctrl('g') | Key::Esc => {
+                    search = None;
+                    continue;
+                }
+                // Accept the match, then handle the key as usual.
+                _ => {
+                    if let Some(entry) = s.found.and_then(|i| history.get(i)) {
+                        line.set(entry);
+                        hist_idx = s.found.unwrap();
+                    }
+                    search = None;
+                }
+            }
+        }
+
+        match key {
+            Key::Enter => {
+                render(&mut stdout, prompt, &line).map_err(|_| ReadError::Unknown)?;
+                writeln!(stdout).map_err(|_| ReadError::Unknown)?;
+                return Ok(line.text());
+            }
+            Key::Char(c) => line.insert(c),
+            Key::Tab => {
+                let prefix = line.word_before_cursor();
+                let completions = completions.get_or_insert_with(candidates);
+                let (common, matches) = complete(&prefix, completions);
+                if common.len() > prefix.len() {
+                    line.insert_str(&common[prefix.len()..]);
+                } else if matches.len() > 1 {
+                    writeln!(stdout, "\n{}", format_candidates(&matches))
+                        .map_err(|_| ReadError::Unknown)?;
+                }
+            }
+            Key::Backspace | Key::Ctrl('h') => line.backspace(),
+            Key::Delete => line.delete(),
+            Key::Ctrl('d') => {
+                if line.is_empty() {
+                    writeln!(stdout).map_err(|_| ReadError::Unknown)?;
+                    return Err(ReadError::CtrlD);
+                }
+                line.delete();
+            }
+            Key::Left | Key::Ctrl('b') => line.move_left(),
+            Key::Right | Key::Ctrl('f') => line.move_right(),
+            Key::Home | Key::Ctrl('a') => line.move_home(),
+            Key::End | Key::Ctrl('e') => line.move_end(),
+            Key::Ctrl('k') => line.kill_to_end(),
+            Key::Ctrl('u') => line.kill_to_start(),
+            Key::Ctrl('w') => line.kill_word_backward(),
+            Key::Up | Key::Ctrl('p') if hist_idx > 0 => {
+                if hist_idx == history.len() {
+                    draft = line.text();
+                }
+                hist_idx -= 1;
+                line.set(history.get(hist_idx).unwrap_or_default());
+            }
+            Key::Down | Key::Ctrl('n') if hist_idx < history.len() => {
+                hist_idx += 1;
+                match history.get(hist_idx) {
+                    Some(entry) => line.set(entry),
+                    None => line.set(&draft),
+                }
+            }
+            Key::Ctrl('r') => {
+                search = Some(Search {
+                    query: String::new(),
+                    found: None,
+                })
+            }
+            _ => (),
+        }
+    }
+}
+
+fn render(stdout: &mut io::Stdout, prompt: &str, line: &LineBuffer) -> io::Result<()> {
+    write!(stdout, "\r{}{}\x1b[K", prompt, line.text())?;
+    let col = prompt.chars().count() + line.cursor;
+    write!(stdout, "\r")?;
+    if col > 0 {
+        write!(stdout, "\x1b[{}C", col)?;
+    }
+    stdout.flush()
+}
+
+fn render_search(stdout: &mut io::Stdout, search: &Search, history: &History) -> io::Result<()> {
+    let found = search.found.and_then(|i| history.get(i)).unwrap_or("");
+    write!(
+        stdout,
+        "\r(reverse-i-search)`{}': {}\x1b[K",
+        search.query, found
+    )?;
+    stdout.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line_of(s: &str, cursor: usize) -> LineBuffer {
+        LineBuffer {
+            chars: s.chars().collect(),
+            cursor,
+        }
+    }
+
+    #[test]
+    fn insert_chars() {
+        let mut line = LineBuffer::new();
+        line.insert('1');
+        line.insert('2');
+        line.move_left();
+        line.insert('+');
+        assert_eq!(line, line_of("1+2", 2));
+    }
+
+    #[test]
+    fn delete_chars() {
+        let mut line = line_of("abc", 1);
+        line.backspace();
+        assert_eq!(line, line_of("bc", 0));
+        line.backspace();
+        assert_eq!(line, line_of("bc", 0));
+        line.delete();
+        assert_eq!(line, line_of("c", 0));
+    }
+
+    #[test]
+    fn move_cursor() {
+        let mut line = line_of("let x", 2);
+        line.move_home();
+        assert_eq!(line.cursor, 0);
+        line.move_left();
+        assert_eq!(line.cursor, 0);
+        line.move_end();
+        assert_eq!(line.cursor, 5);
+        line.move_right();
+        assert_eq!(line.cursor, 5);
+    }
+
+    #[test]
+    fn kill_to_end() {
+        let mut line = line_of("let x = 1", 5);
+        line.kill_to_end();
+        assert_eq!(line, line_of("let x", 5));
+    }
+
+    #[test]
+    fn kill_to_start() {
+        let mut line = line_of("let x = 1", 6);
+        line.kill_to_start();
+        assert_eq!(line, line_of("= 1", 0));
+    }
+
+    #[test]
+    fn kill_word_backward() {
+        let mut line = line_of("let foo = 1  ", 13);
+        line.kill_word_backward();
+        assert_eq!(line, line_of("let foo = ", 10));
+        line.kill_word_backward();
+        assert_eq!(line, line_of("let foo ", 8));
+        line.move_home();
+        line.kill_word_backward();
+        assert_eq!(line, line_of("let foo ", 0));
+    }
+
+    #[test]
+    fn word_before_cursor() {
+        assert_eq!(line_of("let x = fo", 10).word_before_cursor(), "fo");
+        assert_eq!(line_of("1+List.ma", 9).word_before_cursor(), "List.ma");
+        assert_eq!(line_of("foo bar", 3).word_before_cursor(), "foo");
+        assert_eq!(line_of("foo ", 4).word_before_cursor(), "");
+    }
+
+    #[test]
+    fn insert_str() {
+        let mut line = line_of("let x = fo;;", 10);
+        line.insert_str("obar");
+        assert_eq!(line, line_of("let x = foobar;;", 14));
+    }
+
+    #[test]
+    fn set_line() {
+        let mut line = line_of("abc", 1);
+        line.set("1 + 2;;");
+        assert_eq!(line, line_of("1 + 2;;", 7));
+    }
+}
@@ -0,0 +1,144 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+const MAX_ENTRIES: usize = 1000;
+
+// `~/.rcaml_history`
+pub(super) fn default_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".rcaml_history"))
+}
+
+#[derive(Debug, PartialEq)]
+pub(super) struct History {
+    entries: Vec<String>,
+    // File to persist entries to. History is kept in memory only if None.
+    path: Option<PathBuf>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            path: None,
+        }
+    }
+
+    // Loads entries from a history file. A missing or unreadable file is
+    // treated as an empty history.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let mut entries: Vec<String> = match &path {
+            Some(path) => fs::read_to_string(path)
+                .unwrap_or_default()
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(|line| line.to_string())
+                .collect(),
+            None => Vec::new(),
+        };
+
+        if entries.len() > MAX_ENTRIES {
+            entries.drain(..entries.len() - MAX_ENTRIES);
+            if let Some(path) = &path {
+                let _ = fs::write(path, entries.join("\n") + "\n");
+            }
+        }
+
+        Self { entries, path }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&str> {
+        self.entries.get(idx).map(|entry| &**entry)
+    }
+
+    // Appends an entry, skipping empty lines and consecutive duplicates.
+    // The entry is also appended to the history file, if any.
+    pub fn push(&mut self, entry: &str) {
+        let entry = entry.trim();
+        if entry.is_empty()
+            || entry.contains('\n')
+            || self.entries.last().map(|e| &**e) == Some(entry)
+        {
+            return;
+        }
+
+        self.entries.push(entry.to_string());
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.remove(0);
+        }
+
+        if let Some(path) = &self.path {
+            if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+                let _ = writeln!(file, "{}", entry);
+            }
+        }
+    }
+
+    // Finds the latest entry containing `query` among the entries before
+    // `before`.
+    pub fn search_backward(&self, query: &str, before: usize) -> Option<usize> {
+        let before = before.min(self.entries.len());
+        self.entries[..before]
+            .iter()
+            .rposition(|entry| entry.contains(query))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history_of(entries: &[&str]) -> History {
+        let mut history = History::new();
+        for entry in entries {
+            history.push(entry);
+        }
+        history
+    }
+
+    #[test]
+    fn push_entries() {
+        let history = history_of(&["1 + 2;;", "let x = 3;;"]);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.get(0), Some("1 + 2;;"));
+        assert_eq!(history.get(1), Some("let x = 3;;"));
+        assert_eq!(history.get(2), None);
+    }
+
+    #[test]
+    fn skip_empty_and_duplicated_entries() {
+        let history = history_of(&["x;;", "", "  ", "x;;", "y;;", "x;;"]);
+        assert_eq!(history, history_of(&["x;;", "y;;", "x;;"]));
+    }
+
+    #[test]
+    fn search_entries_backward() {
+        let history = history_of(&["let foo = 1;;", "bar;;", "foo + 1;;"]);
+        assert_eq!(history.search_backward("foo", 3), Some(2));
+        assert_eq!(history.search_backward("foo", 2), Some(0));
+        assert_eq!(history.search_backward("foo", 0), None);
+        assert_eq!(history.search_backward("baz", 3), None);
+    }
+
+    #[test]
+    fn persist_entries_to_file() {
+        let path = env::temp_dir().join(format!("rcaml_history_test_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut history = History::load(Some(path.clone()));
+        history.push("1;;");
+        history.push("2;;");
+
+        let loaded = History::load(Some(path.clone()));
+        assert_eq!(loaded.get(0), Some("1;;"));
+        assert_eq!(loaded.get(1), Some("2;;"));
+        assert_eq!(loaded.len(), 2);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,65 @@
+use crate::signal::take_interrupt;
+use std::io;
+use std::mem::MaybeUninit;
+
+pub(super) fn is_tty() -> bool {
+    unsafe { libc::isatty(libc::STDIN_FILENO) == 1 }
+}
+
+// Puts the terminal into raw mode while alive, and restores the original
+// mode on drop.
+pub(super) struct RawMode {
+    original: libc::termios,
+}
+
+impl RawMode {
+    pub(super) fn enable() -> io::Result<Self> {
+        let original = unsafe {
+            let mut termios = MaybeUninit::<libc::termios>::uninit();
+            if libc::tcgetattr(libc::STDIN_FILENO, termios.as_mut_ptr()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            termios.assume_init()
+        };
+
+        let mut raw = original;
+        // Read bytes one by one without echoing them back. Output processing
+        // is kept, so "\n" still moves to the beginning of the next line.
+        raw.c_iflag &= !(libc::ICRNL | libc::IXON);
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::IEXTEN);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &self.original);
+        }
+    }
+}
+
+// Reads a single byte from stdin. Returns None on EOF, or an error of kind
+// `Interrupted` on Ctrl-C.
+pub(super) fn read_byte() -> io::Result<Option<u8>> {
+    let mut byte = 0u8;
+    loop {
+        let n = unsafe { libc::read(libc::STDIN_FILENO, &mut byte as *mut u8 as *mut _, 1) };
+        match n {
+            1 => return Ok(Some(byte)),
+            0 => return Ok(None),
+            _ => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted || take_interrupt() {
+                    return Err(err);
+                }
+            }
+        }
+    }
+}
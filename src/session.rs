@@ -0,0 +1,827 @@
+use crate::error::Error;
+use crate::eval::{self, eval_phrase, parse_type_scheme, EvalLimits, Func, List};
+use crate::{Backend, Bounds};
+use std::fmt;
+use std::rc::Rc;
+use std::sync::atomic::AtomicBool;
+
+/// Interpreter state kept across phrases, for embedding rcaml in a Rust
+/// program:
+///
+/// ```
+/// # use rcaml::Session;
+/// # fn main() -> Result<(), rcaml::Error> {
+/// let mut session = Session::new();
+/// session.set("x", 20_i64)?;
+/// session.eval_phrase("let y = x * 2 + 2")?;
+/// assert_eq!(session.get::<i64>("y"), Some(42));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Session {
+    bounds: Bounds,
+}
+
+impl Session {
+    /// Session with nothing bound but the primitives.
+    pub fn new() -> Self {
+        Self {
+            bounds: Bounds::new(),
+        }
+    }
+
+    /// Evaluates a phrase, e.g. `let x = 1`, with or without ";;". Bindings
+    /// are kept for later phrases. Directives are not available.
+    pub fn eval_phrase(&mut self, input: &str) -> Result<EvalOutput, Error> {
+        if eval::terminator(input).is_some() {
+            eval_phrase(input, &mut self.bounds)
+        } else {
+            eval_phrase(&format!("{};;", input), &mut self.bounds)
+        }
+    }
+
+    /// Value bound to a name, if any.
+    pub fn value(&self, name: &str) -> Option<Value> {
+        self.bounds.get(name).cloned().map(Value)
+    }
+
+    /// Type of a bound name as printed by the toplevel, e.g. `int list`.
+    pub fn type_of(&self, name: &str) -> Option<String> {
+        eval::type_of(name, &self.bounds).map(|ty| ty.to_string())
+    }
+
+    /// Value bound to a name converted to a Rust value, or none if unbound or
+    /// of another type.
+    pub fn get<T: FromValue>(&self, name: &str) -> Option<T> {
+        self.value(name).and_then(|value| T::from_value(&value))
+    }
+
+    /// Binds a name to a Rust value, typed by `T::ocaml_type()`.
+    pub fn set<T: IntoValue>(&mut self, name: &str, value: T) -> Result<(), Error> {
+        let ty = parse_type_scheme(&T::ocaml_type(), &self.bounds.decls)?;
+        self.bounds.bind_type(name.to_string(), ty);
+        self.bounds.bind(name.to_string(), value.into_value().0);
+        Ok(())
+    }
+
+    /// Limits on resources used to evaluate each phrase.
+    pub fn limits(&self) -> EvalLimits {
+        self.bounds.meter.limits()
+    }
+
+    /// Sets the limits for later phrases.
+    pub fn set_limits(&mut self, limits: EvalLimits) {
+        self.bounds.meter.set_limits(limits);
+    }
+
+    /// Backend evaluating later phrases. Values bound by either can be used
+    /// by the other.
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.bounds.backend = backend;
+    }
+
+    /// Makes evaluation raise `Sys.Break`, i.e. an exception of kind
+    /// `ExceptionKind::Interrupted`, once `flag` is set, e.g. by another
+    /// thread to cancel a phrase. The flag is cleared when raised.
+    pub fn set_interrupt(&mut self, flag: &'static AtomicBool) {
+        self.bounds.meter.set_interrupt(flag);
+    }
+
+    /// Registers a Rust function as the primitive `name` of an OCaml type,
+    /// e.g. `string -> string`, and binds `name` to it. The function is
+    /// called once applied to all its arguments, and may be bound to other
+    /// names by `external f : string -> string = "name"`.
+    pub fn register<F>(&mut self, name: &str, ty: &str, f: F) -> Result<(), Error>
+    where
+        F: Fn(&[Value]) -> Result<Value, Error> + 'static,
+    {
+        let apply = move |args: &[eval::Value]| {
+            let args: Vec<_> = args.iter().cloned().map(Value).collect();
+            f(&args).map(|value| value.0)
+        };
+        let native = eval::native(name, ty, &self.bounds.decls, Box::new(apply))?;
+        let native = Rc::new(native);
+        let value = eval::Value::Fn(Func::Native(native.clone(), Rc::from([])));
+        self.bounds.bind_type(name.to_string(), native.ty.clone());
+        self.bounds.bind(name.to_string(), value);
+        self.bounds.natives.insert(name.to_string(), native);
+        Ok(())
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// result of a phrase evaluated in a session
+#[derive(Clone, Debug, PartialEq)]
+pub enum EvalOutput {
+    /// value of an expression, or of a `let` with the bound name
+    Value {
+        name: Option<String>,
+        /// type as printed by the toplevel, e.g. `int list`
+        ty: String,
+        value: Value,
+        text: String,
+    },
+    /// type definitions
+    Types { text: String },
+    /// external declaration binding a name to a primitive
+    External {
+        name: String,
+        ty: String,
+        text: String,
+    },
+}
+
+impl EvalOutput {
+    /// Output as printed by the toplevel, e.g. `val x : int = 1`.
+    pub fn text(&self) -> &str {
+        match self {
+            EvalOutput::Value { text, .. }
+            | EvalOutput::Types { text }
+            | EvalOutput::External { text, .. } => text,
+        }
+    }
+
+    /// Value of an expression or a `let`, if the phrase is one.
+    pub fn value(&self) -> Option<&Value> {
+        match self {
+            EvalOutput::Value { value, .. } => Some(value),
+            EvalOutput::Types { .. } | EvalOutput::External { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for EvalOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.text())
+    }
+}
+
+/// OCaml value, whose representation is hidden from embedders. Values share
+/// their contents, so cloning one takes constant time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Value(pub(crate) eval::Value);
+
+impl Value {
+    /// Int, or none if the value is of another type. The other accessors
+    /// likewise return none for values of other types.
+    pub fn as_int(&self) -> Option<i64> {
+        match self.0 {
+            eval::Value::Int(i) => Some(i),
+            _ => None,
+        }
+    }
+
+    /// Bool.
+    pub fn as_bool(&self) -> Option<bool> {
+        match self.0 {
+            eval::Value::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    /// Whether the value is `()`.
+    pub fn is_unit(&self) -> bool {
+        self.0 == eval::Value::Unit
+    }
+
+    /// Contents of a string, or none if it is not valid UTF-8.
+    pub fn as_str(&self) -> Option<&str> {
+        self.as_bytes().and_then(|s| std::str::from_utf8(s).ok())
+    }
+
+    /// Contents of a string as bytes.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match &self.0 {
+            eval::Value::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Elements of a list.
+    pub fn as_list(&self) -> Option<Vec<Value>> {
+        match &self.0 {
+            eval::Value::List(list) => Some(list.iter().cloned().map(Value).collect()),
+            _ => None,
+        }
+    }
+
+    /// Elements of a tuple.
+    pub fn as_tuple(&self) -> Option<Vec<Value>> {
+        match &self.0 {
+            eval::Value::Tuple(elems) => Some(elems.iter().cloned().map(Value).collect()),
+            _ => None,
+        }
+    }
+
+    /// Elements of an array at the moment.
+    pub fn as_array(&self) -> Option<Vec<Value>> {
+        match &self.0 {
+            eval::Value::Array(elems) => Some(elems.borrow().iter().cloned().map(Value).collect()),
+            _ => None,
+        }
+    }
+
+    /// Current contents of a reference.
+    pub fn contents(&self) -> Option<Value> {
+        match &self.0 {
+            eval::Value::Ref(r) => Some(Value(r.borrow().clone())),
+            _ => None,
+        }
+    }
+
+    /// Name and argument of a variant constructor, e.g. `Some 1`.
+    pub fn as_constructor(&self) -> Option<(&str, Option<Value>)> {
+        match &self.0 {
+            eval::Value::Constr(name, arg) => {
+                Some((name, arg.as_ref().map(|arg| Value((**arg).clone()))))
+            }
+            _ => None,
+        }
+    }
+
+    /// Field of a record.
+    pub fn field(&self, name: &str) -> Option<Value> {
+        match &self.0 {
+            eval::Value::Record(fields) => fields
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| Value(value.clone())),
+            _ => None,
+        }
+    }
+
+    /// Whether the value is a function, which cannot be converted.
+    pub fn is_function(&self) -> bool {
+        matches!(self.0, eval::Value::Fn(_))
+    }
+}
+
+/// conversion from OCaml values, e.g. by `Session::get`
+pub trait FromValue: Sized {
+    /// Converts a value, or returns none if it is of another type.
+    fn from_value(value: &Value) -> Option<Self>;
+}
+
+/// conversion to OCaml values, e.g. by `Session::set`
+pub trait IntoValue {
+    /// type of converted values written in OCaml, e.g. `int list`
+    fn ocaml_type() -> String;
+    /// Converts to a value of that type.
+    fn into_value(self) -> Value;
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Option<Self> {
+        Some(value.clone())
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &Value) -> Option<Self> {
+        value.as_int()
+    }
+}
+
+impl IntoValue for i64 {
+    fn ocaml_type() -> String {
+        "int".to_string()
+    }
+
+    fn into_value(self) -> Value {
+        Value(eval::Value::Int(self))
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Option<Self> {
+        value.as_bool()
+    }
+}
+
+impl IntoValue for bool {
+    fn ocaml_type() -> String {
+        "bool".to_string()
+    }
+
+    fn into_value(self) -> Value {
+        Value(eval::Value::Bool(self))
+    }
+}
+
+impl FromValue for () {
+    fn from_value(value: &Value) -> Option<Self> {
+        value.is_unit().then_some(())
+    }
+}
+
+impl IntoValue for () {
+    fn ocaml_type() -> String {
+        "unit".to_string()
+    }
+
+    fn into_value(self) -> Value {
+        Value(eval::Value::Unit)
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Option<Self> {
+        value.as_str().map(str::to_string)
+    }
+}
+
+impl IntoValue for String {
+    fn ocaml_type() -> String {
+        "string".to_string()
+    }
+
+    fn into_value(self) -> Value {
+        Value(eval::Value::Str(self.into_bytes().into()))
+    }
+}
+
+impl IntoValue for &str {
+    fn ocaml_type() -> String {
+        "string".to_string()
+    }
+
+    fn into_value(self) -> Value {
+        Value(eval::Value::Str(self.as_bytes().into()))
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value) -> Option<Self> {
+        value.as_list()?.iter().map(T::from_value).collect()
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn ocaml_type() -> String {
+        format!("({}) list", T::ocaml_type())
+    }
+
+    fn into_value(self) -> Value {
+        let elems: Vec<_> = self.into_iter().map(|e| e.into_value().0).collect();
+        Value(eval::Value::List(List::from(elems)))
+    }
+}
+
+impl<A: FromValue, B: FromValue> FromValue for (A, B) {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value.as_tuple()?.as_slice() {
+            [a, b] => Some((A::from_value(a)?, B::from_value(b)?)),
+            _ => None,
+        }
+    }
+}
+
+impl<A: IntoValue, B: IntoValue> IntoValue for (A, B) {
+    fn ocaml_type() -> String {
+        format!("({}) * ({})", A::ocaml_type(), B::ocaml_type())
+    }
+
+    fn into_value(self) -> Value {
+        Value(eval::Value::Tuple(Rc::new([
+            self.0.into_value().0,
+            self.1.into_value().0,
+        ])))
+    }
+}
+
+impl<A: FromValue, B: FromValue, C: FromValue> FromValue for (A, B, C) {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value.as_tuple()?.as_slice() {
+            [a, b, c] => Some((A::from_value(a)?, B::from_value(b)?, C::from_value(c)?)),
+            _ => None,
+        }
+    }
+}
+
+impl<A: IntoValue, B: IntoValue, C: IntoValue> IntoValue for (A, B, C) {
+    fn ocaml_type() -> String {
+        format!(
+            "({}) * ({}) * ({})",
+            A::ocaml_type(),
+            B::ocaml_type(),
+            C::ocaml_type()
+        )
+    }
+
+    fn into_value(self) -> Value {
+        Value(eval::Value::Tuple(Rc::new([
+            self.0.into_value().0,
+            self.1.into_value().0,
+            self.2.into_value().0,
+        ])))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Exception;
+
+    #[test]
+    fn eval_phrases_in_session() {
+        let mut session = Session::new();
+        let output = session.eval_phrase("let x = 1 + 2;;").unwrap();
+        assert_eq!(
+            EvalOutput::Value {
+                name: Some("x".to_string()),
+                ty: "int".to_string(),
+                value: 3_i64.into_value(),
+                text: "val x : int = 3".to_string(),
+            },
+            output
+        );
+        let output = session.eval_phrase("[x; x * 2]").unwrap();
+        assert_eq!("- : int list = [3; 6]", output.text());
+        assert_eq!(
+            Some(vec![3, 6]),
+            output.value().and_then(Vec::<i64>::from_value)
+        );
+        let output = session.eval_phrase("type t = A | B of int").unwrap();
+        assert_eq!(None, output.value());
+        assert_eq!("type t = A | B of int", output.to_string());
+        let output = session.eval_phrase("B x").unwrap();
+        let (name, arg) = output.value().unwrap().as_constructor().unwrap();
+        assert_eq!(("B", Some(3)), (name, arg.and_then(|arg| arg.as_int())));
+    }
+
+    #[test]
+    fn error_in_session() {
+        let mut session = Session::new();
+        assert!(matches!(
+            session.eval_phrase("1 + true"),
+            Err(Error::Type(_))
+        ));
+        assert!(matches!(
+            session.eval_phrase("failwith \"oops\""),
+            Err(Error::Exception(_))
+        ));
+        assert!(matches!(
+            session.eval_phrase("#quit"),
+            Err(Error::Directive(_))
+        ));
+    }
+
+    #[test]
+    fn get_bindings() {
+        let mut session = Session::new();
+        session
+            .eval_phrase(r#"let xs = [(1, "a", true); (2, "b", false)]"#)
+            .unwrap();
+        assert_eq!(
+            Some(vec![
+                (1, "a".to_string(), true),
+                (2, "b".to_string(), false)
+            ]),
+            session.get::<Vec<(i64, String, bool)>>("xs")
+        );
+        assert_eq!(None, session.get::<i64>("xs"));
+        assert_eq!(None, session.get::<i64>("ys"));
+        assert_eq!(
+            Some("(int * string * bool) list".to_string()),
+            session.type_of("xs")
+        );
+
+        session
+            .eval_phrase("type r = { a : int; b : int ref }")
+            .unwrap();
+        session.eval_phrase("let r = { a = 1; b = ref 2 }").unwrap();
+        let r = session.value("r").unwrap();
+        assert_eq!(Some(1), r.field("a").and_then(|a| a.as_int()));
+        let b = r.field("b").and_then(|b| b.contents());
+        assert_eq!(Some(2), b.and_then(|b| b.as_int()));
+        session.eval_phrase("let f x = x").unwrap();
+        assert!(session.value("f").unwrap().is_function());
+    }
+
+    #[test]
+    fn set_bindings() {
+        let mut session = Session::new();
+        session.set("x", 20_i64).unwrap();
+        session.set("name", "rcaml").unwrap();
+        session
+            .set("pairs", vec![(1_i64, true), (2, false)])
+            .unwrap();
+        assert_eq!(Some("int".to_string()), session.type_of("x"));
+        assert_eq!(
+            Some("(int * bool) list".to_string()),
+            session.type_of("pairs")
+        );
+        session.eval_phrase("let y = x * 2 + 2").unwrap();
+        assert_eq!(Some(42), session.get::<i64>("y"));
+        let output = session.eval_phrase("(name, pairs)").unwrap();
+        assert_eq!(
+            r#"- : string * (int * bool) list = ("rcaml", [(1, true); (2, false)])"#,
+            output.text()
+        );
+        assert!(matches!(
+            session.eval_phrase("x + name"),
+            Err(Error::Type(_))
+        ));
+    }
+
+    #[test]
+    fn register_natives() {
+        let mut session = Session::new();
+        session
+            .register("concat", "string -> string -> string", |args| {
+                let (a, b) = (args[0].as_str().unwrap(), args[1].as_str().unwrap());
+                Ok(format!("{}{}", a, b).into_value())
+            })
+            .unwrap();
+        session
+            .register("checked_div", "int -> int -> int", |args| {
+                match (args[0].as_int().unwrap(), args[1].as_int().unwrap()) {
+                    (_, 0) => Err(Error::from(Exception::failure("division by zero"))),
+                    (a, b) => Ok((a / b).into_value()),
+                }
+            })
+            .unwrap();
+        assert_eq!(
+            Some("string -> string -> string".to_string()),
+            session.type_of("concat")
+        );
+        let output = session
+            .eval_phrase(r#"let greet = concat "Hello, ""#)
+            .unwrap();
+        assert_eq!("val greet : string -> string = <fun>", output.text());
+        session.eval_phrase(r#"let s = greet "rcaml""#).unwrap();
+        assert_eq!(Some("Hello, rcaml".to_string()), session.get::<String>("s"));
+
+        let output = session
+            .eval_phrase(r#"external div : int -> int -> int = "checked_div""#)
+            .unwrap();
+        assert_eq!(
+            r#"external div : int -> int -> int = "checked_div""#,
+            output.text()
+        );
+        let output = session.eval_phrase("div 7 2").unwrap();
+        assert_eq!("- : int = 3", output.text());
+        let input = "div 1 0;;";
+        let err = session.eval_phrase(input).unwrap_err();
+        assert_eq!(
+            "Exception: Failure \"division by zero\".",
+            err.render(input)
+        );
+        assert_eq!(
+            "type/primitive-type",
+            session
+                .eval_phrase(r#"external div : int -> bool = "checked_div""#)
+                .unwrap_err()
+                .code()
+        );
+        let err = session
+            .register("answer", "int", |_| Ok(42_i64.into_value()))
+            .unwrap_err();
+        assert_eq!("type/external-not-function", err.code());
+        // The type given is not in any phrase.
+        assert_eq!(None, err.span());
+        assert_eq!(
+            concat!(
+                "Error: The primitive \"answer\" must be a function,\n",
+                "       since external identifiers must be functions"
+            ),
+            err.render("int")
+        );
+    }
+
+    #[test]
+    fn limit_resources() {
+        let mut session = Session::new();
+        session.eval_phrase("let twice f x = f (f x)").unwrap();
+        session.eval_phrase("let inc x = x + 1").unwrap();
+        let deep = "1 + (1 + (1 + (1 + (1 + 1))))";
+        let many = "twice (twice (twice (twice inc))) 0";
+
+        session.set_limits(EvalLimits {
+            fuel: Some(100),
+            ..EvalLimits::default()
+        });
+        assert_eq!(
+            Err(Error::OutOfFuel(100)),
+            session.eval_phrase(many).map(|_| ())
+        );
+        // Fuel is given to each phrase.
+        assert_eq!("- : int = 6", session.eval_phrase(deep).unwrap().text());
+        assert_eq!(
+            "Error: Evaluation ran out of fuel after 100 steps",
+            session.eval_phrase(many).unwrap_err().render(many)
+        );
+
+        session.set_limits(EvalLimits {
+            max_depth: Some(5),
+            ..EvalLimits::default()
+        });
+        let err = session.eval_phrase(deep).unwrap_err();
+        assert_eq!("Exception: Stack_overflow.", err.render(deep));
+        assert_eq!(
+            "- : int = 3",
+            session.eval_phrase("1 + (1 + 1)").unwrap().text()
+        );
+
+        session.set_limits(EvalLimits {
+            max_alloc: Some(10),
+            ..EvalLimits::default()
+        });
+        assert_eq!(
+            "- : int list = [1; 2; 3]",
+            session.eval_phrase("[1; 2; 3]").unwrap().text()
+        );
+        let err = session.eval_phrase("[1; 2; 3; 4]").unwrap_err();
+        assert_eq!("exception/out-of-memory", err.code());
+        let err = session.eval_phrase("ref (ref (ref (ref (ref (ref 1)))))");
+        assert_eq!("exception/out-of-memory", err.unwrap_err().code());
+
+        session.set_limits(EvalLimits::default());
+        assert_eq!(
+            "- : int = 16",
+            session
+                .eval_phrase("let xs = [1; 2; 3; 4] in twice (twice (twice (twice inc))) 0")
+                .unwrap()
+                .text()
+        );
+    }
+
+    #[test]
+    fn drop_deep_constructor_chains() {
+        for backend in [Backend::Ast, Backend::Bytecode] {
+            let mut session = Session::new();
+            session.set_backend(backend);
+            session.set_limits(EvalLimits {
+                fuel: Some(50_000_000),
+                max_depth: Some(10_000),
+                max_alloc: Some(1 << 26),
+            });
+            session.eval_phrase("type nat = Z | S of nat").unwrap();
+            let phrase = "let rec mk n acc = if n = 0 then acc else mk (n - 1) (S acc) \
+                          in let a = mk 1000000 Z in 1";
+            assert_eq!("- : int = 1", session.eval_phrase(phrase).unwrap().text());
+        }
+    }
+
+    #[test]
+    fn compare_deep_and_cyclic_values() {
+        for backend in [Backend::Ast, Backend::Bytecode] {
+            let mut session = Session::new();
+            session.set_backend(backend);
+            for phrase in [
+                "type nat = Z | S of nat",
+                "let rec mk n acc = if n = 0 then acc else mk (n - 1) (S acc)",
+                "let a = mk 200000 Z",
+                "let b = mk 200000 Z",
+                "type t = Nil | Cons of t ref",
+                "let r = ref Nil",
+                "let s = ref Nil",
+                "let () = r := Cons r; s := Cons s",
+            ] {
+                session.eval_phrase(phrase).unwrap();
+            }
+            for (phrase, result) in [
+                ("a = b", "true"),
+                ("a == b", "true"),
+                ("a < S b", "true"),
+                ("S a <> b", "true"),
+                ("!r = !r", "true"),
+                ("!r == !r", "true"),
+            ] {
+                let output = session.eval_phrase(phrase).unwrap();
+                assert_eq!(format!("- : bool = {}", result), output.text());
+            }
+            session.set_limits(EvalLimits {
+                fuel: Some(10_000),
+                ..EvalLimits::default()
+            });
+            for phrase in ["!r = !s", "!r == !s"] {
+                let err = session.eval_phrase(phrase).unwrap_err();
+                assert_eq!(Error::OutOfFuel(10_000), err);
+            }
+        }
+    }
+
+    #[test]
+    fn share_consed_lists() {
+        let mut session = Session::new();
+        session.eval_phrase("let xs = [1; 2; 3]").unwrap();
+        // Only the cells consed are allocated, the tail being shared.
+        session.set_limits(EvalLimits {
+            max_alloc: Some(10),
+            ..EvalLimits::default()
+        });
+        session.eval_phrase("let ys = 0 :: xs").unwrap();
+        let (xs, ys) = (session.get::<Value>("xs"), session.get::<Value>("ys"));
+        match (&xs.unwrap().0, &ys.unwrap().0) {
+            (eval::Value::List(xs), eval::Value::List(ys)) => {
+                assert!(ys
+                    .iter()
+                    .skip(1)
+                    .zip(xs.iter())
+                    .all(|(y, x)| std::ptr::eq(y, x)));
+            }
+            values => panic!("Expected lists: {:?}", values),
+        }
+        let err = session.eval_phrase("0 :: 0 :: 0 :: 0 :: xs").unwrap_err();
+        assert_eq!("exception/out-of-memory", err.code());
+    }
+
+    #[test]
+    fn overflow_deep_applications() {
+        let mut session = Session::new();
+        session.eval_phrase("let t f x = f (f x)").unwrap();
+        session.eval_phrase("let inc x = x + 1").unwrap();
+        // The chain of closures built is dropped without overflowing.
+        let phrase = "t t t t t inc 0";
+        let err = session.eval_phrase(phrase).unwrap_err();
+        assert_eq!("Exception: Stack_overflow.", err.render(phrase));
+        session.set_backend(Backend::Bytecode);
+        let err = session.eval_phrase(phrase).unwrap_err();
+        assert_eq!("Exception: Stack_overflow.", err.render(phrase));
+    }
+
+    #[test]
+    fn drop_chains_of_globals() {
+        // Each function keeps the globals it was made with, which hold the
+        // previous one, and the chain is dropped without overflowing.
+        let mut session = Session::new();
+        session.eval_phrase("let f0 x = x").unwrap();
+        for i in 1..5000 {
+            let phrase = format!("let f{} x = f{} x + 1", i, i - 1);
+            session.eval_phrase(&phrase).unwrap();
+        }
+        assert_eq!("- : int = 2", session.eval_phrase("f2 0").unwrap().text());
+        drop(session);
+    }
+
+    #[test]
+    fn switch_backends() {
+        let mut session = Session::new();
+        session.eval_phrase("let twice f x = f (f x)").unwrap();
+        session.set_backend(Backend::Bytecode);
+        session.eval_phrase("let succ x = x + 1").unwrap();
+        session.eval_phrase("let inc = twice succ").unwrap();
+        session.eval_phrase("let add x y = x + y").unwrap();
+        session.set_backend(Backend::Ast);
+        // Functions compiled and not are applied to each other.
+        assert_eq!(
+            "- : int = 14",
+            session.eval_phrase("twice inc (add 10 0)").unwrap().text()
+        );
+        session.set_backend(Backend::Bytecode);
+        assert_eq!(
+            "- : int list = [3; 4]",
+            session
+                .eval_phrase("[add 1 2; twice inc 0]")
+                .unwrap()
+                .text()
+        );
+    }
+
+    #[test]
+    fn interrupt_evaluation() {
+        static INTERRUPT: AtomicBool = AtomicBool::new(false);
+        let mut session = Session::new();
+        session.set_interrupt(&INTERRUPT);
+        session.eval_phrase("let x = 1").unwrap();
+        INTERRUPT.store(true, std::sync::atomic::Ordering::SeqCst);
+        let err = session.eval_phrase("x + 1").unwrap_err();
+        assert_eq!("exception/interrupted", err.code());
+        assert_eq!("Interrupted.", err.render("x + 1;;"));
+        // Bindings survive the interruption.
+        assert_eq!("- : int = 2", session.eval_phrase("x + 1").unwrap().text());
+    }
+
+    #[test]
+    fn clone_sessions_apart() {
+        static INTERRUPT: AtomicBool = AtomicBool::new(false);
+        let mut original = Session::new();
+        original.eval_phrase("let twice f x = f (f x)").unwrap();
+        original.eval_phrase("let inc x = x + 1").unwrap();
+        let mut copy = original.clone();
+        copy.set_limits(EvalLimits {
+            fuel: Some(5),
+            ..EvalLimits::default()
+        });
+        copy.set_interrupt(&INTERRUPT);
+        copy.eval_phrase("let y = 2").unwrap();
+
+        // Limits, the interrupt flag and bindings are set apart.
+        assert_eq!(original.limits(), EvalLimits::default());
+        assert_eq!(original.get::<i64>("y"), None);
+        let many = "twice (twice inc) 0";
+        assert_eq!("- : int = 4", original.eval_phrase(many).unwrap().text());
+        assert_eq!(Err(Error::OutOfFuel(5)), copy.eval_phrase(many).map(|_| ()));
+        INTERRUPT.store(true, std::sync::atomic::Ordering::SeqCst);
+        assert_eq!("- : int = 4", original.eval_phrase(many).unwrap().text());
+        let err = copy.eval_phrase("y").unwrap_err();
+        assert_eq!("exception/interrupted", err.code());
+    }
+}
@@ -0,0 +1,43 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Set on SIGINT, i.e. Ctrl-C, until taken by the evaluator or the line
+// editor.
+pub(crate) static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sigint(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+// Catches SIGINT rather than being killed by it. Blocking reads are not
+// restarted after the signal, so that Ctrl-C at the prompt is noticed.
+pub(crate) fn catch_interrupts() -> io::Result<()> {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+// Returns whether SIGINT has been caught since last taken.
+pub(crate) fn take_interrupt() -> bool {
+    INTERRUPTED.swap(false, Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catch_sigint() {
+        catch_interrupts().unwrap();
+        take_interrupt();
+        unsafe { libc::raise(libc::SIGINT) };
+        assert!(take_interrupt());
+        assert!(!take_interrupt());
+    }
+}
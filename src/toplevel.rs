@@ -0,0 +1,526 @@
+use crate::error::{DirectiveError, DirectiveErrorKind, Error, ParseErrorKind, UsedError};
+use crate::eval::{
+    compile_phrase, eval, lambda, parse_directive, phrases, terminator, type_of, Compiled,
+    Directive, DirectiveArg, Span, Target,
+};
+use crate::Bounds;
+use std::fs;
+use std::io::{self, Write};
+
+// what the toplevel does after running a phrase
+#[derive(Debug, PartialEq)]
+pub(crate) enum Control {
+    Continue,
+    Quit,
+}
+
+const HELP: &str = "\
+#quit;;            Exit the toplevel.
+#use \"file.ml\";;   Read and evaluate the phrases in a file.
+#show name;;       Print the type of a value, or the definition of a type.
+#env;;             Print all bound values with their types.
+#print_depth n;;   Print values nested at most n levels deep.
+#print_length n;;  Print at most n values in total.
+#dlambda b;;       Print the IR of phrases before their results if b is true.
+#help;;            Print this list of directives.";
+
+// Runs a phrase, a directive or an expression, writing its output to `out`.
+pub(crate) fn run(
+    input: &str,
+    bounds: &mut Bounds,
+    out: &mut impl Write,
+) -> Result<Control, Error> {
+    match parse_directive(input)? {
+        Some(directive) => run_directive(&directive, bounds, out),
+        None => {
+            if bounds.dlambda {
+                if let Some(dump) = lambda(input, bounds)? {
+                    print(out, &dump)?;
+                }
+            }
+            let output = eval(input, bounds)?;
+            print(out, &output)?;
+            Ok(Control::Continue)
+        }
+    }
+}
+
+fn run_directive(
+    directive: &Directive,
+    bounds: &mut Bounds,
+    out: &mut impl Write,
+) -> Result<Control, Error> {
+    let error = |kind| {
+        Error::from(DirectiveError {
+            kind,
+            span: directive.span,
+        })
+    };
+    let name = directive.name.as_str();
+    let output = match (name, &directive.arg) {
+        ("quit", None) => return Ok(Control::Quit),
+        ("use", Some(DirectiveArg::Str(path))) => {
+            let source = fs::read_to_string(path)
+                .map_err(|_| error(DirectiveErrorKind::CannotFindFile(path.clone())))?;
+            return use_source(&source, path, bounds, out);
+        }
+        ("show", Some(DirectiveArg::Ident(name))) => {
+            let mut lines = Vec::new();
+            if let Some(ty) = type_of(name, bounds) {
+                lines.push(format!("val {} : {}", name, ty));
+            }
+            if let Some(decl) = bounds.decls.get(name) {
+                lines.push(decl.show("type"));
+            }
+            if lines.is_empty() {
+                return Err(error(DirectiveErrorKind::UnknownElement(name.clone())));
+            }
+            lines.join("\n")
+        }
+        ("env", None) => {
+            let mut names: Vec<&String> = bounds.types.keys().collect();
+            names.sort();
+            names
+                .into_iter()
+                .map(|name| format!("val {} : {}", name, bounds.types[name]))
+                .collect::<Vec<String>>()
+                .join("\n")
+        }
+        ("help", None) => HELP.to_string(),
+        ("print_depth", Some(DirectiveArg::Int(n))) => {
+            bounds.limits.depth = *n as usize;
+            String::new()
+        }
+        ("print_length", Some(DirectiveArg::Int(n))) => {
+            bounds.limits.length = *n as usize;
+            String::new()
+        }
+        ("dlambda", Some(DirectiveArg::Bool(b))) => {
+            bounds.dlambda = *b;
+            String::new()
+        }
+        (
+            "quit" | "use" | "show" | "env" | "help" | "print_depth" | "print_length" | "dlambda",
+            _,
+        ) => return Err(error(DirectiveErrorKind::WrongArgument(name.to_string()))),
+        _ => return Err(error(DirectiveErrorKind::Unknown(name.to_string()))),
+    };
+    print(out, &output)?;
+    Ok(Control::Continue)
+}
+
+// Runs a source file as `ocaml file.ml` does. Only what the program prints
+// is output, and the first error ends it, which is written to `err`.
+// Returns the exit status.
+pub(crate) fn run_script(
+    source: &str,
+    path: &str,
+    bounds: &mut Bounds,
+    err: &mut impl Write,
+) -> i32 {
+    for (input, line) in inputs(&skip_shebang(source)) {
+        let message = match terminated(&input, |input| run(input, bounds, &mut io::sink())) {
+            Ok(Control::Continue) => continue,
+            Ok(Control::Quit) => break,
+            Err(Error::Exception(exn)) => format!("Fatal error: exception {}", exn.fatal()),
+            Err(error) => error.render_phrase(&input, line, Some(path)),
+        };
+        let _ = writeln!(err, "{}", message);
+        return 2;
+    }
+    0
+}
+
+// Compiles a source file to a target as `rcaml compile` does, returning the
+// source compiled. The first error ends it, which is written to `err`.
+pub(crate) fn compile_script(
+    source: &str,
+    path: &str,
+    target: Target,
+    bounds: &mut Bounds,
+    err: &mut impl Write,
+) -> Option<String> {
+    let mut program = Compiled::new(target);
+    for (input, line) in inputs(&skip_shebang(source)) {
+        if let Err(error) = terminated(&input, |input| compile_phrase(input, bounds, &mut program))
+        {
+            let _ = writeln!(err, "{}", error.render_phrase(&input, line, Some(path)));
+            return None;
+        }
+    }
+    Some(program.finish())
+}
+
+// Blanks out a shebang line `#!/usr/bin/env rcaml`.
+fn skip_shebang(source: &str) -> String {
+    match source.strip_prefix("#!") {
+        Some(rest) => {
+            let len = rest.find('\n').unwrap_or(rest.len());
+            " ".repeat(len + 2) + &rest[len..]
+        }
+        None => source.to_string(),
+    }
+}
+
+// Runs sources of phrases in turn, e.g. given by `rcaml -e`, writing their
+// results to `out`. The first error ends them, which is written to `err`.
+// Returns the exit status.
+pub(crate) fn run_inputs(
+    sources: &[String],
+    bounds: &mut Bounds,
+    out: &mut impl Write,
+    err: &mut impl Write,
+) -> i32 {
+    for (input, line) in sources.iter().flat_map(|source| inputs(source)) {
+        match terminated(&input, |input| run(input, bounds, out)) {
+            Ok(Control::Continue) => (),
+            Ok(Control::Quit) => break,
+            Err(error) => {
+                let _ = writeln!(err, "{}", error.render_phrase(&input, line, None));
+                return 2;
+            }
+        }
+    }
+    0
+}
+
+// Phrases of a source to run in turn, as in the source, each paired with
+// the line of the source it begins on. The text preceding a phrase on that
+// line is blanked out, so that errors are located by their columns in the
+// source too.
+fn inputs(source: &str) -> Vec<(String, usize)> {
+    let (mut line, mut line_start, mut pos) = (1, 0, 0);
+    phrases(source)
+        .into_iter()
+        .map(|range| {
+            for (idx, _) in source[pos..range.start].match_indices('\n') {
+                line += 1;
+                line_start = pos + idx + 1;
+            }
+            pos = range.start;
+            let blank = " ".repeat(source[line_start..range.start].chars().count());
+            (blank + source[range].trim_end(), line)
+        })
+        .collect()
+}
+
+// Runs `f` on a phrase of a source, terminated by ";;" if it lacks one.
+// Errors found at the ";;" added are reported at the end of input, as the
+// phrase is rendered as it is in the source.
+fn terminated<T>(phrase: &str, f: impl FnOnce(&str) -> Result<T, Error>) -> Result<T, Error> {
+    if terminator(phrase).is_some() {
+        return f(phrase);
+    }
+    f(&format!("{};;", phrase)).map_err(|err| match err {
+        Error::Parse(mut errs) => {
+            for err in errs
+                .iter_mut()
+                .filter(|err| err.span.start.offset >= phrase.len())
+            {
+                err.span = Span::EOF;
+                if let ParseErrorKind::Expected { found, .. } = &mut err.kind {
+                    *found = None;
+                }
+            }
+            Error::Parse(errs)
+        }
+        err => err,
+    })
+}
+
+// Runs the phrases of a file until one fails, returning its error located
+// in the file. Exceptions are returned as they are, having no location.
+fn use_source(
+    source: &str,
+    path: &str,
+    bounds: &mut Bounds,
+    out: &mut impl Write,
+) -> Result<Control, Error> {
+    for (input, line) in inputs(source) {
+        match terminated(&input, |input| run(input, bounds, out)) {
+            Ok(Control::Continue) => (),
+            Ok(Control::Quit) => return Ok(Control::Quit),
+            Err(
+                error @ (Error::Lex(_) | Error::Parse(_) | Error::Type(_) | Error::Directive(_)),
+            ) => {
+                return Err(Error::Used(Box::new(UsedError {
+                    path: path.to_string(),
+                    input,
+                    line,
+                    error,
+                })))
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(Control::Continue)
+}
+
+// Writes an output on its own lines, unless it is empty.
+fn print(out: &mut impl Write, output: &str) -> Result<(), Error> {
+    if output.is_empty() {
+        return Ok(());
+    }
+    writeln!(out, "{}", output).map_err(|err| Error::Internal(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    // Runs inputs in turn, returning their outputs with errors, and what to do
+    // after the last one.
+    fn run_all(inputs: &[&str], bounds: &mut Bounds) -> (String, Control) {
+        let mut out = Vec::new();
+        let mut control = Control::Continue;
+        for input in inputs {
+            control = match run(input, bounds, &mut out) {
+                Ok(control) => control,
+                Err(err) => {
+                    writeln!(out, "{}", err).unwrap();
+                    Control::Continue
+                }
+            };
+        }
+        (String::from_utf8(out).unwrap(), control)
+    }
+
+    #[test]
+    fn run_print_directives() {
+        let mut bounds = Bounds::new();
+        let (out, _) = run_all(
+            &[
+                "#print_length 4;;",
+                "[1; 2; 3; 4; 5];;",
+                "#print_depth 1;;",
+                "([1], 2);;",
+                "#print_depth true;;",
+            ],
+            &mut bounds,
+        );
+        assert_eq!(
+            out,
+            "- : int list = [1; 2; 3; ...]\n\
+             - : int list * int = (...)\n\
+             Wrong type of argument for directive `print_depth'.\n"
+        );
+    }
+
+    #[test]
+    fn run_show_and_env() {
+        let mut bounds = Bounds::new();
+        let (out, _) = run_all(
+            &[
+                "let y = [true];;",
+                "type t = A;;",
+                "let x = A;;",
+                "#show x;;",
+                "#show t;;",
+                "#show ref;;",
+                "#show z;;",
+                "#env;;",
+            ],
+            &mut bounds,
+        );
+        assert_eq!(
+            out,
+            "val y : bool list = [true]\n\
+             type t = A\n\
+             val x : t = A\n\
+             val x : t\n\
+             type t = A\n\
+             val ref : 'a -> 'a ref\n\
+             Unknown element.\n\
+             val x : t\n\
+             val y : bool list\n"
+        );
+    }
+
+    #[test]
+    fn run_quit_and_help() {
+        let mut bounds = Bounds::new();
+        let (out, control) = run_all(&["#help;;", "#quit;;"], &mut bounds);
+        assert!(out.starts_with("#quit;;"));
+        assert_eq!(control, Control::Quit);
+
+        let (out, control) = run_all(&["#quit 1;;", "#foo;;"], &mut bounds);
+        assert_eq!(
+            out,
+            "Wrong type of argument for directive `quit'.\nUnknown directive `foo'.\n"
+        );
+        assert_eq!(control, Control::Continue);
+    }
+
+    #[test]
+    fn run_dlambda() {
+        let mut bounds = Bounds::new();
+        let (out, _) = run_all(
+            &[
+                "#dlambda true;;",
+                "let x = 1 + 2;;",
+                "type t = A;;",
+                "x + true;;",
+                "#dlambda false;;",
+                "x;;",
+                "#dlambda 1;;",
+            ],
+            &mut bounds,
+        );
+        // The IR is printed before the result, unless the phrase is
+        // ill-typed.
+        assert_eq!(
+            out,
+            "(setglobal x (+ 1 2))\n\
+             val x : int = 3\n\
+             type t = A\n\
+             This expression has type bool but an expression was expected of type int\n\
+             - : int = 3\n\
+             Wrong type of argument for directive `dlambda'.\n"
+        );
+    }
+
+    // Runs a script, returning its exit status and error output.
+    fn run_script_of(source: &str) -> (i32, String) {
+        let mut err = Vec::new();
+        let status = run_script(source, "a.ml", &mut Bounds::new(), &mut err);
+        (status, String::from_utf8(err).unwrap())
+    }
+
+    #[test]
+    fn run_scripts() {
+        let source = "#!/usr/bin/env rcaml\nlet x = 1\nlet y = x\ntype t = A;;\nlet () = ()\n";
+        assert_eq!(run_script_of(source), (0, String::new()));
+        let source = "(* a;; *)\nlet x = 1 (* (* b *) *)\nlet y = x;; (* c *)\n";
+        assert_eq!(run_script_of(source), (0, String::new()));
+
+        // Uncaught exceptions end scripts.
+        let source = "let f s = failwith s\nlet x = f \"boom\"\nlet y = 1 / 0";
+        assert_eq!(
+            run_script_of(source),
+            (2, "Fatal error: exception Failure(\"boom\")\n".to_string())
+        );
+
+        let source = "let x = 1\nlet y = x + true\n";
+        assert_eq!(
+            run_script_of(source),
+            (
+                2,
+                concat!(
+                    "File \"a.ml\", line 2, characters 12-16:\n",
+                    "2 | let y = x + true\n",
+                    "                ^^^^\n",
+                    "Error: This expression has type bool but an expression was expected of type int\n",
+                )
+                .to_string()
+            )
+        );
+
+        // A phrase lacking ";;" ends at the end of input.
+        let source = "let x = 1 +";
+        assert_eq!(
+            run_script_of(source),
+            (
+                2,
+                concat!(
+                    "File \"a.ml\", line 1, characters 11-11:\n",
+                    "1 | let x = 1 +\n",
+                    "               ^\n",
+                    "Error: Syntax error: expected an expression, found end of input\n",
+                )
+                .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn split_inputs_by_lines() {
+        let source = "let x = 1 let y =\n  x\n\nlet z = \"a\nb\";;\ntype t = A";
+        assert_eq!(
+            inputs(source),
+            [
+                ("let x = 1".to_string(), 1),
+                ("          let y =\n  x".to_string(), 1),
+                ("let z = \"a\nb\";;".to_string(), 4),
+                // beginning after ";;" on the line before
+                ("    \ntype t = A".to_string(), 5),
+            ]
+        );
+
+        let source = "let x = 1\n\nlet y = 2 let z =\n  x + true";
+        assert_eq!(
+            run_script_of(source),
+            (
+                2,
+                concat!(
+                    "File \"a.ml\", line 4, characters 6-10:\n",
+                    "4 |   x + true\n",
+                    "          ^^^^\n",
+                    "Error: This expression has type bool but an expression was expected of type int\n",
+                )
+                .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn run_expressions() {
+        let mut bounds = Bounds::new();
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let exprs = ["let x = 1 let y = x".to_string(), "y + 1".to_string()];
+        assert_eq!(run_inputs(&exprs, &mut bounds, &mut out, &mut err), 0);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "val x : int = 1\nval y : int = 1\n- : int = 2\n"
+        );
+
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let exprs = ["z".to_string(), "1".to_string()];
+        assert_eq!(run_inputs(&exprs, &mut bounds, &mut out, &mut err), 2);
+        assert!(out.is_empty());
+        assert_eq!(
+            String::from_utf8(err).unwrap(),
+            "Line 1, characters 0-1:\n1 | z\n    ^\nError: Unbound value z\n"
+        );
+    }
+
+    #[test]
+    fn run_use_file() {
+        let path = env::temp_dir().join(format!("rcaml-use-{}.ml", process::id()));
+        let source = "let x = 1;;\nlet s = \"a;;b\";;\n\nx + true;;\nlet y = 2;;\n";
+        fs::write(&path, source).unwrap();
+        let path = path.to_str().unwrap();
+
+        let mut bounds = Bounds::new();
+        let mut out = Vec::new();
+        let input = format!("#use {:?};;", path);
+        let err = run(&input, &mut bounds, &mut out).unwrap_err();
+        // Running stops at the first error, located by its line in the file.
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "val x : int = 1\nval s : string = \"a;;b\"\n"
+        );
+        assert_eq!(err.code(), "type/mismatch");
+        assert_eq!(
+            err.render(&input),
+            format!(
+                "File \"{}\", line 4, characters 4-8:\n\
+                 4 | x + true;;\n        ^^^^\n\
+                 Error: This expression has type bool but an expression was expected of type int",
+                path
+            )
+        );
+        let (out, _) = run_all(&["#env;;"], &mut bounds);
+        assert_eq!(out, "val s : string\nval x : int\n");
+
+        // Scripts using the file fail likewise.
+        let (status, err) = run_script_of(&format!("{}\nlet z = 3\n", input));
+        fs::remove_file(path).unwrap();
+        assert_eq!(status, 2);
+        assert!(err.starts_with(&format!("File \"{}\", line 4,", path)));
+
+        let (out, _) = run_all(&["#use \"/nonexistent.ml\";;"], &mut bounds);
+        assert_eq!(out, "Cannot find file /nonexistent.ml.\n");
+    }
+}
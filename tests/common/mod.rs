@@ -0,0 +1,37 @@
+// Helpers shared by the tests of compiled programs.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{self, Output};
+
+// Directory of the files of a test, removed once it is dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    // Makes the directory of test `name` in a suite, e.g. "compile".
+    pub fn new(suite: &str, name: &str) -> Self {
+        let dir = env::temp_dir().join(format!("rcaml-{}-{}-{}", suite, process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    pub fn path(&self, file: &str) -> PathBuf {
+        self.0.join(file)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// Outcome of a program, as compared.
+pub fn outcome(output: &Output) -> (Option<i32>, String, String) {
+    (
+        output.status.code(),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
@@ -0,0 +1,255 @@
+// Programs compiled by `rcaml compile` and built by the system's C compiler
+// print what the interpreter does, and exit with the same status. They are
+// skipped where no `cc` is found.
+
+mod common;
+
+use common::{outcome, TempDir};
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+const RCAML: &str = env!("CARGO_BIN_EXE_rcaml");
+
+fn has_cc() -> bool {
+    Command::new("cc").arg("--version").output().is_ok()
+}
+
+fn run(program: &Path, args: &[&Path]) -> Output {
+    Command::new(program).args(args).output().unwrap()
+}
+
+// Runs a program by the interpreter and compiled, returning what both print.
+// Collections are made often by a small heap.
+fn interpreted_and_compiled(name: &str, source: &str) -> Option<(Output, Output)> {
+    if !has_cc() {
+        eprintln!("cc not found, skipping {}", name);
+        return None;
+    }
+    let dir = TempDir::new("compile", name);
+    let (ml, c, exe) = (dir.path("a.ml"), dir.path("a.c"), dir.path("a.out"));
+    fs::write(&ml, source).unwrap();
+    let interpreted = run(Path::new(RCAML), &[&ml]);
+
+    let output = Command::new(RCAML)
+        .arg("compile")
+        .arg(&ml)
+        .arg("-o")
+        .arg(&c)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", outcome(&output));
+    let output = Command::new("cc")
+        .args(["-std=c99", "-O1", "-DRC_HEAP_SIZE=256", "-o"])
+        .arg(&exe)
+        .arg(&c)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", outcome(&output));
+    Some((interpreted, run(&exe, &[])))
+}
+
+fn assert_same_outcome(name: &str, source: &str) {
+    if let Some((interpreted, compiled)) = interpreted_and_compiled(name, source) {
+        assert_eq!(outcome(&compiled), outcome(&interpreted));
+    }
+}
+
+#[test]
+fn compile_arithmetic_and_recursion() {
+    assert_same_outcome(
+        "arithmetic",
+        r#"
+let rec fact n = if n = 0 then 1 else n * fact (n - 1)
+let rec fib n = if n < 2 then n else fib (n - 1) + fib (n - 2)
+let () = print_int (fact 20); print_newline ()
+let () = print_int (fib 25); print_newline ()
+let () = print_int (7 / 2 + (0 - 7) / 2 * 10 + 7 / (0 - 2) * 100); print_newline ()
+let () = print_int (4611686018427387903 - 1 + (0 - 4611686018427387903 - 1))
+"#,
+    );
+    // Self tail calls run in constant stack space.
+    assert_same_outcome(
+        "loops",
+        r#"
+let rec loop i acc = if i = 0 then acc else loop (i - 1) (acc + i)
+let () = print_int (loop 300000 0); print_newline ()
+let rec count i n = if i < n then count (i + 1) n else i
+let () = print_int (count 0 (loop 1000 0)); print_newline ()
+"#,
+    );
+}
+
+#[test]
+fn compile_closures_and_partial_applications() {
+    assert_same_outcome(
+        "closures",
+        r#"
+let add x y = x + y
+let inc = add 1
+let compose f g x = f (g x)
+let twice f x = f (f x)
+let () = print_int (twice (twice inc) 0); print_newline ()
+let () = print_int (compose inc (add 10) 5); print_newline ()
+let f3 a b c = a * 100 + b * 10 + c
+let apply3 f a b c = f a b c
+let () = print_int (apply3 f3 1 2 3 + (f3 4) 5 6 + ((f3 7) 8) 9); print_newline ()
+let counter = let r = ref 0 in let next u = r := !r + 1; !r in next
+let () = counter (); counter (); print_int (counter ()); print_newline ()
+let adder n = let rec go i acc = if i = 0 then acc else go (i - 1) (add n acc) in go
+let () = print_int (adder 3 4 0); print_newline ()
+let ps = print_string
+let () = ps "partial\n"; (print_endline) "applied"
+"#,
+    );
+}
+
+#[test]
+fn compile_data() {
+    assert_same_outcome(
+        "data",
+        r#"
+type shape = Circle of int | Square of int | Dot | Line
+type point = { x : int; y : int }
+let p = { y = 2; x = 1 }
+let q = { x = 1; y = 3 }
+let () = print_int (p.x * 10 + p.y); print_newline ()
+let show b = print_endline (if b then "true" else "false")
+let () = show (p < q); show (p = { x = 1; y = 2 }); show (p <> q)
+let () = show (Circle 1 < Square 0); show (Dot < Circle 0); show (Line > Dot)
+let () = show (Circle 2 = Circle 2); show (Square 1 == Square 2)
+let () = show ([1; 2; 3] < [1; 3]); show ([1; 2] < [1; 2; 0]); show ([] = [])
+let () = show ((1, "b") < (1, "c")); show ("ab" < "abc"); show ("b" > "abc")
+let () = show (1 :: [2] = [1; 2]); show ([|1; 2|] = [|1; 2|])
+let r = ref [1]
+let () = r := 0 :: !r; show (!r = [0; 1]); show (ref 1 < ref 2)
+let s = "quote \" backslash \\ tab \t end"
+let () = print_endline s; print_string "no newline"
+"#,
+    );
+}
+
+#[test]
+fn compile_uncaught_exceptions() {
+    for (name, source) in [
+        (
+            "failure",
+            "let () = print_string \"before\"\nlet f s = failwith s\nlet () = f \"b\\\"o\\nom\"\n",
+        ),
+        (
+            "division",
+            "let x = 1\nlet y = x / (x - 1)\nlet () = print_int y\n",
+        ),
+        ("functional", "let f x = x\nlet b = f = f\n"),
+        (
+            "overflow",
+            "let rec r n = if n = 0 then 0 else 1 + r (n - 1)\nlet () = print_int (r 100000000)\n",
+        ),
+    ] {
+        assert_same_outcome(name, source);
+    }
+}
+
+#[test]
+fn compile_with_collections() {
+    // Values are kept across many collections, in frames, closures, globals
+    // and references.
+    assert_same_outcome(
+        "collections",
+        r#"
+let rec build i acc = if i = 0 then acc else build (i - 1) (i :: acc)
+let big = build 20000 []
+let rec churn i keep =
+  if i = 0 then keep
+  else churn (i - 1) (let t = (i, [i; i + 1], "s") in if i / 1000 * 1000 = i then t :: keep else keep)
+let kept = churn 100000 []
+let () = print_endline (if kept = churn 100000 [] then "same" else "different")
+let () = print_endline (if big = build 20000 [] then "same" else "different")
+let make n = let l = build n [] in let get u = l in get
+let g = make 20000
+let cell = ref (build 100 [])
+let rec spin i = if i = 0 then () else (cell := build (i / 1000) []; spin (i - 1))
+let () = spin 20000
+let () = print_endline (if g () = big then "kept" else "lost")
+let () = print_endline (if !cell = [] then "kept" else "lost")
+"#,
+    );
+}
+
+#[test]
+fn compile_int_overflow() {
+    // Ints wrap at 63 bits when compiled, and at 64 bits when interpreted.
+    let source = "let () = print_int (4611686018427387903 + 1)\n";
+    if let Some((interpreted, compiled)) = interpreted_and_compiled("overflow", source) {
+        assert_eq!(
+            String::from_utf8_lossy(&interpreted.stdout),
+            "4611686018427387904"
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&compiled.stdout),
+            "-4611686018427387904"
+        );
+    }
+
+    // Literals beyond max_int of 63 bits are not compiled.
+    let dir = TempDir::new("compile", "literal");
+    let ml = dir.path("big.ml");
+    fs::write(&ml, "let x = 1\nlet y = 9223372036854775807\n").unwrap();
+    let output = Command::new(RCAML)
+        .arg("compile")
+        .arg(&ml)
+        .output()
+        .unwrap();
+    let (status, _, stderr) = outcome(&output);
+    assert_eq!(status, Some(2));
+    assert!(stderr.ends_with(concat!(
+        ", line 2, characters 8-27:\n",
+        "2 | let y = 9223372036854775807\n",
+        "            ^^^^^^^^^^^^^^^^^^^\n",
+        "Error: Integer literal exceeds the range of representable integers of type int\n",
+    )));
+}
+
+#[test]
+fn compile_errors() {
+    let dir = TempDir::new("compile", "errors");
+    let ml = dir.path("bad.ml");
+    fs::write(&ml, "let x = 1\nlet y = x + true\n").unwrap();
+    let output = Command::new(RCAML)
+        .arg("compile")
+        .arg(&ml)
+        .output()
+        .unwrap();
+    let (status, stdout, stderr) = outcome(&output);
+    assert_eq!((status, stdout.as_str()), (Some(2), ""));
+    assert!(stderr.ends_with(concat!(
+        ", line 2, characters 12-16:\n",
+        "2 | let y = x + true\n",
+        "                ^^^^\n",
+        "Error: This expression has type bool but an expression was expected of type int\n",
+    )));
+    // Nothing is written on errors.
+    assert!(!dir.path("bad.c").exists());
+
+    fs::write(&ml, "#use \"other.ml\";;\n").unwrap();
+    let output = Command::new(RCAML)
+        .arg("compile")
+        .arg(&ml)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unknown directive `use'."));
+
+    let output = Command::new(RCAML)
+        .args(["compile", "missing.ml"])
+        .output()
+        .unwrap();
+    assert_eq!(
+        outcome(&output),
+        (
+            Some(2),
+            String::new(),
+            "Cannot find file missing.ml.\n".to_string()
+        )
+    );
+}
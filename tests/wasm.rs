@@ -0,0 +1,229 @@
+// Programs compiled by `rcaml compile -o out.wat` and run by an embedded
+// WebAssembly interpreter print what the interpreter does, and end with the
+// same exceptions.
+
+mod common;
+
+use common::{outcome, TempDir};
+use std::fs;
+use std::process::Command;
+use wasmi::core::TrapCode;
+use wasmi::{Caller, Engine, Extern, Linker, Module, Store};
+
+const RCAML: &str = env!("CARGO_BIN_EXE_rcaml");
+
+// what the imports of a module are given, as a browser would
+#[derive(Default)]
+struct Host {
+    stdout: Vec<u8>,
+    exn: Option<String>,
+}
+
+fn bytes(caller: &Caller<'_, Host>, ptr: i32, len: i32) -> Vec<u8> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .unwrap();
+    let mut buf = vec![0; len as usize];
+    memory.read(caller, ptr as usize, &mut buf).unwrap();
+    buf
+}
+
+// Runs a module, returning its outcome as that of the interpreter: the exit
+// status, what it prints and the uncaught exception.
+fn run_wasm(wat: &str) -> (Option<i32>, String, String) {
+    let wasm = wat::parse_str(wat).unwrap();
+    let engine = Engine::default();
+    let module = Module::new(&engine, &wasm[..]).unwrap();
+    let mut store = Store::new(&engine, Host::default());
+    let mut linker = Linker::<Host>::new(&engine);
+    linker
+        .func_wrap(
+            "rcaml",
+            "print",
+            |mut caller: Caller<'_, Host>, ptr: i32, len: i32| {
+                let printed = bytes(&caller, ptr, len);
+                caller.data_mut().stdout.extend(printed);
+            },
+        )
+        .unwrap();
+    linker
+        .func_wrap(
+            "rcaml",
+            "raise",
+            |mut caller: Caller<'_, Host>, ptr: i32, len: i32| {
+                let exn = String::from_utf8(bytes(&caller, ptr, len)).unwrap();
+                caller.data_mut().exn = Some(exn);
+            },
+        )
+        .unwrap();
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .unwrap()
+        .start(&mut store)
+        .unwrap();
+    let main = instance.get_typed_func::<(), ()>(&store, "main").unwrap();
+    let result = main.call(&mut store, ());
+    let host = store.data();
+    let stdout = String::from_utf8_lossy(&host.stdout).into_owned();
+    let exn = match (result, &host.exn) {
+        (Ok(()), _) => return (Some(0), stdout, String::new()),
+        (Err(_), Some(exn)) => exn.clone(),
+        (Err(err), None) if err.as_trap_code() == Some(TrapCode::StackOverflow) => {
+            "Stack_overflow".to_string()
+        }
+        (Err(err), None) => panic!("{}", err),
+    };
+    (Some(2), stdout, format!("Fatal error: exception {}\n", exn))
+}
+
+fn compile(dir: &TempDir, source: &str) -> String {
+    let (ml, wat) = (dir.path("a.ml"), dir.path("a.wat"));
+    fs::write(&ml, source).unwrap();
+    let output = Command::new(RCAML)
+        .arg("compile")
+        .arg(&ml)
+        .arg("-o")
+        .arg(&wat)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", outcome(&output));
+    fs::read_to_string(&wat).unwrap()
+}
+
+fn assert_same_outcome(name: &str, source: &str) {
+    let dir = TempDir::new("wasm", name);
+    let wat = compile(&dir, source);
+    let interpreted = Command::new(RCAML).arg(dir.path("a.ml")).output().unwrap();
+    assert_eq!(run_wasm(&wat), outcome(&interpreted));
+}
+
+#[test]
+fn run_arithmetic_and_recursion() {
+    assert_same_outcome(
+        "arithmetic",
+        r#"
+let rec fact n = if n = 0 then 1 else n * fact (n - 1)
+let rec fib n = if n < 2 then n else fib (n - 1) + fib (n - 2)
+let () = print_int (fact 20); print_newline ()
+let () = print_int (fib 20); print_newline ()
+let () = print_int (7 / 2 + (0 - 7) / 2 * 10 + 7 / (0 - 2) * 100); print_newline ()
+let () = print_int (4611686018427387903 - 1 + (0 - 4611686018427387903 - 1))
+"#,
+    );
+    // Self tail calls run in constant stack space.
+    assert_same_outcome(
+        "loops",
+        r#"
+let rec loop i acc = if i = 0 then acc else loop (i - 1) (acc + i)
+let () = print_int (loop 300000 0); print_newline ()
+let rec count i n = if i < n then count (i + 1) n else i
+let () = print_int (count 0 (loop 1000 0)); print_newline ()
+"#,
+    );
+}
+
+#[test]
+fn run_closures_and_partial_applications() {
+    assert_same_outcome(
+        "closures",
+        r#"
+let add x y = x + y
+let inc = add 1
+let compose f g x = f (g x)
+let twice f x = f (f x)
+let () = print_int (twice (twice inc) 0); print_newline ()
+let () = print_int (compose inc (add 10) 5); print_newline ()
+let f3 a b c = a * 100 + b * 10 + c
+let apply3 f a b c = f a b c
+let () = print_int (apply3 f3 1 2 3 + (f3 4) 5 6 + ((f3 7) 8) 9); print_newline ()
+let f5 a b c d e = a + b + c + d + e
+let () = print_int (apply3 (f5 1 2) 3 4 5); print_newline ()
+let counter = let r = ref 0 in let next u = r := !r + 1; !r in next
+let () = counter (); counter (); print_int (counter ()); print_newline ()
+let adder n = let rec go i acc = if i = 0 then acc else go (i - 1) (add n acc) in go
+let () = print_int (adder 3 4 0); print_newline ()
+let ps = print_string
+let () = ps "partial\n"; (print_endline) "applied"
+external set : 'a ref -> 'a -> unit = ":="
+let set1 = set
+let r = ref 1
+let () = set1 r 5; print_int !r; print_newline ()
+"#,
+    );
+}
+
+#[test]
+fn run_data() {
+    assert_same_outcome(
+        "data",
+        r#"
+type shape = Circle of int | Square of int | Dot | Line
+type point = { x : int; y : int }
+let p = { y = 2; x = 1 }
+let q = { x = 1; y = 3 }
+let () = print_int (p.x * 10 + p.y); print_newline ()
+let show b = print_endline (if b then "true" else "false")
+let () = show (p < q); show (p = { x = 1; y = 2 }); show (p <> q)
+let () = show (Circle 1 < Square 0); show (Dot < Circle 0); show (Line > Dot)
+let () = show (Circle 2 = Circle 2); show (Square 1 == Square 2)
+let () = show ([1; 2; 3] < [1; 3]); show ([1; 2] < [1; 2; 0]); show ([] = [])
+let () = show ((1, "b") < (1, "c")); show ("ab" < "abc"); show ("b" > "abc")
+let () = show (1 :: [2] = [1; 2]); show ([|1; 2|] = [|1; 2|])
+let r = ref [1]
+let () = r := 0 :: !r; show (!r = [0; 1]); show (ref 1 < ref 2)
+let s = "quote \" backslash \\ tab \t end"
+let () = print_endline s; print_string "no newline"
+"#,
+    );
+}
+
+#[test]
+fn run_int_overflow() {
+    // Ints wrap at 63 bits when compiled, and at 64 bits when interpreted.
+    let dir = TempDir::new("wasm", "overflow");
+    let wat = compile(&dir, "let () = print_int (4611686018427387903 + 1)\n");
+    let interpreted = Command::new(RCAML).arg(dir.path("a.ml")).output().unwrap();
+    assert_eq!(outcome(&interpreted).1, "4611686018427387904");
+    assert_eq!(run_wasm(&wat).1, "-4611686018427387904");
+}
+
+#[test]
+fn run_uncaught_exceptions() {
+    for (name, source) in [
+        (
+            "failure",
+            "let () = print_string \"before\"\nlet f s = failwith s\nlet () = f \"b\\\"o\\nom\\001\"\n",
+        ),
+        (
+            "division",
+            "let x = 1\nlet y = x / (x - 1)\nlet () = print_int y\n",
+        ),
+        ("functional", "let f x = x\nlet b = f = f\n"),
+        ("compared", "let f x = x\nlet b = f < f\n"),
+        (
+            "overflow",
+            "let rec r n = if n = 0 then 0 else 1 + r (n - 1)\nlet () = print_int (r 100000000)\n",
+        ),
+    ] {
+        assert_same_outcome(name, source);
+    }
+}
+
+#[test]
+fn run_with_growing_memory() {
+    // Memory grows as blocks are allocated, which are never freed.
+    assert_same_outcome(
+        "memory",
+        r#"
+let rec build i acc = if i = 0 then acc else build (i - 1) (i :: acc)
+let big = build 20000 []
+let rec churn i keep =
+  if i = 0 then keep
+  else churn (i - 1) (let t = (i, [i; i + 1], "s") in if i / 1000 * 1000 = i then t :: keep else keep)
+let kept = churn 100000 []
+let () = print_endline (if kept = churn 100000 [] then "same" else "different")
+let () = print_endline (if big = build 20000 [] then "same" else "different")
+"#,
+    );
+}